@@ -0,0 +1,61 @@
+use axum::body::Body;
+use criterion::{criterion_group, criterion_main, Criterion};
+use http::{Request, Response};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tower::{service_fn, Layer, Service};
+use tower_governor::{
+    governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor, GovernorLayer,
+};
+
+const THREADS: usize = 8;
+
+// Hammers a single key from several threads, only exercising the limiter check
+// done in `Service::call`. The burst is never exhausted, while the replenish interval
+// stays long enough for the prefetched cells to be used before they expire.
+fn hot_key(c: &mut Criterion, name: &str, prefetch: u32, unkeyed: bool) {
+    let mut builder = GovernorConfigBuilder::default()
+        .per_millisecond(1)
+        .burst_size(u32::MAX)
+        .prefetch(prefetch)
+        .key_extractor(GlobalKeyExtractor);
+    if unkeyed {
+        builder.unkeyed();
+    }
+    let config = Arc::new(builder.finish().unwrap());
+    let layer = GovernorLayer { config };
+
+    c.bench_function(name, |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            thread::scope(|s| {
+                for _ in 0..THREADS {
+                    let mut service = layer.layer(service_fn(|_: Request<()>| async {
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }));
+                    s.spawn(move || {
+                        for _ in 0..iters {
+                            drop(service.call(Request::new(())));
+                        }
+                    });
+                }
+            });
+            start.elapsed() / THREADS as u32
+        })
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    hot_key(c, "hot key", 0, false);
+    hot_key(c, "hot key with prefetch", 64, false);
+    hot_key(c, "hot key unkeyed", 0, true);
+}
+
+criterion_group! {
+    name = hot_keys;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = benches
+}
+criterion_main!(hot_keys);
@@ -9,6 +9,8 @@ use tower_governor::{
     GovernorLayer,
 };
 
+// A hand written extractor, see `key_extractor::BearerKeyExtractor` of the `typed-header`
+// feature for one handling the quoting and whitespace of the header.
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 struct UserToken;
 
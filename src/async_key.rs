@@ -0,0 +1,270 @@
+//! Rate limiting keys that need an async lookup, e.g. resolving an opaque token to a customer.
+//!
+//! An [`AsyncKeyExtractor`] is wrapped into an [`AsyncKey`], the key extractor of the
+//! configuration, and the configuration is applied with an [`AsyncGovernorLayer`], which
+//! awaits the extraction before the limiter is checked.
+//!
+//! # Example
+//! ```rust
+//! use http::request::Parts;
+//! use std::{sync::Arc, time::Duration};
+//! use tower_governor::{
+//!     async_key::{AsyncGovernorLayer, AsyncKey, AsyncKeyExtractor},
+//!     errors::GovernorError,
+//!     governor::GovernorConfigBuilder,
+//! };
+//!
+//! #[derive(Clone)]
+//! struct CustomerExtractor;
+//!
+//! impl AsyncKeyExtractor for CustomerExtractor {
+//!     type Key = u64;
+//!
+//!     async fn extract(&self, parts: &Parts) -> Result<u64, GovernorError> {
+//!         let token = parts
+//!             .headers
+//!             .get("authorization")
+//!             .ok_or(GovernorError::UnableToExtractKey)?;
+//!         // look the customer of `token` up in a cache
+//!         Ok(token.len() as u64)
+//!     }
+//! }
+//!
+//! let config = GovernorConfigBuilder::default()
+//!     .key_extractor(AsyncKey::new(CustomerExtractor).timeout(Duration::from_millis(50)))
+//!     .finish()
+//!     .unwrap();
+//! let layer = AsyncGovernorLayer::new(Arc::new(config));
+//! ```
+
+use crate::{
+    errors::GovernorError,
+    governor::{Governor, GovernorConfig},
+    headers::RejectionReason,
+    key_extractor::KeyExtractor,
+};
+use axum::body::Body;
+use governor::{clock::Clock, middleware::RateLimitingMiddleware};
+use http::{request::Parts, Request, Response};
+use std::{
+    fmt::Debug,
+    future::Future,
+    hash::Hash,
+    mem,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service};
+
+/// Extraction of a rate limiting key that needs to await a lookup.
+pub trait AsyncKeyExtractor: Clone + Send + Sync + 'static {
+    /// The type of the key.
+    type Key: Clone + Hash + Eq + Debug + Send + Sync + 'static;
+
+    /// Extract the key from the parts of the request.
+    fn extract(
+        &self,
+        parts: &Parts,
+    ) -> impl Future<Output = Result<Self::Key, GovernorError>> + Send;
+}
+
+/// What to do with requests whose key couldn't be extracted, see [`AsyncKey::on_failure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtractionFailure {
+    /// Respond with the error of the extraction through the error handler of the
+    /// configuration, `UnableToExtractKey` for timeouts. This is the default.
+    #[default]
+    Reject,
+    /// Forward the request to the inner service without rate limiting it.
+    Allow,
+}
+
+// The key extracted by an `AsyncGovernor`, read back by `AsyncKey`.
+#[derive(Debug, Clone)]
+struct ExtractedKey<Key>(Key);
+
+/// The [`KeyExtractor`] of configurations whose keys are extracted by an
+/// [`AsyncKeyExtractor`]. Only works together with an [`AsyncGovernorLayer`].
+#[derive(Debug, Clone)]
+pub struct AsyncKey<A> {
+    extractor: A,
+    timeout: Option<Duration>,
+    on_failure: ExtractionFailure,
+}
+
+impl<A: AsyncKeyExtractor> AsyncKey<A> {
+    /// Extract the keys with `extractor`, rejecting the requests it fails on.
+    pub fn new(extractor: A) -> Self {
+        Self {
+            extractor,
+            timeout: None,
+            on_failure: ExtractionFailure::Reject,
+        }
+    }
+
+    /// Give up on extractions taking longer than `timeout`. There is no timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set what to do with requests whose key couldn't be extracted or timed out.
+    pub fn on_failure(mut self, on_failure: ExtractionFailure) -> Self {
+        self.on_failure = on_failure;
+        self
+    }
+
+    async fn extract_async(&self, parts: &Parts) -> Result<A::Key, GovernorError> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.extractor.extract(parts))
+                .await
+                .unwrap_or(Err(GovernorError::UnableToExtractKey)),
+            None => self.extractor.extract(parts).await,
+        }
+    }
+}
+
+impl<A: AsyncKeyExtractor> KeyExtractor for AsyncKey<A> {
+    type Key = A::Key;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "async key"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        req.extensions()
+            .get::<ExtractedKey<A::Key>>()
+            .map(|extracted| extracted.0.clone())
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+/// Layer applying a configuration whose keys are extracted by an [`AsyncKeyExtractor`].
+pub struct AsyncGovernorLayer<A, M, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    config: Arc<GovernorConfig<AsyncKey<A>, M, C>>,
+}
+
+impl<A, M, C> AsyncGovernorLayer<A, M, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Rate limit requests by `config` once their key was extracted.
+    pub fn new(config: Arc<GovernorConfig<AsyncKey<A>, M, C>>) -> Self {
+        Self { config }
+    }
+}
+
+impl<A, M, C> Clone for AsyncGovernorLayer<A, M, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<A, M, C, S> Layer<S> for AsyncGovernorLayer<A, M, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    type Service = AsyncGovernor<A, M, S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AsyncGovernor {
+            config: self.config.clone(),
+            inner,
+        }
+    }
+}
+
+/// Middleware created by an [`AsyncGovernorLayer`].
+pub struct AsyncGovernor<A, M, S, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    config: Arc<GovernorConfig<AsyncKey<A>, M, C>>,
+    inner: S,
+}
+
+impl<A, M, S, C> Clone for AsyncGovernor<A, M, S, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+impl<A, M, S, C, ReqBody> Service<Request<ReqBody>> for AsyncGovernor<A, M, S, C>
+where
+    A: AsyncKeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+    S: Service<Request<ReqBody>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send,
+    Governor<AsyncKey<A>, M, S, C>:
+        Service<Request<ReqBody>, Response = Response<Body>, Error = S::Error>,
+    <Governor<AsyncKey<A>, M, S, C> as Service<Request<ReqBody>>>::Future: Send,
+    GovernorConfig<AsyncKey<A>, M, C>: Send + Sync + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Response<Body>, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let config = self.config.clone();
+        // hand the service that was driven to readiness to the future
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let key_extractor = config.key_extractor();
+            match key_extractor.extract_async(&parts).await {
+                Ok(key) => {
+                    parts.extensions.insert(ExtractedKey(key));
+                }
+                Err(_) if key_extractor.on_failure == ExtractionFailure::Allow => {
+                    return inner.call(Request::from_parts(parts, body)).await;
+                }
+                Err(error) => {
+                    let governor = Governor::new(inner, &config);
+                    let error = error.with_reason(RejectionReason::ExtractionFailed);
+                    return Ok(governor.error_handler()(error));
+                }
+            }
+            let future = Governor::new(inner, &config).call(Request::from_parts(parts, body));
+            future.await
+        })
+    }
+}
@@ -0,0 +1,227 @@
+//! Structured audit log of rate limiting decisions.
+//!
+//! Enabled by the `audit` feature. Every request checked against the limiter produces a
+//! [`GovernorEvent`] handed to the [`AuditSink`] configured through
+//! [`GovernorConfigBuilder::audit_sink`], which allows recording throttling decisions without
+//! going through the tracing infrastructure.
+//!
+//! [`GovernorConfigBuilder::audit_sink`]: crate::governor::GovernorConfigBuilder::audit_sink
+
+use crate::decision::Decision;
+use crate::key_extractor::Source;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{
+        mpsc::{self, Sender, UnboundedSender},
+        oneshot,
+    },
+};
+
+/// Number of events an [`AuditSink::writer`] buffers before dropping the new ones.
+pub const DEFAULT_WRITER_CAPACITY: usize = 1024;
+
+/// A rate limiting decision taken for a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GovernorEvent {
+    /// When the decision was taken.
+    pub timestamp: SystemTime,
+    /// The rate limiting key of the request, as formatted by its `Debug` implementation.
+    pub key: String,
+    /// Where the client IP address the key was derived from was found, if known.
+    pub source: Option<Source>,
+    /// The decision taken.
+    pub decision: Decision,
+    /// The path of the request.
+    pub route: String,
+    /// The name of the policy, see [`policy_name`].
+    ///
+    /// [`policy_name`]: crate::governor::GovernorConfigBuilder::policy_name
+    pub policy: Option<Arc<str>>,
+    /// Whether the quota is enforced for the key, `false` in [`decide_only`] mode and for the
+    /// keys outside of the [`enforce_ratio`] cohort.
+    ///
+    /// [`decide_only`]: crate::governor::GovernorConfigBuilder::decide_only
+    /// [`enforce_ratio`]: crate::governor::GovernorConfigBuilder::enforce_ratio
+    pub enforced: bool,
+}
+
+impl GovernorEvent {
+    /// Serialize this event into a single line of JSON, without the trailing newline.
+    ///
+    /// The line holds the `timestamp` in milliseconds since the Unix epoch, the `key` and its
+    /// `source`, the `decision` (`allow` or `deny`), the `wait_ms` of rejected requests, the `route`, the
+    /// `policy` name and whether the quota is `enforced`.
+    pub fn to_json_line(&self) -> String {
+        let timestamp = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let (decision, wait_ms) = match self.decision {
+            Decision::Allowed => ("allow", None),
+            Decision::Rejected { wait_time } => ("deny", Some(wait_time.as_millis() as u64)),
+        };
+        serde_json::json!({
+            "timestamp": timestamp,
+            "key": self.key,
+            "source": self.source.map(|source| source.as_str()),
+            "decision": decision,
+            "wait_ms": wait_ms,
+            "route": self.route,
+            "policy": self.policy.as_deref(),
+            "enforced": self.enforced,
+        })
+        .to_string()
+    }
+}
+
+type EventCallback = dyn Fn(GovernorEvent) + Send + Sync;
+
+// A message to the task of a writer sink.
+enum Message {
+    Event(GovernorEvent),
+    // flush the events sent so far and stop
+    Close(oneshot::Sender<()>),
+}
+
+/// Destination of the [`GovernorEvent`]s.
+#[derive(Clone)]
+pub struct AuditSink {
+    record: Arc<EventCallback>,
+    writer: Option<Sender<Message>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditSink {
+    /// Hand every event to `record`. It is called on the request path, so it must not block.
+    pub fn new<F>(record: F) -> Self
+    where
+        F: Fn(GovernorEvent) + Send + Sync + 'static,
+    {
+        Self {
+            record: Arc::new(record),
+            writer: None,
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Send every event through the channel. Events are dropped once the receiver is closed.
+    pub fn channel(sender: UnboundedSender<GovernorEvent>) -> Self {
+        Self::new(move |event| {
+            let _ = sender.send(event);
+        })
+    }
+
+    /// Write every event as a line of JSON to `writer`, see [`GovernorEvent::to_json_line`].
+    ///
+    /// The writes happen on a task spawned onto the current Tokio runtime, so this must be
+    /// called from within one. The task ends when the sink is dropped, a write fails or the
+    /// configuration is [shut down](crate::governor::GovernorConfig::shutdown).
+    ///
+    /// Up to [`DEFAULT_WRITER_CAPACITY`] events wait for the writer, the next ones are dropped
+    /// and counted by [`dropped`](Self::dropped) so that a slow writer never holds up
+    /// requests nor grows the memory use of the process.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{audit::AuditSink, governor::GovernorConfigBuilder};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let config = GovernorConfigBuilder::default()
+    ///     .policy_name("login")
+    ///     .audit_sink(AuditSink::writer(tokio::io::sink()))
+    ///     .finish()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn writer<W>(writer: W) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::writer_with_capacity(writer, DEFAULT_WRITER_CAPACITY)
+    }
+
+    /// Same as [`writer`](Self::writer), buffering up to `capacity` events instead.
+    pub fn writer_with_capacity<W>(mut writer: W, capacity: usize) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, mut receiver) = mpsc::channel::<Message>(capacity.max(1));
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let event = match message {
+                    Message::Event(event) => event,
+                    Message::Close(done) => {
+                        let _ = writer.shutdown().await;
+                        let _ = done.send(());
+                        break;
+                    }
+                };
+                let mut line = event.to_json_line();
+                line.push('\n');
+                if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let events = sender.clone();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let full = dropped.clone();
+        Self {
+            record: Arc::new(move |event| {
+                if let Err(mpsc::error::TrySendError::Full(_)) =
+                    events.try_send(Message::Event(event))
+                {
+                    full.fetch_add(1, Ordering::Relaxed);
+                }
+            }),
+            writer: Some(sender),
+            dropped,
+        }
+    }
+
+    /// The number of events dropped so far because the buffer of the
+    /// [`writer`](Self::writer) was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, event: GovernorEvent) {
+        (self.record)(event)
+    }
+
+    // Wait for the writer task to write out the events recorded so far, and stop it.
+    pub(crate) async fn close(&self) {
+        if let Some(writer) = &self.writer {
+            let (done, closed) = oneshot::channel();
+            if writer.send(Message::Close(done)).await.is_ok() {
+                let _ = closed.await;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AuditSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditSink").finish()
+    }
+}
+
+impl PartialEq for AuditSink {
+    fn eq(&self, _: &Self) -> bool {
+        // there is no easy way to tell two sinks apart
+        true
+    }
+}
+
+impl Eq for AuditSink {}
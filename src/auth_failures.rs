@@ -0,0 +1,98 @@
+use crate::{ban::Bans, expiring::ExpiringMap, governor::ResponseHook};
+use axum::body::Body;
+use http::{Request, Response, StatusCode};
+use std::{
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// Counts the authentication failures of every key, banning those failing too often, see
+// `GovernorConfigBuilder::limit_auth_failures`.
+pub(crate) struct AuthFailures<Key> {
+    max_failures: u32,
+    window: Duration,
+    ban: Duration,
+    // the failures of every key, along with when the first one happened
+    failures: ExpiringMap<Key, (u32, Instant)>,
+}
+
+impl<Key> fmt::Debug for AuthFailures<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthFailures")
+            .field("max_failures", &self.max_failures)
+            .field("window", &self.window)
+            .field("ban", &self.ban)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq> AuthFailures<Key> {
+    pub(crate) fn new(max_failures: u32, window: Duration, ban: Duration) -> Self {
+        Self {
+            max_failures,
+            window,
+            ban,
+            failures: ExpiringMap::new(),
+        }
+    }
+}
+
+impl<Key: Hash + Eq + Clone + Send + Sync + 'static> AuthFailures<Key> {
+    /// Count a failure of `key`, returning whether it reached the maximum.
+    fn record(&self, key: &Key) -> bool {
+        let now = Instant::now();
+        let mut failures = self.failures.lock(key);
+        failures.purge(|(_, since)| now.duration_since(*since) >= self.window);
+        let (count, since) = failures.entry(key.clone()).or_insert((0, now));
+        if now.duration_since(*since) >= self.window {
+            *count = 0;
+            *since = now;
+        }
+        *count += 1;
+        if *count < self.max_failures {
+            return false;
+        }
+        failures.remove(key);
+        true
+    }
+
+    /// Watch the response to a request of `key`, banning the key once it failed to
+    /// authenticate too often.
+    pub(crate) fn watch(self: &Arc<Self>, key: Key, bans: Arc<Bans<Key>>) -> AuthWatch {
+        let failures = self.clone();
+        AuthWatch(Arc::new(move |response| {
+            let status = response.map(Response::status);
+            let failed = matches!(
+                status,
+                Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            );
+            if failed && failures.record(&key) {
+                bans.ban(key.clone(), failures.ban);
+            }
+        }))
+    }
+}
+
+type WatchCallback = dyn Fn(Option<&Response<Body>>) + Send + Sync;
+
+// Watches the response to an allowed request for authentication failures, handed over to its
+// response future through the request extensions.
+#[derive(Clone)]
+pub(crate) struct AuthWatch(Arc<WatchCallback>);
+
+impl AuthWatch {
+    pub(crate) fn attach<B>(self, req: &mut Request<B>) {
+        req.extensions_mut().insert(self);
+    }
+
+    /// The watch attached to `req`, if any.
+    pub(crate) fn take<B>(req: &mut Request<B>) -> Option<Self> {
+        req.extensions_mut().remove::<Self>()
+    }
+
+    pub(crate) fn into_hook(self) -> ResponseHook {
+        ResponseHook(Box::new(move |response| (self.0)(response)))
+    }
+}
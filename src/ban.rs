@@ -0,0 +1,182 @@
+use crate::{deadline, decision::RejectionContext, errors::SnapshotError, expiring::ExpiringMap};
+use std::{
+    fmt::{self, Display},
+    hash::Hash,
+    io::{BufRead, Write},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// First line of the ban lists written by [`Bans::save`].
+const BANS_HEADER: &str = "tower-governor-bans 1";
+
+type BanCallback = dyn Fn(&RejectionContext<'_>) + Send + Sync;
+
+// Observer of the keys getting banned.
+#[derive(Clone)]
+pub(crate) struct BanObserver(pub(crate) Arc<BanCallback>);
+
+impl fmt::Debug for BanObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BanObserver").finish()
+    }
+}
+
+impl PartialEq for BanObserver {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for BanObserver {}
+
+// Temporary bans of the keys so far over their quota that their wait time exceeds a threshold,
+// see `GovernorConfigBuilder::ban_above`, and of the keys banned by hand, see
+// `GovernorConfigBuilder::ban_list`.
+pub(crate) struct Bans<Key> {
+    // the threshold and the duration of the bans, if keys are banned automatically
+    escalation: Option<(Duration, Duration)>,
+    pub(crate) observer: Option<BanObserver>,
+    keys: ExpiringMap<Key, Instant>,
+}
+
+impl<Key> fmt::Debug for Bans<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bans")
+            .field("escalation", &self.escalation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The outcome of [`Bans::escalate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ban {
+    /// The key was banned by this request, for the given time.
+    New(Duration),
+    /// The key was already banned, for the given time left.
+    Ongoing(Duration),
+}
+
+impl Ban {
+    pub(crate) fn time_left(self) -> Duration {
+        match self {
+            Ban::New(left) | Ban::Ongoing(left) => left,
+        }
+    }
+}
+
+impl<Key: Hash + Eq + Clone> Bans<Key> {
+    pub(crate) fn new(
+        escalation: Option<(Duration, Duration)>,
+        observer: Option<BanObserver>,
+    ) -> Self {
+        Self {
+            escalation,
+            observer,
+            keys: ExpiringMap::new(),
+        }
+    }
+
+    /// The time left until `key` is let through again, if banned.
+    pub(crate) fn banned_for(&self, key: &Key) -> Option<Duration> {
+        let keys = self.keys.lock(key);
+        let until = keys.get(key)?;
+        until
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Ban `key` if it is rejected for `wait_time` or longer than the threshold, returning
+    /// `None` if it isn't banned.
+    pub(crate) fn escalate(&self, key: &Key, wait_time: Duration) -> Option<Ban> {
+        if let Some(left) = self.banned_for(key) {
+            return Some(Ban::Ongoing(left));
+        }
+        let (threshold, duration) = self.escalation?;
+        if wait_time < threshold {
+            return None;
+        }
+        self.ban(key.clone(), duration);
+        Some(Ban::New(duration))
+    }
+
+    /// Ban `key` for `duration`, replacing its current ban.
+    pub(crate) fn ban(&self, key: Key, duration: Duration) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock(&key);
+        keys.purge(|until| *until <= now);
+        keys.insert(key, deadline(now, duration));
+    }
+
+    /// Lift the ban of `key`, returning whether it was banned.
+    pub(crate) fn unban(&self, key: &Key) -> bool {
+        let now = Instant::now();
+        let mut keys = self.keys.lock(key);
+        keys.remove(key).is_some_and(|until| until > now)
+    }
+
+    /// The banned keys, along with the time left until they are let through again.
+    pub(crate) fn list(&self) -> Vec<(Key, Duration)> {
+        let now = Instant::now();
+        self.keys
+            .filter_map(|key, until| (*until > now).then(|| (key.clone(), *until - now)))
+    }
+
+    /// Write the banned keys to `writer`, along with the time left on their ban.
+    pub(crate) fn save<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        Key: Display,
+    {
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        writeln!(writer, "{}", BANS_HEADER)?;
+        writeln!(writer, "{}", taken_at)?;
+        for (key, left) in self.list() {
+            let key = key.to_string();
+            // the key ends the line, so keys spanning lines can't be restored
+            if !key.contains(['\n', '\r']) {
+                writeln!(writer, "{} {}", left.as_nanos(), key)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Ban the keys of a list written by [`save`](Self::save) for the time left on their ban,
+    /// returning the number of keys still banned.
+    pub(crate) fn load<R: BufRead>(&self, reader: R) -> Result<usize, SnapshotError>
+    where
+        Key: FromStr,
+    {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != BANS_HEADER {
+            return Err(SnapshotError::UnsupportedVersion(header));
+        }
+        let taken_at = lines.next().transpose()?.unwrap_or_default();
+        let taken_at = u128::from_str(&taken_at).map_err(|_| SnapshotError::Malformed(2))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let elapsed = now.checked_sub(taken_at).ok_or(SnapshotError::ClockSkew)?;
+        let elapsed = Duration::from_millis(elapsed.try_into().unwrap_or(u64::MAX));
+
+        let mut restored = 0;
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let malformed = || SnapshotError::Malformed(i + 3);
+            let (left, key) = line.split_once(' ').ok_or_else(malformed)?;
+            let left = left.parse::<u64>().map_err(|_| malformed())?;
+            let key = key.parse::<Key>().map_err(|_| malformed())?;
+            let left = Duration::from_nanos(left).checked_sub(elapsed);
+            if let Some(left) = left.filter(|left| !left.is_zero()) {
+                self.ban(key, left);
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
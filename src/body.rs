@@ -0,0 +1,104 @@
+//! The response bodies the rejections of the layer can be rendered into.
+//!
+//! [`GovernorError::as_response`] and [`handle_error::into_response`] build their responses
+//! with any [`RejectionBody`], so that adapters for other frameworks don't have to map a
+//! `Response<String>` into their own body type.
+//!
+//! Besides `String`, `Vec<u8>` and [`Bytes`], it is implemented for:
+//! - axum's `Body`, with the `axum` feature
+//! - http-body-util's `Full`, `BoxBody` and `UnsyncBoxBody`, hyper's usual bodies, with the
+//!   `http-body-util` feature
+//! - tonic's `Body`, with the `tonic` feature
+//!
+//! # Example
+//! ```rust
+//! use bytes::Bytes;
+//! use tower_governor::{body::RejectionBody, errors::GovernorError};
+//!
+//! // a body type of another framework
+//! struct FrameworkBody(Bytes);
+//!
+//! impl RejectionBody for FrameworkBody {
+//!     fn from_bytes(bytes: Bytes) -> Self {
+//!         FrameworkBody(bytes)
+//!     }
+//! }
+//!
+//! let response = GovernorError::UnableToExtractKey.as_response::<FrameworkBody>();
+//! assert_eq!(&response.body().0[..], b"Unable To Extract Key!");
+//! ```
+//!
+//! [`GovernorError::as_response`]: crate::errors::GovernorError::as_response
+//! [`handle_error::into_response`]: crate::handle_error::into_response
+
+use bytes::Bytes;
+
+/// A response body holding the message of a rejection.
+pub trait RejectionBody {
+    /// The body holding `bytes`.
+    fn from_bytes(bytes: Bytes) -> Self;
+}
+
+impl RejectionBody for Bytes {
+    fn from_bytes(bytes: Bytes) -> Self {
+        bytes
+    }
+}
+
+impl RejectionBody for Vec<u8> {
+    fn from_bytes(bytes: Bytes) -> Self {
+        bytes.into()
+    }
+}
+
+impl RejectionBody for String {
+    fn from_bytes(bytes: Bytes) -> Self {
+        match String::from_utf8(bytes.into()) {
+            Ok(string) => string,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+impl RejectionBody for axum::body::Body {
+    fn from_bytes(bytes: Bytes) -> Self {
+        bytes.into()
+    }
+}
+
+#[cfg(feature = "http-body-util")]
+impl<D: From<Bytes> + bytes::Buf> RejectionBody for http_body_util::Full<D> {
+    fn from_bytes(bytes: Bytes) -> Self {
+        http_body_util::Full::new(bytes.into())
+    }
+}
+
+#[cfg(feature = "http-body-util")]
+impl<E: 'static> RejectionBody for http_body_util::combinators::BoxBody<Bytes, E> {
+    fn from_bytes(bytes: Bytes) -> Self {
+        use http_body_util::BodyExt;
+
+        http_body_util::Full::new(bytes)
+            .map_err(|never| match never {})
+            .boxed()
+    }
+}
+
+#[cfg(feature = "http-body-util")]
+impl<E: 'static> RejectionBody for http_body_util::combinators::UnsyncBoxBody<Bytes, E> {
+    fn from_bytes(bytes: Bytes) -> Self {
+        use http_body_util::BodyExt;
+
+        http_body_util::Full::new(bytes)
+            .map_err(|never| match never {})
+            .boxed_unsync()
+    }
+}
+
+#[cfg(feature = "tonic")]
+impl RejectionBody for tonic::body::Body {
+    fn from_bytes(bytes: Bytes) -> Self {
+        tonic::body::Body::new(http_body_util::Full::new(bytes))
+    }
+}
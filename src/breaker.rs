@@ -0,0 +1,126 @@
+use crate::governor::ResponseHook;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of responses a window needs before its failure ratio can engage the breaker.
+const MIN_REQUESTS: u64 = 20;
+
+/// Ratios are configured in parts per million, so that the builder stays `Eq`.
+pub(crate) const PARTS: u64 = 1_000_000;
+
+// The responses observed since the window started.
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    requests: u64,
+    failures: u64,
+}
+
+impl Window {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            requests: 0,
+            failures: 0,
+        }
+    }
+
+    // Whether failures make up at least `ratio` parts per million of the requests.
+    fn reaches(&self, ratio: u64) -> bool {
+        self.requests > 0 && self.failures * PARTS >= ratio * self.requests
+    }
+}
+
+// Engages the limiter while the inner service fails, see
+// `GovernorConfigBuilder::circuit_breaker`.
+pub(crate) struct Breaker {
+    engage: u64,
+    disengage: u64,
+    window: Duration,
+    engaged: AtomicBool,
+    current: Mutex<Window>,
+}
+
+impl fmt::Debug for Breaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Breaker")
+            .field("engage", &self.engage)
+            .field("disengage", &self.disengage)
+            .field("window", &self.window)
+            .field("engaged", &self.engaged.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Breaker {
+    pub(crate) fn new(engage: u32, disengage: u32, window: Duration) -> Self {
+        let engage = u64::from(engage).min(PARTS);
+        Self {
+            engage,
+            disengage: u64::from(disengage).min(engage),
+            window,
+            engaged: AtomicBool::new(false),
+            current: Mutex::new(Window::new(Instant::now())),
+        }
+    }
+
+    /// Whether the limiter is engaged.
+    pub(crate) fn engaged(&self) -> bool {
+        if !self.engaged.load(Ordering::Relaxed) {
+            return false;
+        }
+        // the limiter may have rejected every request since the window ended
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        self.roll(&mut current, Instant::now());
+        self.engaged.load(Ordering::Relaxed)
+    }
+
+    /// Record a response of the inner service.
+    pub(crate) fn record(&self, failure: bool) {
+        let now = Instant::now();
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        self.roll(&mut current, now);
+        current.requests += 1;
+        current.failures += u64::from(failure);
+        if !self.engaged.load(Ordering::Relaxed)
+            && current.requests >= MIN_REQUESTS
+            && current.reaches(self.engage)
+        {
+            self.engaged.store(true, Ordering::Relaxed);
+            *current = Window::new(now);
+            #[cfg(feature = "tracing")]
+            tracing::warn!("Circuit breaker engaged, the limiter now applies");
+        }
+    }
+
+    // Start a new window once the current one ended, disengaging if it stayed under the
+    // lower threshold.
+    fn roll(&self, current: &mut Window, now: Instant) {
+        if now.duration_since(current.start) < self.window {
+            return;
+        }
+        if self.engaged.load(Ordering::Relaxed) && !current.reaches(self.disengage.max(1)) {
+            self.engaged.store(false, Ordering::Relaxed);
+            #[cfg(feature = "tracing")]
+            tracing::info!("Circuit breaker disengaged");
+        }
+        *current = Window::new(now);
+    }
+
+    /// A hook recording the response of a forwarded request, then calling `then`.
+    pub(crate) fn watch(self: &Arc<Self>, then: Option<ResponseHook>) -> ResponseHook {
+        let breaker = self.clone();
+        ResponseHook(Box::new(move |response| {
+            breaker.record(response.is_none_or(|response| response.status().is_server_error()));
+            if let Some(hook) = then {
+                hook.call(response);
+            }
+        }))
+    }
+}
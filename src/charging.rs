@@ -0,0 +1,94 @@
+use crate::expiring::ExpiringMap;
+use axum::body::Body;
+use http::Response;
+use std::{
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type ResponsePredicate = dyn Fn(&Response<Body>) -> bool + Send + Sync;
+
+// Predicate deciding whether a response is charged against the quota.
+#[derive(Clone)]
+pub(crate) struct ResponseFilter(pub(crate) Arc<ResponsePredicate>);
+
+impl fmt::Debug for ResponseFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFilter").finish()
+    }
+}
+
+impl PartialEq for ResponseFilter {
+    fn eq(&self, _: &Self) -> bool {
+        // there is no easy way to tell two object equals.
+        true
+    }
+}
+
+impl Eq for ResponseFilter {}
+
+// State of the failure-only counting mode.
+//
+// Requests are only charged once their response is classified as a failure, so the limiter
+// can't tell whether a key is over quota before the request runs. Keys whose failure could not
+// be charged are remembered here until the limiter would accept them again.
+pub(crate) struct FailureCharging<Key> {
+    filter: ResponseFilter,
+    blocked: ExpiringMap<Key, Instant>,
+}
+
+impl<Key> fmt::Debug for FailureCharging<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailureCharging").finish()
+    }
+}
+
+impl<Key: Hash + Eq> FailureCharging<Key> {
+    pub(crate) fn new(filter: ResponseFilter) -> Self {
+        Self {
+            filter,
+            blocked: ExpiringMap::new(),
+        }
+    }
+
+    /// Whether the response, or an error of the inner service when `None`, is charged.
+    pub(crate) fn is_failure(&self, response: Option<&Response<Body>>) -> bool {
+        match response {
+            Some(response) => (self.filter.0)(response),
+            None => true,
+        }
+    }
+}
+
+impl<Key: Hash + Eq> FailureCharging<Key> {
+    /// Time left until the key may make requests again, if it is blocked.
+    pub(crate) fn blocked_for(&self, key: &Key) -> Option<Duration> {
+        let blocked = self.blocked.lock(key);
+        blocked
+            .get(key)
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|wait| !wait.is_zero())
+    }
+
+    /// The blocked keys, with the time left until they may make requests again.
+    pub(crate) fn blocked(&self) -> Vec<(Key, Duration)>
+    where
+        Key: Clone,
+    {
+        let now = Instant::now();
+        self.blocked.filter_map(|key, until| {
+            let left = until.checked_duration_since(now)?;
+            (!left.is_zero()).then(|| (key.clone(), left))
+        })
+    }
+
+    /// Block the key for `wait`.
+    pub(crate) fn block(&self, key: Key, wait: Duration) {
+        let now = Instant::now();
+        let mut blocked = self.blocked.lock(&key);
+        blocked.purge(|until| *until <= now);
+        blocked.insert(key, now + wait);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::expiring::ExpiringMap;
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::Hash,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type ChurnCallback = dyn Fn(IpAddr) + Send + Sync;
+
+// Observer of the peers exceeding their distinct key budget.
+#[derive(Clone)]
+pub(crate) struct ChurnObserver(pub(crate) Arc<ChurnCallback>);
+
+impl fmt::Debug for ChurnObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChurnObserver").finish()
+    }
+}
+
+impl PartialEq for ChurnObserver {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ChurnObserver {}
+
+// Distinct keys introduced by a peer within the current window.
+struct Introduced<Key> {
+    since: Instant,
+    keys: HashSet<Key>,
+}
+
+// Guard against a single peer IP cycling through many keys, such as API keys being enumerated.
+pub(crate) struct KeyChurn<Key> {
+    max_keys: usize,
+    window: Duration,
+    observer: Option<ChurnObserver>,
+    peers: ExpiringMap<IpAddr, Introduced<Key>>,
+}
+
+impl<Key> fmt::Debug for KeyChurn<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyChurn")
+            .field("max_keys", &self.max_keys)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> KeyChurn<Key> {
+    pub(crate) fn new(max_keys: u32, window: Duration, observer: Option<ChurnObserver>) -> Self {
+        Self {
+            max_keys: max_keys as usize,
+            window,
+            observer,
+            peers: ExpiringMap::new(),
+        }
+    }
+
+    /// Record `key` as used by `peer`, returning how long to reject the peer for if it
+    /// introduced too many distinct keys.
+    pub(crate) fn check(&self, peer: IpAddr, key: &Key) -> Option<Duration> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        peers.purge(|introduced| now.duration_since(introduced.since) >= self.window);
+        let introduced = peers.entry(peer).or_insert_with(|| Introduced {
+            since: now,
+            keys: HashSet::new(),
+        });
+        if now.duration_since(introduced.since) >= self.window {
+            introduced.since = now;
+            introduced.keys.clear();
+        }
+        if introduced.keys.contains(key) {
+            return None;
+        }
+        if introduced.keys.len() < self.max_keys {
+            introduced.keys.insert(key.clone());
+            return None;
+        }
+
+        let wait_time = self
+            .window
+            .saturating_sub(now.duration_since(introduced.since));
+        drop(peers);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            "{} introduced more than {} distinct keys within {:?}",
+            peer,
+            self.max_keys,
+            self.window
+        );
+        if let Some(observer) = &self.observer {
+            (observer.0)(peer);
+        }
+        Some(wait_time)
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{expiring::ExpiringMap, governor::SharedRateLimiter};
+use governor::{clock::Clock, middleware::RateLimitingMiddleware, Quota};
+use http::{HeaderMap, HeaderValue, Method, Request, Uri};
+use std::{
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type ClassifyFn = dyn Fn(&Method, &Uri, &HeaderMap) -> Option<&'static str> + Send + Sync;
+
+// Closure naming the class of a request, see `GovernorConfigBuilder::classify`.
+#[derive(Clone)]
+pub(crate) struct Classifier(pub(crate) Arc<ClassifyFn>);
+
+impl fmt::Debug for Classifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Classifier").finish()
+    }
+}
+
+impl PartialEq for Classifier {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Classifier {}
+
+// Quota of a class of requests, with the limiter keeping the buckets of its keys.
+pub(crate) struct RequestClass<Key, M, C>
+where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    pub(crate) name: &'static str,
+    pub(crate) quota: Quota,
+    pub(crate) limiter: SharedRateLimiter<Key, M, C>,
+    pub(crate) scope: Option<HeaderValue>,
+}
+
+// The classes recently named for each key, see `GovernorConfigBuilder::cache_classes`.
+struct ClassCache<Key> {
+    ttl: Duration,
+    keys: ExpiringMap<Key, (Option<&'static str>, Instant)>,
+}
+
+impl<Key: Hash + Eq + Clone> ClassCache<Key> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            keys: ExpiringMap::new(),
+        }
+    }
+
+    /// The class cached for `key` within the time to live, or the one named by `classify`,
+    /// which is called without holding the lock.
+    fn get_or_classify(
+        &self,
+        key: &Key,
+        classify: impl FnOnce() -> Option<&'static str>,
+    ) -> Option<&'static str> {
+        let now = Instant::now();
+        {
+            let keys = self.keys.lock(key);
+            if let Some((class, _)) = keys
+                .get(key)
+                .filter(|(_, since)| now.duration_since(*since) < self.ttl)
+            {
+                return *class;
+            }
+        }
+        let class = classify();
+        let mut keys = self.keys.lock(key);
+        keys.purge(|(_, since)| now.duration_since(*since) >= self.ttl);
+        keys.insert(key.clone(), (class, now));
+        class
+    }
+}
+
+// The classes of a configuration, each key having a separate bucket per class.
+pub(crate) struct Classes<Key, M, C>
+where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    classifier: Classifier,
+    classes: Vec<RequestClass<Key, M, C>>,
+    cache: Option<ClassCache<Key>>,
+}
+
+impl<Key, M, C> fmt::Debug for Classes<Key, M, C>
+where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.classes.iter().map(|class| class.name).collect();
+        f.debug_struct("Classes").field("classes", &names).finish()
+    }
+}
+
+impl<Key, M, C> Classes<Key, M, C>
+where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// The classes named by `classifier`, cached per key for `cache_ttl` if any.
+    pub(crate) fn new(
+        classifier: Classifier,
+        classes: Vec<RequestClass<Key, M, C>>,
+        cache_ttl: Option<Duration>,
+    ) -> Self {
+        Self {
+            classifier,
+            classes,
+            cache: cache_ttl.map(ClassCache::new),
+        }
+    }
+
+    /// The name of the class of the request of `key`, whether it has a quota of its own or
+    /// not. `method` stands for the method of the request.
+    pub(crate) fn name<B>(
+        &self,
+        method: &Method,
+        req: &Request<B>,
+        key: &Key,
+    ) -> Option<&'static str> {
+        let classify = || (self.classifier.0)(method, req.uri(), req.headers());
+        match &self.cache {
+            Some(cache) => cache.get_or_classify(key, classify),
+            None => classify(),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&RequestClass<Key, M, C>> {
+        self.classes.iter().find(|class| class.name == name)
+    }
+
+    /// The number of keys in the stores of the classes.
+    pub(crate) fn len(&self) -> usize {
+        self.classes.iter().map(|class| class.limiter.len()).sum()
+    }
+
+    /// Clean up the stores of the classes, see [`RateLimiter::retain_recent`].
+    ///
+    /// [`RateLimiter::retain_recent`]: governor::RateLimiter::retain_recent
+    pub(crate) fn retain_recent(&self) {
+        for class in &self.classes {
+            class.limiter.retain_recent();
+            class.limiter.shrink_to_fit();
+        }
+    }
+
+    /// The same classes, with the stores made by `limiter` for their quotas.
+    pub(crate) fn detached(&self, limiter: impl Fn(Quota) -> SharedRateLimiter<Key, M, C>) -> Self {
+        let classes = self
+            .classes
+            .iter()
+            .map(|class| RequestClass {
+                name: class.name,
+                quota: class.quota,
+                limiter: limiter(class.quota),
+                scope: class.scope.clone(),
+            })
+            .collect();
+        Self::new(
+            self.classifier.clone(),
+            classes,
+            self.cache.as_ref().map(|cache| cache.ttl),
+        )
+    }
+}
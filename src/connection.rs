@@ -0,0 +1,77 @@
+//! Reuse of rate limiting keys across the requests of a connection.
+//!
+//! See [`GovernorConfigBuilder::cache_connection_keys`].
+//!
+//! [`GovernorConfigBuilder::cache_connection_keys`]: crate::governor::GovernorConfigBuilder::cache_connection_keys
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+/// Source of the slots identifying each configuration within the caches.
+static NEXT_SLOT: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn next_slot() -> u64 {
+    NEXT_SLOT.fetch_add(1, Ordering::Relaxed)
+}
+
+type Slots = HashMap<u64, Box<dyn Any + Send + Sync>>;
+
+/// Keys extracted for the requests of a single connection.
+///
+/// Insert a clone of the same cache into the extensions of every request of a connection,
+/// for instance from the service handed to hyper for that connection. Configurations
+/// [caching connection keys] then only extract the key of the first request.
+///
+/// **Only use this for connections made by clients directly**, a reverse proxy may send
+/// requests of several clients over the same connection.
+///
+/// [caching connection keys]: crate::governor::GovernorConfigBuilder::cache_connection_keys
+#[derive(Clone, Default)]
+pub struct ConnectionKeyCache(Arc<Mutex<Slots>>);
+
+impl ConnectionKeyCache {
+    /// Create an empty cache, to be used for a single connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The key cached in `slot`, extracting and caching it with `extract` if there is none.
+    pub(crate) fn get_or_try_insert<Key, E>(
+        &self,
+        slot: u64,
+        extract: impl FnOnce() -> Result<Key, E>,
+    ) -> Result<Key, E>
+    where
+        Key: Clone + Send + Sync + 'static,
+    {
+        let cached = self
+            .lock()
+            .get(&slot)
+            .and_then(|key| key.downcast_ref())
+            .cloned();
+        if let Some(key) = cached {
+            return Ok(key);
+        }
+
+        let key = extract()?;
+        self.lock().insert(slot, Box::new(key.clone()));
+        Ok(key)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for ConnectionKeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionKeyCache").finish()
+    }
+}
@@ -0,0 +1,80 @@
+//! Quotas counted in credits rather than requests, as metered by LLM-style APIs.
+//!
+//! [`GovernorConfigBuilder::credits`] gives every key a balance of credits replenished at a
+//! steady rate, and [`GovernorConfigBuilder::request_cost`] tells how many credits a request
+//! consumes. The responses carry the [`CREDITS_LIMIT_HEADER`] and [`CREDITS_REMAINING_HEADER`]
+//! instead of the `x-ratelimit-limit` and `x-ratelimit-remaining` headers.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//! use tower_governor::governor::GovernorConfigBuilder;
+//!
+//! // up to 10000 credits, one credit replenished every millisecond
+//! let config = GovernorConfigBuilder::default()
+//!     .credits(10_000, Duration::from_millis(1))
+//!     .request_cost(|request| {
+//!         request
+//!             .headers
+//!             .get("x-max-tokens")
+//!             .and_then(|value| value.to_str().ok()?.parse().ok())
+//!             .unwrap_or(1)
+//!     })
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! [`GovernorConfigBuilder::credits`]: crate::governor::GovernorConfigBuilder::credits
+//! [`GovernorConfigBuilder::request_cost`]: crate::governor::GovernorConfigBuilder::request_cost
+//! [`CREDITS_LIMIT_HEADER`]: crate::headers::CREDITS_LIMIT_HEADER
+//! [`CREDITS_REMAINING_HEADER`]: crate::headers::CREDITS_REMAINING_HEADER
+
+use http::{HeaderMap, Method, Request, Uri};
+use std::{fmt, num::NonZeroU32, sync::Arc};
+
+/// The parts of a request its cost is derived from, see
+/// [`GovernorConfigBuilder::request_cost`].
+///
+/// [`GovernorConfigBuilder::request_cost`]: crate::governor::GovernorConfigBuilder::request_cost
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct CostContext<'a> {
+    /// The method of the request.
+    pub method: &'a Method,
+    /// The URI of the request.
+    pub uri: &'a Uri,
+    /// The headers of the request.
+    pub headers: &'a HeaderMap,
+}
+
+type CostCallback = dyn Fn(&CostContext<'_>) -> u32 + Send + Sync;
+
+/// Callback telling how many elements of the quota a request consumes.
+#[derive(Clone)]
+pub(crate) struct RequestCost(pub(crate) Arc<CostCallback>);
+
+impl RequestCost {
+    /// The cost of `req`, at least one.
+    pub(crate) fn of<B>(&self, req: &Request<B>) -> NonZeroU32 {
+        let cost = (self.0)(&CostContext {
+            method: req.method(),
+            uri: req.uri(),
+            headers: req.headers(),
+        });
+        NonZeroU32::new(cost).unwrap_or(NonZeroU32::MIN)
+    }
+}
+
+impl fmt::Debug for RequestCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestCost").finish()
+    }
+}
+
+impl PartialEq for RequestCost {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RequestCost {}
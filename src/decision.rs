@@ -0,0 +1,178 @@
+use crate::errors::GovernorError;
+use http::{header::HeaderName, HeaderMap, HeaderValue, Request, Response};
+use std::time::Duration;
+
+/// Header added to the request in [decide-only] mode, set to `allow` or `deny`.
+///
+/// [decide-only]: crate::governor::GovernorConfigBuilder::decide_only
+pub const DECISION_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-decision");
+
+/// Outcome of consulting the rate limiter for a request.
+///
+/// In [decide-only] mode this is inserted into the request extensions before the request is
+/// handed to the inner service.
+///
+/// [decide-only]: crate::governor::GovernorConfigBuilder::decide_only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The request conforms to the quota.
+    Allowed,
+    /// The request exceeds the quota and would be allowed after `wait_time`.
+    Rejected { wait_time: Duration },
+}
+
+impl Decision {
+    /// Returns `true` if the request conforms to the quota.
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allowed)
+    }
+
+    /// Record this decision into the request extensions and the [`DECISION_HEADER`].
+    pub(crate) fn annotate<B>(self, req: &mut Request<B>) {
+        let value = if self.is_allowed() { "allow" } else { "deny" };
+        req.headers_mut()
+            .insert(DECISION_HEADER, HeaderValue::from_static(value));
+        req.extensions_mut().insert(self);
+    }
+}
+
+/// Outcome of checking a request outside of the HTTP middleware, see
+/// [`GovernorConfig::check_request`].
+///
+/// [`GovernorConfig::check_request`]: crate::governor::GovernorConfig::check_request
+#[derive(Debug, Clone)]
+pub enum RequestDecision {
+    /// The request conforms to the quota and was charged to it.
+    Allowed { snapshot: RateLimitSnapshot },
+    /// The request exceeds the quota and would be allowed after `wait_time`.
+    Rejected { wait_time: Duration },
+    /// The key of the request is exempt from the quota.
+    Exempt,
+    /// The key of the request couldn't be extracted.
+    Error(GovernorError),
+}
+
+/// Header added to the requests let through over the quota by
+/// [`sample_over_quota`](crate::governor::GovernorConfigBuilder::sample_over_quota), set to
+/// `true`.
+pub const SAMPLED_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-sampled");
+
+/// Marks a request let through although its key is over quota, see
+/// [`sample_over_quota`](crate::governor::GovernorConfigBuilder::sample_over_quota).
+///
+/// Inserted into the request extensions, along with the [`SAMPLED_HEADER`], before the request
+/// is handed to the inner service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sampled {
+    /// The time until the request would have been allowed.
+    pub wait_time: Duration,
+}
+
+impl Sampled {
+    /// Record this sample into the request extensions and the [`SAMPLED_HEADER`].
+    pub(crate) fn annotate<B>(self, req: &mut Request<B>) {
+        req.headers_mut()
+            .insert(SAMPLED_HEADER, HeaderValue::from_static("true"));
+        req.extensions_mut().insert(self);
+    }
+}
+
+/// Header added to the responses while [`enforce_ratio`] rolls a policy out, set to
+/// `enforce` or `observe` after the cohort of the key.
+///
+/// [`enforce_ratio`]: crate::governor::GovernorConfigBuilder::enforce_ratio
+pub const MODE_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-mode");
+
+/// Whether the quota is enforced for the key of a request while [`enforce_ratio`] rolls a
+/// policy out.
+///
+/// Inserted into the request extensions before the request is handed to the inner service,
+/// and reported to the client in the [`MODE_HEADER`] of the response.
+///
+/// [`enforce_ratio`]: crate::governor::GovernorConfigBuilder::enforce_ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnforcementMode {
+    /// The requests exceeding the quota are rejected.
+    Enforce,
+    /// The requests exceeding the quota are only marked with a [`Decision`].
+    Observe,
+}
+
+impl EnforcementMode {
+    /// The value of the [`MODE_HEADER`] for this mode.
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Self::Enforce => "enforce",
+            Self::Observe => "observe",
+        })
+    }
+
+    /// Record this mode into the request extensions.
+    pub(crate) fn annotate<B>(self, req: &mut Request<B>) {
+        req.extensions_mut().insert(self);
+    }
+
+    /// The mode recorded for `req`, if any.
+    pub(crate) fn of<B>(req: &Request<B>) -> Option<Self> {
+        req.extensions().get::<Self>().copied()
+    }
+
+    /// Report this mode in the [`MODE_HEADER`] of `response`.
+    pub(crate) fn report<B>(self, response: &mut Response<B>) {
+        response
+            .headers_mut()
+            .insert(MODE_HEADER, self.header_value());
+    }
+}
+
+/// State of the quota of a key once its request was checked.
+///
+/// Inserted into the request extensions of allowed requests and into the response extensions
+/// of both allowed and rejected requests, so layers wrapping the governor can record it
+/// without parsing the rate limiting headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitSnapshot {
+    /// The burst size of the quota.
+    pub limit: u32,
+    /// The number of requests the key can still make right away. Only known for allowed
+    /// requests when the [x-ratelimit headers] are enabled.
+    ///
+    /// [x-ratelimit headers]: crate::governor::GovernorConfigBuilder::use_headers
+    pub remaining: Option<u32>,
+    /// The decision taken for the request.
+    pub decision: Decision,
+}
+
+/// Context of a rejected request, handed to the [rejection hook] along with its response.
+///
+/// [rejection hook]: crate::governor::GovernorConfigBuilder::on_rejection
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct RejectionContext<'a> {
+    /// The name of the rate limiting key, redacted to its first characters so it can be
+    /// shown to the client, see [`KeyExtractor::key_name`].
+    ///
+    /// [`KeyExtractor::key_name`]: crate::key_extractor::KeyExtractor::key_name
+    pub key: Option<&'a str>,
+    /// The time until the request would be allowed.
+    pub wait_time: Duration,
+    /// The name of the policy, see [`policy_name`].
+    ///
+    /// [`policy_name`]: crate::governor::GovernorConfigBuilder::policy_name
+    pub policy: Option<&'a str>,
+    /// The path of the request.
+    pub route: &'a str,
+    /// The headers of the request, e.g. to copy its correlation ID into the response.
+    pub headers: &'a HeaderMap,
+}
+
+/// Number of characters of the key name kept by [`redact`].
+const REDACTED_PREFIX: usize = 4;
+
+/// Keep the first characters of a key name, masking the rest.
+pub(crate) fn redact(name: &str) -> String {
+    match name.char_indices().nth(REDACTED_PREFIX) {
+        Some((end, _)) => format!("{}***", &name[..end]),
+        None => "***".to_owned(),
+    }
+}
@@ -1,5 +1,6 @@
+use crate::{body::RejectionBody, headers::RejectionReason};
 use http::{HeaderMap, Response, StatusCode};
-use std::mem;
+use std::{mem, time::Duration};
 use thiserror::Error;
 
 /// The error type returned by tower-governor.
@@ -7,7 +8,10 @@ use thiserror::Error;
 pub enum GovernorError {
     #[error("Too Many Requests! Wait for {wait_time}s")]
     TooManyRequests {
+        /// The advertised wait time in whole seconds, as sent in `retry-after`.
         wait_time: u64,
+        /// The advertised wait time, without rounding.
+        wait_duration: Duration,
         headers: Option<HeaderMap>,
     },
     #[error("Unable to extract key!")]
@@ -24,26 +28,31 @@ pub enum GovernorError {
 impl GovernorError {
     /// Convert self into a "default response", as if no error handler was set using
     /// [`GovernorConfigBuilder::error_handler`].
+    ///
+    /// The body can be of any [`RejectionBody`] type, such as axum's `Body` or `String`.
     pub fn as_response<ResB>(&mut self) -> Response<ResB>
     where
-        ResB: From<String>,
+        ResB: RejectionBody,
     {
         match mem::replace(self, Self::UnableToExtractKey) {
-            GovernorError::TooManyRequests { wait_time, headers } => {
+            GovernorError::TooManyRequests {
+                wait_time, headers, ..
+            } => {
                 let response = Response::new(format!("Too Many Requests! Wait for {}s", wait_time));
                 let (mut parts, body) = response.into_parts();
                 parts.status = StatusCode::TOO_MANY_REQUESTS;
                 if let Some(headers) = headers {
                     parts.headers = headers;
                 }
-                Response::from_parts(parts, ResB::from(body))
+                Response::from_parts(parts, ResB::from_bytes(body.into()))
             }
             GovernorError::UnableToExtractKey => {
                 let response = Response::new("Unable To Extract Key!".to_string());
                 let (mut parts, body) = response.into_parts();
                 parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+                RejectionReason::ExtractionFailed.insert(&mut parts.headers);
 
-                Response::from_parts(parts, ResB::from(body))
+                Response::from_parts(parts, ResB::from_bytes(body.into()))
             }
             GovernorError::Other { msg, code, headers } => {
                 let response = Response::new("Other Error!".to_string());
@@ -56,8 +65,93 @@ impl GovernorError {
                     body = msg;
                 }
 
-                Response::from_parts(parts, ResB::from(body))
+                Response::from_parts(parts, ResB::from_bytes(body.into()))
             }
         }
     }
+
+    /// Why the request was rejected, as sent in the
+    /// [`REASON_HEADER`](crate::headers::REASON_HEADER) of the response.
+    pub fn reason(&self) -> Option<RejectionReason> {
+        match self {
+            Self::UnableToExtractKey => Some(RejectionReason::ExtractionFailed),
+            Self::TooManyRequests { headers, .. } | Self::Other { headers, .. } => {
+                headers.as_ref().and_then(RejectionReason::of)
+            }
+        }
+    }
+
+    /// Add `reason` to the headers of the response, unless it already has one.
+    pub(crate) fn with_reason(mut self, reason: RejectionReason) -> Self {
+        if let Self::TooManyRequests { headers, .. } | Self::Other { headers, .. } = &mut self {
+            let headers = headers.get_or_insert_with(HeaderMap::new);
+            if RejectionReason::of(headers).is_none() {
+                reason.insert(headers);
+            }
+        }
+        self
+    }
+}
+
+/// The error returned when a [`GovernorConfigBuilder`] can't be turned into a configuration.
+///
+/// [`GovernorConfigBuilder`]: crate::governor::GovernorConfigBuilder
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("burst size must not be zero")]
+    ZeroBurstSize,
+    #[error("replenishment period must not be zero")]
+    ZeroPeriod,
+    #[error("prefetch batch must not exceed the burst size")]
+    PrefetchExceedsBurst,
+    #[error(
+        "replenishing the whole burst must take less than u64::MAX nanoseconds (about 584 years)"
+    )]
+    QuotaOverflow,
+    #[error("a rate of {0} requests per second exceeds one request per nanosecond")]
+    UnrepresentableRate(u64),
+    #[error("a key extractor must be set explicitly, the default one uses the peer IP address")]
+    ImplicitKeyExtractor,
+    #[error("invalid key extractor: {0}")]
+    InvalidKeyExtractor(String),
+    #[error("failed to map the shared memory file {0}")]
+    SharedMemory(String),
+    #[error("invalid rejection template: {0}")]
+    InvalidTemplate(String),
+    #[error("the keys of the key extractor may change within a connection, they can't be cached")]
+    UnstableConnectionKeys,
+}
+
+/// The error returned when a state snapshot or a ban list can't be restored, see
+/// [`GovernorConfig::restore_from`] and [`GovernorConfig::restore_bans`].
+///
+/// [`GovernorConfig::restore_from`]: crate::governor::GovernorConfig::restore_from
+/// [`GovernorConfig::restore_bans`]: crate::governor::GovernorConfig::restore_bans
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("failed to read the snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(String),
+    #[error("malformed snapshot line {0}")]
+    Malformed(usize),
+    #[error("the snapshot was taken with another quota")]
+    QuotaMismatch,
+    #[error("the snapshot was taken in the future, the clocks of the hosts disagree")]
+    ClockSkew,
+    #[error("state tracking is disabled, see `GovernorConfigBuilder::track_state`")]
+    NotTracked,
+    #[error("bans are disabled, see `GovernorConfigBuilder::ban_list`")]
+    NoBans,
+}
+
+/// Error reserving quota with [`GovernorConfig::reserve`].
+///
+/// [`GovernorConfig::reserve`]: crate::governor::GovernorConfig::reserve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum LeaseError {
+    #[error("leases are disabled, see `GovernorConfigBuilder::leases`")]
+    Disabled,
+    #[error("the quota of the key is exhausted, retry in {}ms", wait_time.as_millis())]
+    TooManyRequests { wait_time: Duration },
 }
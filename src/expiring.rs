@@ -0,0 +1,98 @@
+use crate::sync::{Mutex, MutexGuard};
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+};
+
+/// Number of shards of an [`ExpiringMap`].
+const SHARDS: usize = 16;
+
+/// Number of entries of a shard after which its expired entries are first purged.
+const PURGE_THRESHOLD: usize = 256;
+
+// Per key state of the policies of a configuration, whose entries expire.
+//
+// The entries are spread over shards locked separately, so that requests of different keys
+// rarely contend. The expired entries of a shard are purged before an insertion once it grew
+// past a mark, which doubles the number of entries left after the purge so that the cost of
+// purging stays amortized over the insertions, even while most entries are still alive.
+pub(crate) struct ExpiringMap<K, V> {
+    hasher: RandomState,
+    shards: Box<[Mutex<Shard<K, V>>]>,
+}
+
+// The entries of a shard, along with the number of entries after which they are purged next.
+pub(crate) struct Shard<K, V> {
+    entries: HashMap<K, V>,
+    purge_at: usize,
+}
+
+impl<K: Hash + Eq, V> ExpiringMap<K, V> {
+    pub(crate) fn new() -> Self {
+        let shards = (0..SHARDS)
+            .map(|_| {
+                Mutex::new(Shard {
+                    entries: HashMap::new(),
+                    purge_at: PURGE_THRESHOLD,
+                })
+            })
+            .collect();
+        Self {
+            hasher: RandomState::new(),
+            shards,
+        }
+    }
+
+    /// Lock the shard holding `key`.
+    pub(crate) fn lock(&self, key: &K) -> MutexGuard<'_, Shard<K, V>> {
+        let shard = self.hasher.hash_one(key) as usize % SHARDS;
+        self.shards[shard].lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The results of `f` over the entries, locking one shard at a time.
+    pub(crate) fn filter_map<T>(&self, mut f: impl FnMut(&K, &V) -> Option<T>) -> Vec<T> {
+        let mut results = Vec::new();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            results.extend(
+                shard
+                    .entries
+                    .iter()
+                    .filter_map(|(key, value)| f(key, value)),
+            );
+        }
+        results
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ExpiringMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Shard<K, V> {
+    /// Remove the entries `expired` returns `true` for, if the shard grew past its mark.
+    pub(crate) fn purge(&mut self, mut expired: impl FnMut(&V) -> bool) {
+        if self.entries.len() < self.purge_at {
+            return;
+        }
+        self.entries.retain(|_, value| !expired(value));
+        self.purge_at = (self.entries.len() * 2).max(PURGE_THRESHOLD);
+    }
+}
+
+impl<K, V> Deref for Shard<K, V> {
+    type Target = HashMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl<K, V> DerefMut for Shard<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    errors::GovernorError,
+    expiring::ExpiringMap,
+    headers::{self, WaitTimeUnit, AFTER_HEADER, AFTER_MS_HEADER},
+};
+use http::HeaderMap;
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+// Last extraction failure of a peer, with the number of requests it answered.
+#[derive(Debug)]
+struct Failure {
+    error: GovernorError,
+    since: Instant,
+    hits: u32,
+}
+
+// Extraction failures cached per peer IP, see `GovernorConfigBuilder::cache_extraction_failures`.
+pub(crate) struct FailureCache {
+    ttl: Duration,
+    max_hits: u32,
+    retry_after: bool,
+    after_unit: WaitTimeUnit,
+    peers: ExpiringMap<IpAddr, Failure>,
+}
+
+impl FailureCache {
+    pub(crate) fn new(
+        ttl: Duration,
+        max_hits: u32,
+        retry_after: bool,
+        after_unit: WaitTimeUnit,
+    ) -> Self {
+        Self {
+            ttl,
+            max_hits,
+            retry_after,
+            after_unit,
+            peers: ExpiringMap::new(),
+        }
+    }
+
+    /// The error to answer a request of `peer` with, if its last extraction failed within
+    /// the time to live. Once the failure answered `max_hits` requests, the peer is told to
+    /// wait until it expires.
+    pub(crate) fn get(&self, peer: IpAddr) -> Option<GovernorError> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        let failure = peers.get_mut(&peer)?;
+        let age = now.duration_since(failure.since);
+        if age >= self.ttl {
+            peers.remove(&peer);
+            return None;
+        }
+        failure.hits = failure.hits.saturating_add(1);
+        if failure.hits <= self.max_hits {
+            return Some(failure.error.clone());
+        }
+        // round up so that clients don't retry before the failure expired
+        let wait_duration = self.ttl - age;
+        let wait_time = wait_duration.as_secs_f64().ceil() as u64;
+        let mut headers = HeaderMap::new();
+        match self.after_unit {
+            WaitTimeUnit::Seconds => headers.insert(AFTER_HEADER, wait_time.into()),
+            WaitTimeUnit::Milliseconds => {
+                headers.insert(AFTER_MS_HEADER, headers::millis(wait_duration).into())
+            }
+        };
+        if self.retry_after {
+            headers.insert("retry-after", wait_time.into());
+        }
+        Some(GovernorError::TooManyRequests {
+            wait_time,
+            wait_duration,
+            headers: Some(headers),
+        })
+    }
+
+    pub(crate) fn insert(&self, peer: IpAddr, error: &GovernorError) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        peers.purge(|failure| now.duration_since(failure.since) >= self.ttl);
+        peers.insert(
+            peer,
+            Failure {
+                error: error.clone(),
+                since: now,
+                hits: 1,
+            },
+        );
+    }
+}
+
+impl fmt::Debug for FailureCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailureCache")
+            .field("ttl", &self.ttl)
+            .field("max_hits", &self.max_hits)
+            .field("retry_after", &self.retry_after)
+            .field("after_unit", &self.after_unit)
+            .finish_non_exhaustive()
+    }
+}
+
+// Peer IPs recently found exempt, see `GovernorConfigBuilder::cache_exemptions`.
+pub(crate) struct ExemptCache {
+    ttl: Duration,
+    peers: ExpiringMap<IpAddr, Instant>,
+}
+
+impl fmt::Debug for ExemptCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExemptCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExemptCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            peers: ExpiringMap::new(),
+        }
+    }
+
+    /// Whether `peer` was found exempt within the time to live.
+    pub(crate) fn contains(&self, peer: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        match peers.get(&peer) {
+            Some(since) if now.duration_since(*since) < self.ttl => true,
+            Some(_) => {
+                peers.remove(&peer);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn insert(&self, peer: IpAddr) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        peers.purge(|since| now.duration_since(*since) >= self.ttl);
+        peers.insert(peer, now);
+    }
+}
@@ -0,0 +1,619 @@
+//! Parsing and trusting the headers reverse proxies use to forward the client IP address.
+//!
+//! The IP based key extractors look the client IP address up with a [`ClientIpResolver`].
+//! Custom key extractors can use one too, or the functions reading a single source, instead
+//! of parsing the headers themselves, and policy hooks can read the other parameters of the
+//! `forwarded` headers with [`forwarded_elements`].
+//!
+//! Parsing is bounded by [`HeaderLimits`], so that oversized headers are skipped rather than
+//! costing CPU time on every request.
+//!
+//! # Example
+//! ```rust
+//! use std::net::{IpAddr, SocketAddr};
+//! use tower_governor::forwarding::{ClientIpResolver, Source, Trust};
+//!
+//! // our proxies run on the private network and append to `x-forwarded-for`
+//! let resolver = ClientIpResolver::new([Source::XForwardedFor, Source::Peer])
+//!     .rightmost()
+//!     .trust(Trust::Private);
+//!
+//! let mut req = http::Request::get("/")
+//!     .header("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")
+//!     .body(())
+//!     .unwrap();
+//! # #[cfg(feature = "axum")]
+//! req.extensions_mut()
+//!     .insert(axum::extract::ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+//! # #[cfg(not(feature = "axum"))]
+//! # req.extensions_mut().insert(SocketAddr::from(([10, 0, 0, 1], 4000)));
+//!
+//! // the address spoofed by the client on the left is ignored
+//! assert_eq!(resolver.resolve(&req), Some(IpAddr::from([1, 2, 3, 4])));
+//! ```
+
+use forwarded_header_value::{ForwardedHeaderValue, Identifier, Protocol};
+use http::{header::FORWARDED, uri::Scheme, HeaderMap, Request};
+use std::net::{IpAddr, SocketAddr};
+
+pub(crate) const X_REAL_IP: &str = "x-real-ip";
+pub(crate) const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A place the client IP address can be found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// The `x-forwarded-for` header, by default its first valid address.
+    XForwardedFor,
+    /// The `x-real-ip` header.
+    XRealIp,
+    /// The `for` parameters of the `forwarded` header, by default the first one holding an
+    /// address.
+    Forwarded,
+    /// The peer address of the connection.
+    Peer,
+}
+
+impl Source {
+    /// Whether clients can set this source to anything, i.e. it is a header.
+    pub fn is_spoofable(&self) -> bool {
+        !matches!(self, Source::Peer)
+    }
+
+    /// A short name of this source, the name of the header or `peer`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Source::XForwardedFor => X_FORWARDED_FOR,
+            Source::XRealIp => X_REAL_IP,
+            Source::Forwarded => "forwarded",
+            Source::Peer => "peer",
+        }
+    }
+
+    /// Look up the client IP address in this source.
+    pub fn resolve<T>(&self, req: &Request<T>) -> Option<IpAddr> {
+        match self {
+            Source::XForwardedFor => x_forwarded_for(req.headers(), Position::Leftmost),
+            Source::XRealIp => x_real_ip(req.headers()),
+            Source::Forwarded => forwarded_for(req.headers(), Position::Leftmost),
+            Source::Peer => peer_ip(req),
+        }
+    }
+
+    // Every address of this source, from the client to the closest proxy.
+    fn addresses<T>(
+        &self,
+        req: &Request<T>,
+        limits: &HeaderLimits,
+        duplicates: DuplicateHeaders,
+    ) -> Result<Vec<IpAddr>, Unreadable> {
+        match self {
+            Source::XForwardedFor => x_forwarded_for_chain(req.headers(), limits, duplicates),
+            Source::XRealIp => Ok(real_ip(req.headers(), limits)?.into_iter().collect()),
+            Source::Forwarded => forwarded_for_chain(req.headers(), limits),
+            Source::Peer => Ok(self.resolve(req).into_iter().collect()),
+        }
+    }
+}
+
+/// Bounds on the work spent parsing the forwarding headers, so that clients can't burn CPU
+/// with huge headers.
+///
+/// The headers over the limits are skipped, as malformed ones are, unless
+/// [`reject`](Self::reject) is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// The longest header value parsed, in bytes.
+    pub max_value_len: usize,
+    /// The most entries examined across the `x-forwarded-for` headers, or elements across
+    /// the `forwarded` headers.
+    pub max_entries: usize,
+    /// Whether the client IP address of requests with headers over the limits is unknown,
+    /// failing their key extraction, rather than looked up in the next source.
+    pub reject: bool,
+}
+
+impl HeaderLimits {
+    /// 4 KiB values and 64 entries, skipping the headers over them.
+    pub const DEFAULT: Self = Self {
+        max_value_len: 4096,
+        max_entries: 64,
+        reject: false,
+    };
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// Why the client IP address can't be read from a header.
+#[derive(Debug)]
+enum Unreadable {
+    // The header is over the limits.
+    Exceeded,
+    // The header is repeated, under `DuplicateHeaders::Reject`.
+    Duplicated,
+}
+
+/// How to read several `x-forwarded-for` headers sent with a request.
+///
+/// Proxies appending to the header should extend its last line, so duplicates usually come
+/// from clients splitting the header to smuggle addresses past proxies only looking at the
+/// first or the last line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateHeaders {
+    /// Read the headers in order as a single list of addresses. This is the default.
+    #[default]
+    Merge,
+    /// Only read the last header, the one the closest proxy appended to.
+    Last,
+    /// Fail the key extraction of the requests with several headers.
+    Reject,
+}
+
+/// Which address of a header listing several of them is the client IP address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Position {
+    /// The first address, as set by the client or the first proxy. This is the default.
+    #[default]
+    Leftmost,
+    /// The last address, as set by the closest proxy, skipping the [trusted](Trust) proxies.
+    ///
+    /// Clients can't spoof it as long as the closest proxy appends to the header.
+    Rightmost,
+}
+
+/// Which peers the forwarding headers are accepted from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Trust {
+    /// Accept the headers from any peer, or when the peer address is unknown. This is the
+    /// default, only suitable when the service can't be reached without going through the
+    /// proxies.
+    #[default]
+    Any,
+    /// Accept the headers from loopback and private peers only, e.g. proxies of the same
+    /// private network.
+    Private,
+    /// Accept the headers from these peers only.
+    Peers(Vec<IpAddr>),
+}
+
+impl Trust {
+    /// Whether `ip` is the address of a trusted proxy, in either form for IPv4-mapped IPv6
+    /// addresses.
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match self {
+            Trust::Any => true,
+            Trust::Private => is_internal(ip),
+            Trust::Peers(peers) => peers.iter().any(|peer| peer.to_canonical() == ip),
+        }
+    }
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private(),
+        IpAddr::V6(ip) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00,
+    }
+}
+
+/// Looks up the client IP address of requests in a list of [sources](Source), according to a
+/// [trust policy](Trust) and a [position](Position) in the headers listing several addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIpResolver {
+    sources: Vec<Source>,
+    position: Position,
+    trust: Trust,
+    canonical: bool,
+    peers: Vec<PeerSource>,
+    limits: HeaderLimits,
+    duplicates: DuplicateHeaders,
+}
+
+impl Default for ClientIpResolver {
+    /// Looks up the [`SmartIpKeyExtractor::DEFAULT_SOURCES`], in the leftmost position, from
+    /// any peer.
+    ///
+    /// [`SmartIpKeyExtractor::DEFAULT_SOURCES`]: crate::key_extractor::SmartIpKeyExtractor::DEFAULT_SOURCES
+    fn default() -> Self {
+        Self::new(crate::key_extractor::SmartIpKeyExtractor::DEFAULT_SOURCES)
+    }
+}
+
+impl ClientIpResolver {
+    /// A resolver looking up the given sources, in order, in the leftmost position, from any
+    /// peer, and [canonicalizing](Self::canonical) the addresses.
+    pub fn new(sources: impl IntoIterator<Item = Source>) -> Self {
+        Self {
+            sources: sources.into_iter().collect(),
+            position: Position::Leftmost,
+            trust: Trust::Any,
+            canonical: true,
+            peers: PeerSource::DEFAULT.to_vec(),
+            limits: HeaderLimits::DEFAULT,
+            duplicates: DuplicateHeaders::Merge,
+        }
+    }
+
+    /// Take the last address of the headers listing several of them, see
+    /// [`Position::Rightmost`].
+    pub fn rightmost(mut self) -> Self {
+        self.position = Position::Rightmost;
+        self
+    }
+
+    /// Set the position of the client IP address in the headers listing several of them.
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Set which peers the forwarding headers are accepted from.
+    pub fn trust(mut self, trust: Trust) -> Self {
+        self.trust = trust;
+        self
+    }
+
+    /// Whether to convert IPv4-mapped IPv6 addresses, such as `::ffff:1.2.3.4`, to their
+    /// IPv4 form, so that a client reaching a dual-stack listener or proxy over both
+    /// protocols gets the same address. Enabled by default.
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Set the extensions the peer address is read from, in order, [`PeerSource::DEFAULT`]
+    /// by default.
+    pub fn peer_sources(mut self, sources: impl IntoIterator<Item = PeerSource>) -> Self {
+        self.peers = sources.into_iter().collect();
+        self
+    }
+
+    /// Set the bounds on parsing the forwarding headers, [`HeaderLimits::DEFAULT`] by default.
+    pub fn limits(mut self, limits: HeaderLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set how several `x-forwarded-for` headers are read, [`DuplicateHeaders::Merge`] by
+    /// default.
+    pub fn duplicate_headers(mut self, duplicates: DuplicateHeaders) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// The sources looked up, in order.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// The client IP address of `req`, if any source has one.
+    pub fn resolve<T>(&self, req: &Request<T>) -> Option<IpAddr> {
+        self.resolve_with_source(req).map(|(ip, _)| ip)
+    }
+
+    /// The client IP address of `req` along with the source it was found in.
+    pub fn resolve_with_source<T>(&self, req: &Request<T>) -> Option<(IpAddr, Source)> {
+        let peer = peer_ip_from(req, &self.peers);
+        let trusted = match peer {
+            Some(peer) => self.trust.trusts(peer),
+            None => self.trust == Trust::Any,
+        };
+        for source in &self.sources {
+            let ip = match source {
+                Source::Peer => peer,
+                _ if !trusted => None,
+                _ => match source.addresses(req, &self.limits, self.duplicates) {
+                    Ok(addresses) => self.pick(addresses),
+                    Err(Unreadable::Duplicated) => return None,
+                    Err(Unreadable::Exceeded) if self.limits.reject => return None,
+                    Err(Unreadable::Exceeded) => None,
+                },
+            };
+            if let Some(ip) = ip {
+                let ip = match self.canonical {
+                    true => ip.to_canonical(),
+                    false => ip,
+                };
+                return Some((ip, *source));
+            }
+        }
+        None
+    }
+
+    fn pick(&self, addresses: Vec<IpAddr>) -> Option<IpAddr> {
+        match self.position {
+            Position::Leftmost => addresses.first().copied(),
+            // skip the trusted proxies appending to the header in front of the closest one
+            Position::Rightmost => addresses
+                .iter()
+                .rev()
+                .find(|ip| self.trust == Trust::Any || !self.trust.trusts(**ip))
+                .or(addresses.first())
+                .copied(),
+        }
+    }
+}
+
+/// A request extension the peer address of the connection can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PeerSource {
+    /// axum's `ConnectInfo<SocketAddr>`, inserted by the services of
+    /// `into_make_service_with_connect_info`.
+    #[cfg(feature = "axum")]
+    ConnectInfo,
+    /// A bare `SocketAddr`, e.g. inserted by the service handed to a hyper server for each
+    /// connection.
+    SocketAddr,
+    /// The remote address of hyper-util's `HttpInfo`.
+    #[cfg(feature = "hyper-util")]
+    HttpInfo,
+}
+
+impl PeerSource {
+    /// The extensions looked up by default, in order: `ConnectInfo` with the `axum` feature,
+    /// `SocketAddr`, then `HttpInfo` with the `hyper-util` feature.
+    pub const DEFAULT: &'static [PeerSource] = &[
+        #[cfg(feature = "axum")]
+        PeerSource::ConnectInfo,
+        PeerSource::SocketAddr,
+        #[cfg(feature = "hyper-util")]
+        PeerSource::HttpInfo,
+    ];
+
+    /// The peer address held by this extension of `req`, if any.
+    pub fn resolve<T>(&self, req: &Request<T>) -> Option<SocketAddr> {
+        let extensions = req.extensions();
+        match self {
+            #[cfg(feature = "axum")]
+            PeerSource::ConnectInfo => extensions
+                .get::<axum::extract::ConnectInfo<SocketAddr>>()
+                .map(|info| info.0),
+            PeerSource::SocketAddr => extensions.get::<SocketAddr>().copied(),
+            #[cfg(feature = "hyper-util")]
+            PeerSource::HttpInfo => extensions
+                .get::<hyper_util::client::legacy::connect::HttpInfo>()
+                .map(|info| info.remote_addr()),
+        }
+    }
+}
+
+// Utility functions for the SmartIpExtractor
+// Shamelessly snatched from the axum-client-ip crate here:
+// https://crates.io/crates/axum-client-ip
+
+/// The peer address of the connection, from the first of the [`PeerSource::DEFAULT`]
+/// extensions holding one.
+pub fn peer_ip<T>(req: &Request<T>) -> Option<IpAddr> {
+    peer_ip_from(req, PeerSource::DEFAULT)
+}
+
+/// The peer address of the connection, from the first of the `sources` holding one.
+pub fn peer_ip_from<T>(req: &Request<T>, sources: &[PeerSource]) -> Option<IpAddr> {
+    sources
+        .iter()
+        .find_map(|source| source.resolve(req))
+        .map(|addr| addr.ip())
+}
+
+/// The address at `position` of the `x-forwarded-for` headers, skipping invalid entries.
+///
+/// The headers over the [`HeaderLimits::DEFAULT`] are skipped, and [duplicates are
+/// merged](DuplicateHeaders::Merge).
+pub fn x_forwarded_for(headers: &HeaderMap, position: Position) -> Option<IpAddr> {
+    let chain =
+        x_forwarded_for_chain(headers, &HeaderLimits::DEFAULT, DuplicateHeaders::Merge).ok()?;
+    match position {
+        Position::Leftmost => chain.first().copied(),
+        Position::Rightmost => chain.last().copied(),
+    }
+}
+
+fn x_forwarded_for_chain(
+    headers: &HeaderMap,
+    limits: &HeaderLimits,
+    duplicates: DuplicateHeaders,
+) -> Result<Vec<IpAddr>, Unreadable> {
+    let values = headers.get_all(X_FORWARDED_FOR);
+    let skip = match duplicates {
+        DuplicateHeaders::Merge => 0,
+        DuplicateHeaders::Last => values.iter().count().saturating_sub(1),
+        DuplicateHeaders::Reject if values.iter().nth(1).is_some() => {
+            return Err(Unreadable::Duplicated)
+        }
+        DuplicateHeaders::Reject => 0,
+    };
+    let mut chain = Vec::new();
+    let mut entries = 0;
+    for hv in values.iter().skip(skip) {
+        if hv.len() > limits.max_value_len {
+            return Err(Unreadable::Exceeded);
+        }
+        let Ok(value) = hv.to_str() else {
+            continue;
+        };
+        for entry in value.split(',') {
+            entries += 1;
+            if entries > limits.max_entries {
+                return Err(Unreadable::Exceeded);
+            }
+            if let Ok(ip) = entry.trim().parse::<IpAddr>() {
+                chain.push(ip);
+            }
+        }
+    }
+    Ok(chain)
+}
+
+/// The address of the `x-real-ip` header.
+pub fn x_real_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    real_ip(headers, &HeaderLimits::DEFAULT).ok().flatten()
+}
+
+fn real_ip(headers: &HeaderMap, limits: &HeaderLimits) -> Result<Option<IpAddr>, Unreadable> {
+    let Some(hv) = headers.get(X_REAL_IP) else {
+        return Ok(None);
+    };
+    if hv.len() > limits.max_value_len {
+        return Err(Unreadable::Exceeded);
+    }
+    Ok(hv
+        .to_str()
+        .ok()
+        .and_then(|s| s.trim().parse::<IpAddr>().ok()))
+}
+
+/// The address at `position` among the `for` parameters of the `forwarded` headers, skipping
+/// those not holding an address.
+///
+/// The headers over the [`HeaderLimits::DEFAULT`] are skipped.
+pub fn forwarded_for(headers: &HeaderMap, position: Position) -> Option<IpAddr> {
+    let chain = forwarded_for_chain(headers, &HeaderLimits::DEFAULT).ok()?;
+    match position {
+        Position::Leftmost => chain.first().copied(),
+        Position::Rightmost => chain.last().copied(),
+    }
+}
+
+fn forwarded_for_chain(
+    headers: &HeaderMap,
+    limits: &HeaderLimits,
+) -> Result<Vec<IpAddr>, Unreadable> {
+    Ok(bounded_forwarded_elements(headers, limits)?
+        .iter()
+        .filter_map(|element| element.for_node.as_ref()?.ip())
+        .collect())
+}
+
+/// A node named by the `for` or `by` parameter of a `forwarded` header element.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    /// An address along with its port.
+    Addr(SocketAddr),
+    /// An address.
+    Ip(IpAddr),
+    /// An obfuscated identifier, such as `_edge1`.
+    Obfuscated(String),
+    /// The `unknown` identifier.
+    Unknown,
+}
+
+impl Node {
+    /// The address of the node, if known.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Node::Addr(addr) => Some(addr.ip()),
+            Node::Ip(ip) => Some(*ip),
+            Node::Obfuscated(_) | Node::Unknown => None,
+        }
+    }
+}
+
+impl From<&Identifier> for Node {
+    fn from(identifier: &Identifier) -> Self {
+        match identifier {
+            Identifier::SocketAddr(addr) => Node::Addr(*addr),
+            Identifier::IpAddr(ip) => Node::Ip(*ip),
+            Identifier::String(name) => Node::Obfuscated(name.clone()),
+            Identifier::Unknown => Node::Unknown,
+        }
+    }
+}
+
+/// The parameters of an element of the `forwarded` headers, as added by one proxy per
+/// [RFC 7239](https://www.rfc-editor.org/rfc/rfc7239).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ForwardedElement {
+    /// The `for` parameter, the node that made the request to the proxy.
+    pub for_node: Option<Node>,
+    /// The `by` parameter, the interface of the proxy that received the request.
+    pub by: Option<Node>,
+    /// The `proto` parameter, the scheme the proxy received the request over.
+    pub proto: Option<Scheme>,
+    /// The `host` parameter, the `host` header the proxy received.
+    pub host: Option<String>,
+}
+
+/// The elements of the `forwarded` headers, from the one added by the first proxy to the one
+/// added by the closest, skipping the malformed headers. None are returned when the headers
+/// are over the [`HeaderLimits::DEFAULT`].
+///
+/// Policy hooks receiving the headers of the request, such as
+/// [`classify`](crate::governor::GovernorConfigBuilder::classify), can use them to tell how
+/// the request entered the network.
+///
+/// # Example
+/// ```rust
+/// use http::{uri::Scheme, Method};
+/// use std::time::Duration;
+/// use tower_governor::{
+///     forwarding::{forwarded_elements, Node},
+///     governor::GovernorConfigBuilder,
+/// };
+///
+/// let config = GovernorConfigBuilder::default()
+///     .classify(|_method, _uri, headers| {
+///         let edge = forwarded_elements(headers).into_iter().next()?;
+///         if edge.proto == Some(Scheme::HTTP) {
+///             Some("plain-http")
+///         } else if edge.by == Some(Node::Obfuscated("_partner-edge".to_owned())) {
+///             Some("partner")
+///         } else {
+///             None
+///         }
+///     })
+///     .class_quota("plain-http", Duration::from_secs(1), 5)
+///     .class_quota("partner", Duration::from_millis(10), 500)
+///     .finish()
+///     .unwrap();
+/// ```
+pub fn forwarded_elements(headers: &HeaderMap) -> Vec<ForwardedElement> {
+    bounded_forwarded_elements(headers, &HeaderLimits::DEFAULT).unwrap_or_default()
+}
+
+fn bounded_forwarded_elements(
+    headers: &HeaderMap,
+    limits: &HeaderLimits,
+) -> Result<Vec<ForwardedElement>, Unreadable> {
+    let mut elements = Vec::new();
+    for hv in headers.get_all(FORWARDED) {
+        // checked before parsing, which is the costly part
+        if hv.len() > limits.max_value_len {
+            return Err(Unreadable::Exceeded);
+        }
+        let Some(value) = hv
+            .to_str()
+            .ok()
+            .and_then(|value| ForwardedHeaderValue::from_forwarded(value).ok())
+        else {
+            continue;
+        };
+        for stanza in value.iter() {
+            if elements.len() == limits.max_entries {
+                return Err(Unreadable::Exceeded);
+            }
+            elements.push(ForwardedElement {
+                for_node: stanza.forwarded_for.as_ref().map(Node::from),
+                by: stanza.forwarded_by.as_ref().map(Node::from),
+                proto: stanza.forwarded_proto.map(|proto| match proto {
+                    Protocol::Http => Scheme::HTTP,
+                    Protocol::Https => Scheme::HTTPS,
+                }),
+                host: stanza.forwarded_host.clone(),
+            });
+        }
+    }
+    Ok(elements)
+}
+
+/// The client IP address named by the forwarding headers, looked up and canonicalized like
+/// the [SmartIpKeyExtractor](crate::key_extractor::SmartIpKeyExtractor) does.
+pub(crate) fn forwarded_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    x_forwarded_for(headers, Position::Leftmost)
+        .or_else(|| x_real_ip(headers))
+        .or_else(|| forwarded_for(headers, Position::Leftmost))
+        .map(|ip| ip.to_canonical())
+}
@@ -1,24 +1,246 @@
+#[cfg(feature = "audit")]
+use crate::audit::{AuditSink, GovernorEvent};
+#[cfg(feature = "tracing")]
+use crate::logging::RejectionLevel;
+#[cfg(feature = "shared-memory")]
+use crate::shared_memory::SharedMemory;
+#[cfg(feature = "stream")]
+use crate::stream::{DecisionStream, DecisionStreams};
+#[cfg(feature = "template")]
+use crate::template::RejectionTemplate;
+#[cfg(feature = "test-util")]
+use crate::test_util::{Forced, Injections};
 use crate::{
-    key_extractor::{KeyExtractor, PeerIpKeyExtractor},
-    GovernorError,
+    auth_failures::{AuthFailures, AuthWatch},
+    ban::{Ban, BanObserver, Bans},
+    breaker::{self, Breaker},
+    charging::{FailureCharging, ResponseFilter},
+    churn::{ChurnObserver, KeyChurn},
+    class::{Classes, Classifier, RequestClass},
+    connection::{self, ConnectionKeyCache},
+    credits::{CostContext, RequestCost},
+    decision::{
+        redact, Decision, EnforcementMode, RateLimitSnapshot, RejectionContext, RequestDecision,
+        Sampled,
+    },
+    errors::{ConfigError, LeaseError, SnapshotError},
+    extraction_cache::{ExemptCache, FailureCache},
+    forwarding::{forwarded_ip, Trust},
+    headers::{
+        self, BareMiddleware, QuotaHeaders, RejectionAttributes, RejectionMode, RejectionReason,
+        UpstreamHeaders, WaitTimeUnit, WINDOW_START_HEADER,
+    },
+    key_extractor::{
+        GlobalKeyExtractor, KeyExtractor, PeerIpKeyExtractor, PreExtractedKey, Scoped,
+    },
+    lease::Lease,
+    matcher::{RequestMatcher, STATIC_ASSET_EXTENSIONS},
+    methods::MethodRules,
+    partition::Instances,
+    penalty::Penalties,
+    prefetch::Prefetch,
+    proxy_check::ProxyCheck,
+    refund::{Refund, Refunds},
+    replay::{self, Remaining, ReplayEntry, ReplayLog},
+    report::{
+        Arrivals, NearLimitKey, QuotaSuggestion, RateCounters, Rates, ReportFormat, RouteRates,
+        StatusCounts, Statuses, Tracker, DEFAULT_COHORT,
+    },
+    retain::Watermarks,
+    settings::GovernorSettings,
+    share::{FairShare, Shares},
+    sliding_log::SlidingLog,
+    state::{KeyIter, KeyStates, WindowStart},
+    windows::{WindowPolicies, Windows},
+    GovernorError, GovernorLayer,
 };
 use axum::body::Body;
+use bytes::Bytes;
 use governor::{
-    clock::{DefaultClock, QuantaInstant},
+    clock::Clock,
     middleware::{NoOpMiddleware, RateLimitingMiddleware, StateInformationMiddleware},
-    state::keyed::DefaultKeyedStateStore,
-    Quota, RateLimiter,
+    state::{keyed::DefaultKeyedStateStore, InMemoryState, NotKeyed},
+    NotUntil, Quota, RateLimiter,
+};
+use http::{
+    header::{
+        HeaderName, HeaderValue, ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE, UPGRADE, USER_AGENT,
+    },
+    request::Parts,
+    HeaderMap, Method, Request, Response, StatusCode, Uri,
+};
+#[cfg(feature = "shared-memory")]
+use std::path::PathBuf;
+#[cfg(feature = "audit")]
+use std::time::SystemTime;
+use std::{
+    any::Any,
+    collections::{
+        hash_map::{DefaultHasher, RandomState},
+        BTreeMap,
+    },
+    fmt::{self, Display},
+    future::Future,
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+    io::{self, BufRead, Write},
+    marker::PhantomData,
+    net::IpAddr,
+    num::NonZeroU32,
+    ops::ControlFlow,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{Duration, UNIX_EPOCH},
 };
-use http::{Method, Response};
-use std::{fmt, marker::PhantomData, num::NonZeroU32, sync::Arc, time::Duration};
 
 pub const DEFAULT_PERIOD: Duration = Duration::from_millis(500);
 pub const DEFAULT_BURST_SIZE: u32 = 8;
+/// Header added to requests that bypass the rate limiter when using [`use_headers`].
+///
+/// [`use_headers`]: GovernorConfigBuilder::use_headers
+pub const DEFAULT_WHITELISTED_HEADER: HeaderName =
+    HeaderName::from_static("x-ratelimit-whitelisted");
+
+/// The user agents of common health checkers: Kubernetes probes, AWS load balancers and Google
+/// Cloud load balancers, see [`GovernorConfigBuilder::exempt_health_checks`].
+pub const HEALTH_CHECK_AGENTS: [&str; 3] = ["kube-probe/", "ELB-HealthChecker/", "GoogleHC/"];
+
+#[cfg(feature = "tracing")]
+pub use crate::logging::REJECTION_TARGET;
+#[cfg(all(feature = "tracing", feature = "tarpit"))]
+pub use crate::logging::TARPIT_SPAN;
+
+/// Header naming the class of the request, see [`GovernorConfigBuilder::classify`].
+pub const CLASS_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-class");
+
+/// How `HEAD` requests are rate limited, see [`GovernorConfigBuilder::head_requests`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadRequests {
+    /// `HEAD` is a method of its own, only limited if part of the configured
+    /// [`methods`](GovernorConfigBuilder::methods). This is the default.
+    #[default]
+    Separate,
+    /// `HEAD` requests are limited as if they were `GET` requests, both for the configured
+    /// methods and the [request classes](GovernorConfigBuilder::classify).
+    AsGet,
+    /// `HEAD` requests are never limited.
+    Exempt,
+}
+
+/// The groups of equivalent methods, `GET` with `HEAD` and `PUT` with `PATCH`, see
+/// [`GovernorConfigBuilder::group_methods`].
+pub const EQUIVALENT_METHODS: [[Method; 2]; 2] =
+    [[Method::GET, Method::HEAD], [Method::PUT, Method::PATCH]];
+
+/// How upgrade requests, `CONNECT` requests and those asking to switch protocols with an
+/// `upgrade` header such as WebSocket handshakes, are rate limited, see
+/// [`GovernorConfigBuilder::upgrade_requests`].
+///
+/// Unless [`Standard`](Self::Standard), upgrade requests are limited whatever the configured
+/// [`methods`](GovernorConfigBuilder::methods) and
+/// [`method_rules`](GovernorConfigBuilder::method_rules).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpgradeRequests {
+    /// Upgrade requests are limited like the others. This is the default.
+    #[default]
+    Standard,
+    /// Upgrade requests are charged this number of elements of the quota instead of their
+    /// [`request_cost`](GovernorConfigBuilder::request_cost), e.g. as they hold a connection
+    /// for long. Costs of zero are charged one element, costs above the burst size the whole
+    /// burst.
+    Cost(u32),
+    /// Upgrade requests are denied with `403 Forbidden`, unless their key is exempt.
+    Deny,
+}
+
+/// Whether `req` asks for a connection of its own, see [`UpgradeRequests`].
+fn is_upgrade<B>(req: &Request<B>) -> bool {
+    req.method() == Method::CONNECT || req.headers().contains_key(UPGRADE)
+}
+
+/// Header of requests already rate limited by an edge gateway, see
+/// [`GovernorConfigBuilder::trust_edge_marker`].
+pub const EDGE_LIMITED_HEADER: HeaderName = HeaderName::from_static("x-edge-limited");
+
+/// What to do with requests carrying a valid [`EDGE_LIMITED_HEADER`], see
+/// [`GovernorConfigBuilder::trust_edge_marker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeLimited {
+    /// Forward them without consulting the limiter.
+    Bypass,
+    /// Consult the limiter but forward them even if they exceed the quota, as in
+    /// [`decide_only`](GovernorConfigBuilder::decide_only) mode.
+    DryRun,
+}
+
+/// The clock of the rate limiters unless [`GovernorConfigBuilder::clock`] sets another one:
+/// governor's TSC based `QuantaClock`, or its [`MonotonicClock`] reading [`std::time::Instant`]
+/// with the `std-clock` feature.
+///
+/// [`MonotonicClock`]: governor::clock::MonotonicClock
+#[cfg(not(feature = "std-clock"))]
+pub type DefaultClock = governor::clock::DefaultClock;
+
+/// The clock of the rate limiters unless [`GovernorConfigBuilder::clock`] sets another one:
+/// governor's TSC based `QuantaClock`, or its [`MonotonicClock`] reading [`std::time::Instant`]
+/// with the `std-clock` feature.
+///
+/// [`MonotonicClock`]: governor::clock::MonotonicClock
+#[cfg(feature = "std-clock")]
+pub type DefaultClock = governor::clock::MonotonicClock;
+
+/// The instants measured by the [`DefaultClock`].
+pub type DefaultInstant = <DefaultClock as Clock>::Instant;
 
 // Required by Governor's RateLimiter to share it across threads
 // See Governor User Guide: https://docs.rs/governor/0.6.0/governor/_guide/index.html
-pub type SharedRateLimiter<Key, M> =
-    Arc<RateLimiter<Key, DefaultKeyedStateStore<Key>, DefaultClock, M>>;
+pub type SharedRateLimiter<Key, M, C = DefaultClock> =
+    Arc<RateLimiter<Key, DefaultKeyedStateStore<Key>, C, M>>;
+
+// The number of keys held by `limiter` and by the stores of the class quotas and windows.
+fn stored_keys<Key, M, C>(
+    limiter: &SharedRateLimiter<Key, M, C>,
+    classes: Option<&Classes<Key, M, C>>,
+    windows: Option<&Windows<Key, C>>,
+) -> usize
+where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    limiter.len() + classes.map_or(0, Classes::len) + windows.map_or(0, Windows::len)
+}
+
+// Clean up the store of `limiter` and those of the class quotas and windows.
+fn retain_recent<Key, M, C>(
+    limiter: &SharedRateLimiter<Key, M, C>,
+    classes: Option<&Classes<Key, M, C>>,
+    windows: Option<&Windows<Key, C>>,
+) where
+    Key: Hash + Eq + Clone,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    limiter.retain_recent();
+    limiter.shrink_to_fit();
+    if let Some(classes) = classes {
+        classes.retain_recent();
+    }
+    if let Some(windows) = windows {
+        windows.retain_recent();
+    }
+}
+
+// The limiter of configurations rate limiting all requests together, see
+// `GovernorConfigBuilder::unkeyed`.
+type DirectRateLimiter<M, C> = RateLimiter<NotKeyed, InMemoryState, C, M>;
+
+// The prefetched cells of a configuration, see `GovernorConfigBuilder::prefetch`.
+type Prefetched<K, M, C> = Prefetch<
+    <K as KeyExtractor>::Key,
+    <M as RateLimitingMiddleware<<C as Clock>::Instant>>::PositiveOutcome,
+    <C as Clock>::Instant,
+>;
 
 /// Helper struct for building a configuration for the governor middleware.
 ///
@@ -50,22 +272,153 @@ pub type SharedRateLimiter<Key, M> =
 ///     .unwrap();
 /// ```
 #[derive(Debug, Eq, Clone, PartialEq)]
-pub struct GovernorConfigBuilder<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
+pub struct GovernorConfigBuilder<
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock = DefaultClock,
+> {
+    key_extractor: K,
+    options: Options,
+    clock: BuilderClock<C>,
+    middleware: PhantomData<M>,
+}
+
+// The options of a builder, apart from its key extractor, middleware and clock, which change
+// the type of the builder.
+#[derive(Debug, Eq, Clone, PartialEq)]
+struct Options {
     period: Duration,
     burst_size: u32,
     methods: Option<Vec<Method>>,
-    key_extractor: K,
     error_handler: ErrorHandler,
-    middleware: PhantomData<M>,
+    whitelisted_header: Option<HeaderName>,
+    max_wait_time: Option<Duration>,
+    decide_only: bool,
+    prefetch: u32,
+    exempt_loopback: bool,
+    exempt_private_ranges: bool,
+    charge_filter: Option<ResponseFilter>,
+    policy_name: Option<Arc<str>>,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<AuditSink>,
+    require_explicit_key_extractor: bool,
+    key_extractor_chosen: bool,
+    proxy_check: Option<(u32, Duration)>,
+    proxy_fallback: Option<Trust>,
+    exempt_preflight: Option<bool>,
+    retry_jitter: Option<Duration>,
+    cache_connection_keys: bool,
+    instances: Option<Instances>,
+    inner_error_hook: Option<InnerErrorHook>,
+    trailers: bool,
+    report_window: Option<Duration>,
+    retain_watermarks: Option<(usize, usize)>,
+    classifier: Option<Classifier>,
+    class_quotas: Vec<(&'static str, Duration, u32)>,
+    key_churn_limit: Option<(u32, Duration)>,
+    churn_observer: Option<ChurnObserver>,
+    rejection_message: Option<RejectionMessage>,
+    enforce_threshold: Option<u64>,
+    head_requests: HeadRequests,
+    rejection_hook: Option<RejectionHook>,
+    track_rates: bool,
+    extraction_failure_ttl: Option<Duration>,
+    track_state: bool,
+    ban_escalation: Option<(Duration, Duration)>,
+    ban_observer: Option<BanObserver>,
+    unkeyed: bool,
+    upstream_headers: UpstreamHeaders,
+    retry_after: bool,
+    replay_capacity: usize,
+    edge_marker: Option<EdgeMarker>,
+    request_cost: Option<RequestCost>,
+    credits: bool,
+    unrepresentable_rate: Option<u64>,
+    async_error_handler: Option<AsyncErrorHandler>,
+    tarpit: Option<Duration>,
+    track_route_rates: bool,
+    rejection_attributes: RejectionAttributes,
+    penalty_escalation: Option<(u32, Duration)>,
+    methods_header: Option<HeaderName>,
+    circuit_breaker: Option<(u32, u32, Duration)>,
+    limit_on_rejections: bool,
+    analyze_traffic: bool,
+    fair_share: Option<(Duration, u32, Shares)>,
+    method_rules: Option<MethodRules>,
+    wait_time_unit: WaitTimeUnit,
+    #[cfg(feature = "tracing")]
+    rejection_log_level: tracing::Level,
+    upgrade_requests: UpgradeRequests,
+    track_statuses: bool,
+    window_quotas: Vec<(&'static str, Duration, u32)>,
+    health_check_agents: Option<Arc<[Arc<str>]>>,
+    exemptions: Vec<RequestMatcher>,
+    window_start_header: bool,
+    refund_cancelled: bool,
+    ban_list: bool,
+    sample_over_quota: Option<NonZeroU32>,
+    auth_failure_limit: Option<(u32, Duration, Duration)>,
+    exemption_ttl: Option<Duration>,
+    track_cohort_rates: bool,
+    response_mapper: Option<ResponseMapper>,
+    leases: bool,
+    charge_once: bool,
+    sliding_log: Option<(u32, Duration)>,
+    #[cfg(feature = "shared-memory")]
+    shared_memory: Option<(PathBuf, usize)>,
+    write_percent: Option<u32>,
+    class_cache_ttl: Option<Duration>,
+    method_groups: Option<Arc<[(Method, Method)]>>,
 }
 
+type ErrorFn = dyn Fn(GovernorError) -> Response<Body> + Send + Sync;
+
 // function for handling GovernorError and produce valid http Response type.
-#[derive(Clone)]
-struct ErrorHandler(Arc<dyn Fn(GovernorError) -> Response<Body> + Send + Sync>);
+// `None` renders the default responses, which keeps the builder usable in const contexts.
+#[derive(Clone, Default)]
+struct ErrorHandler(Option<Arc<ErrorFn>>);
 
-impl Default for ErrorHandler {
-    fn default() -> Self {
-        Self(Arc::new(|mut e| e.as_response()))
+static DEFAULT_REJECTIONS: RejectionCache = RejectionCache::new();
+
+fn default_error_handler(error: GovernorError) -> Response<Body> {
+    DEFAULT_REJECTIONS.render(error)
+}
+
+/// Number of distinct `wait_time` values (in seconds) whose default rejection body is cached.
+const CACHED_WAIT_TIMES: usize = 64;
+
+// Pre-rendered bodies of the default `TooManyRequests` response, indexed by wait time.
+// Under a flood every blocked request would otherwise format a fresh body string.
+pub(crate) struct RejectionCache {
+    bodies: [OnceLock<Bytes>; CACHED_WAIT_TIMES],
+}
+
+impl RejectionCache {
+    pub(crate) const fn new() -> Self {
+        Self {
+            bodies: [const { OnceLock::new() }; CACHED_WAIT_TIMES],
+        }
+    }
+
+    pub(crate) fn render(&self, error: GovernorError) -> Response<Body> {
+        match error {
+            GovernorError::TooManyRequests {
+                wait_time, headers, ..
+            } if wait_time < CACHED_WAIT_TIMES as u64 => {
+                let body = self.bodies[wait_time as usize]
+                    .get_or_init(|| {
+                        Bytes::from(format!("Too Many Requests! Wait for {}s", wait_time))
+                    })
+                    .clone();
+                let mut response = Response::new(Body::from(body));
+                *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                if let Some(headers) = headers {
+                    *response.headers_mut() = headers;
+                }
+                response
+            }
+            mut e => e.as_response(),
+        }
     }
 }
 
@@ -84,7 +437,111 @@ impl PartialEq for ErrorHandler {
 
 impl Eq for ErrorHandler {}
 
-impl Default for GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware> {
+/// Future of a response produced by the layer itself.
+pub(crate) type DeferredResponse = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+type AsyncErrorFn = dyn Fn(GovernorError) -> DeferredResponse + Send + Sync;
+
+// Async function handling GovernorError, see `GovernorConfigBuilder::async_error_handler`.
+#[derive(Clone)]
+pub(crate) struct AsyncErrorHandler(Arc<AsyncErrorFn>);
+
+impl fmt::Debug for AsyncErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncErrorHandler").finish()
+    }
+}
+
+impl PartialEq for AsyncErrorHandler {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for AsyncErrorHandler {}
+
+type MessageFn = dyn Fn(u64, &Parts) -> String + Send + Sync;
+
+// Body of the default rejections along with its content type, see
+// `GovernorConfigBuilder::rejection_message`.
+#[derive(Clone)]
+struct RejectionMessage(Arc<MessageFn>, Option<HeaderValue>);
+
+impl RejectionMessage {
+    fn render<B>(&self, wait_time: u64, req: &Request<B>) -> String {
+        let mut parts = Request::new(()).into_parts().0;
+        parts.method = req.method().clone();
+        parts.uri = req.uri().clone();
+        parts.version = req.version();
+        parts.headers = req.headers().clone();
+        parts.extensions = req.extensions().clone();
+        (self.0)(wait_time, &parts)
+    }
+}
+
+impl fmt::Debug for RejectionMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RejectionMessage").finish()
+    }
+}
+
+impl PartialEq for RejectionMessage {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RejectionMessage {}
+
+// Clock chosen with `GovernorConfigBuilder::clock`, `None` standing for the default clock so
+// that the default builder can be created in const contexts.
+struct BuilderClock<C>(Option<C>);
+
+impl<C: Clone> Clone for BuilderClock<C> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<C> fmt::Debug for BuilderClock<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str(std::any::type_name::<C>()),
+            None => f.write_str("DefaultClock"),
+        }
+    }
+}
+
+impl<C> PartialEq for BuilderClock<C> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<C> Eq for BuilderClock<C> {}
+
+/// The quota replenishing one element per `period`, with bursts of up to `burst_size`.
+fn checked_quota(period: Duration, burst_size: u32) -> Result<Quota, ConfigError> {
+    let burst_size = NonZeroU32::new(burst_size).ok_or(ConfigError::ZeroBurstSize)?;
+    // governor keeps `period * burst_size` in nanoseconds as an u64, which would overflow
+    let replenished_in = period.as_nanos() * u128::from(burst_size.get());
+    if replenished_in > u128::from(u64::MAX) {
+        return Err(ConfigError::QuotaOverflow);
+    }
+    Ok(Quota::with_period(period)
+        .ok_or(ConfigError::ZeroPeriod)?
+        .allow_burst(burst_size))
+}
+
+/// The clock of a builder that wasn't given one.
+fn default_clock<C: Clock + 'static>() -> C {
+    // only `GovernorConfigBuilder::clock` changes the clock type, and it always sets a clock
+    *(Box::new(DefaultClock::default()) as Box<dyn Any>)
+        .downcast()
+        .expect("builders without a clock use the default clock type")
+}
+
+impl Default for GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>> {
     /// The default configuration which is suitable for most services.
     /// Allows burst with up to eight requests and replenishes one element after 500ms, based on peer IP.
     /// The values can be modified by calling other methods on this struct.
@@ -93,8 +550,14 @@ impl Default for GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware> {
     }
 }
 
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<K, M> {
-    /// Set handler function for handling [GovernorError]
+impl<K, M, C> GovernorConfigBuilder<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock + Clone + 'static,
+{
+    /// Set handler function for handling [GovernorError], such as the ready-made ones of the
+    /// [`handlers`](crate::handlers) module.
     /// # Example
     /// ```rust
     /// # use http::Response;
@@ -111,50 +574,425 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBu
     where
         F: Fn(GovernorError) -> Response<Body> + Send + Sync + 'static,
     {
-        self.error_handler = ErrorHandler(Arc::new(func));
+        self.options.error_handler = ErrorHandler(Some(Arc::new(func)));
+        self
+    }
+
+    /// Same as [`error_handler`](Self::error_handler), for handlers returning any axum
+    /// `IntoResponse`, such as the error types of the application or a `Json` wrapper.
+    ///
+    /// # Example
+    /// ```rust
+    /// use axum::{http::StatusCode, Json};
+    /// use serde_json::json;
+    /// use tower_governor::{governor::GovernorConfigBuilder, GovernorError};
+    ///
+    /// GovernorConfigBuilder::default().axum_error_handler(|error| match error {
+    ///     GovernorError::TooManyRequests { wait_time, .. } => (
+    ///         StatusCode::TOO_MANY_REQUESTS,
+    ///         Json(json!({ "error": "rate_limited", "retry_after": wait_time })),
+    ///     ),
+    ///     _ => (
+    ///         StatusCode::INTERNAL_SERVER_ERROR,
+    ///         Json(json!({ "error": "internal" })),
+    ///     ),
+    /// });
+    /// ```
+    #[cfg(feature = "axum")]
+    pub fn axum_error_handler<F, R>(&mut self, func: F) -> &mut Self
+    where
+        F: Fn(GovernorError) -> R + Send + Sync + 'static,
+        R: axum::response::IntoResponse,
+    {
+        self.error_handler(move |error| func(error).into_response())
+    }
+
+    /// Set an async function handling [GovernorError], for handlers that need to await, e.g.
+    /// to render a template loaded from a store. Takes precedence over the [`error_handler`]
+    /// and the [`rejection_message`].
+    ///
+    /// The responses are produced when the response future of the layer is polled, the
+    /// [`on_rejection`] hook running once they are ready.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use axum::body::Body;
+    /// # use http::Response;
+    /// # use tower_governor::{governor::GovernorConfigBuilder, GovernorError};
+    /// # async fn render_error_page(error: &GovernorError) -> String { String::new() }
+    /// GovernorConfigBuilder::default().async_error_handler(|mut error| async move {
+    ///     let page = render_error_page(&error).await;
+    ///     let mut response = error.as_response::<Body>();
+    ///     *response.body_mut() = Body::from(page);
+    ///     response
+    /// });
+    /// ```
+    ///
+    /// [`error_handler`]: Self::error_handler
+    /// [`rejection_message`]: Self::rejection_message
+    /// [`on_rejection`]: Self::on_rejection
+    pub fn async_error_handler<F, Fut>(&mut self, func: F) -> &mut Self
+    where
+        F: Fn(GovernorError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response<Body>> + Send + 'static,
+    {
+        self.options.async_error_handler = Some(AsyncErrorHandler(Arc::new(move |error| {
+            Box::pin(func(error))
+        })));
+        self
+    }
+
+    /// Hold the responses of the layer itself, such as rejections, for `delay` before sending
+    /// them, slowing down clients that retry right away instead of honoring `retry-after`.
+    ///
+    /// Rejections are never held past their wait time, when the client could succeed. The
+    /// connections stay open while held, so keep `delay` short.
+    ///
+    /// With the `tracing` feature, the held responses are covered by a
+    /// `tower_governor::tarpit` span recording the delay, the class of the request when known
+    /// and the time it was parked, so that tracing subscribers and tokio-console show the time
+    /// requests spend in the governor.
+    #[cfg(feature = "tarpit")]
+    pub const fn tarpit(&mut self, delay: Duration) -> &mut Self {
+        self.options.tarpit = Some(delay);
+        self
+    }
+
+    /// Set the body of the default `429 Too Many Requests` responses, e.g. to localize the
+    /// `Too Many Requests! Wait for {wait_time}s` message shown to browsers.
+    ///
+    /// `func` is given the advertised wait time in seconds and the parts of the rejected
+    /// request, such as its `Accept-Language` header. It has no effect when an
+    /// [`error_handler`](Self::error_handler) is set.
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// GovernorConfigBuilder::default().rejection_message(|wait_time, parts| {
+    ///     let french = parts
+    ///         .headers
+    ///         .get("accept-language")
+    ///         .and_then(|value| value.to_str().ok())
+    ///         .is_some_and(|value| value.starts_with("fr"));
+    ///     if french {
+    ///         format!("Trop de requêtes ! Réessayez dans {}s", wait_time)
+    ///     } else {
+    ///         format!("Too Many Requests! Wait for {}s", wait_time)
+    ///     }
+    /// });
+    /// ```
+    pub fn rejection_message<F>(&mut self, func: F) -> &mut Self
+    where
+        F: Fn(u64, &Parts) -> String + Send + Sync + 'static,
+    {
+        self.options.rejection_message = Some(RejectionMessage(Arc::new(func), None));
+        self
+    }
+
+    /// Render the body of the default `429 Too Many Requests` responses from `template`,
+    /// served as `text/html`, e.g. for a branded throttle page. It replaces the
+    /// [`rejection_message`](Self::rejection_message) and has no effect when an
+    /// [`error_handler`](Self::error_handler) is set.
+    ///
+    /// See the [`template`](crate::template) module for the variables of the template.
+    #[cfg(feature = "template")]
+    pub fn rejection_template(&mut self, template: RejectionTemplate) -> &mut Self {
+        self.options.rejection_message = Some(RejectionMessage(
+            Arc::new(move |wait_time, parts| template.render(wait_time, parts)),
+            Some(HeaderValue::from_static("text/html; charset=utf-8")),
+        ));
+        self
+    }
+
+    /// Set the `cache-control` header of the rejections lacking one, e.g. `no-store` so that
+    /// CDNs and shared caches don't hand a rejection to other clients. Not set by default.
+    ///
+    /// # Example
+    /// ```rust
+    /// use http::HeaderValue;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .rejection_cache_control(HeaderValue::from_static("no-store"))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn rejection_cache_control(&mut self, value: HeaderValue) -> &mut Self {
+        self.options.rejection_attributes.cache_control = Some(value);
+        self
+    }
+
+    /// Mark the rejections with the [`SkipCompression`] extension, for compression layers
+    /// to leave them alone: they are small, and compressing them wastes CPU time when the
+    /// service is overloaded. Disabled by default.
+    ///
+    /// [`SkipCompression`]: crate::headers::SkipCompression
+    pub const fn skip_rejection_compression(&mut self, enabled: bool) -> &mut Self {
+        self.options.rejection_attributes.skip_compression = enabled;
+        self
+    }
+
+    /// Set the status of the rejections exceeding the quota, `429 Too Many Requests` by
+    /// default.
+    ///
+    /// [`RejectionMode::ServiceUnavailable`] suits infrastructure-level caps shared by every
+    /// client, signaling overload with a `503 Service Unavailable` and its `retry-after`
+    /// rather than a quota the client exceeded. Rejections given another status by the
+    /// [error handler](Self::error_handler) keep it.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{
+    ///     governor::GovernorConfigBuilder, headers::RejectionMode, key_extractor::GlobalKeyExtractor,
+    /// };
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .key_extractor(GlobalKeyExtractor)
+    ///     .per_millisecond(1)
+    ///     .burst_size(1000)
+    ///     .rejection_mode(RejectionMode::ServiceUnavailable)
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub const fn rejection_mode(&mut self, mode: RejectionMode) -> &mut Self {
+        self.options.rejection_attributes.mode = mode;
+        self
+    }
+}
+
+impl<K, C> GovernorConfigBuilder<K, NoOpMiddleware<C::Instant>, C>
+where
+    K: KeyExtractor,
+    C: Clock + Clone + 'static,
+{
+    /// Set the clock the rate limiter measures time with, the [`DefaultClock`] by default.
+    ///
+    /// The clock, key extractor, middleware and error handler can be set in any order, none
+    /// of them drops the settings made before.
+    ///
+    /// # Example
+    /// ```rust
+    /// use governor::clock::FakeRelativeClock;
+    /// use std::time::Duration;
+    /// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor};
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .clock(clock.clone())
+    ///     .key_extractor(SmartIpKeyExtractor)
+    ///     .use_headers()
+    ///     .finish()
+    ///     .unwrap();
+    ///
+    /// // move the time of the rate limiter forward, e.g. in tests
+    /// clock.advance(Duration::from_secs(1));
+    /// ```
+    pub fn clock<C2: Clock + Clone + 'static>(
+        &mut self,
+        clock: C2,
+    ) -> GovernorConfigBuilder<K, NoOpMiddleware<C2::Instant>, C2> {
+        self.rebuild(self.key_extractor.clone(), Some(clock))
+    }
+}
+
+impl<K, C> GovernorConfigBuilder<K, StateInformationMiddleware, C>
+where
+    K: KeyExtractor,
+    C: Clock + Clone + 'static,
+{
+    /// Set how the rate limiting headers already present on the responses of the inner
+    /// service are handled, e.g. when a gateway forwards those of upstream services. Defaults
+    /// to [`UpstreamHeaders::PreferLocal`], replacing them.
+    pub const fn upstream_headers(&mut self, upstream: UpstreamHeaders) -> &mut Self {
+        self.options.upstream_headers = upstream;
+        self
+    }
+
+    /// Set the clock the rate limiter measures time with, see the method of the same name on
+    /// builders without [`use_headers`](Self::use_headers).
+    pub fn clock<C2: Clock + Clone + 'static>(
+        &mut self,
+        clock: C2,
+    ) -> GovernorConfigBuilder<K, StateInformationMiddleware, C2> {
+        self.rebuild(self.key_extractor.clone(), Some(clock))
+    }
+}
+
+impl<M, C> GovernorConfigBuilder<GlobalKeyExtractor, M, C>
+where
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Check the requests against a single lock-free limiter instead of the keyed store, as
+    /// all requests share the same key. This speeds up hard global caps.
+    ///
+    /// The keyed store, as returned by [`GovernorConfig::limiter`], is still used by
+    /// [`prefetch`], [`charge_only`] and the [class quotas], which thus don't benefit from it.
+    ///
+    /// [`prefetch`]: Self::prefetch
+    /// [`charge_only`]: Self::charge_only
+    /// [class quotas]: Self::class_quota
+    pub const fn unkeyed(&mut self) -> &mut Self {
+        self.options.unkeyed = true;
         self
     }
 }
 
 /// Sets the default Governor Config and defines all the different configuration functions
 /// This one is used when the default PeerIpKeyExtractor is used
-impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyExtractor, M> {
-    pub fn const_default() -> Self {
+impl<M: RateLimitingMiddleware<DefaultInstant>> GovernorConfigBuilder<PeerIpKeyExtractor, M> {
+    /// The default configuration, usable in const contexts.
+    ///
+    /// All setters taking plain values, such as [`period`] or [`burst_size`], are `const` too:
+    ///
+    /// ```rust
+    /// use governor::middleware::NoOpMiddleware;
+    /// use tower_governor::{
+    ///     governor::{DefaultInstant, GovernorConfigBuilder},
+    ///     key_extractor::PeerIpKeyExtractor,
+    /// };
+    ///
+    /// const LOGIN: GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>> = {
+    ///     let mut builder = GovernorConfigBuilder::const_default();
+    ///     builder.per_second(4).burst_size(2).exempt_loopback(true);
+    ///     builder
+    /// };
+    ///
+    /// let config = { LOGIN }.finish().unwrap();
+    /// ```
+    ///
+    /// [`period`]: Self::period
+    /// [`burst_size`]: Self::burst_size
+    pub const fn const_default() -> Self {
         GovernorConfigBuilder {
-            period: DEFAULT_PERIOD,
-            burst_size: DEFAULT_BURST_SIZE,
-            methods: None,
             key_extractor: PeerIpKeyExtractor,
-            error_handler: ErrorHandler::default(),
+            options: Options {
+                period: DEFAULT_PERIOD,
+                burst_size: DEFAULT_BURST_SIZE,
+                methods: None,
+                error_handler: ErrorHandler(None),
+                whitelisted_header: Some(DEFAULT_WHITELISTED_HEADER),
+                max_wait_time: None,
+                decide_only: false,
+                prefetch: 0,
+                exempt_loopback: false,
+                exempt_private_ranges: false,
+                charge_filter: None,
+                policy_name: None,
+                #[cfg(feature = "audit")]
+                audit_sink: None,
+                require_explicit_key_extractor: false,
+                key_extractor_chosen: false,
+                proxy_check: None,
+                proxy_fallback: None,
+                exempt_preflight: None,
+                retry_jitter: None,
+                cache_connection_keys: false,
+                instances: None,
+                inner_error_hook: None,
+                trailers: false,
+                report_window: None,
+                retain_watermarks: None,
+                classifier: None,
+                class_quotas: Vec::new(),
+                key_churn_limit: None,
+                churn_observer: None,
+                rejection_message: None,
+                enforce_threshold: None,
+                head_requests: HeadRequests::Separate,
+                rejection_hook: None,
+                track_rates: false,
+                extraction_failure_ttl: None,
+                track_state: false,
+                ban_escalation: None,
+                ban_observer: None,
+                unkeyed: false,
+                upstream_headers: UpstreamHeaders::PreferLocal,
+                retry_after: true,
+                replay_capacity: 0,
+                edge_marker: None,
+                request_cost: None,
+                credits: false,
+                unrepresentable_rate: None,
+                async_error_handler: None,
+                tarpit: None,
+                track_route_rates: false,
+                rejection_attributes: RejectionAttributes {
+                    cache_control: None,
+                    skip_compression: false,
+                    mode: RejectionMode::TooManyRequests,
+                },
+                penalty_escalation: None,
+                methods_header: None,
+                circuit_breaker: None,
+                limit_on_rejections: false,
+                analyze_traffic: false,
+                fair_share: None,
+                method_rules: None,
+                wait_time_unit: WaitTimeUnit::Seconds,
+                #[cfg(feature = "tracing")]
+                rejection_log_level: tracing::Level::INFO,
+                upgrade_requests: UpgradeRequests::Standard,
+                track_statuses: false,
+                window_quotas: Vec::new(),
+                health_check_agents: None,
+                exemptions: Vec::new(),
+                window_start_header: false,
+                refund_cancelled: false,
+                ban_list: false,
+                sample_over_quota: None,
+                auth_failure_limit: None,
+                exemption_ttl: None,
+                track_cohort_rates: false,
+                response_mapper: None,
+                leases: false,
+                charge_once: false,
+                sliding_log: None,
+                #[cfg(feature = "shared-memory")]
+                shared_memory: None,
+                write_percent: None,
+                class_cache_ttl: None,
+                method_groups: None,
+            },
+            clock: BuilderClock(None),
             middleware: PhantomData,
         }
     }
+}
+
+/// Sets configuration options when any Key Extractor is provided
+impl<K, M, C> GovernorConfigBuilder<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock + Clone + 'static,
+{
     /// Set the interval after which one element of the quota is replenished.
     ///
     /// **The interval must not be zero.**
-    pub fn const_period(mut self, duration: Duration) -> Self {
-        self.period = duration;
+    pub const fn period(&mut self, duration: Duration) -> &mut Self {
+        self.options.period = duration;
         self
     }
     /// Set the interval after which one element of the quota is replenished in seconds.
     ///
     /// **The interval must not be zero.**
-    pub fn const_per_second(mut self, seconds: u64) -> Self {
-        self.period = Duration::from_secs(seconds);
+    pub const fn per_second(&mut self, seconds: u64) -> &mut Self {
+        self.options.period = Duration::from_secs(seconds);
         self
     }
     /// Set the interval after which one element of the quota is replenished in milliseconds.
     ///
     /// **The interval must not be zero.**
-    pub fn const_per_millisecond(mut self, milliseconds: u64) -> Self {
-        self.period = Duration::from_millis(milliseconds);
+    pub const fn per_millisecond(&mut self, milliseconds: u64) -> &mut Self {
+        self.options.period = Duration::from_millis(milliseconds);
         self
     }
     /// Set the interval after which one element of the quota is replenished in nanoseconds.
     ///
     /// **The interval must not be zero.**
-    pub fn const_per_nanosecond(mut self, nanoseconds: u64) -> Self {
-        self.period = Duration::from_nanos(nanoseconds);
+    pub const fn per_nanosecond(&mut self, nanoseconds: u64) -> &mut Self {
+        self.options.period = Duration::from_nanos(nanoseconds);
         self
     }
     /// Set quota size that defines how many requests can occur
@@ -162,176 +1000,3083 @@ impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<PeerIpKeyEx
     /// clients have to wait until the elements of the quota are replenished.
     ///
     /// **The burst_size must not be zero.**
-    pub fn const_burst_size(mut self, burst_size: u32) -> Self {
-        self.burst_size = burst_size;
+    pub const fn burst_size(&mut self, burst_size: u32) -> &mut Self {
+        self.options.burst_size = burst_size;
         self
     }
-}
 
-/// Sets configuration options when any Key Extractor is provided
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfigBuilder<K, M> {
-    /// Set the interval after which one element of the quota is replenished.
+    /// Set the sustained rate of requests allowed per second, replenishing one element of the
+    /// quota every `1s / rps`. Pair it with [`burst`] to allow short spikes above that rate.
     ///
-    /// **The interval must not be zero.**
-    pub fn period(&mut self, duration: Duration) -> &mut Self {
-        self.period = duration;
+    /// The interval is rounded to the nearest nanosecond, the precision of the limiter, so
+    /// high rates such as `sustained_rps(1_000_000)` are represented exactly. Rates above one
+    /// request per nanosecond can't be represented and make [`try_finish`] fail with
+    /// [`ConfigError::UnrepresentableRate`].
+    ///
+    /// **The rate must not be zero.**
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .sustained_rps(100)
+    ///     .burst(500)
+    ///     .finish()
+    ///     .unwrap();
+    /// assert_eq!(config.period(), Duration::from_millis(10));
+    /// assert_eq!(config.burst_size(), 500);
+    /// ```
+    ///
+    /// [`burst`]: Self::burst
+    /// [`try_finish`]: Self::try_finish
+    pub const fn sustained_rps(&mut self, rps: u64) -> &mut Self {
+        const NANOS_PER_SEC: u64 = 1_000_000_000;
+        self.options.period = match rps {
+            0 => Duration::ZERO,
+            rps if rps > NANOS_PER_SEC => {
+                self.options.unrepresentable_rate = Some(rps);
+                Duration::ZERO
+            }
+            rps => Duration::from_nanos((NANOS_PER_SEC + rps / 2) / rps),
+        };
         self
     }
-    /// Set the interval after which one element of the quota is replenished in seconds.
-    ///
-    /// **The interval must not be zero.**
-    pub fn per_second(&mut self, seconds: u64) -> &mut Self {
-        self.period = Duration::from_secs(seconds);
+    /// Same as [`burst_size`](Self::burst_size), reading naturally after
+    /// [`sustained_rps`](Self::sustained_rps).
+    pub const fn burst(&mut self, burst_size: u32) -> &mut Self {
+        self.burst_size(burst_size)
+    }
+
+    /// By-value variant of [`period`](Self::period), for chaining in const contexts.
+    pub const fn const_period(mut self, duration: Duration) -> Self {
+        self.period(duration);
         self
     }
-    /// Set the interval after which one element of the quota is replenished in milliseconds.
-    ///
-    /// **The interval must not be zero.**
-    pub fn per_millisecond(&mut self, milliseconds: u64) -> &mut Self {
-        self.period = Duration::from_millis(milliseconds);
+    /// By-value variant of [`per_second`](Self::per_second), for chaining in const contexts.
+    pub const fn const_per_second(mut self, seconds: u64) -> Self {
+        self.per_second(seconds);
         self
     }
-    /// Set the interval after which one element of the quota is replenished in nanoseconds.
-    ///
-    /// **The interval must not be zero.**
-    pub fn per_nanosecond(&mut self, nanoseconds: u64) -> &mut Self {
-        self.period = Duration::from_nanos(nanoseconds);
+    /// By-value variant of [`per_millisecond`](Self::per_millisecond), for chaining in const
+    /// contexts.
+    pub const fn const_per_millisecond(mut self, milliseconds: u64) -> Self {
+        self.per_millisecond(milliseconds);
         self
     }
-    /// Set quota size that defines how many requests can occur
-    /// before the governor middleware starts blocking requests from an IP address and
-    /// clients have to wait until the elements of the quota are replenished.
-    ///
-    /// **The burst_size must not be zero.**
-    pub fn burst_size(&mut self, burst_size: u32) -> &mut Self {
-        self.burst_size = burst_size;
+    /// By-value variant of [`per_nanosecond`](Self::per_nanosecond), for chaining in const
+    /// contexts.
+    pub const fn const_per_nanosecond(mut self, nanoseconds: u64) -> Self {
+        self.per_nanosecond(nanoseconds);
+        self
+    }
+    /// By-value variant of [`burst_size`](Self::burst_size), for chaining in const contexts.
+    pub const fn const_burst_size(mut self, burst_size: u32) -> Self {
+        self.burst_size(burst_size);
+        self
+    }
+
+    /// Set the period, burst size and methods of `settings`, e.g. read from a configuration
+    /// file checked by [`GovernorSettings::validate`].
+    pub fn settings(&mut self, settings: &GovernorSettings) -> &mut Self {
+        self.options.period = settings.period;
+        self.options.burst_size = settings.burst_size;
+        self.options.methods = settings.methods.clone();
         self
     }
 
     /// Set the HTTP methods this configuration should apply to.
     /// By default this is all methods.
     pub fn methods(&mut self, methods: Vec<Method>) -> &mut Self {
-        self.methods = Some(methods);
+        self.options.methods = Some(methods);
         self
     }
 
-    /// Set the key extractor this configuration should use.
-    /// By default this is using the [PeerIpKeyExtractor].
-    pub fn key_extractor<K2: KeyExtractor>(
-        &mut self,
-        key_extractor: K2,
-    ) -> GovernorConfigBuilder<K2, M> {
-        GovernorConfigBuilder {
-            period: self.period,
-            burst_size: self.burst_size,
-            methods: self.methods.to_owned(),
-            key_extractor,
-            error_handler: self.error_handler.clone(),
-            middleware: PhantomData,
-        }
-    }
-    /// Set ratelimit headers to response, the headers is
-    /// - `x-ratelimit-limit`       - Request limit
-    /// - `x-ratelimit-remaining`   - The number of requests left for the time window
-    /// - `x-ratelimit-after`       - Number of seconds in which the API will become available after its rate limit has been exceeded
-    /// - `retry-after`             - Same value as `x-ratelimit-after`
-    /// - `x-ratelimit-whitelisted` - If the request method not in methods, this header will be add it, use [`methods`] to add methods
+    /// Choose the rate limited methods of every request by its class or its key with
+    /// `rules`, falling back onto [`methods`](Self::methods) for the requests matching none,
+    /// e.g. to limit anonymous keys on all methods but the others only when writing.
     ///
-    /// By default `x-ratelimit-after` and `retry-after` are enabled, with [`use_headers`] will enable `x-ratelimit-limit`, `x-ratelimit-whitelisted` and `x-ratelimit-remaining`
-    ///
-    /// [`methods`]: crate::GovernorConfigBuilder::methods()
-    /// [`use_headers`]: Self::use_headers
-    pub fn use_headers(&mut self) -> GovernorConfigBuilder<K, StateInformationMiddleware> {
+    /// The requests are only bypassed once their key is extracted, see [`MethodRules`].
+    pub fn method_rules(&mut self, rules: MethodRules) -> &mut Self {
+        self.options.method_rules = Some(rules);
+        self
+    }
+
+    /// Set how `HEAD` requests are rate limited, e.g. [`HeadRequests::Exempt`] so that the
+    /// `HEAD` requests of monitoring systems don't burn the quota of their clients.
+    /// Defaults to [`HeadRequests::Separate`].
+    pub const fn head_requests(&mut self, head_requests: HeadRequests) -> &mut Self {
+        self.options.head_requests = head_requests;
+        self
+    }
+
+    /// Limit the requests of every method of a group as if they were made with the first
+    /// method of the group, e.g. [`EQUIVALENT_METHODS`], so that clients can't double their
+    /// budget by alternating verbs limited by different [classes](Self::classify).
+    ///
+    /// This applies to the configured [`methods`](Self::methods), the
+    /// [`method_rules`](Self::method_rules) and the classes, after
+    /// [`head_requests`](Self::head_requests). A method is only grouped once, with the first
+    /// group it belongs to, and the groups replace the previous ones.
+    ///
+    /// # Example
+    /// ```rust
+    /// use http::Method;
+    /// use tower_governor::governor::{GovernorConfigBuilder, EQUIVALENT_METHODS};
+    ///
+    /// // `HEAD` requests are charged to the reads, `PATCH` requests to the writes
+    /// let config = GovernorConfigBuilder::default()
+    ///     .classify(|method, _, _| match *method {
+    ///         Method::GET => Some("read"),
+    ///         _ => Some("write"),
+    ///     })
+    ///     .group_methods(EQUIVALENT_METHODS)
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn group_methods<G>(&mut self, groups: impl IntoIterator<Item = G>) -> &mut Self
+    where
+        G: IntoIterator<Item = Method>,
+    {
+        let mut members: Vec<(Method, Method)> = Vec::new();
+        for group in groups {
+            let mut group = group.into_iter();
+            let Some(first) = group.next() else {
+                continue;
+            };
+            // the first method may belong to a previous group already
+            let first = members
+                .iter()
+                .find(|(member, _)| *member == first)
+                .map_or(first, |(_, grouped)| grouped.clone());
+            for method in group {
+                let grouped = members
+                    .iter()
+                    .any(|(member, first)| *member == method || *first == method);
+                if method != first && !grouped {
+                    members.push((method, first.clone()));
+                }
+            }
+        }
+        self.options.method_groups = (!members.is_empty()).then(|| members.into());
+        self
+    }
+
+    /// Set how upgrade requests such as WebSocket handshakes are rate limited, e.g.
+    /// [`UpgradeRequests::Cost`] to charge them more than plain requests. Defaults to
+    /// [`UpgradeRequests::Standard`].
+    pub const fn upgrade_requests(&mut self, upgrade_requests: UpgradeRequests) -> &mut Self {
+        self.options.upgrade_requests = upgrade_requests;
+        self
+    }
+
+    /// Set the header added to responses of requests that bypass the rate limiter, such as
+    /// requests whose method is not in [`methods`]. Pass `None` to not emit any header.
+    ///
+    /// Only used together with [`use_headers`], defaults to `x-ratelimit-whitelisted`.
+    ///
+    /// [`methods`]: Self::methods
+    /// [`use_headers`]: Self::use_headers
+    pub fn whitelisted_header(&mut self, header: Option<HeaderName>) -> &mut Self {
+        self.options.whitelisted_header = header;
+        self
+    }
+
+    /// Set the header listing the rate limited [`methods`] on rejections, e.g. the
+    /// [`METHODS_HEADER`], so that clients integrating against method scoped limits can tell
+    /// which of their requests count against the quota. Pass `None`, the default, to not emit
+    /// any header.
+    ///
+    /// The header is only added when [`methods`] are set, with the same format as `allow`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use http::Method;
+    /// use tower_governor::{governor::GovernorConfigBuilder, headers::METHODS_HEADER};
+    ///
+    /// // rejections carry `x-ratelimit-methods: POST, DELETE`
+    /// let config = GovernorConfigBuilder::default()
+    ///     .methods(vec![Method::POST, Method::DELETE])
+    ///     .methods_header(Some(METHODS_HEADER))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`methods`]: Self::methods
+    /// [`METHODS_HEADER`]: crate::headers::METHODS_HEADER
+    pub fn methods_header(&mut self, header: Option<HeaderName>) -> &mut Self {
+        self.options.methods_header = header;
+        self
+    }
+
+    /// Set the upper bound for the wait time reported to rate limited clients.
+    ///
+    /// Reported wait times are always clamped to the time needed to replenish the whole burst,
+    /// since anything above that points to a misbehaving clock source on the host.
+    /// Use this to report an even lower maximum.
+    pub const fn max_wait_time(&mut self, max: Duration) -> &mut Self {
+        self.options.max_wait_time = Some(max);
+        self
+    }
+
+    /// Set whether rejections carry the `retry-after` header, enabled by default. The
+    /// `x-ratelimit-after` header is sent either way.
+    ///
+    /// Disable it behind CDNs that treat `retry-after` specially, e.g. by caching the
+    /// `429 Too Many Requests` responses.
+    pub const fn retry_after(&mut self, enabled: bool) -> &mut Self {
+        self.options.retry_after = enabled;
+        self
+    }
+
+    /// Set the unit of the wait time of rejections, e.g. [`WaitTimeUnit::Milliseconds`] to
+    /// send `x-ratelimit-after-ms` instead of `x-ratelimit-after` to clients wanting precise
+    /// pushback. `retry-after` is always in seconds. Defaults to [`WaitTimeUnit::Seconds`].
+    pub const fn wait_time_unit(&mut self, unit: WaitTimeUnit) -> &mut Self {
+        self.options.wait_time_unit = unit;
+        self
+    }
+
+    /// Set the level of the events logged for rejections, `INFO` by default, e.g. `DEBUG` in
+    /// environments where rejections are routine. The events have the [`REJECTION_TARGET`]
+    /// target, and their level can be changed at runtime with
+    /// [`GovernorConfig::set_rejection_log_level`].
+    #[cfg(feature = "tracing")]
+    pub const fn rejection_log_level(&mut self, level: tracing::Level) -> &mut Self {
+        self.options.rejection_log_level = level;
+        self
+    }
+
+    /// Set whether rejections carry the `x-ratelimit-limit` header even without
+    /// [`use_headers`], disabled by default. The limit is the burst size of the quota, known
+    /// from the configuration, so this doesn't need the state of the keys.
+    ///
+    /// Has no effect with [`use_headers`], which adds it anyway, nor with
+    /// [`bare_responses`].
+    ///
+    /// [`use_headers`]: Self::use_headers
+    /// [`bare_responses`]: Self::bare_responses
+    pub const fn limit_on_rejections(&mut self, enabled: bool) -> &mut Self {
+        self.options.limit_on_rejections = enabled;
+        self
+    }
+
+    /// Let one in `one_in` of the requests of the keys over quota through at random, to keep
+    /// a trickle of traffic flowing for debugging, e.g. a blocked integration partner, without
+    /// lifting their limit. The sampled requests carry the [`Sampled`] extension and the
+    /// [`SAMPLED_HEADER`], without rate limiting headers. Banned keys are never sampled.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::num::NonZeroU32;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// // let 1% of the requests over quota through
+    /// GovernorConfigBuilder::default().sample_over_quota(NonZeroU32::new(100).unwrap());
+    /// ```
+    ///
+    /// [`Sampled`]: crate::decision::Sampled
+    /// [`SAMPLED_HEADER`]: crate::decision::SAMPLED_HEADER
+    pub const fn sample_over_quota(&mut self, one_in: NonZeroU32) -> &mut Self {
+        self.options.sample_over_quota = Some(one_in);
+        self
+    }
+
+    /// Add a random delay of up to `max` to the wait time advertised to rate limited clients,
+    /// so clients rejected at the same time don't all retry at the same instant. The
+    /// advertised wait time never drops below the actual one, and the jittered one is still
+    /// capped by [`max_wait_time`](Self::max_wait_time).
+    pub const fn retry_jitter(&mut self, max: Duration) -> &mut Self {
+        self.options.retry_jitter = Some(max);
+        self
+    }
+
+    /// Only decide whether requests exceed the quota instead of rejecting them.
+    ///
+    /// Every request is forwarded to the inner service with the [`Decision`] recorded in its
+    /// extensions and in the [`DECISION_HEADER`], which is useful for proxies that want to leave
+    /// the enforcement to the upstream service.
+    ///
+    /// [`Decision`]: crate::decision::Decision
+    /// [`DECISION_HEADER`]: crate::decision::DECISION_HEADER
+    pub const fn decide_only(&mut self, enabled: bool) -> &mut Self {
+        self.options.decide_only = enabled;
+        self
+    }
+
+    /// Only enforce the quota for a share of the keys, the other keys running as in
+    /// [`decide_only`](Self::decide_only) mode when exceeding it.
+    ///
+    /// Keys are assigned to the enforced cohort by hash, so a key stays in the same cohort
+    /// across requests and restarts. This allows ramping up a new policy gradually, e.g. from
+    /// `0.2` to `1.0`, while comparing the rejections of both cohorts through the
+    /// `x-ratelimit-decision` header or the audit events. `ratio` is clamped to `0.0..=1.0`.
+    ///
+    /// Below `1.0`, the responses to the requests subject to the quota carry the
+    /// [`MODE_HEADER`] so that clients can segment their telemetry by cohort.
+    ///
+    /// [`MODE_HEADER`]: crate::decision::MODE_HEADER
+    pub const fn enforce_ratio(&mut self, ratio: f64) -> &mut Self {
+        self.options.enforce_threshold = if ratio >= 1.0 {
+            None
+        } else {
+            Some((ratio.clamp(0.0, 1.0) * u64::MAX as f64) as u64)
+        };
+        self
+    }
+
+    /// Claim cells from the limiter in batches of `batch` and hand them out from
+    /// per-key caches, so that a single very hot key doesn't make every request
+    /// contend on the same bucket state.
+    ///
+    /// This trades some accuracy for throughput: the reported `x-ratelimit-remaining` is only
+    /// updated once per batch, and cells left unused for a replenish interval are dropped.
+    /// A `batch` of zero or one disables prefetching, which is the default.
+    ///
+    /// **The batch must not exceed the burst size.**
+    pub const fn prefetch(&mut self, batch: u32) -> &mut Self {
+        self.options.prefetch = batch;
+        self
+    }
+
+    /// Never rate limit requests from loopback addresses, such as local health probes.
+    ///
+    /// Only applies to key extractors exposing the client IP through [`KeyExtractor::key_ip`].
+    pub const fn exempt_loopback(&mut self, exempt: bool) -> &mut Self {
+        self.options.exempt_loopback = exempt;
+        self
+    }
+
+    /// Never rate limit requests from private address ranges (`10.0.0.0/8`, `172.16.0.0/12`,
+    /// `192.168.0.0/16` and `fc00::/7`), such as cluster-internal traffic.
+    ///
+    /// Only applies to key extractors exposing the client IP through [`KeyExtractor::key_ip`].
+    pub const fn exempt_private_ranges(&mut self, exempt: bool) -> &mut Self {
+        self.options.exempt_private_ranges = exempt;
+        self
+    }
+
+    /// Give the charge of allowed requests back when their response future is dropped before
+    /// the inner service responded, e.g. because the client disconnected.
+    ///
+    /// The refunded cells let the next requests of the key through once it exceeded its quota,
+    /// until the quota would have been replenished anyway. Only the quota of this configuration
+    /// is refunded, not those of the [classes](Self::class_quota) or the
+    /// [windows](Self::window_quota).
+    ///
+    /// Clients decide when their requests are cancelled, so a client dropping every request
+    /// right after the inner service started working on it would otherwise never be charged.
+    /// At most a burst is refunded to a key per period the whole quota takes to replenish,
+    /// which bounds such a client to twice its quota.
+    pub const fn refund_cancelled(&mut self) -> &mut Self {
+        self.options.refund_cancelled = true;
+        self
+    }
+
+    /// Allow reserving quota for long-running jobs with [`GovernorConfig::reserve`].
+    ///
+    /// The cost a [`Lease`](crate::lease::Lease) leaves unused is refunded as the charges of
+    /// [cancelled requests](Self::refund_cancelled) are.
+    pub const fn leases(&mut self) -> &mut Self {
+        self.options.leases = true;
+        self
+    }
+
+    /// Charge every request at most once to this configuration, even when layers built from
+    /// it are nested, e.g. applied both to a router and to some of its routes.
+    ///
+    /// The first layer marks the request in its extensions, and the nested layers forward it
+    /// untouched, leaving the rate limiting headers to the first one. The clones of the
+    /// configuration share the mark, while the configurations derived from it with
+    /// [`with_quota`](GovernorConfig::with_quota), [`scoped`](GovernorConfig::scoped) or
+    /// [`with_key_extractor`](GovernorConfig::with_key_extractor) still charge the request.
+    pub const fn charge_once(&mut self) -> &mut Self {
+        self.options.charge_once = true;
+        self
+    }
+
+    /// Only charge requests whose response `is_failure` against the quota.
+    ///
+    /// Requests of a key that exceeded its quota are rejected until the quota is replenished.
+    /// Errors of the inner service are always charged. With [`use_headers`], the allowed
+    /// requests carry the `x-ratelimit-limit` header but not `x-ratelimit-remaining`, as they
+    /// are only charged once their response is known.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// // throttle clients producing too many client errors, e.g. failed logins
+    /// GovernorConfigBuilder::default()
+    ///     .charge_only(|response| response.status().is_client_error());
+    /// ```
+    ///
+    /// [`use_headers`]: Self::use_headers
+    pub fn charge_only<F>(&mut self, is_failure: F) -> &mut Self
+    where
+        F: Fn(&Response<Body>) -> bool + Send + Sync + 'static,
+    {
+        self.options.charge_filter = Some(ResponseFilter(Arc::new(is_failure)));
+        self
+    }
+
+    /// Send the `x-ratelimit-limit` and, with [`use_headers`], `x-ratelimit-remaining`
+    /// metadata of allowed gRPC requests as trailers, after the last message of the response.
+    ///
+    /// gRPC clients such as tonic's only expose the trailers of streaming responses once the
+    /// stream ended, which is where they also read the status from.
+    ///
+    /// [`use_headers`]: Self::use_headers
+    #[cfg(feature = "grpc")]
+    pub const fn use_trailers(&mut self, enable: bool) -> &mut Self {
+        self.options.trailers = enable;
+        self
+    }
+
+    /// Clean up the limiter stores with [`GovernorConfig::retain_recent`] as soon as they hold
+    /// `high` keys together, instead of only from a periodic task, so that memory stays bounded
+    /// during sudden spikes of key cardinality.
+    ///
+    /// The cleanup runs on the request that reached the mark. When it can't bring the store
+    /// below `low` keys, because most keys are still in use, the next cleanup waits until
+    /// `high - low` more keys were added. `low` is capped to `high`.
+    pub const fn retain_watermarks(&mut self, high: usize, low: usize) -> &mut Self {
+        self.options.retain_watermarks = Some((high, low));
+        self
+    }
+
+    /// Count the requests and rejections of every key over rolling windows of `window`, to
+    /// be exported with [`GovernorConfig::export_report`].
+    pub const fn report_window(&mut self, window: Duration) -> &mut Self {
+        self.options.report_window = Some(window);
+        self
+    }
+
+    /// Record the recent arrivals of every key, up to [`MAX_ARRIVALS`] per key, to have
+    /// [`GovernorConfig::suggest_quota`] derive a quota from the traffic.
+    ///
+    /// Meant to run for a while, e.g. with [`decide_only`] on a new route, before settling on
+    /// a quota: recording takes a lock on every request.
+    ///
+    /// [`MAX_ARRIVALS`]: crate::report::MAX_ARRIVALS
+    /// [`decide_only`]: Self::decide_only
+    pub const fn analyze_traffic(&mut self) -> &mut Self {
+        self.options.analyze_traffic = true;
+        self
+    }
+
+    /// Count the allowed and rejected requests over the trailing 1, 5 and 15 minutes, to be
+    /// read with [`GovernorConfig::rates`], e.g. for autoscaling or alerting decisions.
+    pub const fn track_rates(&mut self) -> &mut Self {
+        self.options.track_rates = true;
+        self
+    }
+
+    /// Count the allowed and rejected requests of every route over the trailing 1, 5 and 15
+    /// minutes, to be read with [`GovernorConfig::route_rates`], e.g. to export per endpoint
+    /// rejection rates.
+    ///
+    /// Requests are counted under the route axum matched them to, as found in their
+    /// `MatchedPath` extension, rather than under their path, so that the number of routes
+    /// stays bounded. Requests without one, such as those of the fallback or of a layer
+    /// added before routing, are counted under [`UNMATCHED_ROUTE`].
+    ///
+    /// Without the `axum` feature every request is counted under [`UNMATCHED_ROUTE`].
+    ///
+    /// [`UNMATCHED_ROUTE`]: crate::report::UNMATCHED_ROUTE
+    pub const fn track_route_rates(&mut self) -> &mut Self {
+        self.options.track_route_rates = true;
+        self
+    }
+
+    /// Count the allowed and rejected requests of every [cohort](crate::key_extractor::Cohorts)
+    /// of keys over the trailing 1, 5 and 15 minutes, to be read with
+    /// [`GovernorConfig::cohort_rates`], e.g. to export rejection rates labeled by cohort.
+    ///
+    /// The keys without a cohort are counted under [`DEFAULT_COHORT`].
+    ///
+    /// [`DEFAULT_COHORT`]: crate::report::DEFAULT_COHORT
+    pub const fn track_cohort_rates(&mut self) -> &mut Self {
+        self.options.track_cohort_rates = true;
+        self
+    }
+
+    /// Count the responses of the allowed requests of every class with a
+    /// [quota of its own](Self::class_quota) by status, to be read with
+    /// [`GovernorConfig::status_counts`], e.g. to tell whether the throttled clients are also
+    /// those causing server errors.
+    pub const fn track_statuses(&mut self) -> &mut Self {
+        self.options.track_statuses = true;
+        self
+    }
+
+    /// Record the last `capacity` decisions, to be read with [`GovernorConfig::replay`], e.g.
+    /// to check a client's claim that it wasn't over the limit. Disabled by default.
+    pub const fn replay_log(&mut self, capacity: usize) -> &mut Self {
+        self.options.replay_capacity = capacity;
+        self
+    }
+
+    /// Keep track of the quota state of every key, so that it can be saved with
+    /// [`GovernorConfig::save_state`] and restored with [`GovernorConfig::restore_from`],
+    /// e.g. so that restarts don't grant every client a fresh burst.
+    ///
+    /// Only the quota of the configuration is tracked, not those of the [classes], nor the
+    /// requests charged by [`charge_only`].
+    ///
+    /// [classes]: Self::class_quota
+    /// [`charge_only`]: Self::charge_only
+    pub const fn track_state(&mut self) -> &mut Self {
+        self.options.track_state = true;
+        self
+    }
+
+    /// Add the [`WINDOW_START_HEADER`] to the allowed responses, holding when the current
+    /// usage cycle of the key started as seconds since the Unix epoch: its first request since
+    /// its quota was last full. This enables [`track_state`](Self::track_state).
+    ///
+    /// The requests of the [classes](Self::class_quota) aren't tracked, so they only carry the
+    /// header once their key made a request under the quota of the configuration.
+    ///
+    /// [`WINDOW_START_HEADER`]: crate::headers::WINDOW_START_HEADER
+    pub const fn window_start_header(&mut self) -> &mut Self {
+        self.options.window_start_header = true;
+        self
+    }
+
+    /// Split requests into classes with quotas of their own, e.g. for reads and writes, each
+    /// key having a separate bucket per class.
+    ///
+    /// `classify` names the class of a request from its method, URI and headers. Requests of
+    /// no class, or of a class without a [`class_quota`], use the quota of this configuration.
+    /// The class is reported in the `x-ratelimit-class` header of rejections, of allowed
+    /// requests with [`use_headers`], and in tracing.
+    ///
+    /// # Example
+    /// ```rust
+    /// use http::Method;
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .classify(|method, _uri, _headers| match *method {
+    ///         Method::GET | Method::HEAD => Some("read"),
+    ///         _ => Some("write"),
+    ///     })
+    ///     // 100 reads per second
+    ///     .class_quota("read", Duration::from_millis(10), 100)
+    ///     // 10 writes per second
+    ///     .class_quota("write", Duration::from_millis(100), 10)
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`class_quota`]: Self::class_quota
+    /// [`use_headers`]: Self::use_headers
+    pub fn classify<F>(&mut self, classify: F) -> &mut Self
+    where
+        F: Fn(&Method, &Uri, &HeaderMap) -> Option<&'static str> + Send + Sync + 'static,
+    {
+        self.options.classifier = Some(Classifier(Arc::new(classify)));
+        self
+    }
+
+    /// Give the requests of class `name` a quota of their own, replenishing one element every
+    /// `period` with bursts of up to `burst_size` requests, see [`classify`](Self::classify).
+    pub fn class_quota(
+        &mut self,
+        name: &'static str,
+        period: Duration,
+        burst_size: u32,
+    ) -> &mut Self {
+        self.options
+            .class_quotas
+            .retain(|(class, ..)| *class != name);
+        self.options.class_quotas.push((name, period, burst_size));
+        self
+    }
+
+    /// Cache the class of every key for `ttl`, so that an expensive [`classify`], e.g. matching
+    /// user agents with regular expressions, only runs once per key and time to live.
+    ///
+    /// The later requests of a key get the class of its first request, whatever their method,
+    /// URI and headers, so this only suits classifiers naming the class of the client, not of
+    /// the request: not the one of [`write_quota_percent`].
+    ///
+    /// [`classify`]: Self::classify
+    /// [`write_quota_percent`]: Self::write_quota_percent
+    pub const fn cache_classes(&mut self, ttl: Duration) -> &mut Self {
+        self.options.class_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Give the requests of mutating methods `write_percent` percent of the quota of the
+    /// configuration, between 1 and 100, in a [class](Self::classify) of their own.
+    ///
+    /// This classifies the `GET`, `HEAD`, `OPTIONS` and `TRACE` requests as `read`, keeping the
+    /// quota of the configuration, and the others as `write`, replacing the previous
+    /// [`classify`](Self::classify). The `write` quota is derived from the quota of the
+    /// configuration once built, unless set with [`class_quota`](Self::class_quota): its burst
+    /// size and its rate are `write_percent` percent of those of the configuration.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// // 100 reads per second, bursting to 50, and 10 writes per second, bursting to 5
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_millisecond(10)
+    ///     .burst_size(50)
+    ///     .write_quota_percent(10)
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn write_quota_percent(&mut self, write_percent: u32) -> &mut Self {
+        self.options.write_percent = Some(write_percent.clamp(1, 100));
+        self.classify(|method, _, _| match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE => Some("read"),
+            _ => Some("write"),
+        })
+    }
+
+    /// Share a quota of all the requests, replenishing one element every `period` with bursts
+    /// of up to `burst_size` requests, between the [classes](Self::classify) of `shares` by
+    /// weight, on top of the quotas of the keys.
+    ///
+    /// Every class is guaranteed its weighted part of the quota, e.g. 70% of the requests for
+    /// `("partner", 7)` along with `("public", 3)`, and borrows whatever the other classes
+    /// leave unused. Requests of other classes only get to borrow. The rejections of the
+    /// shared quota carry the [`use_headers`] of the share of the class: its part of the burst
+    /// size as limit, and its name as class and scope.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .classify(|_, _, headers| match headers.contains_key("x-partner-key") {
+    ///         true => Some("partner"),
+    ///         false => Some("public"),
+    ///     })
+    ///     .fair_share(Duration::from_millis(10), 100, [("partner", 7), ("public", 3)])
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`use_headers`]: Self::use_headers
+    pub fn fair_share(
+        &mut self,
+        period: Duration,
+        burst_size: u32,
+        shares: impl IntoIterator<Item = (&'static str, u32)>,
+    ) -> &mut Self {
+        self.options.fair_share = Some((period, burst_size, shares.into_iter().collect()));
+        self
+    }
+
+    /// Add a quota every key must also stay within, replenishing one element every `period`
+    /// with bursts of up to `burst_size` requests, e.g. 1000 requests per hour on top of 10
+    /// per second.
+    ///
+    /// The windows are checked in the order they were added, once a request conforms to the
+    /// quota of its key or of its class. A request exceeding one of them is rejected with the
+    /// [`use_headers`] of that window: its burst size as limit and `name` as scope, after the
+    /// [policy name](Self::policy_name) if any. The requests allowed with [`use_headers`] list
+    /// the quota of the request and every window in the [`POLICY_HEADER`] and the
+    /// [`RATELIMIT_HEADER`], so that clients see the budget left in each of them.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .burst_size(10)
+    ///     .window_quota("hour", Duration::from_secs(3600) / 1000, 1000)
+    ///     .use_headers()
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`use_headers`]: Self::use_headers
+    /// [`POLICY_HEADER`]: crate::headers::POLICY_HEADER
+    /// [`RATELIMIT_HEADER`]: crate::headers::RATELIMIT_HEADER
+    pub fn window_quota(
+        &mut self,
+        name: &'static str,
+        period: Duration,
+        burst_size: u32,
+    ) -> &mut Self {
+        self.options
+            .window_quotas
+            .retain(|(window, ..)| *window != name);
+        self.options.window_quotas.push((name, period, burst_size));
+        self
+    }
+
+    /// Allow every key exactly `max_requests` within any `window`, e.g. 3 password reset emails
+    /// per 24 hours, rather than the approximation of a [window quota](Self::window_quota).
+    ///
+    /// The times of the last `max_requests` allowed requests of every key are logged, so this
+    /// suits low volume endpoints: the memory grows with the number of keys times
+    /// `max_requests`. The log is checked once a request conforms to the quota of its key and
+    /// to the windows, and a request it rejects waits until the oldest logged request leaves
+    /// the window. Set a quota at least as generous as the log for the log alone to bind.
+    ///
+    /// Fails to build if `max_requests` or `window` is zero.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .burst_size(3)
+    ///     .sliding_log(3, Duration::from_secs(24 * 3600))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub const fn sliding_log(&mut self, max_requests: u32, window: Duration) -> &mut Self {
+        self.options.sliding_log = Some((max_requests, window));
+        self
+    }
+
+    /// Share the quota of every key with the other processes of the host through the memory
+    /// mapped file at `path`, e.g. the workers of a server listening with `SO_REUSEPORT`, so
+    /// that they enforce one combined limit without a network hop.
+    ///
+    /// The file holds `slots` keys, created by the first process and reused by the next ones,
+    /// which must use the same quota and number of slots. Keys that replenished their whole
+    /// quota give their slot up, and a key finding no free slot among the few it hashes to
+    /// shares the quota of another one, so size `slots` after the number of keys active at
+    /// once. The keys are identified by a hash that only matches across processes built by
+    /// the same compiler.
+    ///
+    /// The shared quota is checked once a request conforms to the quota of the process, which
+    /// the [`use_headers`](Self::use_headers) keep describing. Fails to build if the file
+    /// can't be mapped or was created for another quota.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .burst_size(10)
+    ///     .shared_memory("/dev/shm/api-governor", 65_536)
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "shared-memory")]
+    pub fn shared_memory(&mut self, path: impl Into<PathBuf>, slots: usize) -> &mut Self {
+        self.options.shared_memory = Some((path.into(), slots));
+        self
+    }
+
+    /// Reject the requests of peer IPs that used more than `max_keys` distinct keys within
+    /// `window` until the window ends, e.g. to stop API keys from being enumerated when
+    /// rate limiting by API key.
+    ///
+    /// The peer IP is the one of the connection, as used by [`PeerIpKeyExtractor`]. Requests
+    /// without one are not limited. See [`on_key_churn`] to observe the offending peers.
+    ///
+    /// [`on_key_churn`]: Self::on_key_churn
+    pub const fn limit_keys_per_peer(&mut self, max_keys: u32, window: Duration) -> &mut Self {
+        self.options.key_churn_limit = Some((max_keys, window));
+        self
+    }
+
+    /// Call `observer` whenever a peer IP is rejected for using too many distinct keys, see
+    /// [`limit_keys_per_peer`](Self::limit_keys_per_peer).
+    pub fn on_key_churn<F>(&mut self, observer: F) -> &mut Self
+    where
+        F: Fn(IpAddr) + Send + Sync + 'static,
+    {
+        self.options.churn_observer = Some(ChurnObserver(Arc::new(observer)));
+        self
+    }
+
+    /// Ban the keys rejected for `threshold` or longer for `duration`, instead of advertising
+    /// wait times that clients are likely to ignore. Banned keys get a `403 Forbidden`
+    /// response with a `retry-after` of the time left until the ban ends, without being
+    /// checked against the limiter.
+    ///
+    /// The wait time is compared after being capped by [`max_wait_time`], which must thus be
+    /// longer than `threshold` for bans to happen. See [`on_ban`] to observe the banned keys.
+    ///
+    /// The keys whose rejections aren't enforced, such as with [`decide_only`], are never banned.
+    ///
+    /// [`max_wait_time`]: Self::max_wait_time
+    /// [`on_ban`]: Self::on_ban
+    /// [`decide_only`]: Self::decide_only
+    pub const fn ban_above(&mut self, threshold: Duration, duration: Duration) -> &mut Self {
+        self.options.ban_escalation = Some((threshold, duration));
+        self
+    }
+
+    /// Let keys be banned by hand with [`GovernorConfig::ban`], on top of those banned by
+    /// [`ban_above`](Self::ban_above) if set, e.g. to block an abusive client from an admin
+    /// endpoint. The bans can be saved and restored across restarts with
+    /// [`GovernorConfig::save_bans`] and [`GovernorConfig::restore_bans`].
+    pub const fn ban_list(&mut self) -> &mut Self {
+        self.options.ban_list = true;
+        self
+    }
+
+    /// Ban the keys whose requests get `max_failures` responses with the status
+    /// `401 Unauthorized` or `403 Forbidden` from the inner service within `window`, for
+    /// `ban`, as a defense against brute-forcing credentials.
+    ///
+    /// The failures are counted on the allowed requests, whatever their quota, and the banned
+    /// keys get a `403 Forbidden` response as with [`ban_above`](Self::ban_above).
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// // 10 failed logins within a minute lock the client out for 15 minutes
+    /// GovernorConfigBuilder::default().limit_auth_failures(
+    ///     10,
+    ///     Duration::from_secs(60),
+    ///     Duration::from_secs(900),
+    /// );
+    /// ```
+    pub const fn limit_auth_failures(
+        &mut self,
+        max_failures: u32,
+        window: Duration,
+        ban: Duration,
+    ) -> &mut Self {
+        self.options.auth_failure_limit = Some((max_failures, window, ban));
+        self
+    }
+
+    /// Multiply the wait time of the keys rejected again and again by `factor` at every
+    /// consecutive rejection, up to `cap`, so that persistent abusers back off faster than
+    /// the quota alone dictates. The longer wait time is both advertised and enforced: the
+    /// key is rejected without being checked against the limiter until it elapsed.
+    ///
+    /// Every allowed request halves the count of consecutive rejections of its key, and keys
+    /// not rejected for `cap` after their last penalty start over.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// // wait times of 1s, 2s, 4s, 8s... up to 5 minutes
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .penalize_violations(2, Duration::from_secs(300))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub const fn penalize_violations(&mut self, factor: u32, cap: Duration) -> &mut Self {
+        self.options.penalty_escalation = Some((factor, cap));
+        self
+    }
+
+    /// Call `observer` whenever a key gets banned, see [`ban_above`](Self::ban_above). The
+    /// `wait_time` of the [`RejectionContext`] is the duration of the ban.
+    ///
+    /// [`RejectionContext`]: crate::decision::RejectionContext
+    pub fn on_ban<F>(&mut self, observer: F) -> &mut Self
+    where
+        F: Fn(&RejectionContext<'_>) + Send + Sync + 'static,
+    {
+        self.options.ban_observer = Some(BanObserver(Arc::new(observer)));
+        self
+    }
+
+    /// Call `hook` with the `429 Too Many Requests` response of every rejected request, once
+    /// built by the [`error_handler`](Self::error_handler), along with the
+    /// [`RejectionContext`] of the request.
+    ///
+    /// This allows decorating the rejections, e.g. with a correlation ID, without rewriting
+    /// the whole error handler.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// GovernorConfigBuilder::default().on_rejection(|response, context| {
+    ///     if let Some(id) = context.headers.get("x-request-id") {
+    ///         response.headers_mut().insert("x-request-id", id.clone());
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`RejectionContext`]: crate::decision::RejectionContext
+    pub fn on_rejection<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut Response<Body>, &RejectionContext<'_>) + Send + Sync + 'static,
+    {
+        self.options.rejection_hook = Some(RejectionHook(Arc::new(hook)));
+        self
+    }
+
+    /// Call `hook` with the response of every request the limiter decided on, allowed or
+    /// rejected, along with its [`RateLimitSnapshot`], once the rate limiting headers were
+    /// added.
+    ///
+    /// This allows adding headers or cookies of your own based on the decision without
+    /// rewriting the header logic, e.g. to warn clients close to their limit.
+    /// The allowed requests of configurations with [`bare_responses`](Self::bare_responses)
+    /// aren't decided on with a snapshot, so their responses are left untouched.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// use http::HeaderValue;
+    ///
+    /// GovernorConfigBuilder::default().map_response(|response, snapshot| {
+    ///     if snapshot.decision.is_allowed() && snapshot.remaining == Some(0) {
+    ///         let warning = HeaderValue::from_static("throttled-soon");
+    ///         response.headers_mut().insert("x-quota-warning", warning);
+    ///     }
+    /// });
+    /// ```
+    pub fn map_response<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut Response<Body>, &RateLimitSnapshot) + Send + Sync + 'static,
+    {
+        self.options.response_mapper = Some(ResponseMapper(Arc::new(hook)));
+        self
+    }
+
+    /// Trust the [`EDGE_LIMITED_HEADER`] of requests already rate limited by an edge gateway,
+    /// handling them as told by `action` when `verify` accepts the value of the header along
+    /// with the URI of the request. Requests whose marker is rejected are rate limited as
+    /// usual.
+    ///
+    /// `verify` should check a signature, e.g. an HMAC shared with the gateway, as clients can
+    /// set the header too.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::{EdgeLimited, GovernorConfigBuilder};
+    /// # fn hmac_matches(signature: &[u8], path: &str) -> bool { false }
+    /// GovernorConfigBuilder::default().trust_edge_marker(EdgeLimited::Bypass, |value, uri| {
+    ///     hmac_matches(value.as_bytes(), uri.path())
+    /// });
+    /// ```
+    pub fn trust_edge_marker<F>(&mut self, action: EdgeLimited, verify: F) -> &mut Self
+    where
+        F: Fn(&HeaderValue, &Uri) -> bool + Send + Sync + 'static,
+    {
+        self.options.edge_marker = Some(EdgeMarker {
+            action,
+            verify: Arc::new(verify),
+        });
+        self
+    }
+
+    /// Charge every request the number of elements of the quota `cost` tells, e.g. the
+    /// number of tokens an LLM-style API call may produce. Costs of zero are charged one
+    /// element, costs above the burst size the whole burst.
+    ///
+    /// Meant for [`credits`](Self::credits) quotas, see the [`credits`](crate::credits)
+    /// module for an example.
+    pub fn request_cost<F>(&mut self, cost: F) -> &mut Self
+    where
+        F: Fn(&CostContext<'_>) -> u32 + Send + Sync + 'static,
+    {
+        self.options.request_cost = Some(RequestCost(Arc::new(cost)));
+        self
+    }
+
+    /// Hand the [`RateLimitSnapshot`] of allowed requests to `hook` when the inner service
+    /// fails, as the error of the inner service can't carry the rate limiting headers.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// GovernorConfigBuilder::default().on_inner_error(|snapshot| {
+    ///     eprintln!("inner service failed with {:?} requests left", snapshot.remaining);
+    /// });
+    /// ```
+    pub fn on_inner_error<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&RateLimitSnapshot) + Send + Sync + 'static,
+    {
+        self.options.inner_error_hook = Some(InnerErrorHook(Arc::new(hook)));
+        self
+    }
+
+    /// Only charge requests which `classifier` marks as failures against the quota,
+    /// see [`charge_only`].
+    ///
+    /// Responses that can only be classified at the end of their stream, such as gRPC
+    /// responses carrying their status in trailers, are not charged.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use tower_governor::governor::GovernorConfigBuilder;
+    /// use tower_http::classify::{GrpcCode, GrpcErrorsAsFailures};
+    ///
+    /// GovernorConfigBuilder::default()
+    ///     .charge_failures_only(GrpcErrorsAsFailures::new().with_success(GrpcCode::NotFound));
+    /// ```
+    ///
+    /// [`charge_only`]: Self::charge_only
+    #[cfg(feature = "tower-http")]
+    pub fn charge_failures_only<R>(&mut self, classifier: R) -> &mut Self
+    where
+        R: tower_http::classify::ClassifyResponse + Clone + Send + Sync + 'static,
+    {
+        use tower_http::classify::ClassifiedResponse;
+
+        self.charge_only(move |response| {
+            matches!(
+                classifier.clone().classify_response(response),
+                ClassifiedResponse::Ready(Err(_))
+            )
+        })
+    }
+
+    /// Forward every request without charging it, until the inner service fails, and only
+    /// then apply the quota, as an emergency brake rather than a day-to-day limit, e.g. with
+    /// the [`GlobalKeyExtractor`] and a strict quota.
+    ///
+    /// The breaker engages once server errors and errors of the inner service make up
+    /// `engage` of at least 20 responses within `window`, and disengages once they made up
+    /// less than `disengage` of the responses of a whole `window`. Keeping `disengage` below
+    /// `engage` keeps the breaker from flapping. Both ratios are clamped to `0.0..=1.0`, and
+    /// `disengage` to `engage`.
+    ///
+    /// The responses of the requests forwarded by the breaker carry no rate limiting header.
+    /// The [banned](Self::ban_list) keys stay banned, at the cost of extracting the key of
+    /// every request while the breaker is disengaged.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor};
+    ///
+    /// // 100 requests per second at most while over half the responses fail
+    /// let config = GovernorConfigBuilder::default()
+    ///     .key_extractor(GlobalKeyExtractor)
+    ///     .per_millisecond(10)
+    ///     .burst_size(100)
+    ///     .circuit_breaker(0.5, 0.1, Duration::from_secs(10))
+    ///     .finish()
+    ///     .unwrap();
+    /// assert!(!config.circuit_breaker_engaged());
+    /// ```
+    pub const fn circuit_breaker(
+        &mut self,
+        engage: f64,
+        disengage: f64,
+        window: Duration,
+    ) -> &mut Self {
+        let parts = breaker::PARTS as f64;
+        self.options.circuit_breaker = Some((
+            (engage.clamp(0.0, 1.0) * parts) as u32,
+            (disengage.clamp(0.0, 1.0) * parts) as u32,
+            window,
+        ));
+        self
+    }
+
+    /// Only enforce a share of the quota on this instance, so that `instances` replicas
+    /// behind a sticky load balancer approximately enforce the quota together, without a
+    /// shared store.
+    ///
+    /// Each request costs as many elements of the quota as there are instances, up to the
+    /// burst size. Keep the burst size a multiple of the largest expected instance count.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{governor::GovernorConfigBuilder, partition::Instances};
+    ///
+    /// let instances = Instances::new(3);
+    /// let config = GovernorConfigBuilder::default()
+    ///     .burst_size(30)
+    ///     .partition(instances.clone())
+    ///     .finish()
+    ///     .unwrap();
+    /// // later on, once the autoscaler added a replica
+    /// instances.set(4);
+    /// ```
+    pub fn partition(&mut self, instances: Instances) -> &mut Self {
+        self.options.instances = Some(instances);
+        self
+    }
+
+    /// Reuse the key extracted for the first request of a connection for all the following
+    /// ones, skipping extraction on keep-alive connections.
+    ///
+    /// Only applies to requests carrying a [`ConnectionKeyCache`] in their extensions.
+    /// [`try_finish`](Self::try_finish) fails with [`ConfigError::UnstableConnectionKeys`]
+    /// unless the keys of the key extractor are
+    /// [stable within a connection](KeyExtractor::connection_stable), as those of the
+    /// [`PeerIpKeyExtractor`] are.
+    pub const fn cache_connection_keys(&mut self, enabled: bool) -> &mut Self {
+        self.options.cache_connection_keys = enabled;
+        self
+    }
+
+    /// Answer the requests of a peer IP whose key extraction failed with the same error for
+    /// `ttl`, without extracting their key again, e.g. when a client floods the service with
+    /// garbage `Authorization` headers.
+    ///
+    /// The cached error answers as many requests as the burst size, further requests get a
+    /// `429 Too Many Requests` until the failure expires. As the failure applies to the whole
+    /// peer IP, keep `ttl` short when clients share an IP address, such as behind a NAT.
+    ///
+    /// The failures of requests forwarded on behalf of another client, as told by the
+    /// forwarding headers, aren't cached: the peer is a proxy whose other clients would get
+    /// the error too, and the headers can be forged to blame another client.
+    pub const fn cache_extraction_failures(&mut self, ttl: Duration) -> &mut Self {
+        self.options.extraction_failure_ttl = Some(ttl);
+        self
+    }
+
+    /// Remember for `ttl` the peer IPs found [exempt](Self::exempt_loopback), letting their
+    /// following requests bypass the rate limiter before their key is extracted, so that
+    /// high-volume internal traffic costs next to nothing.
+    ///
+    /// Only applies to the [connection stable](KeyExtractor::connection_stable) key extractors,
+    /// such as [`PeerIpKeyExtractor`], without [`detect_proxy_misconfiguration`]: other
+    /// extractors may key the next requests of an exempt peer by the forwarding headers, so
+    /// a trusted proxy forwarding the requests of other clients isn't exempt itself.
+    ///
+    /// [`detect_proxy_misconfiguration`]: Self::detect_proxy_misconfiguration
+    pub const fn cache_exemptions(&mut self, ttl: Duration) -> &mut Self {
+        self.options.exemption_ttl = Some(ttl);
+        self
+    }
+
+    /// Let the requests matching `matcher` bypass the rate limiter, on top of the previous
+    /// exemptions.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{governor::GovernorConfigBuilder, matcher::RequestMatcher};
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .exempt(RequestMatcher::new().path("/metrics"))
+    ///     .exempt(RequestMatcher::new().path("/assets/*"))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn exempt(&mut self, matcher: RequestMatcher) -> &mut Self {
+        self.options.exemptions.push(matcher);
+        self
+    }
+
+    /// The recommended setup of browser-facing apps: CORS preflights, `HEAD` requests and the
+    /// `GET` requests of the [`STATIC_ASSET_EXTENSIONS`] bypass the rate limiter, so that
+    /// throttling doesn't break the frontend, and the [rate limiting headers](Self::use_headers)
+    /// are enabled.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .burst_size(30)
+    ///     .web_defaults()
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [`STATIC_ASSET_EXTENSIONS`]: crate::matcher::STATIC_ASSET_EXTENSIONS
+    pub fn web_defaults(&mut self) -> GovernorConfigBuilder<K, StateInformationMiddleware, C> {
+        let assets = RequestMatcher::new()
+            .methods([Method::GET])
+            .extensions(STATIC_ASSET_EXTENSIONS);
+        self.exempt_preflight(true)
+            .head_requests(HeadRequests::Exempt)
+            .exempt(assets)
+            .use_headers()
+    }
+
+    /// Let the requests of the [`HEALTH_CHECK_AGENTS`] bypass the rate limiter, e.g. when the
+    /// probes hit `/` and can't be told apart by their path.
+    ///
+    /// The `user-agent` header is trivially spoofed: only use this when the clients able to
+    /// send it can't exhaust the service, e.g. behind a load balancer that sets it, or
+    /// along with a key extractor exempting the network of the probes.
+    pub fn exempt_health_checks(&mut self) -> &mut Self {
+        self.exempt_user_agents(HEALTH_CHECK_AGENTS)
+    }
+
+    /// Let the requests whose `user-agent` starts with one of `agents` bypass the rate
+    /// limiter, replacing the previous ones. Off by default, see
+    /// [`exempt_health_checks`](Self::exempt_health_checks).
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::{GovernorConfigBuilder, HEALTH_CHECK_AGENTS};
+    ///
+    /// // e.g. read from the settings of the deployment
+    /// let agents = vec!["Consul Health Check".to_owned()];
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .exempt_user_agents(HEALTH_CHECK_AGENTS.into_iter().map(String::from).chain(agents))
+    ///     .finish()
+    ///     .unwrap();
+    /// ```
+    pub fn exempt_user_agents(
+        &mut self,
+        agents: impl IntoIterator<Item = impl Into<Arc<str>>>,
+    ) -> &mut Self {
+        let agents: Arc<[Arc<str>]> = agents.into_iter().map(Into::into).collect();
+        self.options.health_check_agents = (!agents.is_empty()).then_some(agents);
+        self
+    }
+
+    /// Set whether CORS preflight requests, `OPTIONS` requests carrying an
+    /// `Access-Control-Request-Method` header, bypass the rate limiter. Browsers report
+    /// throttled preflights as opaque CORS failures.
+    ///
+    /// Enabled by default unless [`methods`](Self::methods) are set.
+    pub const fn exempt_preflight(&mut self, exempt: bool) -> &mut Self {
+        self.options.exempt_preflight = Some(exempt);
+        self
+    }
+
+    /// Detect at runtime that the app is deployed behind a reverse proxy while keying clients
+    /// by the peer IP, which rate limits all of them together.
+    ///
+    /// The configuration is flagged once the same peer IP sent `threshold` requests within
+    /// `window`, each carrying forwarding headers that name another client. This logs an error
+    /// with the `tracing` feature, and can be checked with
+    /// [`GovernorConfig::proxy_misconfiguration_detected`]. Only applies to key extractors
+    /// deriving keys from IP addresses.
+    pub const fn detect_proxy_misconfiguration(
+        &mut self,
+        threshold: u32,
+        window: Duration,
+    ) -> &mut Self {
+        self.options.proxy_check = Some((threshold, window));
+        self
+    }
+
+    /// Once a peer [trusted](Trust) by `trust` was [detected] forwarding requests, key its
+    /// requests by the client IP named by the `x-forwarded-for`, `x-real-ip` or `forwarded`
+    /// headers, as the [SmartIpKeyExtractor] would.
+    ///
+    /// The requests of the other peers keep being keyed by the peer IP, and the keys taken
+    /// from the headers are never [exempt](Self::exempt_loopback), as clients can forge them.
+    /// **Avoid [`Trust::Any`], which lets any client get detected and pick its own keys.**
+    ///
+    /// [detected]: Self::detect_proxy_misconfiguration
+    /// [SmartIpKeyExtractor]: crate::key_extractor::SmartIpKeyExtractor
+    pub fn proxy_fallback(&mut self, trust: Trust) -> &mut Self {
+        self.options.proxy_fallback = Some(trust);
+        self
+    }
+
+    /// Name this policy, e.g. after the routes it protects. The name is part of the events
+    /// recorded by the [`audit_sink`].
+    ///
+    /// [`audit_sink`]: Self::audit_sink
+    pub fn policy_name(&mut self, name: impl Into<Arc<str>>) -> &mut Self {
+        self.options.policy_name = Some(name.into());
+        self
+    }
+
+    /// Record a [`GovernorEvent`] for every request checked against the limiter. Requests
+    /// bypassing the limiter, such as exempt or unconfigured methods, are not recorded.
+    ///
+    /// [`GovernorEvent`]: crate::audit::GovernorEvent
+    #[cfg(feature = "audit")]
+    pub fn audit_sink(&mut self, sink: AuditSink) -> &mut Self {
+        self.options.audit_sink = Some(sink);
+        self
+    }
+
+    /// Make [`try_finish`] fail with [`ConfigError::ImplicitKeyExtractor`] unless a key
+    /// extractor was set through [`key_extractor`].
+    ///
+    /// The default [PeerIpKeyExtractor] rate limits all clients together when the app is
+    /// deployed behind a reverse proxy, this guards against shipping it by accident.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor};
+    ///
+    /// let mut builder = GovernorConfigBuilder::default();
+    /// builder.require_explicit_key_extractor();
+    /// assert!(builder.try_finish().is_err());
+    /// assert!(builder.key_extractor(SmartIpKeyExtractor).try_finish().is_ok());
+    /// ```
+    ///
+    /// [`try_finish`]: Self::try_finish
+    /// [`key_extractor`]: Self::key_extractor
+    pub const fn require_explicit_key_extractor(&mut self) -> &mut Self {
+        self.options.require_explicit_key_extractor = true;
+        self
+    }
+
+    /// Namespace the keys of this configuration by `name`, wrapping the key extractor into
+    /// [`Scoped`].
+    ///
+    /// Further namespaces sharing the limiter store, and thus its cleanup and its
+    /// [`len`](governor::RateLimiter::len), are derived from the resulting configuration with
+    /// [`GovernorConfig::scoped`].
+    pub fn namespace(
+        &mut self,
+        name: impl Into<Arc<str>>,
+    ) -> GovernorConfigBuilder<Scoped<K>, M, C> {
+        let key_extractor = Scoped::new(self.key_extractor.clone()).with_scope(name);
+        let mut builder = self.key_extractor(key_extractor);
+        // wrapping the extractor doesn't make it any more explicit
+        builder.options.key_extractor_chosen = self.options.key_extractor_chosen;
+        builder
+    }
+
+    /// Set the key extractor this configuration should use.
+    /// By default this is using the [PeerIpKeyExtractor].
+    pub fn key_extractor<K2: KeyExtractor>(
+        &mut self,
+        key_extractor: K2,
+    ) -> GovernorConfigBuilder<K2, M, C> {
+        let mut builder = self.rebuild(key_extractor, self.clock.0.clone());
+        builder.options.key_extractor_chosen = true;
+        builder
+    }
+    /// Set ratelimit headers to response, the headers is
+    /// - `x-ratelimit-limit`       - Request limit
+    /// - `x-ratelimit-remaining`   - The number of requests left for the time window
+    /// - `x-ratelimit-after`       - Number of seconds in which the API will become available after its rate limit has been exceeded
+    /// - `retry-after`             - Same value as `x-ratelimit-after`, unless disabled with [`retry_after`]
+    /// - `x-ratelimit-whitelisted` - If the request method not in methods, this header will be add it, use [`methods`] to add methods
+    ///   and [`whitelisted_header`] to rename or disable it
+    ///
+    /// By default `x-ratelimit-after` and `retry-after` are enabled, with [`use_headers`] will enable `x-ratelimit-limit`, `x-ratelimit-whitelisted` and `x-ratelimit-remaining`
+    ///
+    /// [`methods`]: crate::GovernorConfigBuilder::methods()
+    /// [`whitelisted_header`]: Self::whitelisted_header
+    /// [`use_headers`]: Self::use_headers
+    /// [`retry_after`]: Self::retry_after
+    pub fn use_headers(&mut self) -> GovernorConfigBuilder<K, StateInformationMiddleware, C> {
+        self.rebuild(self.key_extractor.clone(), self.clock.0.clone())
+    }
+
+    /// Drop the rate limiting headers from every response, for internal services where even
+    /// building them is too much work: allowed responses are left untouched and rejections
+    /// are bare `429 Too Many Requests` responses.
+    ///
+    /// The headers are compiled out of the layers of configurations built with
+    /// [`BareMiddleware`], rather than skipped at runtime.
+    ///
+    /// [`BareMiddleware`]: crate::headers::BareMiddleware
+    pub fn bare_responses(&mut self) -> GovernorConfigBuilder<K, BareMiddleware, C> {
+        self.rebuild(self.key_extractor.clone(), self.clock.0.clone())
+    }
+
+    /// Count the quota in credits, see the [`credits`](crate::credits) module: every key
+    /// gets a balance of up to `balance` credits, one credit being replenished every `refill`.
+    ///
+    /// Sets the burst size and period, and enables the headers like [`use_headers`], naming
+    /// them `x-credits-limit` and `x-credits-remaining` instead. Requests cost one credit
+    /// unless a [`request_cost`] is set.
+    ///
+    /// [`use_headers`]: Self::use_headers
+    /// [`request_cost`]: Self::request_cost
+    pub fn credits(
+        &mut self,
+        balance: u32,
+        refill: Duration,
+    ) -> GovernorConfigBuilder<K, StateInformationMiddleware, C> {
+        self.options.burst_size = balance;
+        self.options.period = refill;
+        self.options.credits = true;
+        self.use_headers()
+    }
+
+    /// The same settings with another key extractor, middleware or clock, so that switching
+    /// any of them in the middle of the chain doesn't drop the settings made before.
+    fn rebuild<K2, M2, C2>(
+        &self,
+        key_extractor: K2,
+        clock: Option<C2>,
+    ) -> GovernorConfigBuilder<K2, M2, C2>
+    where
+        K2: KeyExtractor,
+        M2: RateLimitingMiddleware<C2::Instant>,
+        C2: Clock,
+    {
         GovernorConfigBuilder {
-            period: self.period,
-            burst_size: self.burst_size,
-            methods: self.methods.to_owned(),
+            key_extractor,
+            options: self.options.clone(),
+            clock: BuilderClock(clock),
+            middleware: PhantomData,
+        }
+    }
+
+    /// Finish building the configuration and return the configuration for the middleware.
+    /// Returns `None` if either burst size or period interval are zero.
+    pub fn finish(&mut self) -> Option<GovernorConfig<K, M, C>> {
+        self.try_finish().ok()
+    }
+
+    /// Finish building the configuration like [`finish`], but report why the
+    /// configuration is invalid.
+    ///
+    /// [`finish`]: Self::finish
+    pub fn try_finish(&mut self) -> Result<GovernorConfig<K, M, C>, ConfigError> {
+        let options = &self.options;
+        if options.require_explicit_key_extractor && !options.key_extractor_chosen {
+            return Err(ConfigError::ImplicitKeyExtractor);
+        }
+        self.key_extractor.validate()?;
+        if options.cache_connection_keys && !self.key_extractor.connection_stable() {
+            return Err(ConfigError::UnstableConnectionKeys);
+        }
+        if let (true, Some(rate)) = (options.period.is_zero(), options.unrepresentable_rate) {
+            return Err(ConfigError::UnrepresentableRate(rate));
+        }
+        if options.burst_size == 0 {
+            return Err(ConfigError::ZeroBurstSize);
+        }
+        if options.prefetch > options.burst_size {
+            return Err(ConfigError::PrefetchExceedsBurst);
+        }
+        let quota = checked_quota(options.period, options.burst_size)?;
+        let clock = self.clock.0.clone().unwrap_or_else(default_clock);
+        let limiter = |quota| {
+            Arc::new(
+                RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                    quota,
+                    DefaultKeyedStateStore::default(),
+                    clock.clone(),
+                )
+                .with_middleware::<M>(),
+            )
+        };
+        let direct = options.unkeyed.then(|| {
+            Arc::new(
+                RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                    quota,
+                    InMemoryState::default(),
+                    clock.clone(),
+                )
+                .with_middleware::<M>(),
+            )
+        });
+        let mut class_quotas = options.class_quotas.clone();
+        if let Some(percent) = options.write_percent {
+            if !class_quotas.iter().any(|(class, ..)| *class == "write") {
+                let period = options
+                    .period
+                    .checked_mul(100)
+                    .ok_or(ConfigError::QuotaOverflow)?
+                    / percent;
+                let burst_size = u64::from(options.burst_size) * u64::from(percent) / 100;
+                class_quotas.push(("write", period, (burst_size as u32).max(1)));
+            }
+        }
+        let classes = match &options.classifier {
+            Some(classifier) => {
+                let classes = class_quotas
+                    .iter()
+                    .map(|&(name, period, burst_size)| {
+                        let quota = checked_quota(period, burst_size)?;
+                        Ok(RequestClass {
+                            name,
+                            quota,
+                            limiter: limiter(quota),
+                            scope: headers::scope(options.policy_name.as_deref(), Some(name)),
+                        })
+                    })
+                    .collect::<Result<_, ConfigError>>()?;
+                Some(Arc::new(Classes::new(
+                    classifier.clone(),
+                    classes,
+                    options.class_cache_ttl,
+                )))
+            }
+            None => None,
+        };
+        let mut exemptions = options.exemptions.clone();
+        if options
+            .exempt_preflight
+            .unwrap_or(options.methods.is_none())
+        {
+            exemptions.push(
+                RequestMatcher::new()
+                    .methods([Method::OPTIONS])
+                    .header(ACCESS_CONTROL_REQUEST_METHOD),
+            );
+        }
+        if let Some(agents) = &options.health_check_agents {
+            exemptions
+                .push(RequestMatcher::new().header_prefix(USER_AGENT, agents.iter().cloned()));
+        }
+        let exemptions = (!exemptions.is_empty()).then(|| exemptions.into());
+        let windows = match options.window_quotas.is_empty() {
+            true => None,
+            false => {
+                let quotas = options
+                    .window_quotas
+                    .iter()
+                    .map(|&(name, period, burst_size)| {
+                        let scope = headers::scope(options.policy_name.as_deref(), Some(name));
+                        Ok((name, checked_quota(period, burst_size)?, scope))
+                    })
+                    .collect::<Result<Vec<_>, ConfigError>>()?;
+                Some(Arc::new(Windows::new(quotas, &clock)))
+            }
+        };
+        let sliding_log = match options.sliding_log {
+            Some((0, _)) => return Err(ConfigError::ZeroBurstSize),
+            Some((_, window)) if window.is_zero() => return Err(ConfigError::ZeroPeriod),
+            Some((max_requests, window)) => Some(Arc::new(SlidingLog::new(max_requests, window))),
+            None => None,
+        };
+        let fair_share = match &options.fair_share {
+            Some((period, burst_size, shares)) => Some(Arc::new(FairShare::new(
+                checked_quota(*period, *burst_size)?,
+                shares,
+                options.policy_name.as_deref(),
+            ))),
+            None => None,
+        };
+
+        Ok(GovernorConfig {
             key_extractor: self.key_extractor.clone(),
+            quota,
+            limiter: limiter(quota),
+            methods: options.methods.clone(),
+            error_handler: options.error_handler.clone(),
+            whitelisted_header: options.whitelisted_header.clone(),
+            max_wait_time: options.max_wait_time,
+            decide_only: options.decide_only,
+            #[cfg(feature = "test-util")]
+            injections: Arc::default(),
+            failure_charging: options
+                .charge_filter
+                .clone()
+                .map(|filter| Arc::new(FailureCharging::new(filter))),
+            prefetch: NonZeroU32::new(options.prefetch)
+                .filter(|batch| batch.get() > 1)
+                .map(|batch| Arc::new(Prefetch::new(batch, quota.replenish_interval()))),
+            exempt_loopback: options.exempt_loopback,
+            exempt_private_ranges: options.exempt_private_ranges,
+            policy_name: options.policy_name.clone(),
+            proxy_check: options.proxy_check.map(|(threshold, window)| {
+                Arc::new(ProxyCheck::new(
+                    threshold,
+                    window,
+                    options.proxy_fallback.clone(),
+                ))
+            }),
+            connection_slot: options.cache_connection_keys.then(connection::next_slot),
+            #[cfg(feature = "audit")]
+            audit_sink: options.audit_sink.clone(),
+            #[cfg(feature = "stream")]
+            decision_streams: Arc::default(),
+            retry_jitter: options.retry_jitter,
+            instances: options.instances.clone(),
+            inner_error_hook: options.inner_error_hook.clone(),
+            trailers: options.trailers,
+            report: options
+                .report_window
+                .map(|window| Arc::new(Tracker::new(window))),
+            watermarks: options
+                .retain_watermarks
+                .map(|(high, low)| Arc::new(Watermarks::new(high, low))),
+            classes,
+            key_churn: options.key_churn_limit.map(|(max_keys, window)| {
+                Arc::new(KeyChurn::new(
+                    max_keys,
+                    window,
+                    options.churn_observer.clone(),
+                ))
+            }),
+            rejection_message: options.rejection_message.clone(),
+            enforce_threshold: options.enforce_threshold,
+            head_requests: options.head_requests,
+            rejection_hook: options.rejection_hook.clone(),
+            rates: options.track_rates.then(|| Arc::new(RateCounters::new())),
+            extraction_failures: options.extraction_failure_ttl.map(|ttl| {
+                Arc::new(FailureCache::new(
+                    ttl,
+                    options.burst_size,
+                    options.retry_after,
+                    options.wait_time_unit,
+                ))
+            }),
+            scope: headers::scope(options.policy_name.as_deref(), None),
+            key_states: (options.track_state || options.window_start_header).then(|| {
+                Arc::new(KeyStates::new(
+                    quota.replenish_interval(),
+                    quota.burst_size().get(),
+                ))
+            }),
+            bans: (options.ban_escalation.is_some()
+                || options.ban_list
+                || options.auth_failure_limit.is_some())
+            .then(|| {
+                Arc::new(Bans::new(
+                    options.ban_escalation,
+                    options.ban_observer.clone(),
+                ))
+            }),
+            direct,
+            upstream_headers: options.upstream_headers,
+            retry_after: options.retry_after,
+            replay: (options.replay_capacity > 0)
+                .then(|| Arc::new(ReplayLog::new(options.replay_capacity))),
+            edge_marker: options.edge_marker.clone(),
+            request_cost: options.request_cost.clone(),
+            credits: options.credits,
+            async_error_handler: options.async_error_handler.clone(),
+            tarpit: options.tarpit,
+            route_rates: options
+                .track_route_rates
+                .then(|| Arc::new(RouteRates::default())),
+            rejection_attributes: options.rejection_attributes.clone(),
+            penalties: options
+                .penalty_escalation
+                .map(|(factor, cap)| Arc::new(Penalties::new(factor, cap))),
+            methods_hint: options
+                .methods_header
+                .clone()
+                .zip(options.methods.as_deref().and_then(headers::methods)),
+            breaker: options.circuit_breaker.map(|(engage, disengage, window)| {
+                Arc::new(Breaker::new(engage, disengage, window))
+            }),
+            limit_on_rejections: options.limit_on_rejections,
+            arrivals: options.analyze_traffic.then(|| Arc::new(Arrivals::new())),
+            fair_share,
+            method_rules: options.method_rules.clone().map(Arc::new),
+            wait_time_unit: options.wait_time_unit,
+            #[cfg(feature = "tracing")]
+            rejection_level: Arc::new(RejectionLevel::new(options.rejection_log_level)),
+            upgrade_requests: options.upgrade_requests,
+            statuses: options
+                .track_statuses
+                .then(|| Arc::new(Statuses::default())),
+            windows,
+            exemptions,
+            limited: options
+                .methods
+                .clone()
+                .map(|methods| RequestMatcher::new().methods(methods)),
+            window_start_header: options.window_start_header,
+            refunds: (options.refund_cancelled || options.leases).then(|| {
+                Arc::new(Refunds::new(
+                    quota.burst_size_replenished_in(),
+                    quota.burst_size().get(),
+                ))
+            }),
+            sample_over_quota: options.sample_over_quota,
+            auth_failures: options
+                .auth_failure_limit
+                .map(|(max_failures, window, ban)| {
+                    Arc::new(AuthFailures::new(max_failures, window, ban))
+                }),
+            // only the peers keyed by their connection are exempt for all their requests
+            exempt_peers: options
+                .exemption_ttl
+                .filter(|_| self.key_extractor.connection_stable() && options.proxy_check.is_none())
+                .map(|ttl| Arc::new(ExemptCache::new(ttl))),
+            cohort_rates: options
+                .track_cohort_rates
+                .then(|| Arc::new(RouteRates::default())),
+            response_mapper: options.response_mapper.clone(),
+            refund_cancelled: options.refund_cancelled,
+            charge_once: options.charge_once.then(connection::next_slot),
+            sliding_log,
+            #[cfg(feature = "shared-memory")]
+            shared_memory: match &options.shared_memory {
+                Some((path, slots)) => Some(Arc::new(SharedMemory::open(path, *slots, quota)?)),
+                None => None,
+            },
+            method_groups: options.method_groups.clone(),
+        })
+    }
+
+    /// Finish building the configuration and wrap it into a [`GovernorLayer`], reporting why
+    /// the configuration is invalid.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower::ServiceBuilder;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// # fn main() -> Result<(), tower_governor::errors::ConfigError> {
+    /// let stack = ServiceBuilder::new().layer(
+    ///     GovernorConfigBuilder::default()
+    ///         .per_second(2)
+    ///         .burst_size(5)
+    ///         .into_layer()?,
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_layer(&mut self) -> Result<GovernorLayer<K, M, C>, ConfigError> {
+        GovernorLayer::try_from_builder(self)
+    }
+}
+
+/// Configuration for the Governor middleware.
+///
+/// Its `Display` implementation summarizes the configuration in a single line suitable for
+/// startup logs, such as `10 req / 60s burst=10 extractor=SmartIpKeyExtractor headers=on store=0`.
+pub struct GovernorConfig<
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock = DefaultClock,
+> {
+    key_extractor: K,
+    quota: Quota,
+    limiter: SharedRateLimiter<K::Key, M, C>,
+    methods: Option<Vec<Method>>,
+    error_handler: ErrorHandler,
+    pub(crate) whitelisted_header: Option<HeaderName>,
+    max_wait_time: Option<Duration>,
+    decide_only: bool,
+    prefetch: Option<Arc<Prefetched<K, M, C>>>,
+    failure_charging: Option<Arc<FailureCharging<K::Key>>>,
+    #[cfg(feature = "test-util")]
+    pub(crate) injections: Arc<Injections<K::Key>>,
+    exempt_loopback: bool,
+    exempt_private_ranges: bool,
+    policy_name: Option<Arc<str>>,
+    proxy_check: Option<Arc<ProxyCheck>>,
+    connection_slot: Option<u64>,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<AuditSink>,
+    #[cfg(feature = "stream")]
+    decision_streams: Arc<DecisionStreams>,
+    retry_jitter: Option<Duration>,
+    instances: Option<Instances>,
+    pub(crate) inner_error_hook: Option<InnerErrorHook>,
+    pub(crate) trailers: bool,
+    report: Option<Arc<Tracker<K::Key>>>,
+    watermarks: Option<Arc<Watermarks>>,
+    classes: Option<Arc<Classes<K::Key, M, C>>>,
+    key_churn: Option<Arc<KeyChurn<K::Key>>>,
+    rejection_message: Option<RejectionMessage>,
+    enforce_threshold: Option<u64>,
+    head_requests: HeadRequests,
+    rejection_hook: Option<RejectionHook>,
+    rates: Option<Arc<RateCounters>>,
+    extraction_failures: Option<Arc<FailureCache>>,
+    scope: Option<HeaderValue>,
+    key_states: Option<Arc<KeyStates<K::Key>>>,
+    bans: Option<Arc<Bans<K::Key>>>,
+    direct: Option<Arc<DirectRateLimiter<M, C>>>,
+    pub(crate) upstream_headers: UpstreamHeaders,
+    retry_after: bool,
+    replay: Option<Arc<ReplayLog>>,
+    edge_marker: Option<EdgeMarker>,
+    request_cost: Option<RequestCost>,
+    pub(crate) credits: bool,
+    async_error_handler: Option<AsyncErrorHandler>,
+    pub(crate) tarpit: Option<Duration>,
+    route_rates: Option<Arc<RouteRates>>,
+    rejection_attributes: RejectionAttributes,
+    penalties: Option<Arc<Penalties<K::Key>>>,
+    methods_hint: Option<(HeaderName, HeaderValue)>,
+    breaker: Option<Arc<Breaker>>,
+    limit_on_rejections: bool,
+    arrivals: Option<Arc<Arrivals<K::Key>>>,
+    fair_share: Option<Arc<FairShare>>,
+    method_rules: Option<Arc<MethodRules>>,
+    wait_time_unit: WaitTimeUnit,
+    #[cfg(feature = "tracing")]
+    rejection_level: Arc<RejectionLevel>,
+    upgrade_requests: UpgradeRequests,
+    statuses: Option<Arc<Statuses>>,
+    windows: Option<Arc<Windows<K::Key, C>>>,
+    exemptions: Option<Arc<[RequestMatcher]>>,
+    limited: Option<RequestMatcher>,
+    window_start_header: bool,
+    refunds: Option<Arc<Refunds<K::Key>>>,
+    sample_over_quota: Option<NonZeroU32>,
+    auth_failures: Option<Arc<AuthFailures<K::Key>>>,
+    exempt_peers: Option<Arc<ExemptCache>>,
+    cohort_rates: Option<Arc<RouteRates>>,
+    pub(crate) response_mapper: Option<ResponseMapper>,
+    refund_cancelled: bool,
+    charge_once: Option<u64>,
+    sliding_log: Option<Arc<SlidingLog<K::Key>>>,
+    #[cfg(feature = "shared-memory")]
+    shared_memory: Option<Arc<SharedMemory>>,
+    method_groups: Option<Arc<[(Method, Method)]>>,
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> GovernorConfig<K, M, C> {
+    /// Whether the x-ratelimit headers are enabled, see [`GovernorConfigBuilder::use_headers`],
+    /// unknown for the middlewares of other crates.
+    fn uses_headers(&self) -> Option<bool> {
+        // told apart by their exact names, as only the layer is bound to the known middlewares
+        let middleware = std::any::type_name::<M>();
+        if middleware == std::any::type_name::<StateInformationMiddleware>() {
+            Some(true)
+        } else if middleware == std::any::type_name::<NoOpMiddleware<C::Instant>>()
+            || middleware == std::any::type_name::<BareMiddleware>()
+        {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The number of requests replenished per second, minute, hour or day, whichever is the
+    /// shortest to replenish one, e.g. `(10, 60s)` for one request every 6 seconds.
+    fn rate(&self) -> (u128, Duration) {
+        let period = self.period();
+        [1, 60, 3600, 86400]
+            .map(Duration::from_secs)
+            .into_iter()
+            .find(|unit| period <= *unit)
+            .map_or((1, period), |unit| {
+                (unit.as_nanos() / period.as_nanos().max(1), unit)
+            })
+    }
+}
+
+/// Name of the type without module paths, e.g. `Scoped<PeerIpKeyExtractor>`.
+fn short_type_name<T: ?Sized>() -> String {
+    let mut name = String::new();
+    for (i, segment) in std::any::type_name::<T>().split("::").enumerate() {
+        if i > 0 {
+            // drop the module the segment belongs to
+            let module_len: usize = name
+                .chars()
+                .rev()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .map(char::len_utf8)
+                .sum();
+            name.truncate(name.len() - module_len);
+        }
+        name.push_str(segment);
+    }
+    name
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> fmt::Display
+    for GovernorConfig<K, M, C>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (count, per) = self.rate();
+        write!(
+            f,
+            "{} req / {:?} burst={} extractor={} headers={}",
+            count,
+            per,
+            self.burst_size(),
+            short_type_name::<K>(),
+            match self.uses_headers() {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "unknown",
+            },
+        )?;
+        if let Some(methods) = &self.methods {
+            let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+            write!(f, " methods={}", methods.join(","))?;
+        }
+        if let Some(name) = &self.policy_name {
+            write!(f, " policy={}", name)?;
+        }
+        write!(f, " store={}", self.limiter.len())
+    }
+}
+
+// Handlers and hooks are left out, and the key extractor is only named, so that logging a
+// configuration doesn't leak anything sensitive.
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> fmt::Debug
+    for GovernorConfig<K, M, C>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorConfig")
+            .field("period", &self.period())
+            .field("burst_size", &self.burst_size())
+            .field("key_extractor", &short_type_name::<K>())
+            .field("headers", &self.uses_headers())
+            .field("methods", &self.methods)
+            .field("policy_name", &self.policy_name)
+            .field("whitelisted_header", &self.whitelisted_header)
+            .field("max_wait_time", &self.max_wait_time)
+            .field("decide_only", &self.decide_only)
+            .field("store_size", &self.limiter.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// https://stegosaurusdormant.com/understanding-derive-clone/
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> Clone
+    for GovernorConfig<K, M, C>
+{
+    /// Same as [`clone_shared`](GovernorConfig::clone_shared): the clones count against the
+    /// same quotas, as the layers cloned from a single configuration have to.
+    fn clone(&self) -> Self {
+        self.share(self.key_extractor.clone(), self.connection_slot)
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> GovernorConfig<K, M, C> {
+    // The same configuration and state, with another key extractor.
+    fn share<K2: KeyExtractor<Key = K::Key>>(
+        &self,
+        key_extractor: K2,
+        connection_slot: Option<u64>,
+    ) -> GovernorConfig<K2, M, C> {
+        GovernorConfig {
+            key_extractor,
+            quota: self.quota,
+            limiter: self.limiter.clone(),
+            methods: self.methods.clone(),
             error_handler: self.error_handler.clone(),
-            middleware: PhantomData,
+            whitelisted_header: self.whitelisted_header.clone(),
+            max_wait_time: self.max_wait_time,
+            decide_only: self.decide_only,
+            prefetch: self.prefetch.clone(),
+            failure_charging: self.failure_charging.clone(),
+            #[cfg(feature = "test-util")]
+            injections: self.injections.clone(),
+            exempt_loopback: self.exempt_loopback,
+            exempt_private_ranges: self.exempt_private_ranges,
+            policy_name: self.policy_name.clone(),
+            proxy_check: self.proxy_check.clone(),
+            connection_slot,
+            #[cfg(feature = "audit")]
+            audit_sink: self.audit_sink.clone(),
+            #[cfg(feature = "stream")]
+            decision_streams: self.decision_streams.clone(),
+            retry_jitter: self.retry_jitter,
+            instances: self.instances.clone(),
+            inner_error_hook: self.inner_error_hook.clone(),
+            trailers: self.trailers,
+            report: self.report.clone(),
+            watermarks: self.watermarks.clone(),
+            classes: self.classes.clone(),
+            key_churn: self.key_churn.clone(),
+            rejection_message: self.rejection_message.clone(),
+            enforce_threshold: self.enforce_threshold,
+            head_requests: self.head_requests,
+            rejection_hook: self.rejection_hook.clone(),
+            rates: self.rates.clone(),
+            extraction_failures: self.extraction_failures.clone(),
+            scope: self.scope.clone(),
+            key_states: self.key_states.clone(),
+            bans: self.bans.clone(),
+            direct: self.direct.clone(),
+            upstream_headers: self.upstream_headers,
+            retry_after: self.retry_after,
+            replay: self.replay.clone(),
+            edge_marker: self.edge_marker.clone(),
+            request_cost: self.request_cost.clone(),
+            credits: self.credits,
+            async_error_handler: self.async_error_handler.clone(),
+            tarpit: self.tarpit,
+            route_rates: self.route_rates.clone(),
+            rejection_attributes: self.rejection_attributes.clone(),
+            penalties: self.penalties.clone(),
+            methods_hint: self.methods_hint.clone(),
+            breaker: self.breaker.clone(),
+            limit_on_rejections: self.limit_on_rejections,
+            arrivals: self.arrivals.clone(),
+            fair_share: self.fair_share.clone(),
+            method_rules: self.method_rules.clone(),
+            wait_time_unit: self.wait_time_unit,
+            #[cfg(feature = "tracing")]
+            rejection_level: self.rejection_level.clone(),
+            upgrade_requests: self.upgrade_requests,
+            statuses: self.statuses.clone(),
+            windows: self.windows.clone(),
+            exemptions: self.exemptions.clone(),
+            limited: self.limited.clone(),
+            window_start_header: self.window_start_header,
+            refunds: self.refunds.clone(),
+            sample_over_quota: self.sample_over_quota,
+            auth_failures: self.auth_failures.clone(),
+            exempt_peers: self.exempt_peers.clone(),
+            cohort_rates: self.cohort_rates.clone(),
+            response_mapper: self.response_mapper.clone(),
+            refund_cancelled: self.refund_cancelled,
+            charge_once: self.charge_once,
+            sliding_log: self.sliding_log.clone(),
+            #[cfg(feature = "shared-memory")]
+            shared_memory: self.shared_memory.clone(),
+            method_groups: self.method_groups.clone(),
+        }
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> GovernorConfig<K, M, C> {
+    pub fn limiter(&self) -> &SharedRateLimiter<K::Key, M, C> {
+        &self.limiter
+    }
+
+    #[cfg(feature = "async-key")]
+    pub(crate) fn key_extractor(&self) -> &K {
+        &self.key_extractor
+    }
+
+    /// Clean up the keys whose quota is fully replenished from the store of the
+    /// [`limiter`](Self::limiter) and from those of the
+    /// [class quotas](GovernorConfigBuilder::class_quota) and
+    /// [windows](GovernorConfigBuilder::window_quota), as a periodic task should.
+    ///
+    /// See [`retain_watermarks`](GovernorConfigBuilder::retain_watermarks) to clean them up
+    /// as they grow instead.
+    pub fn retain_recent(&self) {
+        retain_recent(
+            &self.limiter,
+            self.classes.as_deref(),
+            self.windows.as_deref(),
+        );
+    }
+
+    /// The number of keys held by the store of the limiter and by those of the class quotas
+    /// and windows.
+    pub fn stored_keys(&self) -> usize {
+        stored_keys(
+            &self.limiter,
+            self.classes.as_deref(),
+            self.windows.as_deref(),
+        )
+    }
+
+    /// The quota enforced for every key.
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// The interval after which one element of the quota is replenished.
+    pub fn period(&self) -> Duration {
+        self.quota.replenish_interval()
+    }
+
+    /// How many requests can occur before requests start being blocked.
+    pub fn burst_size(&self) -> u32 {
+        self.quota.burst_size().get()
+    }
+
+    /// The HTTP methods this configuration applies to, `None` meaning all methods.
+    pub fn methods(&self) -> Option<&[Method]> {
+        self.methods.as_deref()
+    }
+
+    /// The period, burst size and methods of this configuration, e.g. to
+    /// [`diff`](GovernorSettings::diff) them against the settings about to be deployed.
+    pub fn settings(&self) -> GovernorSettings {
+        GovernorSettings {
+            period: self.period(),
+            burst_size: self.burst_size(),
+            methods: self.methods.clone(),
+        }
+    }
+
+    /// Whether a proxy misconfiguration was detected, see
+    /// [`GovernorConfigBuilder::detect_proxy_misconfiguration`].
+    pub fn proxy_misconfiguration_detected(&self) -> bool {
+        self.proxy_check
+            .as_ref()
+            .is_some_and(|check| check.detected())
+    }
+
+    /// Whether the quota currently applies, see [`GovernorConfigBuilder::circuit_breaker`].
+    /// Always `false` without a circuit breaker.
+    pub fn circuit_breaker_engaged(&self) -> bool {
+        self.breaker
+            .as_ref()
+            .is_some_and(|breaker| breaker.engaged())
+    }
+
+    /// The name of this policy, see [`GovernorConfigBuilder::policy_name`].
+    pub fn policy_name(&self) -> Option<&str> {
+        self.policy_name.as_deref()
+    }
+
+    /// The level of the events logged for rejections, see
+    /// [`GovernorConfigBuilder::rejection_log_level`].
+    #[cfg(feature = "tracing")]
+    pub fn rejection_log_level(&self) -> tracing::Level {
+        self.rejection_level.get()
+    }
+
+    /// Change the level of the events logged for rejections, for this configuration, its
+    /// clones and the layers built from them, without rebuilding them.
+    #[cfg(feature = "tracing")]
+    pub fn set_rejection_log_level(&self, level: tracing::Level) {
+        self.rejection_level.set(level);
+    }
+
+    /// Check `key` against the quota from synchronous code, e.g. a rayon worker or an FFI
+    /// callback, charging it `cost` elements of the quota as a request of that
+    /// [cost](GovernorConfigBuilder::request_cost) would be.
+    ///
+    /// The check shares the limiter store, and thus the buckets, of the [`GovernorLayer`]s
+    /// built from this configuration, and reads the same [clock](GovernorConfigBuilder::clock).
+    /// It never waits: the store is only locked for the duration of the update of the key,
+    /// and a rejection tells how long to wait, e.g. with `std::thread::sleep`, before trying
+    /// again. A `cost` of zero is charged as one, and a `cost` over the burst size as the
+    /// burst size.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::net::IpAddr;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .per_second(1)
+    ///     .burst_size(5)
+    ///     .finish()
+    ///     .unwrap();
+    /// let ip = IpAddr::from([10, 0, 0, 1]);
+    ///
+    /// assert!(config.check_blocking(&ip, 4).is_allowed());
+    /// assert!(!config.check_blocking(&ip, 2).is_allowed());
+    /// ```
+    ///
+    /// [`GovernorLayer`]: crate::GovernorLayer
+    pub fn check_blocking(&self, key: &K::Key, cost: u32) -> Decision
+    where
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone,
+    {
+        let weight = NonZeroU32::new(cost).unwrap_or(NonZeroU32::MIN);
+        Governor::new((), self).decide(key, weight)
+    }
+
+    /// Decide on `req` as the [`GovernorLayer`] would, for integrations outside of
+    /// [`tower`], e.g. the middleware of another framework.
+    ///
+    /// The key of the request is extracted, and the request is charged to the quota of this
+    /// configuration as a request of its [cost](GovernorConfigBuilder::request_cost). The
+    /// classes, windows, bans and other per-request policies of the layer aren't applied, and
+    /// the request is left untouched: rendering a rejection is up to the caller.
+    ///
+    /// # Example
+    /// ```rust
+    /// use axum::extract::ConnectInfo;
+    /// use std::net::SocketAddr;
+    /// use tower_governor::{decision::RequestDecision, governor::GovernorConfigBuilder};
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .burst_size(1)
+    ///     .finish()
+    ///     .unwrap();
+    /// let mut req = http::Request::new(());
+    /// req.extensions_mut()
+    ///     .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+    ///
+    /// assert!(matches!(config.check_request(&req), RequestDecision::Allowed { .. }));
+    /// assert!(matches!(config.check_request(&req), RequestDecision::Rejected { .. }));
+    /// ```
+    ///
+    /// [`GovernorLayer`]: crate::GovernorLayer
+    // `Remaining` seals the middlewares to those the layer supports
+    #[allow(private_bounds)]
+    pub fn check_request<B>(&self, req: &Request<B>) -> RequestDecision
+    where
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone + Remaining,
+    {
+        Governor::new((), self).check_request(req)
+    }
+
+    /// Reserve `cost` elements of the quota of `key` for a long-running job, holding them
+    /// until the returned [`Lease`] is dropped or `ttl` elapsed. A `ttl` too long to
+    /// represent, such as `Duration::MAX`, never elapses.
+    ///
+    /// The whole cost is charged right away, as [`check_blocking`](Self::check_blocking)
+    /// would. The cost left unconsumed when the lease is dropped before it expires is given
+    /// back to the key, letting its next requests through once it exceeds the quota until the
+    /// quota would have been replenished anyway.
+    ///
+    /// Fails with [`LeaseError::Disabled`] unless [`GovernorConfigBuilder::leases`] is set.
+    pub fn reserve(
+        &self,
+        key: K::Key,
+        cost: u32,
+        ttl: Duration,
+    ) -> Result<Lease<K::Key>, LeaseError>
+    where
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone,
+    {
+        let refunds = self.refunds.clone().ok_or(LeaseError::Disabled)?;
+        let weight = NonZeroU32::new(cost).unwrap_or(NonZeroU32::MIN);
+        Governor::new((), self).lease(key, weight, ttl, refunds)
+    }
+
+    /// Report of the keys blocked by [`GovernorConfigBuilder::charge_only`] and of the keys
+    /// that made the most requests within the current window, along with the number of
+    /// rejections, e.g. for a nightly export to a SIEM.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::report_window`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::{governor::GovernorConfigBuilder, report::ReportFormat};
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .report_window(Duration::from_secs(3600))
+    ///     .finish()
+    ///     .unwrap();
+    /// let csv = config.export_report(ReportFormat::Csv).unwrap();
+    /// assert_eq!(csv, "kind,key,requests,rejected,banned_for_ms\n");
+    /// ```
+    pub fn export_report(&self, format: ReportFormat) -> Option<String> {
+        let banned = self
+            .failure_charging
+            .as_ref()
+            .map(|charging| charging.blocked())
+            .unwrap_or_default();
+        Some(self.report.as_ref()?.export(format, &banned))
+    }
+
+    /// The keys that made at least `threshold` of the requests the quota allows within the
+    /// [`report_window`], the busiest first, e.g. to reach out to the customers about to see
+    /// rejections. A `threshold` of `0.8` lists the keys above 80% of their quota.
+    ///
+    /// The quota allows the burst size plus the elements replenished within the window. Keys
+    /// already rejected are listed too, with a [usage](NearLimitKey::usage) above `1.0`.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::report_window`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .report_window(Duration::from_secs(3600))
+    ///     .finish()
+    ///     .unwrap();
+    /// for near in config.near_limit_keys(0.8).unwrap() {
+    ///     println!("{:?} used {:.0}% of its quota", near.key, near.usage() * 100.0);
+    /// }
+    /// ```
+    ///
+    /// [`report_window`]: GovernorConfigBuilder::report_window
+    pub fn near_limit_keys(&self, threshold: f64) -> Option<Vec<NearLimitKey<K::Key>>> {
+        let report = self.report.as_ref()?;
+        let period = self.quota.replenish_interval().as_nanos().max(1);
+        let replenished = report.window().as_nanos() / period;
+        let capacity = u64::try_from(replenished)
+            .unwrap_or(u64::MAX)
+            .saturating_add(self.burst_size().into());
+        Some(report.near_limit(capacity, threshold))
+    }
+
+    /// The quota that would have rejected at most `target_rejection_rate` of the requests
+    /// recorded by [`GovernorConfigBuilder::analyze_traffic`], e.g. `0.01` for 1%.
+    ///
+    /// The recent arrivals of every key are replayed against quotas whose periods are spread
+    /// over the observed inter-arrival times, each with the smallest burst size up to
+    /// [`MAX_SUGGESTED_BURST`] meeting the target. Of those, the quota allowing the fewest
+    /// requests over the recorded time is suggested.
+    ///
+    /// Returns `None` unless traffic analysis is enabled, until a key made two requests, or
+    /// when no quota meets the target.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .analyze_traffic()
+    ///     .decide_only(true)
+    ///     .finish()
+    ///     .unwrap();
+    /// // later, once the traffic was recorded for a while
+    /// if let Some(suggestion) = config.suggest_quota(0.01) {
+    ///     println!(
+    ///         "period={:?} burst={} rejecting {:.1}%",
+    ///         suggestion.period,
+    ///         suggestion.burst_size,
+    ///         suggestion.rejection_rate * 100.0
+    ///     );
+    /// }
+    /// ```
+    ///
+    /// [`MAX_SUGGESTED_BURST`]: crate::report::MAX_SUGGESTED_BURST
+    pub fn suggest_quota(&self, target_rejection_rate: f64) -> Option<QuotaSuggestion> {
+        self.arrivals.as_ref()?.suggest(target_rejection_rate)
+    }
+
+    /// The allowed and rejected requests over the trailing 1, 5 and 15 minutes.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::track_rates`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default().track_rates().finish().unwrap();
+    /// let rates = config.rates().unwrap();
+    /// if rates.five_minutes.rejection_rate() > 0.1 {
+    ///     eprintln!("more than 10% of the requests were rejected");
+    /// }
+    /// ```
+    pub fn rates(&self) -> Option<Rates> {
+        Some(self.rates.as_ref()?.rates())
+    }
+
+    /// The allowed and rejected requests of every route over the trailing 1, 5 and 15
+    /// minutes, by route.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::track_route_rates`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .track_route_rates()
+    ///     .finish()
+    ///     .unwrap();
+    /// for (route, rates) in config.route_rates().unwrap() {
+    ///     println!("{} {}", route, rates.one_minute.rejection_rate());
+    /// }
+    /// ```
+    pub fn route_rates(&self) -> Option<BTreeMap<String, Rates>> {
+        Some(self.route_rates.as_ref()?.rates())
+    }
+
+    /// The allowed and rejected requests of every cohort of keys over the trailing 1, 5 and
+    /// 15 minutes, by cohort.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::track_cohort_rates`] is set.
+    pub fn cohort_rates(&self) -> Option<BTreeMap<String, Rates>> {
+        Some(self.cohort_rates.as_ref()?.rates())
+    }
+
+    /// The responses of the allowed requests of every class by status, `None` standing for
+    /// the requests limited by the quota of the configuration.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::track_statuses`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .track_statuses()
+    ///     .finish()
+    ///     .unwrap();
+    /// for (class, counts) in config.status_counts().unwrap() {
+    ///     println!("{:?} {}", class, counts.error_rate());
+    /// }
+    /// ```
+    pub fn status_counts(&self) -> Option<BTreeMap<Option<&'static str>, StatusCounts>> {
+        Some(self.statuses.as_ref()?.counts())
+    }
+
+    /// The last decisions, oldest first.
+    ///
+    /// Returns `None` unless [`GovernorConfigBuilder::replay_log`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor};
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .key_extractor(GlobalKeyExtractor)
+    ///     .replay_log(1000)
+    ///     .finish()
+    ///     .unwrap();
+    /// let key_hash = config.replay_key_hash(&());
+    /// let disputed = config
+    ///     .replay()
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .filter(|entry| entry.key_hash == key_hash);
+    /// ```
+    pub fn replay(&self) -> Option<Vec<ReplayEntry>> {
+        Some(self.replay.as_ref()?.entries())
+    }
+
+    /// A stream of the decisions taken from now on, buffering up to `capacity` of them and
+    /// dropping the oldest ones once full.
+    ///
+    /// Only requests checked against the limiter produce a decision, not the exempted ones.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfig;
+    ///
+    /// let config = GovernorConfig::default();
+    /// // forward to server-sent events, a websocket...
+    /// let decisions = config.decision_stream(1024);
+    /// ```
+    #[cfg(feature = "stream")]
+    pub fn decision_stream(&self, capacity: usize) -> DecisionStream {
+        self.decision_streams.subscribe(capacity)
+    }
+
+    /// The hash `key` is recorded with in the [`replay`](Self::replay) log.
+    pub fn replay_key_hash(&self, key: &K::Key) -> u64 {
+        replay::key_hash(key)
+    }
+
+    /// Write out the events recorded so far by an [`AuditSink::writer`] and stop its task,
+    /// so that no event is lost when the process exits. Events recorded afterwards are dropped.
+    ///
+    /// Call it once the server has finished its graceful shutdown, e.g. after axum's
+    /// `with_graceful_shutdown` has returned.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// use axum::{routing::get, Router};
+    /// use std::{net::SocketAddr, sync::Arc};
+    /// use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
+    ///
+    /// # async fn shutdown_signal() {}
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let config = Arc::new(GovernorConfigBuilder::default().finish().unwrap());
+    /// let app = Router::new()
+    ///     .route("/", get(|| async { "Hello world" }))
+    ///     .layer(GovernorLayer {
+    ///         config: config.clone(),
+    ///     });
+    ///
+    /// let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await.unwrap();
+    /// axum::serve(
+    ///     listener,
+    ///     app.into_make_service_with_connect_info::<SocketAddr>(),
+    /// )
+    /// .with_graceful_shutdown(shutdown_signal())
+    /// .await
+    /// .unwrap();
+    /// config.shutdown().await;
+    /// # }
+    /// ```
+    ///
+    /// [`AuditSink::writer`]: crate::audit::AuditSink::writer
+    pub async fn shutdown(&self) {
+        #[cfg(feature = "audit")]
+        if let Some(sink) = &self.audit_sink {
+            sink.close().await;
+        }
+    }
+
+    /// Iterate over the keys that made requests recently, along with the number of requests
+    /// they can make right away and when they were last seen, e.g. to page through the live
+    /// state while debugging.
+    ///
+    /// Yields nothing unless [`GovernorConfigBuilder::track_state`] is set.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default().track_state().finish().unwrap();
+    /// for entry in config.iter_keys().take(20) {
+    ///     println!("{}: {} left", entry.key, entry.remaining);
+    /// }
+    /// ```
+    pub fn iter_keys(&self) -> KeyIter<K::Key> {
+        KeyIter::new(self.key_states.clone())
+    }
+
+    /// Write the quota state of the keys that made requests recently to `writer`, to be
+    /// restored with [`restore_from`](Self::restore_from), e.g. on shutdown.
+    ///
+    /// Keys are written with their `Display` implementation. Nothing but the header is
+    /// written unless [`GovernorConfigBuilder::track_state`] is set.
+    pub fn save_state<W: Write>(&self, writer: W) -> io::Result<()>
+    where
+        K::Key: Display,
+    {
+        match &self.key_states {
+            Some(states) => states.save(writer),
+            None => KeyStates::<K::Key>::new(self.period(), self.burst_size()).save(writer),
+        }
+    }
+
+    /// Restore the quota state saved by [`save_state`](Self::save_state), returning the
+    /// number of keys whose quota isn't full yet. Call it before serving requests.
+    ///
+    /// The snapshot must have been taken with the same quota. The elements of the quota
+    /// replenished since the snapshot was taken are granted back to the keys, which relies on
+    /// the clocks of the hosts taking and restoring it agreeing with each other.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default().track_state().finish().unwrap();
+    /// let mut snapshot = Vec::new();
+    /// config.save_state(&mut snapshot).unwrap();
+    ///
+    /// let restarted = GovernorConfigBuilder::default().track_state().finish().unwrap();
+    /// assert_eq!(restarted.restore_from(snapshot.as_slice()).unwrap(), 0);
+    /// ```
+    pub fn restore_from<R: BufRead>(&self, reader: R) -> Result<usize, SnapshotError>
+    where
+        K::Key: FromStr,
+    {
+        let states = self.key_states.as_ref().ok_or(SnapshotError::NotTracked)?;
+        states.load(reader, |key, cells| {
+            // a key restored over its burst is just rejected until it replenished
+            let _ = self.limiter.check_key_n(key, cells);
+        })
+    }
+
+    /// Ban `key` for `duration`, replacing its current ban, returning whether bans are
+    /// enabled with [`GovernorConfigBuilder::ban_list`] or [`ban_above`].
+    ///
+    /// Banned keys get a `403 Forbidden` response without being checked against the limiter.
+    /// Durations over a century, such as `Duration::MAX`, ban the key for a century.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::{net::IpAddr, time::Duration};
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let config = GovernorConfigBuilder::default().ban_list().finish().unwrap();
+    /// let abuser: IpAddr = "203.0.113.7".parse().unwrap();
+    /// assert!(config.ban(abuser, Duration::from_secs(3600)));
+    /// assert_eq!(config.bans().len(), 1);
+    /// assert!(config.unban(&abuser));
+    /// ```
+    ///
+    /// [`ban_above`]: GovernorConfigBuilder::ban_above
+    pub fn ban(&self, key: K::Key, duration: Duration) -> bool {
+        match &self.bans {
+            Some(bans) => {
+                bans.ban(key, duration);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lift the ban of `key`, returning whether it was banned.
+    pub fn unban(&self, key: &K::Key) -> bool {
+        self.bans.as_ref().is_some_and(|bans| bans.unban(key))
+    }
+
+    /// The banned keys, along with the time left until they are let through again.
+    pub fn bans(&self) -> Vec<(K::Key, Duration)> {
+        self.bans
+            .as_ref()
+            .map(|bans| bans.list())
+            .unwrap_or_default()
+    }
+
+    /// Write the banned keys to `writer`, to be restored with
+    /// [`restore_bans`](Self::restore_bans), e.g. on shutdown or after every manual ban.
+    ///
+    /// Keys are written with their `Display` implementation.
+    pub fn save_bans<W: Write>(&self, writer: W) -> io::Result<()>
+    where
+        K::Key: Display,
+    {
+        match &self.bans {
+            Some(bans) => bans.save(writer),
+            None => Bans::<K::Key>::new(None, None).save(writer),
+        }
+    }
+
+    /// Ban the keys saved by [`save_bans`](Self::save_bans) for the time left on their ban,
+    /// returning the number of keys still banned. Call it before serving requests.
+    ///
+    /// The time elapsed since the list was saved counts towards the bans, which relies on the
+    /// clocks of the hosts saving and restoring it agreeing with each other.
+    pub fn restore_bans<R: BufRead>(&self, reader: R) -> Result<usize, SnapshotError>
+    where
+        K::Key: FromStr,
+    {
+        let bans = self.bans.as_ref().ok_or(SnapshotError::NoBans)?;
+        bans.load(reader)
+    }
+
+    /// Whether the keys carry their state over to new quotas and snapshots, see
+    /// [`GovernorConfigBuilder::track_state`].
+    pub(crate) fn tracks_state(&self) -> bool {
+        self.key_states.is_some()
+    }
+
+    /// The same configuration with `quota`, e.g. to reload it, sharing every state of this
+    /// one but its limiter store.
+    ///
+    /// The store of the limiter can't be read, so the keys start over with a full quota
+    /// unless [`GovernorConfigBuilder::track_state`] is set, in which case every key keeps
+    /// the number of elements it has used, up to the new burst size. Under the GCRA these
+    /// elements replenish at the pace of the new quota: a longer period makes the keys wait
+    /// longer for the requests they already made, and a smaller burst size forgives the
+    /// elements over it. The quotas of the [classes](GovernorConfigBuilder::class_quota) are
+    /// left as they are.
+    ///
+    /// Requests checked against this configuration while the new one is built aren't carried
+    /// over. See [`GovernorConfigHandle`] to swap the configuration of running layers.
+    ///
+    /// [`GovernorConfigHandle`]: crate::resolver::GovernorConfigHandle
+    pub fn with_quota(&self, quota: Quota) -> Self
+    where
+        C: Clone,
+    {
+        let clock = self.limiter.clock().clone();
+        let limiter = Arc::new(
+            RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                quota,
+                DefaultKeyedStateStore::default(),
+                clock.clone(),
+            )
+            .with_middleware::<M>(),
+        );
+        let key_states = self.key_states.as_ref().map(|states| {
+            let (period, burst_size) = (quota.replenish_interval(), quota.burst_size().get());
+            Arc::new(states.requota(period, burst_size, |key, cells| {
+                // a key carried over its burst is just rejected until it replenished
+                let _ = limiter.check_key_n(key, cells);
+            }))
+        });
+        let direct = self.direct.as_ref().map(|_| {
+            Arc::new(
+                RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                    quota,
+                    InMemoryState::default(),
+                    clock,
+                )
+                .with_middleware::<M>(),
+            )
+        });
+        GovernorConfig {
+            quota,
+            limiter,
+            key_states,
+            direct,
+            charge_once: self.charge_once.map(|_| connection::next_slot()),
+            ..self.share(self.key_extractor.clone(), self.connection_slot)
+        }
+    }
+
+    /// The same configuration, sharing the limiter store and every other state of this one,
+    /// with another key extractor producing the same keys.
+    ///
+    /// This lets the layers of different protocols count against a single quota, e.g. the
+    /// axum and tonic servers exposing the same API in one process. Extractors identifying
+    /// clients differently can be [normalized](crate::key_extractor::Normalized) to the same
+    /// keys.
+    ///
+    /// Fails if `key_extractor` is [invalid](KeyExtractor::validate).
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use http::HeaderName;
+    /// use tower_governor::{
+    ///     governor::GovernorConfigBuilder,
+    ///     key_extractor::{MetadataKeyExtractor, Normalized},
+    ///     GovernorLayer,
+    /// };
+    ///
+    /// // REST clients send their API key hex encoded, gRPC clients send the raw bytes
+    /// let rest = GovernorConfigBuilder::default()
+    ///     .key_extractor(Normalized::new(
+    ///         MetadataKeyExtractor::new(HeaderName::from_static("x-api-key")),
+    ///         |key: Arc<[u8]>| String::from_utf8_lossy(&key).to_ascii_lowercase(),
+    ///     ))
+    ///     .finish()
+    ///     .unwrap();
+    /// let grpc = rest
+    ///     .with_key_extractor(Normalized::new(
+    ///         MetadataKeyExtractor::new(HeaderName::from_static("api-key-bin")),
+    ///         |key: Arc<[u8]>| key.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    ///     ))
+    ///     .unwrap();
+    ///
+    /// let rest_layer = GovernorLayer::from(Arc::new(rest));
+    /// // added to the tonic server with `Server::builder().layer(grpc_layer)`
+    /// let grpc_layer = GovernorLayer::from(Arc::new(grpc));
+    /// ```
+    pub fn with_key_extractor<K2: KeyExtractor<Key = K::Key>>(
+        &self,
+        key_extractor: K2,
+    ) -> Result<GovernorConfig<K2, M, C>, ConfigError> {
+        key_extractor.validate()?;
+        // the connections cache the keys of their extractor
+        Ok(GovernorConfig {
+            charge_once: self.charge_once.map(|_| connection::next_slot()),
+            ..self.share(
+                key_extractor,
+                self.connection_slot.map(|_| connection::next_slot()),
+            )
+        })
+    }
+
+    /// The same configuration, sharing the limiter store and every other state of this one,
+    /// as [`Clone`] does: the layers of both count against the same quotas.
+    pub fn clone_shared(&self) -> Self {
+        self.clone()
+    }
+
+    /// The same configuration, sharing every state of this one, answering its errors with
+    /// `func`, see [`GovernorConfigBuilder::error_handler`].
+    pub fn with_error_handler<F>(&self, func: F) -> Self
+    where
+        F: Fn(GovernorError) -> Response<Body> + Send + Sync + 'static,
+    {
+        Self {
+            error_handler: ErrorHandler(Some(Arc::new(func))),
+            ..self.clone()
+        }
+    }
+
+    /// The same configuration, sharing every state of this one, calling `hook` with its
+    /// rejections, see [`GovernorConfigBuilder::on_rejection`].
+    pub fn with_rejection_hook<F>(&self, hook: F) -> Self
+    where
+        F: Fn(&mut Response<Body>, &RejectionContext<'_>) + Send + Sync + 'static,
+    {
+        Self {
+            rejection_hook: Some(RejectionHook(Arc::new(hook))),
+            ..self.clone()
+        }
+    }
+
+    /// The same settings with quotas of their own, e.g. to give a second router a budget
+    /// independent from the first one.
+    ///
+    /// The stores of the limiter, of the [classes](GovernorConfigBuilder::class_quota), of
+    /// the [windows](GovernorConfigBuilder::window_quota) and of the
+    /// [sliding log](GovernorConfigBuilder::sliding_log) start empty, as do the
+    /// [fair share](GovernorConfigBuilder::fair_share), the tracked states, the refunds and
+    /// the prefetched cells. The bans, penalties, statistics and hooks are still shared, as is
+    /// the quota of the [shared memory](GovernorConfigBuilder::shared_memory), which is the
+    /// same for every process of the host by design.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::net::IpAddr;
+    /// use tower_governor::governor::GovernorConfigBuilder;
+    ///
+    /// let api = GovernorConfigBuilder::default().burst_size(1).finish().unwrap();
+    /// let admin = api.clone_detached();
+    /// let ip = IpAddr::from([10, 0, 0, 1]);
+    ///
+    /// assert!(api.check_blocking(&ip, 1).is_allowed());
+    /// assert!(!api.clone_shared().check_blocking(&ip, 1).is_allowed());
+    /// assert!(admin.check_blocking(&ip, 1).is_allowed());
+    /// ```
+    pub fn clone_detached(&self) -> Self
+    where
+        C: Clone,
+    {
+        let clock = self.limiter.clock().clone();
+        let limiter = |quota| {
+            Arc::new(
+                RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                    quota,
+                    DefaultKeyedStateStore::default(),
+                    clock.clone(),
+                )
+                .with_middleware::<M>(),
+            )
+        };
+        let (period, burst_size) = (self.quota.replenish_interval(), self.quota.burst_size());
+        GovernorConfig {
+            limiter: limiter(self.quota),
+            direct: self.direct.as_ref().map(|_| {
+                Arc::new(
+                    RateLimiter::<_, _, _, NoOpMiddleware<C::Instant>>::new(
+                        self.quota,
+                        InMemoryState::default(),
+                        clock.clone(),
+                    )
+                    .with_middleware::<M>(),
+                )
+            }),
+            classes: self
+                .classes
+                .as_ref()
+                .map(|classes| Arc::new(classes.detached(limiter))),
+            windows: self
+                .windows
+                .as_ref()
+                .map(|windows| Arc::new(windows.detached())),
+            sliding_log: self
+                .sliding_log
+                .as_ref()
+                .map(|log| Arc::new(log.detached())),
+            fair_share: self
+                .fair_share
+                .as_ref()
+                .map(|share| Arc::new(share.detached())),
+            key_states: self
+                .key_states
+                .as_ref()
+                .map(|_| Arc::new(KeyStates::new(period, burst_size.get()))),
+            refunds: self.refunds.as_ref().map(|_| {
+                Arc::new(Refunds::new(
+                    self.quota.burst_size_replenished_in(),
+                    burst_size.get(),
+                ))
+            }),
+            prefetch: self
+                .prefetch
+                .as_ref()
+                .map(|prefetch| Arc::new(prefetch.detached())),
+            charge_once: self.charge_once.map(|_| connection::next_slot()),
+            ..self.clone()
+        }
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock>
+    GovernorConfig<Scoped<K>, M, C>
+{
+    /// The namespace the keys of this configuration belong to.
+    pub fn namespace(&self) -> &str {
+        self.key_extractor.scope()
+    }
+
+    /// The same configuration, namespacing its keys by `scope`.
+    ///
+    /// All scopes share the limiter store, and thus the quota, of this configuration while
+    /// keeping their keys isolated. Attach each scope to its own route with
+    /// `MethodRouter::route_layer`, as `Router::route_layer` applies to every route added
+    /// before it.
+    ///
+    /// # Example
+    /// ```rust
+    /// use axum::{routing::get, Router};
+    /// use std::sync::Arc;
+    /// use tower_governor::{
+    ///     governor::GovernorConfigBuilder,
+    ///     key_extractor::{PeerIpKeyExtractor, Scoped},
+    ///     GovernorLayer,
+    /// };
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .key_extractor(Scoped::new(PeerIpKeyExtractor))
+    ///     .finish()
+    ///     .unwrap();
+    ///
+    /// let login = GovernorLayer {
+    ///     config: Arc::new(config.scoped("login")),
+    /// };
+    /// let search = GovernorLayer {
+    ///     config: Arc::new(config.scoped("search")),
+    /// };
+    /// let app: Router = Router::new()
+    ///     .route("/login", get(|| async { "login" }).route_layer(login))
+    ///     .route("/search", get(|| async { "search" }).route_layer(search));
+    /// ```
+    pub fn scoped(&self, scope: impl Into<Arc<str>>) -> Self {
+        Self {
+            key_extractor: self.key_extractor.with_scope(scope),
+            connection_slot: self.connection_slot.map(|_| connection::next_slot()),
+            charge_once: self.charge_once.map(|_| connection::next_slot()),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for GovernorConfig<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>> {
+    /// The default configuration which is suitable for most services.
+    /// Allows bursts with up to eight requests and replenishes one element after 500ms, based on peer IP.
+    fn default() -> Self {
+        GovernorConfigBuilder::default().finish().unwrap()
+    }
+}
+
+impl<M: RateLimitingMiddleware<DefaultInstant>> GovernorConfig<PeerIpKeyExtractor, M> {
+    /// A default configuration for security related services.
+    /// Allows bursts with up to two requests and replenishes one element after four seconds, based on peer IP.
+    ///
+    /// This prevents brute-forcing passwords or security tokens
+    /// yet allows to quickly retype a wrong password once before the quota is exceeded.
+    pub fn secure() -> Self {
+        GovernorConfigBuilder::const_default()
+            .period(Duration::from_secs(4))
+            .burst_size(2)
+            .finish()
+            .unwrap()
+    }
+}
+
+/// What to do with a request, decided before it reaches the inner service.
+pub(crate) enum Verdict<P> {
+    /// The request is not subject to rate limiting.
+    Bypass,
+    /// Forward the request without adding any rate limiting headers.
+    Forward,
+    /// The request conforms to the quota, of its class if any.
+    Allowed(P, Option<&'static str>),
+    /// The request conforms to the quota, of its class if any, and is only charged by the hook
+    /// once the inner service responded, see [`GovernorConfigBuilder::charge_only`].
+    Charge(ResponseHook, Option<&'static str>),
+    /// Forward the request and hand its outcome to the hook once the inner service responded.
+    Observe(ResponseHook),
+    /// Respond right away without calling the inner service.
+    Respond(Response<Body>),
+    /// Respond with the response of the future without calling the inner service.
+    Defer(DeferredResponse),
+}
+
+// The configurations a request was already charged to, see
+// `GovernorConfigBuilder::charge_once`.
+#[derive(Debug, Clone, Default)]
+struct Charged(Vec<u64>);
+
+impl Charged {
+    /// Mark `req` as charged to the configuration `id`, returning whether it wasn't yet.
+    fn mark<B>(req: &mut Request<B>, id: u64) -> bool {
+        let Charged(ids) = req.extensions_mut().get_or_insert_default::<Charged>();
+        if ids.contains(&id) {
+            return false;
         }
+        ids.push(id);
+        true
+    }
+}
+
+type ResponseCallback = dyn FnOnce(Option<&Response<Body>>) + Send + Sync;
+
+/// Callback invoked with the response of the inner service, or `None` if it failed.
+pub(crate) struct ResponseHook(pub(crate) Box<ResponseCallback>);
+
+impl ResponseHook {
+    pub(crate) fn call(self, response: Option<&Response<Body>>) {
+        (self.0)(response)
+    }
+}
+
+impl fmt::Debug for ResponseHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseHook").finish()
+    }
+}
+
+type SnapshotCallback = dyn Fn(&RateLimitSnapshot) + Send + Sync;
+
+/// Callback invoked with the snapshot of an allowed request whose inner service failed.
+#[derive(Clone)]
+pub(crate) struct InnerErrorHook(Arc<SnapshotCallback>);
+
+impl InnerErrorHook {
+    pub(crate) fn call(&self, snapshot: &RateLimitSnapshot) {
+        (self.0)(snapshot)
     }
+}
 
-    /// Finish building the configuration and return the configuration for the middleware.
-    /// Returns `None` if either burst size or period interval are zero.
-    pub fn finish(&mut self) -> Option<GovernorConfig<K, M>> {
-        if self.burst_size != 0 && self.period.as_nanos() != 0 {
-            Some(GovernorConfig {
-                key_extractor: self.key_extractor.clone(),
-                limiter: Arc::new(
-                    RateLimiter::keyed(
-                        Quota::with_period(self.period)
-                            .unwrap()
-                            .allow_burst(NonZeroU32::new(self.burst_size).unwrap()),
-                    )
-                    .with_middleware::<M>(),
-                ),
-                methods: self.methods.clone(),
-                error_handler: self.error_handler.clone(),
-            })
-        } else {
-            None
-        }
+impl fmt::Debug for InnerErrorHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InnerErrorHook").finish()
     }
 }
 
-#[derive(Debug, Clone)]
-/// Configuration for the Governor middleware.
-pub struct GovernorConfig<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> {
-    key_extractor: K,
-    limiter: SharedRateLimiter<K::Key, M>,
-    methods: Option<Vec<Method>>,
-    error_handler: ErrorHandler,
+impl PartialEq for InnerErrorHook {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<K, M> {
-    pub fn limiter(&self) -> &SharedRateLimiter<K::Key, M> {
-        &self.limiter
+impl Eq for InnerErrorHook {}
+
+type MapperCallback = dyn Fn(&mut Response<Body>, &RateLimitSnapshot) + Send + Sync;
+
+/// Callback adjusting the responses of the layer by their snapshot, see
+/// [`GovernorConfigBuilder::map_response`].
+#[derive(Clone)]
+pub(crate) struct ResponseMapper(Arc<MapperCallback>);
+
+impl ResponseMapper {
+    pub(crate) fn call(&self, response: &mut Response<Body>, snapshot: &RateLimitSnapshot) {
+        (self.0)(response, snapshot)
     }
 }
 
-impl Default for GovernorConfig<PeerIpKeyExtractor, NoOpMiddleware> {
-    /// The default configuration which is suitable for most services.
-    /// Allows bursts with up to eight requests and replenishes one element after 500ms, based on peer IP.
-    fn default() -> Self {
-        GovernorConfigBuilder::default().finish().unwrap()
+impl fmt::Debug for ResponseMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseMapper").finish()
     }
 }
 
-impl<M: RateLimitingMiddleware<QuantaInstant>> GovernorConfig<PeerIpKeyExtractor, M> {
-    /// A default configuration for security related services.
-    /// Allows bursts with up to two requests and replenishes one element after four seconds, based on peer IP.
-    ///
-    /// This prevents brute-forcing passwords or security tokens
-    /// yet allows to quickly retype a wrong password once before the quota is exceeded.
-    pub fn secure() -> Self {
-        GovernorConfigBuilder {
-            period: Duration::from_secs(4),
-            burst_size: 2,
-            methods: None,
-            key_extractor: PeerIpKeyExtractor,
-            error_handler: ErrorHandler::default(),
-            middleware: PhantomData,
-        }
-        .finish()
-        .unwrap()
+impl PartialEq for ResponseMapper {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ResponseMapper {}
+
+type RejectionCallback = dyn Fn(&mut Response<Body>, &RejectionContext<'_>) + Send + Sync;
+
+/// Callback adjusting the response of a rejected request.
+#[derive(Clone)]
+pub(crate) struct RejectionHook(Arc<RejectionCallback>);
+
+impl fmt::Debug for RejectionHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RejectionHook").finish()
+    }
+}
+
+impl PartialEq for RejectionHook {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RejectionHook {}
+
+type MarkerCallback = dyn Fn(&HeaderValue, &Uri) -> bool + Send + Sync;
+
+/// The verifier of the [`EDGE_LIMITED_HEADER`] and what to do with requests it accepts.
+#[derive(Clone)]
+pub(crate) struct EdgeMarker {
+    action: EdgeLimited,
+    verify: Arc<MarkerCallback>,
+}
+
+impl EdgeMarker {
+    /// The action for `req`, if it carries a valid marker.
+    fn check<B>(&self, req: &Request<B>) -> Option<EdgeLimited> {
+        let value = req.headers().get(EDGE_LIMITED_HEADER)?;
+        (self.verify)(value, req.uri()).then_some(self.action)
+    }
+}
+
+impl fmt::Debug for EdgeMarker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EdgeMarker")
+            .field("action", &self.action)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for EdgeMarker {
+    fn eq(&self, other: &Self) -> bool {
+        self.action == other.action
     }
 }
 
+impl Eq for EdgeMarker {}
+
 /// Governor middleware factory. Hand this a GovernorConfig and it'll create this struct, which
 /// contains everything needed to implement a middleware
 /// https://stegosaurusdormant.com/understanding-derive-clone/
 #[derive(Debug)]
-pub struct Governor<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>, S> {
+pub struct Governor<
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    S,
+    C: Clock = DefaultClock,
+> {
     pub key_extractor: K,
-    pub limiter: SharedRateLimiter<K::Key, M>,
+    pub limiter: SharedRateLimiter<K::Key, M, C>,
     pub methods: Option<Vec<Method>>,
     pub inner: S,
-    error_handler: ErrorHandler,
+    pub(crate) config: Arc<GovernorConfig<K, M, C>>,
 }
 
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>, S: Clone> Clone
-    for Governor<K, M, S>
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, S: Clone, C: Clock> Clone
+    for Governor<K, M, S, C>
 {
     fn clone(&self) -> Self {
         Self {
@@ -339,24 +4084,1203 @@ impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>, S: Clone> Clone
             limiter: self.limiter.clone(),
             methods: self.methods.clone(),
             inner: self.inner.clone(),
-            error_handler: self.error_handler.clone(),
+            config: self.config.clone(),
         }
     }
 }
 
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>, S> Governor<K, M, S> {
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, S, C: Clock> Governor<K, M, S, C> {
     /// Create new governor middleware factory from configuration.
-    pub fn new(inner: S, config: &GovernorConfig<K, M>) -> Self {
+    ///
+    /// The middleware shares the limiter and the state of `config` rather than copying them,
+    /// so it is cheap to build one per branch of a routing service such as `tower::steer`,
+    /// each with the policy of its branch.
+    ///
+    /// # Example
+    /// ```rust
+    /// use http::{Request, Response, StatusCode};
+    /// use std::convert::Infallible;
+    /// use tower::{service_fn, steer::Steer, ServiceExt};
+    /// use tower_governor::{
+    ///     governor::{Governor, GovernorConfigBuilder},
+    ///     key_extractor::GlobalKeyExtractor,
+    /// };
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut partners = GovernorConfigBuilder::default();
+    /// let partners = partners.burst_size(100).key_extractor(GlobalKeyExtractor).finish().unwrap();
+    /// let mut public = GovernorConfigBuilder::default();
+    /// let public = public.burst_size(1).key_extractor(GlobalKeyExtractor).finish().unwrap();
+    ///
+    /// let inner = service_fn(|_: Request<String>| async {
+    ///     Ok::<_, Infallible>(Response::new(axum::body::Body::empty()))
+    /// });
+    /// let service = Steer::new(
+    ///     [Governor::new(inner, &partners), Governor::new(inner, &public)],
+    ///     |req: &Request<String>, _: &[_]| match req.headers().contains_key("x-partner-key") {
+    ///         true => 0,
+    ///         false => 1,
+    ///     },
+    /// );
+    ///
+    /// let req = || Request::new(String::new());
+    /// let response = service.clone().oneshot(req()).await.unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// let response = service.oneshot(req()).await.unwrap();
+    /// assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    /// # }
+    /// ```
+    pub fn new(inner: S, config: &GovernorConfig<K, M, C>) -> Self {
+        Self::shared(inner, Arc::new(config.clone()))
+    }
+
+    /// Create the middleware of the configuration a layer holds, without copying it.
+    pub(crate) fn shared(inner: S, config: Arc<GovernorConfig<K, M, C>>) -> Self {
         Governor {
             key_extractor: config.key_extractor.clone(),
             limiter: config.limiter.clone(),
             methods: config.methods.clone(),
             inner,
-            error_handler: config.error_handler.clone(),
+            config,
+        }
+    }
+
+    /// Check the key against the limiter, charging this instance's share of the quota when
+    /// partitioned or going through the prefetched cells when enabled.
+    pub(crate) fn check_key(
+        &self,
+        key: &K::Key,
+        class: Option<&RequestClass<K::Key, M, C>>,
+        weight: NonZeroU32,
+    ) -> Result<M::PositiveOutcome, M::NegativeOutcome>
+    where
+        M::PositiveOutcome: Clone,
+    {
+        let (limiter, quota) = match class {
+            Some(class) => (&class.limiter, &class.quota),
+            None => (&self.limiter, &self.config.quota),
+        };
+        let direct = match (class, &self.config.prefetch) {
+            (None, None) => self.config.direct.as_deref(),
+            _ => None,
+        };
+        let cost = self.cost(quota.burst_size(), weight);
+        if let (Some(direct), true) = (direct, cost.get() > 1) {
+            return direct
+                .check_n(cost)
+                .expect("the cost never exceeds the burst size");
+        }
+        if cost.get() > 1 {
+            return limiter
+                .check_key_n(key, cost)
+                .expect("the cost never exceeds the burst size");
+        }
+        if let Some(direct) = direct {
+            return direct.check();
+        }
+        if class.is_some() {
+            // prefetched cells are claimed from the default quota
+            return limiter.check_key(key);
+        }
+        match &self.config.prefetch {
+            Some(prefetch) => prefetch.check_key(&self.limiter, key),
+            None => self.limiter.check_key(key),
+        }
+    }
+
+    /// The weight of `req`, as told by the [`request_cost`] hook or the cost of upgrades.
+    ///
+    /// [`request_cost`]: GovernorConfigBuilder::request_cost
+    fn weight<B>(&self, req: &Request<B>) -> NonZeroU32 {
+        match (&self.config.request_cost, self.config.upgrade_requests) {
+            (_, UpgradeRequests::Cost(cost)) if is_upgrade(req) => {
+                NonZeroU32::new(cost).unwrap_or(NonZeroU32::MIN)
+            }
+            (Some(cost), _) => cost.of(req),
+            (None, _) => NonZeroU32::MIN,
+        }
+    }
+
+    /// The number of elements of a quota of `burst_size` charged for a request of `weight`,
+    /// scaled by this instance's share of the quota when partitioned.
+    fn cost(&self, burst_size: NonZeroU32, weight: NonZeroU32) -> NonZeroU32 {
+        let share = match &self.config.instances {
+            Some(instances) => instances.cost(burst_size),
+            None => NonZeroU32::MIN,
+        };
+        share.saturating_mul(weight).min(burst_size)
+    }
+
+    /// Decide what to do with a request. `state_headers` adds the `x-ratelimit-limit` and
+    /// `x-ratelimit-remaining` headers to rejections.
+    pub(crate) fn verdict<B>(
+        &self,
+        req: &mut Request<B>,
+        state_headers: bool,
+    ) -> Verdict<M::PositiveOutcome>
+    where
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>
+            + Send
+            + Sync
+            + 'static,
+        C: Send + Sync + 'static,
+        M::PositiveOutcome: Clone + Remaining,
+    {
+        if let Some(id) = self.config.charge_once {
+            if !Charged::mark(req, id) {
+                return Verdict::Forward;
+            }
+        }
+        let method = match (self.config.head_requests, req.method()) {
+            (HeadRequests::Exempt, &Method::HEAD) => return Verdict::Bypass,
+            (HeadRequests::AsGet, &Method::HEAD) => Method::GET,
+            (_, method) => method.clone(),
+        };
+        let method = match self
+            .config
+            .method_groups
+            .as_deref()
+            .and_then(|groups| groups.iter().find(|(member, _)| *member == method))
+        {
+            Some((_, first)) => first.clone(),
+            None => method,
+        };
+        // upgrades are handled on their own unless standard
+        let upgrade = self.config.upgrade_requests != UpgradeRequests::Standard && is_upgrade(req);
+        if let (Some(limited), None, false) =
+            (&self.config.limited, &self.config.method_rules, upgrade)
+        {
+            if !limited.matches_parts(&method, req.uri(), req.headers()) {
+                // The request method is not configured, we're ignoring this one.
+                return Verdict::Bypass;
+            }
+        }
+
+        if let Some(exemptions) = &self.config.exemptions {
+            if exemptions.iter().any(|exemption| exemption.matches(req)) {
+                return Verdict::Bypass;
+            }
+        }
+
+        let edge = self
+            .config
+            .edge_marker
+            .as_ref()
+            .and_then(|marker| marker.check(req));
+        if edge == Some(EdgeLimited::Bypass) {
+            return Verdict::Bypass;
+        }
+
+        // the banned keys stay banned while the breaker is disengaged, which takes their key
+        let disengaged = self
+            .config
+            .breaker
+            .as_ref()
+            .filter(|breaker| !breaker.engaged());
+        if let (Some(breaker), None) = (disengaged, &self.config.bans) {
+            return Verdict::Observe(breaker.watch(None));
+        }
+
+        // the peers found exempt skip the extraction of their key
+        let peer = self
+            .config
+            .exempt_peers
+            .as_ref()
+            .and_then(|_| PeerIpKeyExtractor.extract(req).ok());
+        if let (Some(exempt), Some(peer)) = (&self.config.exempt_peers, peer) {
+            if exempt.contains(peer) {
+                return Verdict::Bypass;
+            }
+        }
+
+        // Use the provided key extractor to extract the rate limiting key from the request.
+        let key = match (self.extract(req), disengaged) {
+            (Ok(key), _) => key,
+            (Err(_), Some(breaker)) => return Verdict::Observe(breaker.watch(None)),
+            // Extraction failed, stop right now.
+            (Err(e), None) => {
+                return self.respond(e.with_reason(RejectionReason::ExtractionFailed))
+            }
+        };
+
+        let (key, forwarded) = match &self.config.proxy_check {
+            Some(check) => self.check_proxy(check, req, key),
+            None => (key, false),
+        };
+
+        if !forwarded && self.is_exempt(&key) {
+            if let (Some(exempt), Some(peer)) = (&self.config.exempt_peers, peer) {
+                if self.key_extractor.key_ip(&key) == Some(peer) {
+                    exempt.insert(peer);
+                }
+            }
+            return Verdict::Bypass;
+        }
+        if let (Some(breaker), Some(bans)) = (disengaged, &self.config.bans) {
+            if bans.banned_for(&key).is_none() {
+                return Verdict::Observe(breaker.watch(None));
+            }
+        }
+        let class_of = self
+            .config
+            .classes
+            .as_deref()
+            .and_then(|classes| classes.name(&method, req, &key));
+        if upgrade && self.config.upgrade_requests == UpgradeRequests::Deny {
+            return self.respond(
+                GovernorError::Other {
+                    code: StatusCode::FORBIDDEN,
+                    msg: Some("Upgrades are not allowed".to_owned()),
+                    headers: None,
+                }
+                .with_reason(RejectionReason::UpgradeDenied),
+            );
+        }
+        if let (Some(rules), false) = (&self.config.method_rules, upgrade) {
+            let limited = rules
+                .limits(&method, class_of, || self.key_extractor.key_name(&key))
+                .unwrap_or_else(|| {
+                    self.config.limited.as_ref().is_none_or(|limited| {
+                        limited.matches_parts(&method, req.uri(), req.headers())
+                    })
+                });
+            if !limited {
+                return Verdict::Bypass;
+            }
+        }
+        if let Some(arrivals) = &self.config.arrivals {
+            arrivals.record(&key);
+        }
+        if self.config.enforce_threshold.is_some() {
+            let mode = match self.is_enforced(&key) {
+                true => EnforcementMode::Enforce,
+                false => EnforcementMode::Observe,
+            };
+            mode.annotate(req);
+        }
+
+        let class = class_of.and_then(|name| self.config.classes.as_deref()?.get(name));
+        let (limit, class_name) = match class {
+            Some(class) => (class.quota.burst_size().get(), Some(class.name)),
+            None => (self.config.quota.burst_size().get(), None),
+        };
+
+        // the cost hook is only called once, so that every store is charged the same weight
+        let weight = self.weight(req);
+        // Extraction worked, let's check if rate limiting is needed.
+        let checked = match self.forced(&key) {
+            Some(checked) => checked,
+            None => match self.churn_wait_time(req, &key) {
+                Some(wait_time) => ControlFlow::Continue(wait_time),
+                None => match self.penalized_for(&key) {
+                    Some(left) => ControlFlow::Continue(left),
+                    None => self.check(req, &key, class, weight),
+                },
+            },
+        };
+        // the requests allowed for their key may still exceed the share of their class
+        let (mut share, mut shared) = (None, self.config.direct.is_some());
+        let checked = match (checked, &self.config.fair_share) {
+            (
+                ControlFlow::Break(
+                    verdict @ (Verdict::Allowed(..) | Verdict::Charge(..) | Verdict::Observe(_)),
+                ),
+                Some(fair_share),
+            ) => match fair_share.admit(class_of) {
+                Ok(()) => ControlFlow::Break(verdict),
+                Err((wait_time, class_share)) => {
+                    (share, shared) = (class_share, true);
+                    ControlFlow::Continue(self.clamp_wait_time(wait_time, &fair_share.quota))
+                }
+            },
+            (checked, _) => checked,
+        };
+        // and those allowed by their quota may still exceed a longer window
+        let mut exceeded = None;
+        let checked = match (checked, &self.config.windows) {
+            (
+                ControlFlow::Break(
+                    verdict @ (Verdict::Allowed(..) | Verdict::Charge(..) | Verdict::Observe(_)),
+                ),
+                Some(windows),
+            ) => match windows.check(&key) {
+                Ok(policies) => {
+                    if state_headers {
+                        req.extensions_mut().insert(policies);
+                    }
+                    ControlFlow::Break(verdict)
+                }
+                Err((wait_time, window)) => {
+                    exceeded = Some(window);
+                    ControlFlow::Continue(self.clamp_wait_time(wait_time, &window.quota))
+                }
+            },
+            (checked, _) => checked,
+        };
+        // and those allowed in this process may still exceed the quota shared by the host
+        let checked = match checked {
+            ControlFlow::Break(
+                verdict @ (Verdict::Allowed(..) | Verdict::Charge(..) | Verdict::Observe(_)),
+            ) => match self.shared_wait_time(&key, weight) {
+                Some(wait_time) => {
+                    ControlFlow::Continue(self.clamp_wait_time(wait_time, &self.config.quota))
+                }
+                None => ControlFlow::Break(verdict),
+            },
+            checked => checked,
+        };
+        // and those within every window may still exceed the exact count of the log
+        let mut logged = None;
+        let checked = match (checked, &self.config.sliding_log) {
+            (
+                ControlFlow::Break(
+                    verdict @ (Verdict::Allowed(..) | Verdict::Charge(..) | Verdict::Observe(_)),
+                ),
+                Some(log),
+            ) => match log.check(&key) {
+                Ok(()) => ControlFlow::Break(verdict),
+                Err(wait_time) => {
+                    logged = Some(log.max_requests);
+                    ControlFlow::Continue(
+                        self.config
+                            .max_wait_time
+                            .map_or(wait_time, |max| wait_time.min(max)),
+                    )
+                }
+            },
+            (checked, _) => checked,
+        };
+        if let (
+            ControlFlow::Break(Verdict::Allowed(..) | Verdict::Charge(..)),
+            true,
+            Some(states),
+        ) = (
+            &checked,
+            state_headers && self.config.window_start_header,
+            &self.config.key_states,
+        ) {
+            if let Some(start) = states.window_start(&key) {
+                req.extensions_mut().insert(WindowStart(start));
+            }
+        }
+        if let Some(watermarks) = &self.config.watermarks {
+            let (classes, windows) = (
+                self.config.classes.as_deref(),
+                self.config.windows.as_deref(),
+            );
+            watermarks.check(stored_keys(&self.limiter, classes, windows), || {
+                retain_recent(&self.limiter, classes, windows);
+                stored_keys(&self.limiter, classes, windows)
+            });
+        }
+        let wait_time = match checked {
+            ControlFlow::Continue(wait_time) => wait_time,
+            ControlFlow::Break(verdict) => {
+                if let Some(penalties) = &self.config.penalties {
+                    penalties.forgive(&key);
+                }
+                self.audit(req, &key, Decision::Allowed);
+                self.publish(Decision::Allowed);
+                if let Some(report) = &self.config.report {
+                    report.record(&key, false);
+                }
+                if let Some(rates) = &self.config.rates {
+                    rates.record(false);
+                }
+                if let Some(route_rates) = &self.config.route_rates {
+                    route_rates.record(matched_path(req), false);
+                }
+                if let Some(cohort_rates) = &self.config.cohort_rates {
+                    let cohort = self.key_extractor.cohort(&key).unwrap_or(DEFAULT_COHORT);
+                    cohort_rates.record(Some(cohort), false);
+                }
+                if let Some(replay) = &self.config.replay {
+                    let remaining = match &verdict {
+                        Verdict::Allowed(outcome, _) => outcome.remaining(),
+                        _ => None,
+                    };
+                    replay.record(&key, Decision::Allowed, remaining);
+                }
+                let watch = match (&self.config.auth_failures, &self.config.bans) {
+                    (Some(failures), Some(bans)) => Some(failures.watch(key.clone(), bans.clone())),
+                    _ => None,
+                };
+                let verdict = match (watch, verdict) {
+                    (Some(watch), Verdict::Charge(hook, class)) => {
+                        let watch = watch.into_hook();
+                        let hook = ResponseHook(Box::new(move |response| {
+                            watch.call(response);
+                            hook.call(response);
+                        }));
+                        Verdict::Charge(hook, class)
+                    }
+                    (Some(watch), verdict @ Verdict::Allowed(..)) => {
+                        watch.attach(req);
+                        verdict
+                    }
+                    (_, verdict) => verdict,
+                };
+                // the allowed requests are otherwise counted by their response future
+                let verdict = match (self.status_hook(class_name), verdict) {
+                    (Some(count), Verdict::Charge(hook, class)) => {
+                        let hook = ResponseHook(Box::new(move |response| {
+                            count.call(response);
+                            hook.call(response);
+                        }));
+                        Verdict::Charge(hook, class)
+                    }
+                    (_, verdict) => verdict,
+                };
+                return match (&self.config.breaker, verdict) {
+                    // keep watching the inner service to disengage
+                    (Some(breaker), Verdict::Allowed(..)) => {
+                        Verdict::Observe(breaker.watch(self.response_hook(req, class_name)))
+                    }
+                    (Some(breaker), Verdict::Charge(hook, _) | Verdict::Observe(hook)) => {
+                        Verdict::Observe(breaker.watch(Some(hook)))
+                    }
+                    (_, verdict) => verdict,
+                };
+            }
+        };
+        if let Some(one_in) = self.config.sample_over_quota {
+            let banned = self
+                .config
+                .bans
+                .as_ref()
+                .and_then(|bans| bans.banned_for(&key));
+            if banned.is_none() && random().is_multiple_of(u64::from(one_in.get())) {
+                Sampled { wait_time }.annotate(req);
+                return Verdict::Forward;
+            }
+        }
+        let (limit, class_name, scope) = match (share, exceeded) {
+            (Some(share), _) => (share.burst_size, Some(share.name), share.scope.as_ref()),
+            (None, Some(window)) => (
+                window.quota.burst_size().get(),
+                class_name,
+                window.scope.as_ref(),
+            ),
+            (None, None) => (
+                logged.unwrap_or(limit),
+                class_name,
+                class.map_or(self.config.scope.as_ref(), |class| class.scope.as_ref()),
+            ),
+        };
+        // the requests only observed are neither penalized nor banned
+        let enforced =
+            !self.config.decide_only && edge != Some(EdgeLimited::DryRun) && self.is_enforced(&key);
+        let wait_time = match (&self.config.penalties, enforced) {
+            (Some(penalties), true) => penalties.penalize(&key, wait_time),
+            _ => wait_time,
+        };
+        let ban = self
+            .config
+            .bans
+            .as_ref()
+            .filter(|_| enforced)
+            .and_then(|bans| bans.escalate(&key, wait_time));
+        let wait_time = ban.map_or(wait_time, Ban::time_left);
+        if let (Some(Ban::New(_)), Some(bans)) = (ban, &self.config.bans) {
+            if let Some(observer) = &bans.observer {
+                let key_name = self.key_extractor.key_name(&key).map(|name| redact(&name));
+                (observer.0)(&RejectionContext {
+                    key: key_name.as_deref(),
+                    wait_time,
+                    policy: self.config.policy_name.as_deref(),
+                    route: req.uri().path(),
+                    headers: req.headers(),
+                });
+            }
+        }
+        self.audit(req, &key, Decision::Rejected { wait_time });
+        self.publish(Decision::Rejected { wait_time });
+        if let Some(report) = &self.config.report {
+            report.record(&key, true);
+        }
+        if let Some(rates) = &self.config.rates {
+            rates.record(true);
+        }
+        if let Some(route_rates) = &self.config.route_rates {
+            route_rates.record(matched_path(req), true);
+        }
+        if let Some(cohort_rates) = &self.config.cohort_rates {
+            let cohort = self.key_extractor.cohort(&key).unwrap_or(DEFAULT_COHORT);
+            cohort_rates.record(Some(cohort), true);
+        }
+        if let Some(replay) = &self.config.replay {
+            replay.record(&key, Decision::Rejected { wait_time }, Some(0));
+        }
+
+        if !enforced {
+            Decision::Rejected { wait_time }.annotate(req);
+            return Verdict::Forward;
+        }
+        let advertised = match ban {
+            Some(_) => wait_time,
+            None => self.advertised_wait_time(wait_time),
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            // the cohort of the key stands for it when known
+            let key_name = match self.key_extractor.cohort(&key) {
+                Some(cohort) => Some(cohort.to_owned()),
+                None => self.key_extractor.key_name(&key),
+            };
+            let key_name = match key_name {
+                Some(n) => format!(" [{}]", &n),
+                None => "".to_owned(),
+            };
+            let class_name = match class_name {
+                Some(name) => format!(" on {}", name),
+                None => "".to_owned(),
+            };
+            self.config.rejection_level.log(format_args!(
+                "Rate limit exceeded for {}{}{}, quota reset in {}s",
+                self.key_extractor.name(),
+                key_name,
+                class_name,
+                advertised.as_secs()
+            ));
+        }
+
+        // compiled out for the bare middleware
+        let headers = M::PositiveOutcome::HEADERS.then(|| {
+            let mut headers = HeaderMap::new();
+            QuotaHeaders {
+                limit,
+                remaining: state_headers.then_some(0),
+                bare_limit: self.config.limit_on_rejections,
+                after: Some(advertised),
+                after_unit: self.config.wait_time_unit,
+                retry_after: self.config.retry_after,
+                credits: self.config.credits,
+                class: class_name,
+                scope,
+            }
+            .write(&mut headers);
+            if let Some((name, value)) = &self.config.methods_hint {
+                headers.insert(name.clone(), value.clone());
+            }
+            match (ban, shared) {
+                (Some(_), _) => RejectionReason::Banned,
+                (None, true) => RejectionReason::GlobalCeiling,
+                (None, false) => RejectionReason::QuotaExceeded,
+            }
+            .insert(&mut headers);
+            headers
+        });
+
+        let error = match ban {
+            Some(_) => GovernorError::Other {
+                code: StatusCode::FORBIDDEN,
+                msg: Some(format!("Forbidden! Banned for {}s", advertised.as_secs())),
+                headers,
+            },
+            None => GovernorError::TooManyRequests {
+                wait_time: advertised.as_secs(),
+                wait_duration: advertised,
+                headers,
+            },
+        };
+        let snapshot = RateLimitSnapshot {
+            limit,
+            remaining: Some(0),
+            decision: Decision::Rejected { wait_time },
+        };
+        if let Some(handler) = &self.config.async_error_handler {
+            let future = (handler.0)(error);
+            let hook = self.config.rejection_hook.clone().map(|hook| {
+                let key_name = self.key_extractor.key_name(&key).map(|name| redact(&name));
+                let policy = self.config.policy_name.clone();
+                (
+                    hook,
+                    key_name,
+                    policy,
+                    req.uri().path().to_owned(),
+                    req.headers().clone(),
+                )
+            });
+            let attributes = self.config.rejection_attributes.clone();
+            let mapper = self.config.response_mapper.clone();
+            return Verdict::Defer(Box::pin(async move {
+                let mut response = future.await;
+                attributes.apply(&mut response);
+                response.extensions_mut().insert(snapshot);
+                if let Some((hook, key_name, policy, route, headers)) = hook {
+                    (hook.0)(
+                        &mut response,
+                        &RejectionContext {
+                            key: key_name.as_deref(),
+                            wait_time,
+                            policy: policy.as_deref(),
+                            route: &route,
+                            headers: &headers,
+                        },
+                    );
+                }
+                if let Some(mapper) = mapper {
+                    mapper.call(&mut response, &snapshot);
+                }
+                response
+            }));
+        }
+        let mut response = match (&self.config.rejection_message, error) {
+            (Some(message), GovernorError::TooManyRequests { headers, .. })
+                if self.config.error_handler.0.is_none() =>
+            {
+                let mut response =
+                    Response::new(Body::from(message.render(advertised.as_secs(), req)));
+                *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                *response.headers_mut() = headers.unwrap_or_default();
+                if let Some(content_type) = &message.1 {
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, content_type.clone());
+                }
+                response
+            }
+            (_, error) => self.error_handler()(error),
+        };
+        self.config.rejection_attributes.apply(&mut response);
+        response.extensions_mut().insert(snapshot);
+        if let Some(hook) = &self.config.rejection_hook {
+            let key_name = self.key_extractor.key_name(&key).map(|name| redact(&name));
+            (hook.0)(
+                &mut response,
+                &RejectionContext {
+                    key: key_name.as_deref(),
+                    wait_time,
+                    policy: self.config.policy_name.as_deref(),
+                    route: req.uri().path(),
+                    headers: req.headers(),
+                },
+            );
+        }
+        if let Some(mapper) = &self.config.response_mapper {
+            mapper.call(&mut response, &snapshot);
+        }
+        Verdict::Respond(response)
+    }
+
+    /// Respond with `error`, through the async error handler if any.
+    fn respond<P>(&self, error: GovernorError) -> Verdict<P> {
+        match &self.config.async_error_handler {
+            Some(handler) => Verdict::Defer((handler.0)(error)),
+            None => Verdict::Respond(self.error_handler()(error)),
+        }
+    }
+
+    /// A hook counting the response of an allowed request of `class` by status, if
+    /// [`track_statuses`](GovernorConfigBuilder::track_statuses) is set.
+    pub(crate) fn status_hook(&self, class: Option<&'static str>) -> Option<ResponseHook> {
+        let statuses = self.config.statuses.clone()?;
+        Some(ResponseHook(Box::new(move |response| {
+            statuses.record(class, response.map(Response::status));
+        })))
+    }
+
+    /// The hook watching the response to an allowed request of `class`: the
+    /// [status counts](GovernorConfigBuilder::track_statuses) and the
+    /// [authentication failures](GovernorConfigBuilder::limit_auth_failures) of its key.
+    pub(crate) fn response_hook<B>(
+        &self,
+        req: &mut Request<B>,
+        class: Option<&'static str>,
+    ) -> Option<ResponseHook> {
+        let count = self.status_hook(class);
+        match (AuthWatch::take(req), count) {
+            (Some(watch), Some(count)) => {
+                let watch = watch.into_hook();
+                Some(ResponseHook(Box::new(move |response| {
+                    watch.call(response);
+                    count.call(response);
+                })))
+            }
+            (Some(watch), None) => Some(watch.into_hook()),
+            (None, count) => count,
+        }
+    }
+
+    /// The value of the scope header of the requests of `class`.
+    pub(crate) fn scope(&self, class: Option<&str>) -> Option<&HeaderValue> {
+        match class.and_then(|name| self.config.classes.as_deref()?.get(name)) {
+            Some(class) => class.scope.as_ref(),
+            None => self.config.scope.as_ref(),
+        }
+    }
+
+    /// The headers of an allowed request handed over by [`verdict`](Self::verdict): the
+    /// policies of its quota and of the [windows](GovernorConfigBuilder::window_quota), and
+    /// the [window start](GovernorConfigBuilder::window_start_header) of its key.
+    pub(crate) fn extra_headers<B>(
+        &self,
+        req: &mut Request<B>,
+        remaining: Option<u32>,
+        class: Option<&str>,
+    ) -> Option<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        if let Some(windows) = req.extensions_mut().remove::<WindowPolicies>() {
+            let quota = class
+                .and_then(|name| self.config.classes.as_deref()?.get(name))
+                .map_or(self.config.quota, |class| class.quota);
+            let name = self
+                .scope(class)
+                .and_then(|scope| scope.to_str().ok())
+                .or(class)
+                .unwrap_or("default");
+            windows.with(name, quota, remaining).write(&mut headers);
+        }
+        if let Some(WindowStart(start)) = req.extensions_mut().remove() {
+            let start = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+            headers.insert(WINDOW_START_HEADER, start.as_secs().into());
+        }
+        (!headers.is_empty()).then_some(headers)
+    }
+
+    /// Snapshot of the quota of a key whose request was allowed.
+    pub(crate) fn allowed_snapshot(
+        &self,
+        remaining: Option<u32>,
+        class: Option<&str>,
+    ) -> RateLimitSnapshot {
+        let quota = class
+            .and_then(|name| self.config.classes.as_deref()?.get(name))
+            .map_or(self.config.quota, |class| class.quota);
+        RateLimitSnapshot {
+            limit: quota.burst_size().get(),
+            remaining,
+            decision: Decision::Allowed,
+        }
+    }
+
+    /// Check the key against the limiter, continuing with the time to wait if it exceeds the
+    /// quota.
+    fn check<B>(
+        &self,
+        req: &mut Request<B>,
+        key: &K::Key,
+        class: Option<&RequestClass<K::Key, M, C>>,
+        weight: NonZeroU32,
+    ) -> ControlFlow<Verdict<M::PositiveOutcome>, Duration>
+    where
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>
+            + Send
+            + Sync
+            + 'static,
+        C: Send + Sync + 'static,
+        M::PositiveOutcome: Clone,
+    {
+        match &self.config.failure_charging {
+            Some(charging) => match charging.blocked_for(key) {
+                Some(wait_time) => {
+                    ControlFlow::Continue(self.clamp_wait_time(wait_time, &self.config.quota))
+                }
+                None => {
+                    if self.config.decide_only {
+                        Decision::Allowed.annotate(req);
+                    }
+                    let limiter = class.map_or(&self.limiter, |class| &class.limiter);
+                    let hook = self.charge_failure(key.clone(), charging.clone(), limiter.clone());
+                    ControlFlow::Break(Verdict::Charge(hook, class.map(|class| class.name)))
+                }
+            },
+            None => match self.check_key(key, class, weight) {
+                Ok(outcome) => {
+                    if let (Some(states), None) = (&self.config.key_states, class) {
+                        states.charge(key, self.cost(self.config.quota.burst_size(), weight));
+                    }
+                    if let (Some(refunds), None, true) =
+                        (&self.config.refunds, class, self.config.refund_cancelled)
+                    {
+                        let cost = self.cost(self.config.quota.burst_size(), weight);
+                        Refund::new(refunds.clone(), key.clone(), cost.get()).attach(req);
+                    }
+                    if self.config.decide_only {
+                        Decision::Allowed.annotate(req);
+                    }
+                    ControlFlow::Break(Verdict::Allowed(outcome, class.map(|class| class.name)))
+                }
+                Err(negative) => match (&self.config.refunds, class) {
+                    // spend the cells refunded by cancelled requests, without headers as the
+                    // limiter has no state to report
+                    (Some(refunds), None)
+                        if refunds
+                            .take(key, self.cost(self.config.quota.burst_size(), weight).get()) =>
+                    {
+                        ControlFlow::Break(Verdict::Forward)
+                    }
+                    _ => ControlFlow::Continue(self.wait_time(&negative)),
+                },
+            },
+        }
+    }
+
+    /// The decision forced onto this check by the `test-util` hooks, if any.
+    #[cfg(feature = "test-util")]
+    fn forced<P>(&self, key: &K::Key) -> Option<ControlFlow<Verdict<P>, Duration>> {
+        self.config.injections.take(key).map(|forced| match forced {
+            Forced::Allow => ControlFlow::Break(Verdict::Forward),
+            Forced::Reject { wait_time } => ControlFlow::Continue(wait_time),
+        })
+    }
+
+    #[cfg(not(feature = "test-util"))]
+    fn forced<P>(&self, _key: &K::Key) -> Option<ControlFlow<Verdict<P>, Duration>> {
+        None
+    }
+
+    /// Record the decision taken for the request into the audit sink, if any.
+    #[cfg(feature = "audit")]
+    fn audit<B>(&self, req: &Request<B>, key: &K::Key, decision: Decision) {
+        if let Some(sink) = &self.config.audit_sink {
+            sink.record(GovernorEvent {
+                timestamp: SystemTime::now(),
+                key: format!("{:?}", key),
+                source: self.key_extractor.key_source(key),
+                decision,
+                route: req.uri().path().to_owned(),
+                policy: self.config.policy_name.clone(),
+                enforced: !self.config.decide_only && self.is_enforced(key),
+            });
+        }
+    }
+
+    #[cfg(not(feature = "audit"))]
+    fn audit<B>(&self, _req: &Request<B>, _key: &K::Key, _decision: Decision) {}
+
+    /// Hand the decision to the decision streams, if any.
+    #[cfg(feature = "stream")]
+    fn publish(&self, decision: Decision) {
+        self.config.decision_streams.publish(decision);
+    }
+
+    #[cfg(not(feature = "stream"))]
+    fn publish(&self, _decision: Decision) {}
+
+    /// Whether `key` belongs to the cohort the quota is enforced for, see
+    /// [`GovernorConfigBuilder::enforce_ratio`].
+    fn is_enforced(&self, key: &K::Key) -> bool {
+        match self.config.enforce_threshold {
+            // the default hasher uses fixed keys, keeping the cohorts stable across restarts
+            Some(threshold) => {
+                BuildHasherDefault::<DefaultHasher>::default().hash_one(key) < threshold
+            }
+            None => true,
+        }
+    }
+
+    /// How long to reject the request for if its key exceeds the quota shared by the host,
+    /// charging it otherwise.
+    #[cfg(feature = "shared-memory")]
+    fn shared_wait_time(&self, key: &K::Key, weight: NonZeroU32) -> Option<Duration> {
+        let shared = self.config.shared_memory.as_ref()?;
+        let cells = self.cost(self.config.quota.burst_size(), weight);
+        shared.check(replay::key_hash(key), cells.get()).err()
+    }
+
+    #[cfg(not(feature = "shared-memory"))]
+    fn shared_wait_time(&self, _key: &K::Key, _weight: NonZeroU32) -> Option<Duration> {
+        None
+    }
+
+    /// How long to reject the request for if its peer introduced too many distinct keys.
+    fn churn_wait_time<B>(&self, req: &Request<B>, key: &K::Key) -> Option<Duration> {
+        let churn = self.config.key_churn.as_ref()?;
+        let peer = PeerIpKeyExtractor.extract(req).ok()?;
+        churn.check(peer, key)
+    }
+
+    /// Extract the key of the request, going through the connection cache and the cache of
+    /// extraction failures when enabled, unless the request holds a [`PreExtractedKey`].
+    fn extract<B>(&self, req: &Request<B>) -> Result<K::Key, GovernorError>
+    where
+        K::Key: Send + Sync + 'static,
+    {
+        if let Some(PreExtractedKey(key)) = req.extensions().get::<PreExtractedKey<K::Key>>() {
+            return Ok(key.clone());
+        }
+        let Some(failures) = &self.config.extraction_failures else {
+            return self.extract_uncached(req);
+        };
+        let Ok(peer) = PeerIpKeyExtractor.extract(req) else {
+            return self.extract_uncached(req);
+        };
+        // a proxy relays the requests of many clients
+        if forwarded_ip(req.headers()).is_some_and(|client| client != peer) {
+            return self.extract_uncached(req);
+        }
+        if let Some(error) = failures.get(peer) {
+            return Err(error);
+        }
+        self.extract_uncached(req)
+            .inspect_err(|error| failures.insert(peer, error))
+    }
+
+    /// Extract the key of the request, going through the connection cache when enabled.
+    fn extract_uncached<B>(&self, req: &Request<B>) -> Result<K::Key, GovernorError>
+    where
+        K::Key: Send + Sync + 'static,
+    {
+        match (
+            self.config.connection_slot,
+            req.extensions().get::<ConnectionKeyCache>(),
+        ) {
+            (Some(slot), Some(cache)) => {
+                cache.get_or_try_insert(slot, || self.key_extractor.extract(req))
+            }
+            _ => self.key_extractor.extract(req),
+        }
+    }
+
+    /// Feed the proxy misconfiguration check, returning the key to rate limit the request by
+    /// and whether it was taken from the forwarding headers.
+    fn check_proxy<B>(&self, check: &ProxyCheck, req: &Request<B>, key: K::Key) -> (K::Key, bool) {
+        let Some(peer) = self.key_extractor.key_ip(&key) else {
+            return (key, false);
+        };
+        match forwarded_ip(req.headers()) {
+            Some(client) if client != peer && check.observe(peer) => {
+                match self.key_extractor.key_from_ip(&key, client) {
+                    Some(forwarded) => (forwarded, true),
+                    None => (key, false),
+                }
+            }
+            _ => (key, false),
+        }
+    }
+
+    /// Whether the key is exempt from rate limiting.
+    fn is_exempt(&self, key: &K::Key) -> bool {
+        if !self.config.exempt_loopback && !self.config.exempt_private_ranges {
+            return false;
+        }
+        match self.key_extractor.key_ip(key) {
+            Some(ip) => {
+                (self.config.exempt_loopback && ip.is_loopback())
+                    || (self.config.exempt_private_ranges && is_private(&ip))
+            }
+            None => false,
+        }
+    }
+
+    /// Hook charging the key once the response turns out to be a failure.
+    fn charge_failure(
+        &self,
+        key: K::Key,
+        charging: Arc<FailureCharging<K::Key>>,
+        limiter: SharedRateLimiter<K::Key, M, C>,
+    ) -> ResponseHook
+    where
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>
+            + Send
+            + Sync
+            + 'static,
+        C: Send + Sync + 'static,
+    {
+        ResponseHook(Box::new(move |response| {
+            if charging.is_failure(response) {
+                if let Err(negative) = limiter.check_key(&key) {
+                    charging.block(key, negative.wait_time_from(limiter.clock().now()));
+                }
+            }
+        }))
+    }
+
+    /// Decide on a key outside of any request, as the HTTP middleware would for a request
+    /// of that key and `weight`.
+    pub(crate) fn decide(&self, key: &K::Key, weight: NonZeroU32) -> Decision
+    where
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone,
+    {
+        if self.is_exempt(key) {
+            return Decision::Allowed;
+        }
+        match self.charge(key, weight) {
+            Ok(_) => Decision::Allowed,
+            Err(wait_time) => Decision::Rejected { wait_time },
+        }
+    }
+
+    /// Decide on a request outside of the HTTP middleware, see
+    /// [`GovernorConfig::check_request`].
+    pub(crate) fn check_request<B>(&self, req: &Request<B>) -> RequestDecision
+    where
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone + Remaining,
+    {
+        let key = match self.extract(req) {
+            Ok(key) => key,
+            Err(error) => return RequestDecision::Error(error),
+        };
+        let (key, forwarded) = match &self.config.proxy_check {
+            Some(check) => self.check_proxy(check, req, key),
+            None => (key, false),
+        };
+        if !forwarded && self.is_exempt(&key) {
+            return RequestDecision::Exempt;
+        }
+        match self.charge(&key, self.weight(req)) {
+            Ok(outcome) => RequestDecision::Allowed {
+                snapshot: self.allowed_snapshot(outcome.and_then(|o| o.remaining()), None),
+            },
+            Err(wait_time) => RequestDecision::Rejected { wait_time },
+        }
+    }
+
+    /// Charge `weight` elements of the quota to `key`, returning the outcome of the limiter,
+    /// `None` if refunded cells were spent instead, or the time to wait.
+    fn charge(
+        &self,
+        key: &K::Key,
+        weight: NonZeroU32,
+    ) -> Result<Option<M::PositiveOutcome>, Duration>
+    where
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone,
+    {
+        if let Some(wait_time) = self
+            .config
+            .failure_charging
+            .as_ref()
+            .and_then(|charging| charging.blocked_for(key))
+        {
+            return Err(self.clamp_wait_time(wait_time, &self.config.quota));
+        }
+        match self.check_key(key, None, weight) {
+            Ok(outcome) => match self.shared_wait_time(key, weight) {
+                Some(wait_time) => Err(self.clamp_wait_time(wait_time, &self.config.quota)),
+                None => Ok(Some(outcome)),
+            },
+            Err(_)
+                if self.config.refunds.as_ref().is_some_and(|refunds| {
+                    refunds.take(key, self.cost(self.config.quota.burst_size(), weight).get())
+                }) =>
+            {
+                Ok(None)
+            }
+            Err(negative) => Err(self.wait_time(&negative)),
+        }
+    }
+
+    /// Reserve `weight` of the quota of `key` for a lease, see [`GovernorConfig::reserve`].
+    pub(crate) fn lease(
+        &self,
+        key: K::Key,
+        weight: NonZeroU32,
+        ttl: Duration,
+        refunds: Arc<Refunds<K::Key>>,
+    ) -> Result<Lease<K::Key>, LeaseError>
+    where
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+        M::PositiveOutcome: Clone,
+    {
+        if let Decision::Rejected { wait_time } = self.decide(&key, weight) {
+            return Err(LeaseError::TooManyRequests { wait_time });
+        }
+        // exempt keys aren't charged, there is nothing to give back
+        let cells = match self.is_exempt(&key) {
+            true => 0,
+            false => self.cost(self.config.quota.burst_size(), weight).get(),
+        };
+        Ok(Lease::new(refunds, key, weight.get(), cells, ttl))
+    }
+
+    /// Time until a rejected request would be allowed, clamped to sane bounds.
+    pub(crate) fn wait_time(&self, negative: &NotUntil<C::Instant>) -> Duration {
+        let wait_time = negative.wait_time_from(self.limiter.clock().now());
+        self.clamp_wait_time(wait_time, &negative.quota())
+    }
+
+    fn clamp_wait_time(&self, mut wait_time: Duration, quota: &Quota) -> Duration {
+        let replenished_in = quota.burst_size_replenished_in();
+        if wait_time > replenished_in {
+            #[cfg(feature = "tracing")]
+            {
+                // how many times the whole quota the wait time must exceed to point to clock skew
+                const SKEW_FACTOR: u32 = 10;
+                if wait_time > replenished_in.saturating_mul(SKEW_FACTOR) {
+                    tracing::warn!(
+                        "Computed wait time of {:?} exceeds {} times the {:?} needed to replenish the whole quota, check the clock source of this host",
+                        wait_time,
+                        SKEW_FACTOR,
+                        replenished_in
+                    );
+                }
+            }
+            wait_time = replenished_in;
+        }
+
+        match self.config.max_wait_time {
+            Some(max) => wait_time.min(max),
+            None => wait_time,
+        }
+    }
+
+    /// The time left until `key` is let through again, if banned or penalized.
+    fn penalized_for(&self, key: &K::Key) -> Option<Duration> {
+        let banned = self
+            .config
+            .bans
+            .as_ref()
+            .and_then(|bans| bans.banned_for(key));
+        banned.or_else(|| self.config.penalties.as_ref()?.penalized_for(key))
+    }
+
+    /// The wait time told to the client, jittered if configured.
+    fn advertised_wait_time(&self, wait_time: Duration) -> Duration {
+        match self.config.retry_jitter {
+            Some(jitter) if !jitter.is_zero() => {
+                let jitter_nanos = u64::try_from(jitter.as_nanos()).unwrap_or(u64::MAX);
+                let jittered =
+                    wait_time + Duration::from_nanos(random() % jitter_nanos.saturating_add(1));
+                // clamped after the jitter, still never below the actual wait time
+                match self.config.max_wait_time {
+                    Some(max) => jittered.min(max.max(wait_time)),
+                    None => jittered,
+                }
+            }
+            _ => wait_time,
+        }
+    }
+
+    pub(crate) fn error_handler(&self) -> &ErrorFn {
+        match &self.config.error_handler.0 {
+            Some(handler) => &**handler,
+            None => &default_error_handler,
         }
     }
+}
+
+/// A random number, as a freshly seeded hasher is a cheap source of randomness.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
 
-    pub(crate) fn error_handler(&self) -> &(dyn Fn(GovernorError) -> Response<Body> + Send + Sync) {
-        &*self.error_handler.0
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xfe00) == 0xfc00,
     }
 }
+
+/// The route axum matched the request to, if any.
+#[cfg(feature = "axum")]
+fn matched_path<T>(req: &Request<T>) -> Option<&str> {
+    req.extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|path| path.as_str())
+}
+
+/// The route axum matched the request to, if any.
+#[cfg(not(feature = "axum"))]
+fn matched_path<T>(_req: &Request<T>) -> Option<&str> {
+    None
+}
@@ -0,0 +1,121 @@
+//! Mapping of boxed errors into responses, for the error handling layers of a service stack.
+//!
+//! [`GovernorError`]s are rendered as by their [`as_response`], any other error as a
+//! `500 Internal Server Error`.
+//!
+//! # Example
+//! ```rust
+//! use axum::{error_handling::HandleErrorLayer, extract::Request, response::Response, Router};
+//! use tower::{service_fn, BoxError, ServiceBuilder};
+//! use tower_governor::handle_error::display_error;
+//!
+//! let backend = service_fn(|_: Request| async { Err::<Response, BoxError>("backend down".into()) });
+//! let app: Router = Router::new().route_service(
+//!     "/",
+//!     ServiceBuilder::new()
+//!         .layer(HandleErrorLayer::new(display_error))
+//!         .service(backend),
+//! );
+//! ```
+//!
+//! [`as_response`]: GovernorError::as_response
+
+use crate::{body::RejectionBody, errors::GovernorError};
+use http::{
+    header::{HeaderName, CONTENT_TYPE},
+    HeaderMap, HeaderValue, Response, StatusCode,
+};
+use std::fmt::Write;
+use tower::BoxError;
+
+const GRPC_STATUS: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_MESSAGE: HeaderName = HeaderName::from_static("grpc-message");
+
+/// Split `error` into the status code, headers and body of its response.
+///
+/// The tuple implements axum's `IntoResponse`.
+pub fn into_parts(error: BoxError) -> (StatusCode, HeaderMap, String) {
+    match error.downcast::<GovernorError>() {
+        Ok(mut error) => {
+            let (parts, body) = error.as_response::<String>().into_parts();
+            (parts.status, parts.headers, body)
+        }
+        Err(error) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            HeaderMap::new(),
+            format!("Unhandled internal error: {}", error),
+        ),
+    }
+}
+
+/// Render `error` as a response, for plain `http` services.
+///
+/// The body can be of any [`RejectionBody`] type, such as hyper's `Full<Bytes>`.
+pub fn into_response<B>(error: BoxError) -> Response<B>
+where
+    B: RejectionBody,
+{
+    let (status, headers, body) = into_parts(error);
+    let mut response = Response::new(B::from_bytes(body.into()));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+/// Error handler for axum's `HandleErrorLayer`, see the [module documentation](self).
+pub async fn display_error(error: BoxError) -> (StatusCode, HeaderMap, String) {
+    into_parts(error)
+}
+
+/// Render `error` as a trailers-only gRPC response, as a `tonic::Status` would be.
+///
+/// The gRPC status is derived from the HTTP status of the error, rate limited requests
+/// getting `RESOURCE_EXHAUSTED`. The headers of the error, such as `retry-after`, are kept.
+pub fn into_grpc_response<B>(error: BoxError) -> Response<B>
+where
+    B: Default,
+{
+    let (status, mut headers, message) = into_parts(error);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    headers.insert(GRPC_STATUS, HeaderValue::from(grpc_code(status)));
+    if let Ok(message) = HeaderValue::try_from(percent_encode(&message)) {
+        headers.insert(GRPC_MESSAGE, message);
+    }
+    let mut response = Response::new(B::default());
+    *response.headers_mut() = headers;
+    response
+}
+
+/// The gRPC status code standing for an HTTP status code.
+///
+/// Follows the [gRPC mapping] of HTTP status codes, except for `429 Too Many Requests`
+/// which maps to `RESOURCE_EXHAUSTED` instead of `UNAVAILABLE`.
+///
+/// [gRPC mapping]: https://github.com/grpc/grpc/blob/master/doc/http-grpc-status-mapping.md
+pub fn grpc_code(status: StatusCode) -> u16 {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => 8,
+        StatusCode::BAD_REQUEST => 13,
+        StatusCode::UNAUTHORIZED => 16,
+        StatusCode::FORBIDDEN => 7,
+        StatusCode::NOT_FOUND => 12,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => {
+            14
+        }
+        status if status.is_success() => 0,
+        _ => 2,
+    }
+}
+
+// `grpc-message` values are percent-encoded UTF-8.
+fn percent_encode(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{:02X}", byte);
+        }
+    }
+    out
+}
@@ -0,0 +1,121 @@
+//! Ready-made error handlers, to be set with [`GovernorConfigBuilder::error_handler`].
+//!
+//! Every handler keeps the status code and the headers of the default responses, such as
+//! `retry-after` and the [rate limiting headers](crate::headers), and only changes their body.
+//!
+//! # Example
+//! ```rust
+//! use tower_governor::{governor::GovernorConfigBuilder, handlers};
+//!
+//! let config = GovernorConfigBuilder::default()
+//!     .error_handler(handlers::json())
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! [`GovernorConfigBuilder::error_handler`]: crate::governor::GovernorConfigBuilder::error_handler
+
+use crate::{errors::GovernorError, handle_error, headers::RejectionReason, report::json_string};
+use axum::body::Body;
+use http::{header::CONTENT_TYPE, HeaderValue, Response};
+
+// The default response of `error`, split into the response with an empty body and the message
+// of its body, along with the wait time of rate limited requests.
+fn render(mut error: GovernorError) -> (Response<Body>, String, Option<u64>) {
+    let wait_time = match &error {
+        GovernorError::TooManyRequests { wait_time, .. } => Some(*wait_time),
+        _ => None,
+    };
+    let (parts, message) = error.as_response::<String>().into_parts();
+    (
+        Response::from_parts(parts, Body::empty()),
+        message,
+        wait_time,
+    )
+}
+
+fn with_body(response: Response<Body>, content_type: &'static str, body: String) -> Response<Body> {
+    let (mut parts, _) = response.into_parts();
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// The default handler, answering with a plain text message such as
+/// `Too Many Requests! Wait for 2s`.
+pub fn plain() -> impl Fn(GovernorError) -> Response<Body> + Send + Sync + 'static {
+    |error| {
+        let (response, message, _) = render(error);
+        with_body(response, "text/plain; charset=utf-8", message)
+    }
+}
+
+/// Answer with a JSON object holding the `status` code and the `message` of the error, along
+/// with the `wait_time` in seconds of rate limited requests and the
+/// [`reason`](RejectionReason) of the rejection when known.
+///
+/// ```json
+/// {"status":429,"message":"Too Many Requests! Wait for 2s","wait_time":2,"reason":"quota_exceeded"}
+/// ```
+pub fn json() -> impl Fn(GovernorError) -> Response<Body> + Send + Sync + 'static {
+    |error| {
+        let (response, message, wait_time) = render(error);
+        let mut body = format!(
+            "{{\"status\":{},\"message\":{}",
+            response.status().as_u16(),
+            json_string(&message)
+        );
+        if let Some(wait_time) = wait_time {
+            body.push_str(&format!(",\"wait_time\":{}", wait_time));
+        }
+        if let Some(reason) = RejectionReason::of(response.headers()) {
+            body.push_str(&format!(",\"reason\":\"{}\"", reason.as_str()));
+        }
+        body.push('}');
+        with_body(response, "application/json", body)
+    }
+}
+
+/// Answer with the HTML page `template`, where `{status}`, `{message}` and `{wait_time}` are
+/// replaced by the status code, the HTML escaped message of the error and the wait time in
+/// seconds of rate limited requests (`0` for other errors).
+///
+/// ```rust
+/// # use tower_governor::handlers;
+/// handlers::html("<h1>Slow down</h1><p>Come back in {wait_time} seconds.</p>");
+/// ```
+pub fn html(
+    template: impl Into<String>,
+) -> impl Fn(GovernorError) -> Response<Body> + Send + Sync + 'static {
+    let template = template.into();
+    move |error| {
+        let (response, message, wait_time) = render(error);
+        let body = template
+            .replace("{status}", response.status().as_str())
+            .replace("{message}", &escape_html(&message))
+            .replace("{wait_time}", &wait_time.unwrap_or_default().to_string());
+        with_body(response, "text/html; charset=utf-8", body)
+    }
+}
+
+/// Answer with a trailers-only gRPC response, rate limited requests getting the
+/// `RESOURCE_EXHAUSTED` status, as [`handle_error::into_grpc_response`] does.
+pub fn grpc() -> impl Fn(GovernorError) -> Response<Body> + Send + Sync + 'static {
+    |error| handle_error::into_grpc_response(Box::new(error))
+}
+
+fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,599 @@
+//! The rate limiting headers of the responses.
+//!
+//! Every header describes the quota that bound the request: the quota of its class when
+//! [request classes] are configured, the quota of the configuration otherwise, or the share of
+//! its class of a [fair share] quota that rejected it. The [`SCOPE_HEADER`] names that quota,
+//! so clients and operators can tell which policy rejected a request.
+//!
+//! Clients and the tests of downstream crates can read them with [`RateLimitHeaders`] rather
+//! than spelling out the header names.
+//!
+//! [request classes]: crate::governor::GovernorConfigBuilder::classify
+//! [fair share]: crate::governor::GovernorConfigBuilder::fair_share
+
+use governor::{
+    clock::Reference,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware, StateSnapshot},
+    NotUntil, Quota,
+};
+use http::{
+    header::{HeaderName, CACHE_CONTROL, RETRY_AFTER},
+    HeaderMap, HeaderValue, Method, Response, StatusCode,
+};
+use std::{str::FromStr, time::Duration};
+
+pub use crate::decision::{DECISION_HEADER, MODE_HEADER};
+pub use crate::governor::{CLASS_HEADER, DEFAULT_WHITELISTED_HEADER};
+
+/// Header holding the burst size of the binding quota, see
+/// [`use_headers`](crate::governor::GovernorConfigBuilder::use_headers).
+pub const LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+
+/// Header holding the number of requests the key can still make right away, see
+/// [`use_headers`](crate::governor::GovernorConfigBuilder::use_headers).
+pub const REMAINING_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+/// Header holding the credit balance of the binding quota, replacing the [`LIMIT_HEADER`] of
+/// [credits](crate::credits) quotas.
+pub const CREDITS_LIMIT_HEADER: HeaderName = HeaderName::from_static("x-credits-limit");
+
+/// Header holding the number of credits the key has left, replacing the [`REMAINING_HEADER`]
+/// of [credits](crate::credits) quotas.
+pub const CREDITS_REMAINING_HEADER: HeaderName = HeaderName::from_static("x-credits-remaining");
+
+/// Header of rejections holding the number of seconds until the request would be allowed,
+/// same as `retry-after`.
+pub const AFTER_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-after");
+
+/// Header of rejections holding the number of milliseconds until the request would be
+/// allowed, replacing the [`AFTER_HEADER`] with [`WaitTimeUnit::Milliseconds`].
+pub const AFTER_MS_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-after-ms");
+
+/// The unit of the wait time of rejections, see
+/// [`wait_time_unit`](crate::governor::GovernorConfigBuilder::wait_time_unit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitTimeUnit {
+    /// Whole seconds in the [`AFTER_HEADER`]. This is the default.
+    #[default]
+    Seconds,
+    /// Milliseconds, rounded up, in the [`AFTER_MS_HEADER`]. `retry-after` still holds
+    /// seconds, as its specification requires.
+    Milliseconds,
+}
+
+/// Header of allowed responses holding when the current usage cycle of the key started, in
+/// seconds since the Unix epoch, see
+/// [`window_start_header`](crate::governor::GovernorConfigBuilder::window_start_header).
+pub const WINDOW_START_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-window-start");
+
+/// Header naming the quota that bound the request: the
+/// [policy name](crate::governor::GovernorConfigBuilder::policy_name) and the class of the
+/// request, separated by a `/` when both are known.
+///
+/// Added to rejections, and to allowed responses along with the [`LIMIT_HEADER`].
+pub const SCOPE_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-scope");
+
+/// Header of rejections listing the rate limited methods, see
+/// [`methods_header`](crate::governor::GovernorConfigBuilder::methods_header).
+pub const METHODS_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-methods");
+
+/// Header of rejections holding their [`RejectionReason`].
+pub const REASON_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-reason");
+
+/// Why a request was rejected, sent in the [`REASON_HEADER`] so that clients can branch on
+/// the cause without matching the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RejectionReason {
+    /// The key exceeded its quota, that of its class or of one of its windows.
+    QuotaExceeded,
+    /// The key is banned, see
+    /// [`ban_above`](crate::governor::GovernorConfigBuilder::ban_above).
+    Banned,
+    /// The key couldn't be extracted from the request.
+    ExtractionFailed,
+    /// The request exceeded a quota shared by every client: that of an
+    /// [unkeyed](crate::governor::GovernorConfigBuilder::unkeyed) configuration or a
+    /// [fair share](crate::governor::GovernorConfigBuilder::fair_share).
+    GlobalCeiling,
+    /// The request is an upgrade, see
+    /// [`UpgradeRequests::Deny`](crate::governor::UpgradeRequests::Deny).
+    UpgradeDenied,
+    /// The service is under maintenance. Never sent by the layer itself, but available to
+    /// the key extractors and error handlers turning clients away during maintenance.
+    Maintenance,
+}
+
+impl RejectionReason {
+    /// The value of the reason, in snake case.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::Banned => "banned",
+            Self::ExtractionFailed => "extraction_failed",
+            Self::GlobalCeiling => "global_ceiling",
+            Self::UpgradeDenied => "upgrade_denied",
+            Self::Maintenance => "maintenance",
+        }
+    }
+
+    /// The reason held by `headers`, if any.
+    pub fn of(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(REASON_HEADER)?;
+        [
+            Self::QuotaExceeded,
+            Self::Banned,
+            Self::ExtractionFailed,
+            Self::GlobalCeiling,
+            Self::UpgradeDenied,
+            Self::Maintenance,
+        ]
+        .into_iter()
+        .find(|reason| value == reason.as_str())
+    }
+
+    /// Add the reason to `headers`.
+    pub fn insert(self, headers: &mut HeaderMap) {
+        headers.insert(REASON_HEADER, HeaderValue::from_static(self.as_str()));
+    }
+}
+
+/// The value listing `methods`, formatted like the `allow` header.
+pub(crate) fn methods(methods: &[Method]) -> Option<HeaderValue> {
+    let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    HeaderValue::try_from(methods.join(", ")).ok()
+}
+
+/// Extension marking the rejections that compression layers should leave alone, see
+/// [`skip_rejection_compression`](crate::governor::GovernorConfigBuilder::skip_rejection_compression).
+///
+/// tower-http's `CompressionLayer` can honor it with a predicate such as
+/// `DefaultPredicate::new().and(|_, _, _, extensions: &Extensions| !extensions.contains::<SkipCompression>())`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkipCompression;
+
+/// How the layer signals that a request exceeds the quota, see
+/// [`rejection_mode`](crate::governor::GovernorConfigBuilder::rejection_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectionMode {
+    /// `429 Too Many Requests`, telling the client it exceeded its own quota. This is the
+    /// default.
+    #[default]
+    TooManyRequests,
+    /// `503 Service Unavailable`, telling the client the service is shedding load, e.g. for
+    /// a global cap shared by every client. The headers describing the quota of the key are
+    /// left out, only the wait time is kept.
+    ServiceUnavailable,
+}
+
+/// The attributes added to the rejections of the layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RejectionAttributes {
+    /// The `cache-control` header of the rejections lacking one.
+    pub(crate) cache_control: Option<HeaderValue>,
+    /// Whether to mark the rejections with [`SkipCompression`].
+    pub(crate) skip_compression: bool,
+    /// The status of the rejections exceeding the quota.
+    pub(crate) mode: RejectionMode,
+}
+
+impl RejectionAttributes {
+    pub(crate) fn apply<B>(&self, response: &mut Response<B>) {
+        if let Some(cache_control) = &self.cache_control {
+            response
+                .headers_mut()
+                .entry(CACHE_CONTROL)
+                .or_insert_with(|| cache_control.clone());
+        }
+        if self.skip_compression {
+            response.extensions_mut().insert(SkipCompression);
+        }
+        if let (RejectionMode::ServiceUnavailable, StatusCode::TOO_MANY_REQUESTS) =
+            (self.mode, response.status())
+        {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            let headers = response.headers_mut();
+            for name in [
+                LIMIT_HEADER,
+                REMAINING_HEADER,
+                CREDITS_LIMIT_HEADER,
+                CREDITS_REMAINING_HEADER,
+                CLASS_HEADER,
+                SCOPE_HEADER,
+            ] {
+                headers.remove(name);
+            }
+        }
+    }
+}
+
+/// Middleware of the configurations whose responses carry no rate limiting header, see
+/// [`bare_responses`](crate::governor::GovernorConfigBuilder::bare_responses).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BareMiddleware;
+
+/// The outcome of the requests allowed by the [`BareMiddleware`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bare;
+
+impl<P: Reference> RateLimitingMiddleware<P> for BareMiddleware {
+    type PositiveOutcome = Bare;
+
+    type NegativeOutcome = NotUntil<P>;
+
+    fn allow<K>(_key: &K, _state: impl Into<StateSnapshot>) -> Self::PositiveOutcome {
+        Bare
+    }
+
+    fn disallow<K>(
+        key: &K,
+        state: impl Into<StateSnapshot>,
+        start_time: P,
+    ) -> Self::NegativeOutcome {
+        NoOpMiddleware::<P>::disallow(key, state, start_time)
+    }
+}
+
+/// How the rate limiting headers already present on the responses of the inner service are
+/// handled, see
+/// [`upstream_headers`](crate::governor::GovernorConfigBuilder::upstream_headers).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpstreamHeaders {
+    /// Replace them with the headers of this layer. This is the default.
+    #[default]
+    PreferLocal,
+    /// Combine them with the headers of this layer into the most restrictive picture: the
+    /// lowest [`REMAINING_HEADER`] along with its limit, class and scope, and the longest
+    /// wait time.
+    Combine,
+    /// Keep them, only adding the headers of this layer they lack.
+    PreferUpstream,
+}
+
+/// The value of the [`SCOPE_HEADER`] for a policy and a class, `None` if neither is known
+/// or the names aren't valid header values.
+pub(crate) fn scope(policy: Option<&str>, class: Option<&str>) -> Option<HeaderValue> {
+    let value = match (policy, class) {
+        (Some(policy), Some(class)) => format!("{}/{}", policy, class),
+        (Some(name), None) | (None, Some(name)) => name.to_owned(),
+        (None, None) => return None,
+    };
+    HeaderValue::try_from(value).ok()
+}
+
+/// The rate limiting headers of a response, as parsed by clients or written by other
+/// services speaking the same headers.
+///
+/// # Example
+/// ```rust
+/// use http::HeaderMap;
+/// use tower_governor::headers::RateLimitHeaders;
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert("x-ratelimit-limit", 10.into());
+/// headers.insert("x-ratelimit-remaining", 0.into());
+/// headers.insert("x-ratelimit-after", 3.into());
+///
+/// let parsed = RateLimitHeaders::parse(&headers);
+/// assert_eq!(parsed.limit, Some(10));
+/// assert_eq!(parsed.after, Some(3));
+///
+/// let mut encoded = HeaderMap::new();
+/// parsed.encode(&mut encoded);
+/// assert_eq!(RateLimitHeaders::parse(&encoded), parsed);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RateLimitHeaders {
+    /// The [`LIMIT_HEADER`], or the [`CREDITS_LIMIT_HEADER`] of credits quotas.
+    pub limit: Option<u32>,
+    /// The [`REMAINING_HEADER`], or the [`CREDITS_REMAINING_HEADER`] of credits quotas.
+    pub remaining: Option<u32>,
+    /// Whether the limit and remaining headers are those of credits quotas.
+    pub credits: bool,
+    /// The [`AFTER_HEADER`], in seconds.
+    pub after: Option<u64>,
+    /// The [`AFTER_MS_HEADER`], in milliseconds.
+    pub after_ms: Option<u64>,
+    /// The `retry-after` header, when given in seconds.
+    pub retry_after: Option<u64>,
+    /// The [`CLASS_HEADER`].
+    pub class: Option<String>,
+    /// The [`SCOPE_HEADER`].
+    pub scope: Option<String>,
+    /// Whether the [`DEFAULT_WHITELISTED_HEADER`] marks the request as bypassing the limiter.
+    pub whitelisted: bool,
+}
+
+impl RateLimitHeaders {
+    /// Read the rate limiting headers of `headers`, ignoring those with invalid values.
+    pub fn parse(headers: &HeaderMap) -> Self {
+        fn number<T: FromStr>(headers: &HeaderMap, name: HeaderName) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+        let text = |name| headers.get(name)?.to_str().ok();
+        let credits = headers.contains_key(CREDITS_LIMIT_HEADER)
+            || headers.contains_key(CREDITS_REMAINING_HEADER);
+        let (limit_header, remaining_header) = match credits {
+            true => (CREDITS_LIMIT_HEADER, CREDITS_REMAINING_HEADER),
+            false => (LIMIT_HEADER, REMAINING_HEADER),
+        };
+        Self {
+            limit: number(headers, limit_header),
+            remaining: number(headers, remaining_header),
+            credits,
+            after: number(headers, AFTER_HEADER),
+            after_ms: number(headers, AFTER_MS_HEADER),
+            retry_after: number(headers, RETRY_AFTER),
+            class: text(CLASS_HEADER).map(str::to_owned),
+            scope: text(SCOPE_HEADER).map(str::to_owned),
+            whitelisted: text(DEFAULT_WHITELISTED_HEADER) == Some("true"),
+        }
+    }
+
+    /// The time to wait before retrying, from the [`AFTER_MS_HEADER`], the [`AFTER_HEADER`]
+    /// or else `retry-after`.
+    pub fn wait_time(&self) -> Option<Duration> {
+        match self.after_ms {
+            Some(after_ms) => Some(Duration::from_millis(after_ms)),
+            None => self.after.or(self.retry_after).map(Duration::from_secs),
+        }
+    }
+
+    /// Write the headers that are known into `headers`, replacing the existing ones. The
+    /// class and scope are left out if they aren't valid header values.
+    pub fn encode(&self, headers: &mut HeaderMap) {
+        let (limit_header, remaining_header) = match self.credits {
+            true => (CREDITS_LIMIT_HEADER, CREDITS_REMAINING_HEADER),
+            false => (LIMIT_HEADER, REMAINING_HEADER),
+        };
+        let numbers = [
+            (limit_header, self.limit.map(u64::from)),
+            (remaining_header, self.remaining.map(u64::from)),
+            (AFTER_HEADER, self.after),
+            (AFTER_MS_HEADER, self.after_ms),
+            (RETRY_AFTER, self.retry_after),
+        ];
+        for (name, value) in numbers {
+            if let Some(value) = value {
+                headers.insert(name, value.into());
+            }
+        }
+        for (name, value) in [(CLASS_HEADER, &self.class), (SCOPE_HEADER, &self.scope)] {
+            if let Some(value) = value.as_deref().and_then(|value| value.try_into().ok()) {
+                headers.insert(name, value);
+            }
+        }
+        if self.whitelisted {
+            headers.insert(DEFAULT_WHITELISTED_HEADER, HeaderValue::from_static("true"));
+        }
+    }
+}
+
+/// Header listing the quotas of a request, one `"name";q=<limit>;w=<window>` entry per quota
+/// with the window in seconds, see [`RateLimitPolicies`].
+pub const POLICY_HEADER: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+/// Header listing what is left of the quotas of a request, one
+/// `"name";r=<remaining>;t=<reset>` entry per quota with the reset in seconds, see
+/// [`RateLimitPolicies`].
+pub const RATELIMIT_HEADER: HeaderName = HeaderName::from_static("ratelimit");
+
+/// A quota reported in the [`POLICY_HEADER`] and the [`RATELIMIT_HEADER`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    /// The name of the quota, e.g. its [`SCOPE_HEADER`].
+    pub name: String,
+    /// The burst size of the quota.
+    pub limit: u32,
+    /// The time the quota takes to replenish its whole burst.
+    pub window: Duration,
+    /// The number of requests left, `None` if unknown.
+    pub remaining: Option<u32>,
+    /// The time until the quota is replenished, `None` if unknown.
+    pub reset: Option<Duration>,
+}
+
+impl RateLimitPolicy {
+    /// The policy of `quota`, named `name`, with nothing known of what's left of it.
+    pub fn new(name: impl Into<String>, quota: Quota) -> Self {
+        Self {
+            name: name.into(),
+            limit: quota.burst_size().get(),
+            window: quota.burst_size_replenished_in(),
+            remaining: None,
+            reset: None,
+        }
+    }
+
+    /// Set the number of requests left, along with the time the quota takes to replenish
+    /// the others.
+    pub fn remaining(mut self, remaining: u32) -> Self {
+        let used = self.limit.saturating_sub(remaining);
+        self.remaining = Some(remaining);
+        self.reset = Some(self.window / self.limit.max(1) * used);
+        self
+    }
+}
+
+/// The [`POLICY_HEADER`] and the [`RATELIMIT_HEADER`] of a request subject to any number of
+/// quotas, e.g. a short and a long [window](crate::governor::GovernorConfigBuilder::window_quota).
+///
+/// # Example
+/// ```rust
+/// use governor::Quota;
+/// use http::HeaderMap;
+/// use std::num::NonZeroU32;
+/// use tower_governor::headers::{RateLimitPolicies, RateLimitPolicy, POLICY_HEADER, RATELIMIT_HEADER};
+///
+/// let second = Quota::per_second(NonZeroU32::new(10).unwrap());
+/// let hour = Quota::per_hour(NonZeroU32::new(3600).unwrap());
+/// let mut policies = RateLimitPolicies::new();
+/// policies.push(RateLimitPolicy::new("second", second).remaining(8));
+/// policies.push(RateLimitPolicy::new("hour", hour).remaining(3599));
+///
+/// let mut headers = HeaderMap::new();
+/// policies.write(&mut headers);
+/// assert_eq!(headers[POLICY_HEADER], r#""second";q=10;w=1, "hour";q=3600;w=3600"#);
+/// assert_eq!(headers[RATELIMIT_HEADER], r#""second";r=8;t=1, "hour";r=3599;t=1"#);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RateLimitPolicies(Vec<RateLimitPolicy>);
+
+impl RateLimitPolicies {
+    /// No policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `policy` at the end of the lists.
+    pub fn push(&mut self, policy: RateLimitPolicy) {
+        self.0.push(policy);
+    }
+
+    /// The policies, in order.
+    pub fn policies(&self) -> &[RateLimitPolicy] {
+        &self.0
+    }
+
+    /// Write the headers into `headers`, replacing the existing ones. The
+    /// [`RATELIMIT_HEADER`] only lists the policies whose remaining requests are known, and
+    /// the headers are left out if a name can't be written in one.
+    pub fn write(&self, headers: &mut HeaderMap) {
+        // the names are structured field strings, the times whole seconds rounded up
+        fn name(name: &str) -> String {
+            format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        fn seconds(duration: Duration) -> u64 {
+            duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+        }
+        let policies: Vec<String> = self
+            .0
+            .iter()
+            .map(|policy| {
+                format!(
+                    "{};q={};w={}",
+                    name(&policy.name),
+                    policy.limit,
+                    seconds(policy.window)
+                )
+            })
+            .collect();
+        let remaining: Vec<String> = self
+            .0
+            .iter()
+            .filter_map(|policy| {
+                Some(format!(
+                    "{};r={};t={}",
+                    name(&policy.name),
+                    policy.remaining?,
+                    seconds(policy.reset.unwrap_or_default())
+                ))
+            })
+            .collect();
+        for (header, entries) in [(POLICY_HEADER, policies), (RATELIMIT_HEADER, remaining)] {
+            if entries.is_empty() {
+                continue;
+            }
+            if let Ok(value) = HeaderValue::try_from(entries.join(", ")) {
+                headers.insert(header, value);
+            }
+        }
+    }
+}
+
+/// The rate limiting headers of a response, describing the binding quota.
+#[derive(Debug)]
+pub(crate) struct QuotaHeaders<'a> {
+    /// The burst size of the binding quota.
+    pub(crate) limit: u32,
+    /// The number of requests left, `None` to omit the limit and remaining headers.
+    pub(crate) remaining: Option<u32>,
+    /// Whether to send the limit header when the number of requests left is unknown.
+    pub(crate) bare_limit: bool,
+    /// The advertised wait time of rejections, sent in whole seconds.
+    pub(crate) after: Option<Duration>,
+    /// The unit of the wait time, `retry-after` being in seconds either way.
+    pub(crate) after_unit: WaitTimeUnit,
+    /// Whether to send the wait time as `retry-after` too.
+    pub(crate) retry_after: bool,
+    /// Whether to name the limit and remaining headers after credits.
+    pub(crate) credits: bool,
+    /// The class of the request.
+    pub(crate) class: Option<&'static str>,
+    /// The value of the [`SCOPE_HEADER`].
+    pub(crate) scope: Option<&'a HeaderValue>,
+}
+
+impl QuotaHeaders<'_> {
+    /// Write the headers into `headers`, which may hold those of an upstream service.
+    pub(crate) fn merge(&self, headers: &mut HeaderMap, upstream: UpstreamHeaders) {
+        let mut local = HeaderMap::new();
+        self.write(&mut local);
+        match upstream {
+            UpstreamHeaders::PreferLocal => headers.extend(local),
+            UpstreamHeaders::PreferUpstream => {
+                for (name, value) in local.drain() {
+                    if let Some(name) = name {
+                        headers.entry(name).or_insert(value);
+                    }
+                }
+            }
+            UpstreamHeaders::Combine => {
+                let number = |headers: &HeaderMap, name| -> Option<u64> {
+                    headers.get(name)?.to_str().ok()?.parse().ok()
+                };
+                let upstream_binds = match (
+                    number(headers, REMAINING_HEADER),
+                    number(&local, REMAINING_HEADER),
+                ) {
+                    (Some(upstream), Some(local)) => upstream < local,
+                    (upstream, _) => upstream.is_some(),
+                };
+                for name in [AFTER_HEADER, AFTER_MS_HEADER, RETRY_AFTER] {
+                    if number(headers, name.clone()) > number(&local, name.clone()) {
+                        local.remove(name);
+                    }
+                }
+                if upstream_binds {
+                    for name in [LIMIT_HEADER, REMAINING_HEADER, CLASS_HEADER, SCOPE_HEADER] {
+                        local.remove(name);
+                    }
+                }
+                headers.extend(local);
+            }
+        }
+    }
+
+    pub(crate) fn write(&self, headers: &mut HeaderMap) {
+        if let Some(after) = self.after {
+            match self.after_unit {
+                WaitTimeUnit::Seconds => headers.insert(AFTER_HEADER, after.as_secs().into()),
+                WaitTimeUnit::Milliseconds => headers.insert(AFTER_MS_HEADER, millis(after).into()),
+            };
+            if self.retry_after {
+                headers.insert(RETRY_AFTER, after.as_secs().into());
+            }
+        }
+        let (limit_header, remaining_header) = match self.credits {
+            true => (CREDITS_LIMIT_HEADER, CREDITS_REMAINING_HEADER),
+            false => (LIMIT_HEADER, REMAINING_HEADER),
+        };
+        if let Some(remaining) = self.remaining {
+            headers.insert(limit_header, self.limit.into());
+            headers.insert(remaining_header, remaining.into());
+        } else if self.bare_limit {
+            headers.insert(limit_header, self.limit.into());
+        }
+        if let Some(name) = self.class {
+            headers.insert(CLASS_HEADER, HeaderValue::from_static(name));
+        }
+        if let Some(scope) = self.scope {
+            headers.insert(SCOPE_HEADER, scope.clone());
+        }
+    }
+}
+
+/// `duration` in milliseconds, rounded up so that clients don't retry too early.
+pub(crate) fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos().div_ceil(1_000_000)).unwrap_or(u64::MAX)
+}
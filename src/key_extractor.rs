@@ -1,11 +1,22 @@
-use crate::errors::GovernorError;
-use forwarded_header_value::{ForwardedHeaderValue, Identifier};
+use crate::errors::{ConfigError, GovernorError};
+use crate::forwarding::{
+    forwarded_elements, forwarded_ip, peer_ip, ClientIpResolver, ForwardedElement,
+};
 use http::request::Request;
-use http::{header::FORWARDED, HeaderMap};
-use std::fmt::Debug;
+use http::{
+    header::{HeaderName, HOST},
+    uri::{Authority, Scheme},
+    HeaderMap,
+};
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::mem;
 use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
 use std::{hash::Hash, net::IpAddr};
 
+pub use crate::forwarding::Source;
+
 /// Generic structure of what is needed to extract a rate-limiting key from an incoming request.
 pub trait KeyExtractor: Clone {
     /// The type of the key.
@@ -20,11 +31,64 @@ pub trait KeyExtractor: Clone {
     /// Extraction method, will return [`GovernorError`] response when the extract failed
     fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError>;
 
-    #[cfg(feature = "tracing")]
-    /// Value of the extracted key (only used in tracing).
+    /// Value of the extracted key, used in tracing and in the [`RejectionContext`].
+    ///
+    /// [`RejectionContext`]: crate::decision::RejectionContext
     fn key_name(&self, _key: &Self::Key) -> Option<String> {
         None
     }
+
+    /// The client IP address the key was derived from, if any.
+    ///
+    /// Used by IP based exemptions such as [`exempt_loopback`].
+    ///
+    /// [`exempt_loopback`]: crate::governor::GovernorConfigBuilder::exempt_loopback
+    fn key_ip(&self, _key: &Self::Key) -> Option<IpAddr> {
+        None
+    }
+
+    /// The key `key` would have if the client IP address was `ip` instead, if this extractor
+    /// derives keys from IP addresses.
+    ///
+    /// Used to fall back onto the forwarding headers, see [`proxy_fallback`].
+    ///
+    /// [`proxy_fallback`]: crate::governor::GovernorConfigBuilder::proxy_fallback
+    fn key_from_ip(&self, _key: &Self::Key, _ip: IpAddr) -> Option<Self::Key> {
+        None
+    }
+
+    /// Where the client IP address the key was derived from was found, if known.
+    ///
+    /// Reported to tracing and the [audit log](crate::governor::GovernorConfigBuilder::audit_sink).
+    fn key_source(&self, _key: &Self::Key) -> Option<Source> {
+        None
+    }
+
+    /// The cohort of the key, such as `internal`, `partner` or `public`: a label of low
+    /// cardinality reported instead of the key in tracing and in the
+    /// [cohort rates](crate::governor::GovernorConfigBuilder::track_cohort_rates), see
+    /// [`Cohorts`].
+    fn cohort(&self, _key: &Self::Key) -> Option<&'static str> {
+        None
+    }
+
+    /// Check that the extractor is usable, called when the configuration is built so that
+    /// misconfigured extractors fail at startup rather than on the first request.
+    ///
+    /// Fails with [`ConfigError::InvalidKeyExtractor`] describing the problem. Accepts
+    /// anything by default.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    /// Whether every request of a connection has the same key, such as the peer IP address,
+    /// allowing [`cache_connection_keys`]. Keys read from the requests themselves aren't, as
+    /// a reverse proxy sends the requests of many clients down each of its connections.
+    ///
+    /// [`cache_connection_keys`]: crate::governor::GovernorConfigBuilder::cache_connection_keys
+    fn connection_stable(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,7 +107,6 @@ impl KeyExtractor for GlobalKeyExtractor {
         Ok(())
     }
 
-    #[cfg(feature = "tracing")]
     fn key_name(&self, _key: &Self::Key) -> Option<String> {
         None
     }
@@ -60,6 +123,11 @@ impl KeyExtractor for GlobalKeyExtractor {
 ///  - Use the SmartIpKeyExtractor to get the IP from the `Forwarded` or `X-Forwarded-For` headers that most proxies set
 /// - implement your own [KeyExtractor] that tries to get IP from the `Forwarded` or `X-Forwarded-For` headers that most reverse proxies set
 /// - make absolutely sure that you only trust these headers when the peer IP is the IP of your reverse proxy (otherwise any user could set them to fake its IP)
+///
+/// IPv4-mapped IPv6 addresses, such as `::ffff:1.2.3.4`, are converted to their IPv4 form, so
+/// that a client reaching a dual-stack listener over both protocols gets a single bucket. Use
+/// `SmartIpKeyExtractor::with_resolver(ClientIpResolver::new([Source::Peer]).canonical(false))`
+/// to keep them apart.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct PeerIpKeyExtractor;
 
@@ -73,13 +141,30 @@ impl KeyExtractor for PeerIpKeyExtractor {
 
     //type Key: Clone + Hash + Eq;
     fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
-        maybe_connect_info(req).ok_or(GovernorError::UnableToExtractKey)
+        peer_ip(req)
+            .map(|ip| ip.to_canonical())
+            .ok_or(GovernorError::UnableToExtractKey)
     }
 
-    #[cfg(feature = "tracing")]
     fn key_name(&self, key: &Self::Key) -> Option<String> {
         Some(key.to_string())
     }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        Some(*key)
+    }
+
+    fn key_from_ip(&self, _key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        Some(ip)
+    }
+
+    fn key_source(&self, _key: &Self::Key) -> Option<Source> {
+        Some(Source::Peer)
+    }
+
+    fn connection_stable(&self) -> bool {
+        true
+    }
 }
 
 /// A [KeyExtractor] that tries to get the client IP address from the x-forwarded-for, x-real-ip, and forwarded headers in that order. Falls back to the peer IP address.
@@ -89,6 +174,9 @@ impl KeyExtractor for PeerIpKeyExtractor {
 /// This is a sane default for an app running behind a reverse proxy, with the caveat that one must be careful of ths source of the headers.
 /// It will fall back to the peer IP address if the headers are not present, which would set a global rate limit if behind a reverse proxy.
 /// If it fails to find any of the headers or the peer IP, it will error out.
+///
+/// IPv4-mapped IPv6 addresses are converted to their IPv4 form, see
+/// [`ClientIpResolver::canonical`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SmartIpKeyExtractor;
 
@@ -103,72 +191,1324 @@ impl KeyExtractor for SmartIpKeyExtractor {
     //type Key: Clone + Hash + Eq;
     //type Boxerror:  pub type BoxError = Box<dyn Error + Send + Sync>;
     fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
-        let headers = req.headers();
-
-        maybe_x_forwarded_for(headers)
-            .or_else(|| maybe_x_real_ip(headers))
-            .or_else(|| maybe_forwarded(headers))
-            .or_else(|| maybe_connect_info(req))
+        forwarded_ip(req.headers())
+            .or_else(|| peer_ip(req))
+            .map(|ip| ip.to_canonical())
             .ok_or(GovernorError::UnableToExtractKey)
     }
 
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        Some(*key)
+    }
+
+    fn key_from_ip(&self, _key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        Some(ip)
+    }
+}
+
+impl SmartIpKeyExtractor {
+    /// The sources looked up by the [SmartIpKeyExtractor], in order.
+    pub const DEFAULT_SOURCES: [Source; 4] = [
+        Source::XForwardedFor,
+        Source::XRealIp,
+        Source::Forwarded,
+        Source::Peer,
+    ];
+
+    /// A [KeyExtractor] looking up the client IP address in the given sources, in order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::key_extractor::{Source, SmartIpKeyExtractor};
+    ///
+    /// // our edge only sets the standard `forwarded` header
+    /// let extractor = SmartIpKeyExtractor::with_sources([Source::Forwarded, Source::Peer]);
+    /// ```
+    pub fn with_sources(sources: impl IntoIterator<Item = Source>) -> SourcedIpKeyExtractor {
+        Self::with_resolver(ClientIpResolver::new(sources))
+    }
+
+    /// A [KeyExtractor] looking up the client IP address with `resolver`, e.g. to only trust
+    /// the forwarding headers set by known proxies.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{
+    ///     forwarding::{ClientIpResolver, Trust},
+    ///     key_extractor::SmartIpKeyExtractor,
+    /// };
+    ///
+    /// let extractor = SmartIpKeyExtractor::with_resolver(
+    ///     ClientIpResolver::default().rightmost().trust(Trust::Private),
+    /// );
+    /// ```
+    pub fn with_resolver(resolver: ClientIpResolver) -> SourcedIpKeyExtractor {
+        SourcedIpKeyExtractor { resolver }
+    }
+}
+
+/// A [KeyExtractor] using the client IP address found in the first source that has one,
+/// built with [`SmartIpKeyExtractor::with_sources`].
+///
+/// **Warning:** the same caveats as the [SmartIpKeyExtractor] apply, only list headers your
+/// reverse proxy is guaranteed to set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcedIpKeyExtractor {
+    resolver: ClientIpResolver,
+}
+
+impl SourcedIpKeyExtractor {
+    /// The sources looked up, in order.
+    pub fn sources(&self) -> &[Source] {
+        self.resolver.sources()
+    }
+
+    /// The resolver looking up the client IP address.
+    pub fn resolver(&self) -> &ClientIpResolver {
+        &self.resolver
+    }
+
+    /// The same extractor, with keys recording which source the IP address came from.
+    pub fn with_provenance(self) -> ProvenanceIpKeyExtractor {
+        ProvenanceIpKeyExtractor {
+            resolver: self.resolver,
+        }
+    }
+}
+
+impl KeyExtractor for SourcedIpKeyExtractor {
+    type Key = IpAddr;
+
     #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "sourced IP"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        self.resolver
+            .resolve(req)
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_sources(self.resolver.sources())
+    }
+
     fn key_name(&self, key: &Self::Key) -> Option<String> {
         Some(key.to_string())
     }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        Some(*key)
+    }
+
+    fn key_from_ip(&self, _key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        Some(ip)
+    }
 }
 
-// Utility functions for the SmartIpExtractor
-// Shamelessly snatched from the axum-client-ip crate here:
-// https://crates.io/crates/axum-client-ip
+fn validate_sources(sources: &[Source]) -> Result<(), ConfigError> {
+    if sources.is_empty() {
+        return Err(ConfigError::InvalidKeyExtractor(
+            "no source to look the client IP address up in".to_owned(),
+        ));
+    }
+    Ok(())
+}
 
-const X_REAL_IP: &str = "x-real-ip";
-const X_FORWARDED_FOR: &str = "x-forwarded-for";
+/// A client IP address along with the source it was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpKey {
+    /// The client IP address.
+    pub ip: IpAddr,
+    /// Where the address was found.
+    pub source: Source,
+}
 
-/// Tries to parse the `x-forwarded-for` header
-fn maybe_x_forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
-    headers
-        .get(X_FORWARDED_FOR)
-        .and_then(|hv| hv.to_str().ok())
-        .and_then(|s| s.split(',').find_map(|s| s.trim().parse::<IpAddr>().ok()))
+/// A [KeyExtractor] like the [SourcedIpKeyExtractor], whose keys record the [Source] of the
+/// client IP address. Built with [`SourcedIpKeyExtractor::with_provenance`].
+///
+/// This allows monitoring how much traffic is keyed by spoofable headers. The same address
+/// found in different sources is rate limited separately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenanceIpKeyExtractor {
+    resolver: ClientIpResolver,
+}
+
+impl Default for ProvenanceIpKeyExtractor {
+    /// Looks up the [`SmartIpKeyExtractor::DEFAULT_SOURCES`].
+    fn default() -> Self {
+        SmartIpKeyExtractor::with_sources(SmartIpKeyExtractor::DEFAULT_SOURCES).with_provenance()
+    }
+}
+
+impl KeyExtractor for ProvenanceIpKeyExtractor {
+    type Key = IpKey;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "provenance IP"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        self.resolver
+            .resolve_with_source(req)
+            .map(|(ip, source)| IpKey { ip, source })
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(format!("{} ({})", key.ip, key.source.as_str()))
+    }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        Some(key.ip)
+    }
+
+    fn key_source(&self, key: &Self::Key) -> Option<Source> {
+        Some(key.source)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        validate_sources(self.resolver.sources())
+    }
+}
+
+/// A rate limiting key namespaced by the scope it was extracted for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScopedKey<Key> {
+    /// The scope the key belongs to.
+    pub scope: Arc<str>,
+    /// The key extracted by the wrapped [KeyExtractor].
+    pub key: Key,
+}
+
+/// A [KeyExtractor] wrapping another one and namespacing its keys by a scope.
+///
+/// Configurations using it can be split into several scopes with [`GovernorConfig::scoped`],
+/// each getting its own key space within the same limiter store. This allows attaching a
+/// layer to each route through axum's `Router::route_layer` without multiplying the stores.
+///
+/// [`GovernorConfig::scoped`]: crate::governor::GovernorConfig::scoped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scoped<K> {
+    scope: Arc<str>,
+    inner: K,
+}
+
+impl<K> Scoped<K> {
+    /// Wrap `inner`, namespacing its keys by the empty scope.
+    pub fn new(inner: K) -> Self {
+        Self {
+            scope: Arc::from(""),
+            inner,
+        }
+    }
+
+    /// The scope the extracted keys are namespaced by.
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// The same extractor, namespacing its keys by `scope` instead.
+    pub fn with_scope(&self, scope: impl Into<Arc<str>>) -> Self
+    where
+        K: Clone,
+    {
+        Self {
+            scope: scope.into(),
+            inner: self.inner.clone(),
+        }
+    }
 }
 
-/// Tries to parse the `x-real-ip` header
-fn maybe_x_real_ip(headers: &HeaderMap) -> Option<IpAddr> {
-    headers
-        .get(X_REAL_IP)
+impl<K: KeyExtractor> KeyExtractor for Scoped<K> {
+    type Key = ScopedKey<K::Key>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        Ok(ScopedKey {
+            scope: self.scope.clone(),
+            key: self.inner.extract(req)?,
+        })
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        self.inner
+            .key_name(&key.key)
+            .map(|name| format!("{}:{}", key.scope, name))
+    }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        self.inner.key_ip(&key.key)
+    }
+
+    fn key_from_ip(&self, key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        Some(ScopedKey {
+            scope: key.scope.clone(),
+            key: self.inner.key_from_ip(&key.key, ip)?,
+        })
+    }
+
+    fn key_source(&self, key: &Self::Key) -> Option<Source> {
+        self.inner.key_source(&key.key)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.inner.validate()
+    }
+
+    fn connection_stable(&self) -> bool {
+        self.inner.connection_stable()
+    }
+}
+
+/// The local address of the listener that accepted a request, to be inserted into the request
+/// extensions by the server, e.g. from the service handed to hyper for each connection.
+///
+/// Read by [PerListener] before any forwarding header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalAddr(pub SocketAddr);
+
+/// A rate limiting key extracted before the request reached the layer, e.g. by an
+/// authentication middleware, to be inserted into the request extensions.
+///
+/// When the extensions of a request hold a `PreExtractedKey` of the key type of the
+/// configuration, the layer rate limits the request by it and doesn't run its [KeyExtractor].
+///
+/// ```rust
+/// use tower_governor::key_extractor::PreExtractedKey;
+///
+/// let mut req = http::Request::get("/").body(()).unwrap();
+/// // set by the authentication middleware
+/// req.extensions_mut().insert(PreExtractedKey(String::from("user-42")));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PreExtractedKey<K>(pub K);
+
+/// A rate limiting key namespaced by the listener the request was addressed to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ListenerKey<Key> {
+    /// The scheme the request was addressed to, if known.
+    pub scheme: Option<Scheme>,
+    /// The port the request was addressed to, if known.
+    pub port: Option<u16>,
+    /// The key extracted by the wrapped [KeyExtractor].
+    pub key: Key,
+}
+
+/// A [KeyExtractor] wrapping another one and namespacing its keys by the original destination
+/// scheme and port of the request, so that the listeners of a gateway, e.g. a public one and
+/// a partner one, get independent buckets for the same client.
+///
+/// The port is taken from the [LocalAddr] extension, then the `x-forwarded-port` header, the
+/// `host` of the `forwarded` header, the URI and the `host` header, in that order. The scheme
+/// is taken from the `x-forwarded-proto` header, the `proto` of the `forwarded` header and
+/// the URI. Ports missing from these sources default to those of the scheme.
+///
+/// **Warning:** Only rely on the forwarding headers if you can ensure they are being set by a
+/// trusted provider, as clients could otherwise spread their requests over many buckets.
+///
+/// ```rust
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder,
+///     key_extractor::{PerListener, SmartIpKeyExtractor},
+/// };
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(PerListener::new(SmartIpKeyExtractor))
+///     .finish()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerListener<K> {
+    inner: K,
+}
+
+impl<K> PerListener<K> {
+    /// Wrap `inner`, namespacing its keys by the listener the requests were addressed to.
+    pub fn new(inner: K) -> Self {
+        Self { inner }
+    }
+}
+
+impl<K: KeyExtractor> KeyExtractor for PerListener<K> {
+    type Key = ListenerKey<K::Key>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let scheme = destination_scheme(req);
+        let port = destination_port(req).or_else(|| match scheme.as_ref()?.as_str() {
+            "http" => Some(80),
+            "https" => Some(443),
+            _ => None,
+        });
+        Ok(ListenerKey {
+            scheme,
+            port,
+            key: self.inner.extract(req)?,
+        })
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        let name = self.inner.key_name(&key.key)?;
+        Some(match (&key.scheme, key.port) {
+            (Some(scheme), Some(port)) => format!("{}:{}@{}", scheme, port, name),
+            (Some(scheme), None) => format!("{}@{}", scheme, name),
+            (None, Some(port)) => format!("{}@{}", port, name),
+            (None, None) => name,
+        })
+    }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        self.inner.key_ip(&key.key)
+    }
+
+    fn key_from_ip(&self, key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        Some(ListenerKey {
+            scheme: key.scheme.clone(),
+            port: key.port,
+            key: self.inner.key_from_ip(&key.key, ip)?,
+        })
+    }
+
+    fn key_source(&self, key: &Self::Key) -> Option<Source> {
+        self.inner.key_source(&key.key)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.inner.validate()
+    }
+
+    fn connection_stable(&self) -> bool {
+        self.inner.connection_stable()
+    }
+}
+
+/// The first element of the `forwarded` headers matching `f`.
+fn forwarded_find<T>(headers: &HeaderMap, f: impl Fn(&ForwardedElement) -> Option<T>) -> Option<T> {
+    forwarded_elements(headers).iter().find_map(f)
+}
+
+fn destination_scheme<T>(req: &Request<T>) -> Option<Scheme> {
+    req.headers()
+        .get(X_FORWARDED_PROTO)
         .and_then(|hv| hv.to_str().ok())
-        .and_then(|s| s.parse::<IpAddr>().ok())
-}
-
-/// Tries to parse `forwarded` headers
-fn maybe_forwarded(headers: &HeaderMap) -> Option<IpAddr> {
-    headers.get_all(FORWARDED).iter().find_map(|hv| {
-        hv.to_str()
-            .ok()
-            .and_then(|s| ForwardedHeaderValue::from_forwarded(s).ok())
-            .and_then(|f| {
-                f.iter()
-                    .filter_map(|fs| fs.forwarded_for.as_ref())
-                    .find_map(|ff| match ff {
-                        Identifier::SocketAddr(a) => Some(a.ip()),
-                        Identifier::IpAddr(ip) => Some(*ip),
-                        _ => None,
-                    })
-            })
-    })
+        .and_then(|s| s.trim().parse().ok())
+        .or_else(|| forwarded_find(req.headers(), |element| element.proto.clone()))
+        .or_else(|| req.uri().scheme().cloned())
 }
 
-#[cfg(feature = "axum")]
-/// Looks in `ConnectInfo` extension
-fn maybe_connect_info<T>(req: &Request<T>) -> Option<IpAddr> {
+fn destination_port<T>(req: &Request<T>) -> Option<u16> {
+    let authority_port = |host: &str| host.parse::<Authority>().ok()?.port_u16();
     req.extensions()
-        .get::<axum::extract::ConnectInfo<SocketAddr>>()
-        .map(|addr| addr.ip())
+        .get::<LocalAddr>()
+        .map(|addr| addr.0.port())
+        .or_else(|| {
+            req.headers()
+                .get(X_FORWARDED_PORT)
+                .and_then(|hv| hv.to_str().ok())
+                .and_then(|s| s.trim().parse().ok())
+        })
+        .or_else(|| {
+            forwarded_find(req.headers(), |element| {
+                authority_port(element.host.as_deref()?)
+            })
+        })
+        .or_else(|| req.uri().port_u16())
+        .or_else(|| authority_port(req.headers().get(HOST)?.to_str().ok()?))
+}
+
+/// A bounded set of shared keys, handing out the same `Arc` for equal values so that the
+/// extractors reading their keys from headers, tokens and the like don't allocate one per
+/// request.
+///
+/// Looking a value up only takes a read lock, and a value is copied once, the first time it is
+/// seen. Once the interner holds `capacity` values, or [`max_bytes`](Self::max_bytes) bytes of
+/// them, the values no longer used by anything else, such as the limiter store, are evicted.
+/// Until room is made, the values seen for the first time are copied on every request instead,
+/// as without an interner. Clones share the interned values.
+///
+/// ```rust
+/// use http::Request;
+/// use std::sync::Arc;
+/// use tower_governor::{
+///     errors::GovernorError,
+///     key_extractor::{KeyExtractor, KeyInterner},
+/// };
+///
+/// #[derive(Clone)]
+/// struct BearerToken(KeyInterner);
+///
+/// impl KeyExtractor for BearerToken {
+///     type Key = Arc<str>;
+///
+/// #   #[cfg(feature = "tracing")]
+/// #   fn name(&self) -> &'static str {
+/// #       "bearer token"
+/// #   }
+///     fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+///         req.headers()
+///             .get("authorization")
+///             .and_then(|value| value.to_str().ok()?.strip_prefix("Bearer "))
+///             .map(|token| self.0.intern(token))
+///             .ok_or(GovernorError::UnableToExtractKey)
+///     }
+/// }
+///
+/// let extractor = BearerToken(KeyInterner::default());
+/// let req = Request::get("/")
+///     .header("authorization", "Bearer token")
+///     .body(())
+///     .unwrap();
+/// let key = extractor.extract(&req).unwrap();
+/// assert!(Arc::ptr_eq(&key, &extractor.extract(&req).unwrap()));
+/// ```
+pub struct KeyInterner<T: ?Sized = str> {
+    capacity: usize,
+    max_bytes: usize,
+    keys: Arc<RwLock<InternedValues<T>>>,
+}
+
+struct InternedValues<T: ?Sized> {
+    values: HashSet<Arc<T>>,
+    bytes: usize,
+    // the values missed while full, and how many to miss before purging again, so that the
+    // purges of an interner full of values in use cost a constant time per miss
+    misses: usize,
+    purge_after: usize,
+}
+
+impl<T: ?Sized> Default for InternedValues<T> {
+    fn default() -> Self {
+        Self {
+            values: HashSet::new(),
+            bytes: 0,
+            misses: 0,
+            purge_after: 0,
+        }
+    }
+}
+
+impl<T: ?Sized + Hash + Eq> KeyInterner<T>
+where
+    for<'a> Arc<T>: From<&'a T>,
+{
+    /// The number of values interned by [`KeyInterner::default`].
+    pub const DEFAULT_CAPACITY: usize = 65_536;
+
+    /// The size of the values interned by [`KeyInterner::default`], 4 MiB.
+    pub const DEFAULT_MAX_BYTES: usize = 4 << 20;
+
+    /// An interner holding up to `capacity` values.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes: Self::DEFAULT_MAX_BYTES,
+            keys: Arc::default(),
+        }
+    }
+
+    /// Hold up to `max_bytes` bytes of values, whatever their number.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// The shared copy of `value`.
+    pub fn intern(&self, value: &T) -> Arc<T> {
+        let keys = self.keys.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = keys.values.get(value) {
+            return key.clone();
+        }
+        drop(keys);
+        let size = mem::size_of_val(value);
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = keys.values.get(value) {
+            return key.clone();
+        }
+        let full = |keys: &InternedValues<T>| {
+            keys.values.len() >= self.capacity || keys.bytes + size > self.max_bytes
+        };
+        if full(&keys) {
+            keys.misses += 1;
+            if keys.misses > keys.purge_after {
+                // the values only held by the interner
+                keys.values.retain(|key| Arc::strong_count(key) > 1);
+                keys.bytes = keys.values.iter().map(|key| mem::size_of_val(&**key)).sum();
+                (keys.misses, keys.purge_after) = (0, keys.values.len());
+            }
+            if full(&keys) {
+                return Arc::from(value);
+            }
+        }
+        let key = Arc::from(value);
+        keys.values.insert(Arc::clone(&key));
+        keys.bytes += size;
+        key
+    }
+
+    /// The number of interned values.
+    pub fn len(&self) -> usize {
+        self.keys
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values
+            .len()
+    }
+
+    /// Whether no value is interned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: ?Sized + Hash + Eq> Default for KeyInterner<T>
+where
+    for<'a> Arc<T>: From<&'a T>,
+{
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl<T: ?Sized> Clone for KeyInterner<T> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            max_bytes: self.max_bytes,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized> Debug for KeyInterner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyInterner")
+            .field("capacity", &self.capacity)
+            .field("max_bytes", &self.max_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+// The interned values are a cache, they don't tell extractors apart
+impl<T: ?Sized> PartialEq for KeyInterner<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.capacity == other.capacity && self.max_bytes == other.max_bytes
+    }
+}
+
+impl<T: ?Sized> Eq for KeyInterner<T> {}
+
+/// A string key shared through a [KeyInterner], see [Interned].
+///
+/// Keys interned by the same interner compare by pointer, falling back to their contents for
+/// those copied once the interner was full.
+#[derive(Debug, Clone, Eq)]
+pub struct InternedKey(pub Arc<str>);
+
+impl PartialEq for InternedKey {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Hash for InternedKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
 }
 
-#[cfg(not(feature = "axum"))]
-/// Looks in `ConnectInfo` extension
-fn maybe_connect_info<T>(req: &Request<T>) -> Option<IpAddr> {
-    req.extensions().get::<SocketAddr>().map(|addr| addr.ip())
+impl std::ops::Deref for InternedKey {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InternedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for InternedKey {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(Arc::from(s)))
+    }
+}
+
+/// A [KeyExtractor] wrapping another one with string keys, such as API keys or session ids,
+/// and [interning](KeyInterner) them.
+///
+/// Every copy of a key held by the layer, in the limiter store, the [tracked
+/// state](crate::governor::GovernorConfigBuilder::track_state), the
+/// [bans](crate::governor::GovernorConfigBuilder::ban_above) and so on, then shares a single
+/// allocation, and comparing keys is mostly comparing pointers. The wrapped extractor still
+/// builds its key, implement [KeyExtractor] with a [KeyInterner] directly to save that
+/// allocation too.
+///
+/// ```rust
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder,
+///     key_extractor::Interned,
+/// };
+/// # use http::Request;
+/// # use tower_governor::{errors::GovernorError, key_extractor::KeyExtractor};
+/// # #[derive(Clone)]
+/// # struct SessionId;
+/// # impl KeyExtractor for SessionId {
+/// #     type Key = String;
+/// #     #[cfg(feature = "tracing")]
+/// #     fn name(&self) -> &'static str {
+/// #         "session id"
+/// #     }
+/// #     fn extract<T>(&self, _req: &Request<T>) -> Result<Self::Key, GovernorError> {
+/// #         Ok(String::from("session"))
+/// #     }
+/// # }
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(Interned::new(SessionId))
+///     .finish()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interned<K> {
+    inner: K,
+    interner: KeyInterner,
+}
+
+impl<K> Interned<K> {
+    /// Wrap `inner`, interning up to [`KeyInterner::DEFAULT_CAPACITY`] keys.
+    pub fn new(inner: K) -> Self {
+        Self::with_interner(inner, KeyInterner::default())
+    }
+
+    /// Wrap `inner`, interning its keys with `interner`.
+    pub fn with_interner(inner: K, interner: KeyInterner) -> Self {
+        Self { inner, interner }
+    }
+
+    /// The interner of the keys.
+    pub fn interner(&self) -> &KeyInterner {
+        &self.interner
+    }
+}
+
+impl<K> KeyExtractor for Interned<K>
+where
+    K: KeyExtractor,
+    K::Key: AsRef<str>,
+{
+    type Key = InternedKey;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let key = self.inner.extract(req)?;
+        Ok(InternedKey(self.interner.intern(key.as_ref())))
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.inner.validate()
+    }
+
+    fn connection_stable(&self) -> bool {
+        self.inner.connection_stable()
+    }
+}
+
+/// A [KeyExtractor] wrapping another one and mapping its keys with a normalization function.
+///
+/// Extractors of different requests can then produce the same keys, e.g. to have the REST
+/// and gRPC layers of a service [share a quota](crate::governor::GovernorConfig::with_key_extractor)
+/// although their clients identify differently.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use http::HeaderName;
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder,
+///     key_extractor::{MetadataKeyExtractor, Normalized},
+/// };
+///
+/// // the API keys are sent in any case, but belong to the same client
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(Normalized::new(
+///         MetadataKeyExtractor::new(HeaderName::from_static("x-api-key")),
+///         |key: Arc<[u8]>| key.to_ascii_lowercase(),
+///     ))
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct Normalized<K: KeyExtractor, Key> {
+    inner: K,
+    normalize: Arc<dyn Fn(K::Key) -> Key + Send + Sync>,
+}
+
+impl<K: KeyExtractor, Key> Normalized<K, Key> {
+    /// Wrap `inner`, mapping its keys with `normalize`.
+    pub fn new(inner: K, normalize: impl Fn(K::Key) -> Key + Send + Sync + 'static) -> Self {
+        Self {
+            inner,
+            normalize: Arc::new(normalize),
+        }
+    }
+}
+
+impl<K: KeyExtractor, Key> Clone for Normalized<K, Key> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            normalize: self.normalize.clone(),
+        }
+    }
+}
+
+impl<K: KeyExtractor + Debug, Key> Debug for Normalized<K, Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Normalized")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+// functions can't be compared, only the wrapped extractors are
+impl<K: KeyExtractor + PartialEq, Key> PartialEq for Normalized<K, Key> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<K: KeyExtractor + Eq, Key> Eq for Normalized<K, Key> {}
+
+impl<K, Key> KeyExtractor for Normalized<K, Key>
+where
+    K: KeyExtractor,
+    Key: Clone + Hash + Eq + Debug,
+{
+    type Key = Key;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        self.inner.extract(req).map(|key| (self.normalize)(key))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.inner.validate()
+    }
+
+    fn connection_stable(&self) -> bool {
+        self.inner.connection_stable()
+    }
+}
+
+/// A [KeyExtractor] wrapping another one and sorting its keys into cohorts, reported instead
+/// of the keys in tracing and in the
+/// [cohort rates](crate::governor::GovernorConfigBuilder::track_cohort_rates), so that they
+/// can label metrics without an explosion of their cardinality or leaking the keys.
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder,
+///     key_extractor::{Cohorts, SmartIpKeyExtractor},
+/// };
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(Cohorts::new(SmartIpKeyExtractor, |ip: &IpAddr| {
+///         if ip.is_loopback() {
+///             "internal"
+///         } else {
+///             "public"
+///         }
+///     }))
+///     .track_cohort_rates()
+///     .finish()
+///     .unwrap();
+/// ```
+pub struct Cohorts<K: KeyExtractor> {
+    inner: K,
+    classify: Arc<CohortFn<K::Key>>,
+}
+
+type CohortFn<Key> = dyn Fn(&Key) -> &'static str + Send + Sync;
+
+impl<K: KeyExtractor> Cohorts<K> {
+    /// Wrap `inner`, sorting its keys into the cohorts named by `classify`.
+    pub fn new(
+        inner: K,
+        classify: impl Fn(&K::Key) -> &'static str + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            classify: Arc::new(classify),
+        }
+    }
+}
+
+impl<K: KeyExtractor> Clone for Cohorts<K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            classify: self.classify.clone(),
+        }
+    }
+}
+
+impl<K: KeyExtractor + Debug> Debug for Cohorts<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cohorts")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+// functions can't be compared, only the wrapped extractors are
+impl<K: KeyExtractor + PartialEq> PartialEq for Cohorts<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<K: KeyExtractor + Eq> Eq for Cohorts<K> {}
+
+impl<K: KeyExtractor> KeyExtractor for Cohorts<K> {
+    type Key = K::Key;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        self.inner.extract(req)
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        self.inner.key_name(key)
+    }
+
+    fn key_ip(&self, key: &Self::Key) -> Option<IpAddr> {
+        self.inner.key_ip(key)
+    }
+
+    fn key_from_ip(&self, key: &Self::Key, ip: IpAddr) -> Option<Self::Key> {
+        self.inner.key_from_ip(key, ip)
+    }
+
+    fn key_source(&self, key: &Self::Key) -> Option<Source> {
+        self.inner.key_source(key)
+    }
+
+    fn cohort(&self, key: &Self::Key) -> Option<&'static str> {
+        Some((self.classify)(key))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.inner.validate()
+    }
+
+    fn connection_stable(&self) -> bool {
+        self.inner.connection_stable()
+    }
+}
+
+/// A [KeyExtractor] using the value of a gRPC metadata entry, such as `x-api-key`, as key.
+///
+/// gRPC metadata travels as HTTP/2 headers, so this works with tonic servers as with any
+/// other tower service: add the [`GovernorLayer`] through tonic's `Server::builder().layer`.
+///
+/// ASCII metadata must hold visible ASCII characters only, as required by the gRPC
+/// specification. Binary metadata, whose names end with `-bin`, is base64 decoded, padded or
+/// not, so that clients encoding the same bytes differently share a key. Requests missing the
+/// entry or with an invalid value fail with [`GovernorError::UnableToExtractKey`].
+///
+/// Keys are [interned](KeyInterner), so that the requests of known clients don't allocate.
+///
+/// ```rust
+/// use http::HeaderName;
+/// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::MetadataKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(MetadataKeyExtractor::new(HeaderName::from_static("x-api-key")))
+///     .finish()
+///     .unwrap();
+/// ```
+///
+/// [`GovernorLayer`]: crate::GovernorLayer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataKeyExtractor {
+    name: HeaderName,
+    prefix: Option<Arc<str>>,
+    interner: KeyInterner<[u8]>,
+}
+
+impl MetadataKeyExtractor {
+    /// Use the value of the metadata entry `name` as key.
+    pub fn new(name: HeaderName) -> Self {
+        Self {
+            name,
+            prefix: None,
+            interner: KeyInterner::default(),
+        }
+    }
+
+    /// Intern the keys with `interner`, e.g. to hold more keys than the
+    /// [default](KeyInterner::DEFAULT_CAPACITY).
+    pub fn interner(mut self, interner: KeyInterner<[u8]>) -> Self {
+        self.interner = interner;
+        self
+    }
+
+    /// Strip `prefix` from ASCII values, e.g. `Bearer ` for the `authorization` entry. Values
+    /// without the prefix are rejected.
+    pub fn strip_prefix(mut self, prefix: impl Into<Arc<str>>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Whether the entry holds binary metadata.
+    pub fn is_binary(&self) -> bool {
+        self.name.as_str().ends_with("-bin")
+    }
+}
+
+impl KeyExtractor for MetadataKeyExtractor {
+    type Key = Arc<[u8]>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "gRPC metadata"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        let value = req
+            .headers()
+            .get(&self.name)
+            .ok_or(GovernorError::UnableToExtractKey)?
+            .as_bytes();
+        let key = if self.is_binary() {
+            decode_base64(value).map(|key| self.interner.intern(&key))
+        } else if value.iter().all(|byte| (0x20..0x7f).contains(byte)) {
+            match &self.prefix {
+                Some(prefix) => value.strip_prefix(prefix.as_bytes()),
+                None => Some(value),
+            }
+            .filter(|key| !key.is_empty())
+            .map(|key| self.interner.intern(key))
+        } else {
+            None
+        };
+        key.filter(|key| !key.is_empty())
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        if self.is_binary() {
+            Some(key.iter().map(|byte| format!("{:02x}", byte)).collect())
+        } else {
+            Some(String::from_utf8_lossy(key).into_owned())
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.is_binary() && self.prefix.is_some() {
+            return Err(ConfigError::InvalidKeyExtractor(format!(
+                "prefixes can't be stripped from the binary metadata entry `{}`",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Decodes standard base64, with or without padding, as used by binary gRPC metadata.
+fn decode_base64(value: &[u8]) -> Option<Vec<u8>> {
+    let value = value.trim_ascii();
+    let value = value
+        .strip_suffix(b"==")
+        .or_else(|| value.strip_suffix(b"="))
+        .unwrap_or(value);
+    if value.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(value.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for &byte in value {
+        let sextet = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        // only the bits not written out yet are kept
+        bits = (bits << 6 | u32::from(sextet)) & 0xffff;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// The error of the `Authorization` extractors when the credentials are missing or
+/// malformed.
+#[cfg(any(feature = "typed-header", feature = "jwt"))]
+fn unauthorized() -> GovernorError {
+    GovernorError::Other {
+        code: http::StatusCode::UNAUTHORIZED,
+        msg: Some("Missing or invalid credentials".to_owned()),
+        headers: None,
+    }
+}
+
+/// A [KeyExtractor] using the token of the `Authorization: Bearer` header as key, parsed by
+/// axum-extra's typed [`Authorization<Bearer>`](axum_extra::headers::Authorization).
+///
+/// Enabled by the `typed-header` feature. Requests without a valid bearer token are answered
+/// with a `401 Unauthorized`. The tokens are interned, see [`KeyInterner`].
+///
+/// The tokens are credentials, so the [key name](KeyExtractor::key_name) reported in logs,
+/// reports and [method rules](crate::governor::GovernorConfigBuilder::method_rules) is a
+/// hash of the token rather than the token itself.
+///
+/// # Example
+/// ```rust
+/// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::BearerKeyExtractor};
+///
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(BearerKeyExtractor::default())
+///     .finish()
+///     .unwrap();
+/// ```
+#[cfg(feature = "typed-header")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BearerKeyExtractor {
+    interner: KeyInterner,
+}
+
+#[cfg(feature = "typed-header")]
+impl BearerKeyExtractor {
+    /// Intern the tokens with `interner`, e.g. to hold more keys than the
+    /// [default](KeyInterner::DEFAULT_CAPACITY).
+    pub fn interner(mut self, interner: KeyInterner) -> Self {
+        self.interner = interner;
+        self
+    }
+}
+
+#[cfg(feature = "typed-header")]
+impl KeyExtractor for BearerKeyExtractor {
+    type Key = Arc<str>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "bearer token"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        use axum_extra::headers::{authorization::Bearer, Authorization, HeaderMapExt};
+
+        let Authorization(bearer) = req
+            .headers()
+            .typed_get::<Authorization<Bearer>>()
+            .ok_or_else(unauthorized)?;
+        Ok(self.interner.intern(bearer.token()))
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(fingerprint(key))
+    }
+}
+
+/// A hash of `token`, naming it without revealing it.
+#[cfg(feature = "typed-header")]
+fn fingerprint(token: &str) -> String {
+    use std::hash::{DefaultHasher, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(token.as_bytes());
+    format!("token-{:016x}", hasher.finish())
+}
+
+/// A [KeyExtractor] using the user name of the `Authorization: Basic` header as key, parsed by
+/// axum-extra's typed [`Authorization<Basic>`](axum_extra::headers::Authorization).
+///
+/// Enabled by the `typed-header` feature. Requests without valid basic credentials are
+/// answered with a `401 Unauthorized`. The password isn't checked: authenticate the requests
+/// before rate limiting them, or anyone can exhaust the quota of a user.
+#[cfg(feature = "typed-header")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BasicKeyExtractor {
+    interner: KeyInterner,
+}
+
+#[cfg(feature = "typed-header")]
+impl BasicKeyExtractor {
+    /// Intern the user names with `interner`, e.g. to hold more keys than the
+    /// [default](KeyInterner::DEFAULT_CAPACITY).
+    pub fn interner(mut self, interner: KeyInterner) -> Self {
+        self.interner = interner;
+        self
+    }
+}
+
+#[cfg(feature = "typed-header")]
+impl KeyExtractor for BasicKeyExtractor {
+    type Key = Arc<str>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "basic user name"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        use axum_extra::headers::{authorization::Basic, Authorization, HeaderMapExt};
+
+        let Authorization(basic) = req
+            .headers()
+            .typed_get::<Authorization<Basic>>()
+            .ok_or_else(unauthorized)?;
+        Ok(self.interner.intern(basic.username()))
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
+}
+
+const X_FORWARDED_PORT: &str = "x-forwarded-port";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// A [KeyExtractor] using a claim of the JSON Web Token of the `Authorization: Bearer` header
+/// as key, `sub` by default, so that the requests are rate limited per authenticated principal.
+///
+/// Enabled by the `jwt` feature. The token is decoded and validated with jsonwebtoken: requests
+/// without a valid token, or whose claim isn't a string or a number, are answered with a
+/// `401 Unauthorized`. The keys are interned, see [`KeyInterner`].
+///
+/// # Example
+/// ```rust
+/// use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+/// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::JwtKeyExtractor};
+///
+/// let extractor = JwtKeyExtractor::new(
+///     DecodingKey::from_secret(b"secret"),
+///     Validation::new(Algorithm::HS256),
+/// )
+/// .claim("client_id");
+/// let config = GovernorConfigBuilder::default()
+///     .key_extractor(extractor)
+///     .finish()
+///     .unwrap();
+/// ```
+#[cfg(feature = "jwt")]
+#[derive(Clone)]
+pub struct JwtKeyExtractor {
+    key: Arc<jsonwebtoken::DecodingKey>,
+    validation: Arc<jsonwebtoken::Validation>,
+    claim: Arc<str>,
+    interner: KeyInterner,
+}
+
+#[cfg(feature = "jwt")]
+impl JwtKeyExtractor {
+    /// Decode the tokens with `key`, checking them against `validation`.
+    pub fn new(key: jsonwebtoken::DecodingKey, validation: jsonwebtoken::Validation) -> Self {
+        Self {
+            key: Arc::new(key),
+            validation: Arc::new(validation),
+            claim: Arc::from("sub"),
+            interner: KeyInterner::default(),
+        }
+    }
+
+    /// Use the claim `name` as key instead of `sub`.
+    pub fn claim(mut self, name: &str) -> Self {
+        self.claim = Arc::from(name);
+        self
+    }
+
+    /// Intern the keys with `interner`, e.g. to hold more keys than the
+    /// [default](KeyInterner::DEFAULT_CAPACITY).
+    pub fn interner(mut self, interner: KeyInterner) -> Self {
+        self.interner = interner;
+        self
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl Debug for JwtKeyExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeyExtractor")
+            .field("validation", &self.validation)
+            .field("claim", &self.claim)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl KeyExtractor for JwtKeyExtractor {
+    type Key = Arc<str>;
+
+    #[cfg(feature = "tracing")]
+    fn name(&self) -> &'static str {
+        "JWT claim"
+    }
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        use serde_json::{Map, Value};
+
+        let token = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or_else(unauthorized)?;
+        let claims = jsonwebtoken::decode::<Map<String, Value>>(token, &self.key, &self.validation)
+            .map_err(|_| unauthorized())?
+            .claims;
+        match claims.get(&*self.claim) {
+            Some(Value::String(value)) => Ok(self.interner.intern(value)),
+            Some(Value::Number(value)) => Ok(self.interner.intern(&value.to_string())),
+            _ => Err(unauthorized()),
+        }
+    }
+
+    fn key_name(&self, key: &Self::Key) -> Option<String> {
+        Some(key.to_string())
+    }
 }
@@ -0,0 +1,121 @@
+//! Quota held for long-running jobs, see [`GovernorConfig::reserve`].
+//!
+//! A [`Lease`] charges its whole cost to the key up front, so that a job started now can't be
+//! starved by the requests arriving while it runs. The job then [consumes](Lease::consume) the
+//! lease as it goes, and the cost left unused when the lease is dropped is given back to the
+//! key, unless the lease expired by then.
+//!
+//! # Example
+//! ```rust
+//! use std::{net::IpAddr, time::Duration};
+//! use tower_governor::governor::GovernorConfigBuilder;
+//!
+//! let config = GovernorConfigBuilder::default()
+//!     .per_second(1)
+//!     .burst_size(10)
+//!     .leases()
+//!     .finish()
+//!     .unwrap();
+//! let ip = IpAddr::from([10, 0, 0, 1]);
+//!
+//! let mut lease = config.reserve(ip, 8, Duration::from_secs(60)).unwrap();
+//! assert!(!config.check_blocking(&ip, 4).is_allowed());
+//! // the export only needed half of its reservation
+//! assert!(lease.consume(4));
+//! drop(lease);
+//! assert!(config.check_blocking(&ip, 4).is_allowed());
+//! ```
+//!
+//! [`GovernorConfig::reserve`]: crate::governor::GovernorConfig::reserve
+
+use crate::refund::Refunds;
+use std::{
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Quota reserved for a key, giving the unused cost back when dropped before it expires.
+pub struct Lease<Key: Hash + Eq + Clone> {
+    refunds: Arc<Refunds<Key>>,
+    key: Key,
+    cost: u32,
+    // the cells of the quota actually charged, lower than the cost when clamped
+    cells: u32,
+    used: u32,
+    // `None` for the time to live too long to represent, never expiring
+    expires_at: Option<Instant>,
+}
+
+impl<Key: Hash + Eq + Clone> Lease<Key> {
+    pub(crate) fn new(
+        refunds: Arc<Refunds<Key>>,
+        key: Key,
+        cost: u32,
+        cells: u32,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            refunds,
+            key,
+            cost,
+            cells,
+            used: 0,
+            expires_at: Instant::now().checked_add(ttl),
+        }
+    }
+
+    /// The key the quota is reserved for.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+
+    /// The cost left to consume, zero once the lease expired.
+    pub fn remaining(&self) -> u32 {
+        match self.is_expired() {
+            true => 0,
+            false => self.cost - self.used,
+        }
+    }
+
+    /// Whether the lease outlived its time to live, forfeiting the cost left.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| Instant::now() >= expires_at)
+    }
+
+    /// Consume `cost` of the lease, returning whether that much was left.
+    pub fn consume(&mut self, cost: u32) -> bool {
+        if cost > self.remaining() {
+            return false;
+        }
+        self.used += cost;
+        true
+    }
+}
+
+impl<Key: Hash + Eq + Clone + fmt::Debug> fmt::Debug for Lease<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lease")
+            .field("key", &self.key)
+            .field("cost", &self.cost)
+            .field("used", &self.used)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> Drop for Lease<Key> {
+    fn drop(&mut self) {
+        let unused = self.remaining();
+        if unused == 0 {
+            return;
+        }
+        // the cells charged for the unused cost, rounded down
+        let cells = (u64::from(self.cells) * u64::from(unused) / u64::from(self.cost)) as u32;
+        if cells > 0 {
+            self.refunds.refund(self.key.clone(), cells);
+        }
+    }
+}
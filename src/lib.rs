@@ -1,61 +1,432 @@
 #![doc = include_str!("../README.md")]
+// the README is written for GitHub, where the continuations of its list items aren't indented
+#![allow(clippy::doc_lazy_continuation)]
 
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "async-key")]
+pub mod async_key;
+#[cfg(feature = "audit")]
+pub mod audit;
+mod auth_failures;
+mod ban;
+pub mod body;
+mod breaker;
+mod charging;
+mod churn;
+mod class;
+pub mod connection;
+pub mod credits;
+pub mod decision;
 pub mod errors;
+mod expiring;
+mod extraction_cache;
+pub mod forwarding;
 pub mod governor;
+pub mod handle_error;
+pub mod handlers;
+pub mod headers;
 pub mod key_extractor;
-use crate::governor::{Governor, GovernorConfig};
-use ::governor::clock::{Clock, DefaultClock, QuantaInstant};
+pub mod lease;
+#[cfg(feature = "tracing")]
+mod logging;
+pub mod matcher;
+pub mod methods;
+#[cfg(feature = "utoipa")]
+pub mod openapi;
+pub mod partition;
+mod penalty;
+mod prefetch;
+mod proxy_check;
+mod refund;
+pub mod replay;
+pub mod report;
+pub mod resolver;
+mod retain;
+pub mod service;
+pub mod settings;
+mod share;
+#[cfg(feature = "shared-memory")]
+mod shared_memory;
+mod sliding_log;
+pub mod stack;
+pub mod state;
+#[cfg(feature = "stream")]
+pub mod stream;
+mod sync;
+#[cfg(feature = "template")]
+pub mod template;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod trailers;
+mod windows;
+#[cfg(feature = "tarpit")]
+use crate::decision::Decision;
+use crate::decision::{EnforcementMode, RateLimitSnapshot};
+use crate::errors::ConfigError;
+use crate::governor::{
+    DefaultClock, DefaultInstant, DeferredResponse, Governor, GovernorConfig,
+    GovernorConfigBuilder, InnerErrorHook, ResponseHook, ResponseMapper, Verdict,
+};
+use crate::headers::{BareMiddleware, QuotaHeaders, UpstreamHeaders, WaitTimeUnit};
+use crate::refund::RefundGuard;
+use ::governor::clock::Clock;
 use ::governor::middleware::{NoOpMiddleware, RateLimitingMiddleware, StateInformationMiddleware};
 use axum::body::Body;
 pub use errors::GovernorError;
 use http::response::Response;
 
-use http::header::{HeaderName, HeaderValue};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
 use http::request::Request;
-use http::HeaderMap;
-use key_extractor::KeyExtractor;
+use key_extractor::{
+    GlobalKeyExtractor, KeyExtractor, PeerIpKeyExtractor, Scoped, SmartIpKeyExtractor,
+};
 use pin_project::pin_project;
+use std::fmt;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use std::{future::Future, pin::Pin, task::ready};
 use tower::{Layer, Service};
 
 /// The Layer type that implements tower::Layer and is passed into `.layer()`
-pub struct GovernorLayer<K, M>
+///
+/// Every option of the layer lives in its [`GovernorConfig`], so `config` stays its only
+/// field and `GovernorLayer { config }` keeps compiling as options are added. They are set
+/// on the builder, or on a built layer with the `with_*` methods:
+///
+/// ```rust
+/// use tower_governor::{governor::GovernorConfigBuilder, handlers, GovernorLayer};
+///
+/// let config = GovernorConfigBuilder::default().finish().unwrap();
+/// let layer = GovernorLayer::from_config(config).with_error_handler(handlers::json());
+/// ```
+pub struct GovernorLayer<K, M, C = DefaultClock>
 where
     K: KeyExtractor,
-    M: RateLimitingMiddleware<QuantaInstant>,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
 {
-    pub config: Arc<GovernorConfig<K, M>>,
+    pub config: Arc<GovernorConfig<K, M, C>>,
 }
 
-impl<K, M, S> Layer<S> for GovernorLayer<K, M>
+impl<K, M, C> GovernorLayer<K, M, C>
 where
     K: KeyExtractor,
-    M: RateLimitingMiddleware<QuantaInstant>,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock + Clone + 'static,
 {
-    type Service = Governor<K, M, S>;
+    /// Finish the given builder and wrap the resulting configuration into a layer.
+    ///
+    /// # Example
+    /// ```rust
+    /// use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
+    ///
+    /// let layer = GovernorLayer::try_from_builder(
+    ///     GovernorConfigBuilder::default().per_second(2).burst_size(5),
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn try_from_builder(
+        builder: &mut GovernorConfigBuilder<K, M, C>,
+    ) -> Result<Self, ConfigError> {
+        Ok(Self {
+            config: Arc::new(builder.try_finish()?),
+        })
+    }
+}
+
+impl<K, M, C> GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// A layer applying `config`, given as is or in an [`Arc`] shared with other layers.
+    pub fn from_config(config: impl Into<Arc<GovernorConfig<K, M, C>>>) -> Self {
+        Self {
+            config: config.into(),
+        }
+    }
+
+    /// The same layer, answering its errors with `func`, see
+    /// [`GovernorConfig::with_error_handler`].
+    pub fn with_error_handler<F>(self, func: F) -> Self
+    where
+        F: Fn(GovernorError) -> Response<Body> + Send + Sync + 'static,
+    {
+        Self::from_config(self.config.with_error_handler(func))
+    }
+
+    /// The same layer, calling `hook` with its rejections, see
+    /// [`GovernorConfig::with_rejection_hook`].
+    pub fn with_rejection_hook<F>(self, hook: F) -> Self
+    where
+        F: Fn(&mut Response<Body>, &decision::RejectionContext<'_>) + Send + Sync + 'static,
+    {
+        Self::from_config(self.config.with_rejection_hook(hook))
+    }
+}
+
+impl<M> GovernorLayer<PeerIpKeyExtractor, M>
+where
+    M: RateLimitingMiddleware<DefaultInstant>,
+{
+    /// A layer applying [`GovernorConfig::secure`].
+    pub fn secure() -> Self {
+        Self::from(GovernorConfig::secure())
+    }
+
+    /// Build a layer from the default configuration, adjusted by `f`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use governor::middleware::NoOpMiddleware;
+    /// use tower_governor::{
+    ///     governor::DefaultInstant, key_extractor::PeerIpKeyExtractor, GovernorLayer,
+    /// };
+    ///
+    /// let layer = GovernorLayer::<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>>::with(|builder| {
+    ///     builder.per_second(2).burst_size(5);
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn with<F>(f: F) -> Result<Self, ConfigError>
+    where
+        F: FnOnce(&mut GovernorConfigBuilder<PeerIpKeyExtractor, M>),
+    {
+        let mut builder = GovernorConfigBuilder::const_default();
+        f(&mut builder);
+        Self::try_from_builder(&mut builder)
+    }
+}
+
+/// Number of keys at which the stores of the one-liner layers are cleaned up, see
+/// [`GovernorConfigBuilder::retain_watermarks`].
+const ONE_LINER_HIGH_WATERMARK: usize = 100_000;
+const ONE_LINER_LOW_WATERMARK: usize = 50_000;
+
+impl GovernorLayer<PeerIpKeyExtractor, StateInformationMiddleware> {
+    /// A layer allowing each peer IP address `per_second` requests per second on average,
+    /// in bursts of up to `burst_size` requests.
+    ///
+    /// The responses carry the [x-ratelimit headers], and the limiter store is cleaned up
+    /// once it holds many keys, see [`retain_watermarks`]. Use the [`GovernorConfigBuilder`]
+    /// for anything else.
+    ///
+    /// # Example
+    /// ```rust
+    /// use axum::{routing::get, Router};
+    /// use tower_governor::GovernorLayer;
+    ///
+    /// let app: Router = Router::new()
+    ///     .route("/", get(|| async { "Hello world" }))
+    ///     .layer(GovernorLayer::per_ip(5, 10).unwrap());
+    /// ```
+    ///
+    /// [x-ratelimit headers]: GovernorConfigBuilder::use_headers
+    /// [`retain_watermarks`]: GovernorConfigBuilder::retain_watermarks
+    pub fn per_ip(per_second: u32, burst_size: u32) -> Result<Self, ConfigError> {
+        one_liner(PeerIpKeyExtractor, per_second, burst_size)
+            .retain_watermarks(ONE_LINER_HIGH_WATERMARK, ONE_LINER_LOW_WATERMARK)
+            .into_layer()
+    }
+}
+
+impl GovernorLayer<SmartIpKeyExtractor, StateInformationMiddleware> {
+    /// Same as [`per_ip`](GovernorLayer::per_ip), for the client IP address found by the
+    /// [`SmartIpKeyExtractor`], for apps behind a reverse proxy.
+    ///
+    /// **Warning:** the same caveats as the [`SmartIpKeyExtractor`] apply.
+    pub fn per_smart_ip(per_second: u32, burst_size: u32) -> Result<Self, ConfigError> {
+        one_liner(SmartIpKeyExtractor, per_second, burst_size)
+            .retain_watermarks(ONE_LINER_HIGH_WATERMARK, ONE_LINER_LOW_WATERMARK)
+            .into_layer()
+    }
+}
+
+impl GovernorLayer<GlobalKeyExtractor, StateInformationMiddleware> {
+    /// A layer allowing `per_second` requests per second on average across all clients, in
+    /// bursts of up to `burst_size` requests, checked by a single [unkeyed] limiter.
+    ///
+    /// The responses carry the [x-ratelimit headers].
+    ///
+    /// [unkeyed]: GovernorConfigBuilder::unkeyed
+    /// [x-ratelimit headers]: GovernorConfigBuilder::use_headers
+    pub fn global(per_second: u32, burst_size: u32) -> Result<Self, ConfigError> {
+        one_liner(GlobalKeyExtractor, per_second, burst_size)
+            .unkeyed()
+            .into_layer()
+    }
+}
+
+/// The longest time the internals wait for, standing for "forever": a century, which is
+/// still representable on every platform and within the `u64` nanoseconds of the snapshots.
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// The instant `duration` after `now`, at most [`FOREVER`] later.
+pub(crate) fn deadline(now: Instant, duration: Duration) -> Instant {
+    now.checked_add(duration.min(FOREVER))
+        .unwrap_or(now + FOREVER)
+}
+
+// The builder of the one-liner layers, replenishing `per_second` elements every second.
+fn one_liner<K: KeyExtractor>(
+    key_extractor: K,
+    per_second: u32,
+    burst_size: u32,
+) -> GovernorConfigBuilder<K, StateInformationMiddleware> {
+    let mut builder = GovernorConfigBuilder::default()
+        .key_extractor(key_extractor)
+        .use_headers();
+    // a zero rate makes a zero period, which the builder rejects
+    let period = Duration::from_secs(1)
+        .checked_div(per_second)
+        .unwrap_or_default();
+    builder.period(period).burst_size(burst_size);
+    builder
+}
+
+impl<M> Default for GovernorLayer<PeerIpKeyExtractor, M>
+where
+    M: RateLimitingMiddleware<DefaultInstant>,
+{
+    /// A layer applying the default configuration, see [`GovernorConfig::default`].
+    fn default() -> Self {
+        Self::from(
+            GovernorConfigBuilder::const_default()
+                .finish()
+                .expect("the default configuration is valid"),
+        )
+    }
+}
+
+impl<K, M, C> From<GovernorConfig<K, M, C>> for GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn from(config: GovernorConfig<K, M, C>) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<K, M, C> From<Arc<GovernorConfig<K, M, C>>> for GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn from(config: Arc<GovernorConfig<K, M, C>>) -> Self {
+        Self { config }
+    }
+}
+
+impl<K, M, C> TryFrom<&mut GovernorConfigBuilder<K, M, C>> for GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock + Clone + 'static,
+{
+    type Error = ConfigError;
+
+    fn try_from(builder: &mut GovernorConfigBuilder<K, M, C>) -> Result<Self, ConfigError> {
+        Self::try_from_builder(builder)
+    }
+}
+
+impl<K, M, C> GovernorLayer<Scoped<K>, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// A layer namespacing its keys by `scope` while sharing the limiter store of this one,
+    /// see [`GovernorConfig::scoped`].
+    pub fn scoped(&self, scope: impl Into<Arc<str>>) -> Self {
+        Self {
+            config: Arc::new(self.config.scoped(scope)),
+        }
+    }
+}
+
+impl<K, M, S, C> Layer<S> for GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    type Service = Governor<K, M, S, C>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        Governor::new(inner, &self.config)
+        Governor::shared(inner, self.config.clone())
     }
 }
 
 /// https://stegosaurusdormant.com/understanding-derive-clone/
-impl<K: KeyExtractor, M: RateLimitingMiddleware<QuantaInstant>> Clone for GovernorLayer<K, M> {
+impl<K, M, C> Clone for GovernorLayer<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
     fn clone(&self) -> Self {
         Self {
             config: self.config.clone(),
         }
     }
 }
+impl<K, M, S, C> Governor<K, M, S, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    // Forward an allowed request, with the headers of its quota when `headers` is set.
+    fn forward_allowed<ReqBody>(
+        &mut self,
+        mut req: Request<ReqBody>,
+        remaining: Option<u32>,
+        class: Option<&'static str>,
+        on_response: Option<ResponseHook>,
+        headers: bool,
+    ) -> Kind<S::Future>
+    where
+        S: Service<Request<ReqBody>>,
+    {
+        let snapshot = self.allowed_snapshot(remaining, class);
+        let extra_headers = match headers {
+            true => self.extra_headers(&mut req, remaining, class),
+            false => None,
+        };
+        req.extensions_mut().insert(snapshot);
+        Kind::Allowed {
+            future: self.inner.call(req),
+            snapshot,
+            headers,
+            credits: headers && self.config.credits,
+            upstream: self.config.upstream_headers,
+            class,
+            scope: self.scope(class).filter(|_| headers).cloned(),
+            trailers: self.config.trailers,
+            on_error: self.config.inner_error_hook.clone(),
+            mapper: self.config.response_mapper.clone(),
+            on_response,
+            extra_headers,
+        }
+    }
+}
+
 // Implement tower::Service for Governor
-impl<K, S, ReqBody> Service<Request<ReqBody>> for Governor<K, NoOpMiddleware, S>
+impl<K, S, ReqBody, C> Service<Request<ReqBody>> for Governor<K, NoOpMiddleware<C::Instant>, S, C>
 where
     K: KeyExtractor,
+    C: Clock + Send + Sync + 'static,
+    K::Key: Send + Sync + 'static,
     S: Service<Request<ReqBody>, Response = Response<Body>>,
 {
     type Response = S::Response;
@@ -66,70 +437,80 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        if let Some(configured_methods) = &self.methods {
-            if !configured_methods.contains(req.method()) {
-                // The request method is not configured, we're ignoring this one.
-                let future = self.inner.call(req);
-                return ResponseFuture {
-                    inner: Kind::Passthrough { future },
-                };
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let verdict = self.verdict(&mut req, false);
+        let refund = RefundGuard::take(&mut req);
+        let mode = EnforcementMode::of(&req);
+        let inner = match verdict {
+            Verdict::Bypass | Verdict::Forward => Kind::Passthrough {
+                future: self.inner.call(req),
+            },
+            Verdict::Allowed((), class) => {
+                let on_response = self.response_hook(&mut req, class);
+                self.forward_allowed(req, None, class, on_response, false)
             }
+            Verdict::Charge(hook, class) => {
+                self.forward_allowed(req, None, class, Some(hook), false)
+            }
+            Verdict::Observe(hook) => Kind::Observed {
+                future: self.inner.call(req),
+                on_response: Some(hook),
+            },
+            Verdict::Respond(response) => Kind::rejection(response, self.config.tarpit),
+            Verdict::Defer(response) => Kind::deferred(response, self.config.tarpit),
+        };
+        ResponseFuture {
+            inner,
+            refund,
+            mode,
         }
-        // Use the provided key extractor to extract the rate limiting key from the request.
-        match self.key_extractor.extract(&req) {
-            // Extraction worked, let's check if rate limiting is needed.
-            Ok(key) => match self.limiter.check_key(&key) {
-                Ok(_) => {
-                    let future = self.inner.call(req);
-                    ResponseFuture {
-                        inner: Kind::Passthrough { future },
-                    }
-                }
+    }
+}
 
-                Err(negative) => {
-                    let wait_time = negative
-                        .wait_time_from(DefaultClock::default().now())
-                        .as_secs();
+// Implementation of Service for Governor using the BareMiddleware, without any header.
+impl<K, S, ReqBody, C> Service<Request<ReqBody>> for Governor<K, BareMiddleware, S, C>
+where
+    K: KeyExtractor,
+    C: Clock + Send + Sync + 'static,
+    K::Key: Send + Sync + 'static,
+    S: Service<Request<ReqBody>, Response = Response<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
 
-                    #[cfg(feature = "tracing")]
-                    {
-                        let key_name = match self.key_extractor.key_name(&key) {
-                            Some(n) => format!(" [{}]", &n),
-                            None => "".to_owned(),
-                        };
-                        tracing::info!(
-                            "Rate limit exceeded for {}{}, quota reset in {}s",
-                            self.key_extractor.name(),
-                            key_name,
-                            &wait_time
-                        );
-                    }
-                    let mut headers = HeaderMap::new();
-                    headers.insert("x-ratelimit-after", wait_time.into());
-                    headers.insert("retry-after", wait_time.into());
-
-                    let error_response = self.error_handler()(GovernorError::TooManyRequests {
-                        wait_time,
-                        headers: Some(headers),
-                    });
-
-                    ResponseFuture {
-                        inner: Kind::Error {
-                            error_response: Some(error_response),
-                        },
-                    }
-                }
-            },
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
 
-            Err(e) => {
-                let error_response = self.error_handler()(e);
-                ResponseFuture {
-                    inner: Kind::Error {
-                        error_response: Some(error_response),
-                    },
-                }
-            }
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let verdict = self.verdict(&mut req, false);
+        let refund = RefundGuard::take(&mut req);
+        let mode = EnforcementMode::of(&req);
+        let inner = match verdict {
+            Verdict::Allowed(_, class) => match self.response_hook(&mut req, class) {
+                Some(hook) => Kind::Observed {
+                    future: self.inner.call(req),
+                    on_response: Some(hook),
+                },
+                None => Kind::Passthrough {
+                    future: self.inner.call(req),
+                },
+            },
+            Verdict::Bypass | Verdict::Forward => Kind::Passthrough {
+                future: self.inner.call(req),
+            },
+            Verdict::Charge(hook, _) | Verdict::Observe(hook) => Kind::Observed {
+                future: self.inner.call(req),
+                on_response: Some(hook),
+            },
+            Verdict::Respond(response) => Kind::rejection(response, self.config.tarpit),
+            Verdict::Defer(response) => Kind::deferred(response, self.config.tarpit),
+        };
+        ResponseFuture {
+            inner,
+            refund,
+            mode,
         }
     }
 }
@@ -137,9 +518,18 @@ where
 #[derive(Debug)]
 #[pin_project]
 /// Response future for [`Governor`].
+///
+/// Its state is private: the future only promises to resolve to the response of the inner
+/// service or to a response of the layer, so that new rejection and delay modes don't change
+/// the type. It is `Unpin` whenever the future of the inner service is, unless the `tarpit`
+/// feature is enabled.
 pub struct ResponseFuture<F> {
     #[pin]
     inner: Kind<F>,
+    // gives the charges of the request back if it is cancelled
+    refund: RefundGuard,
+    // reported in the response while a policy is rolled out
+    mode: Option<EnforcementMode>,
 }
 
 #[derive(Debug)]
@@ -149,21 +539,122 @@ enum Kind<F> {
         #[pin]
         future: F,
     },
-    RateLimitHeader {
+    Allowed {
         #[pin]
         future: F,
-        #[pin]
-        burst_size: u32,
-        #[pin]
-        remaining_burst_capacity: u32,
+        snapshot: RateLimitSnapshot,
+        // whether to add the x-ratelimit headers
+        headers: bool,
+        // whether to name them after credits
+        credits: bool,
+        upstream: UpstreamHeaders,
+        class: Option<&'static str>,
+        scope: Option<HeaderValue>,
+        // whether to add the x-ratelimit trailers to gRPC responses
+        trailers: bool,
+        on_error: Option<InnerErrorHook>,
+        mapper: Option<ResponseMapper>,
+        // counts the response by status
+        on_response: Option<ResponseHook>,
+        // the headers of the windows and the window start of the request
+        extra_headers: Option<HeaderMap>,
     },
     WhitelistedHeader {
         #[pin]
         future: F,
+        header: HeaderName,
+    },
+    Observed {
+        #[pin]
+        future: F,
+        on_response: Option<ResponseHook>,
     },
     Error {
         error_response: Option<Response<Body>>,
     },
+    // A response of the layer, held back until the delay elapsed.
+    #[cfg(feature = "tarpit")]
+    Delayed {
+        #[pin]
+        delay: tokio::time::Sleep,
+        response: Option<Response<Body>>,
+        #[cfg(feature = "tracing")]
+        parked: logging::Parked,
+    },
+    // A response of the layer produced by a future, e.g. an async error handler.
+    Deferred {
+        response: Deferred,
+    },
+}
+
+/// The time to hold `response` for, no longer than the wait time of rejections: the client
+/// may retry successfully by then.
+#[cfg(feature = "tarpit")]
+fn tarpit_delay(delay: Duration, response: &Response<Body>) -> Duration {
+    match response.extensions().get::<RateLimitSnapshot>() {
+        Some(RateLimitSnapshot {
+            decision: Decision::Rejected { wait_time },
+            ..
+        }) => delay.min(*wait_time),
+        _ => delay,
+    }
+}
+
+// The future of a deferred response, which can't derive `Debug`.
+struct Deferred(DeferredResponse);
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deferred").finish_non_exhaustive()
+    }
+}
+
+impl<F> Kind<F> {
+    /// Respond with `response`, after `delay` if any.
+    fn rejection(response: Response<Body>, delay: Option<Duration>) -> Self {
+        match delay {
+            #[cfg(feature = "tarpit")]
+            Some(delay) => {
+                let delay = tarpit_delay(delay, &response);
+                Kind::Delayed {
+                    delay: tokio::time::sleep(delay),
+                    response: Some(response),
+                    #[cfg(feature = "tracing")]
+                    parked: logging::Parked::new(delay),
+                }
+            }
+            _ => Kind::Error {
+                error_response: Some(response),
+            },
+        }
+    }
+
+    /// Respond with the response of `future`, after `delay` if any.
+    fn deferred(future: DeferredResponse, delay: Option<Duration>) -> Self {
+        let response = match delay {
+            #[cfg(feature = "tarpit")]
+            Some(delay) => Box::pin(async move {
+                let response = future.await;
+                let delay = tarpit_delay(delay, &response);
+                #[cfg(feature = "tracing")]
+                {
+                    use tracing::Instrument;
+                    let parked = logging::Parked::new(delay);
+                    tokio::time::sleep(delay)
+                        .instrument(parked.span.clone())
+                        .await;
+                    parked.release(&response);
+                }
+                #[cfg(not(feature = "tracing"))]
+                tokio::time::sleep(delay).await;
+                response
+            }),
+            _ => future,
+        };
+        Kind::Deferred {
+            response: Deferred(response),
+        }
+    }
 }
 
 impl<F, E> Future for ResponseFuture<F>
@@ -173,50 +664,131 @@ where
     type Output = Result<Response<Body>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match self.project().inner.project() {
+        let this = self.project();
+        let mut result = ready!(this.inner.poll(cx));
+        this.refund.disarm();
+        if let (Some(mode), Ok(response)) = (this.mode, &mut result) {
+            mode.report(response);
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl<F, E> Future for Kind<F>
+where
+    F: Future<Output = Result<Response<Body>, E>>,
+{
+    type Output = Result<Response<Body>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
             KindProj::Passthrough { future } => future.poll(cx),
-            KindProj::RateLimitHeader {
+            KindProj::Allowed {
                 future,
-                burst_size,
-                remaining_burst_capacity,
+                snapshot,
+                headers,
+                credits,
+                upstream,
+                class,
+                scope,
+                trailers,
+                on_error,
+                mapper,
+                on_response,
+                extra_headers,
             } => {
-                let mut response = ready!(future.poll(cx))?;
+                let mut response = match ready!(future.poll(cx)) {
+                    Ok(response) => response,
+                    Err(error) => {
+                        if let Some(hook) = on_error {
+                            hook.call(snapshot);
+                        }
+                        if let Some(hook) = on_response.take() {
+                            hook.call(None);
+                        }
+                        return Poll::Ready(Err(error));
+                    }
+                };
 
-                let mut headers = HeaderMap::new();
-                headers.insert(
-                    HeaderName::from_static("x-ratelimit-limit"),
-                    HeaderValue::from(*burst_size),
-                );
-                headers.insert(
-                    HeaderName::from_static("x-ratelimit-remaining"),
-                    HeaderValue::from(*remaining_burst_capacity),
-                );
-                response.headers_mut().extend(headers.drain());
+                if *headers {
+                    QuotaHeaders {
+                        limit: snapshot.limit,
+                        remaining: snapshot.remaining,
+                        bare_limit: true,
+                        after: None,
+                        after_unit: WaitTimeUnit::Seconds,
+                        retry_after: false,
+                        credits: *credits,
+                        class: *class,
+                        scope: scope.as_ref(),
+                    }
+                    .merge(response.headers_mut(), *upstream);
+                    if let Some(extra_headers) = extra_headers.take() {
+                        response.headers_mut().extend(extra_headers);
+                    }
+                }
+                if *trailers {
+                    response = trailers::append(response, snapshot);
+                }
+                response.extensions_mut().insert(*snapshot);
+                if let Some(mapper) = mapper {
+                    mapper.call(&mut response, snapshot);
+                }
+                if let Some(hook) = on_response.take() {
+                    hook.call(Some(&response));
+                }
 
                 Poll::Ready(Ok(response))
             }
-            KindProj::WhitelistedHeader { future } => {
+            KindProj::WhitelistedHeader { future, header } => {
                 let mut response = ready!(future.poll(cx))?;
 
                 let headers = response.headers_mut();
-                headers.insert(
-                    HeaderName::from_static("x-ratelimit-whitelisted"),
-                    HeaderValue::from_static("true"),
-                );
+                headers.insert(header.clone(), HeaderValue::from_static("true"));
 
                 Poll::Ready(Ok(response))
             }
+            KindProj::Observed {
+                future,
+                on_response,
+            } => {
+                let result = ready!(future.poll(cx));
+                if let Some(hook) = on_response.take() {
+                    hook.call(result.as_ref().ok());
+                }
+                Poll::Ready(result)
+            }
             KindProj::Error { error_response } => Poll::Ready(Ok(error_response.take().expect("
                 <Governor as Service<Request<_>>>::call must produce Response<String> when GovernorError occurs.
             "))),
+            #[cfg(feature = "tarpit")]
+            KindProj::Delayed {
+                delay,
+                response,
+                #[cfg(feature = "tracing")]
+                parked,
+            } => {
+                #[cfg(feature = "tracing")]
+                let _entered = parked.span.enter();
+                ready!(delay.poll(cx));
+                let response = response
+                    .take()
+                    .expect("the delayed response is only taken once");
+                #[cfg(feature = "tracing")]
+                parked.release(&response);
+                Poll::Ready(Ok(response))
+            }
+            KindProj::Deferred { response } => response.0.as_mut().poll(cx).map(Ok),
         }
     }
 }
 
 // Implementation of Service for Governor using the StateInformationMiddleware.
-impl<K, S, ReqBody> Service<Request<ReqBody>> for Governor<K, StateInformationMiddleware, S>
+impl<K, S, ReqBody, C> Service<Request<ReqBody>> for Governor<K, StateInformationMiddleware, S, C>
 where
     K: KeyExtractor,
+    C: Clock + Send + Sync + 'static,
+    K::Key: Send + Sync + 'static,
     S: Service<Request<ReqBody>, Response = Response<Body>>,
     // Body type of response must impl From<String> trait to convert potential error
     // produced by governor to re
@@ -231,81 +803,43 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
-        if let Some(configured_methods) = &self.methods {
-            if !configured_methods.contains(req.method()) {
-                // The request method is not configured, we're ignoring this one.
-                let fut = self.inner.call(req);
-                return ResponseFuture {
-                    inner: Kind::WhitelistedHeader { future: fut },
-                };
-            }
-        }
-        // Use the provided key extractor to extract the rate limiting key from the request.
-        match self.key_extractor.extract(&req) {
-            // Extraction worked, let's check if rate limiting is needed.
-            Ok(key) => match self.limiter.check_key(&key) {
-                Ok(snapshot) => {
-                    let fut = self.inner.call(req);
-                    ResponseFuture {
-                        inner: Kind::RateLimitHeader {
-                            future: fut,
-                            burst_size: snapshot.quota().burst_size().get(),
-                            remaining_burst_capacity: snapshot.remaining_burst_capacity(),
-                        },
-                    }
-                }
-
-                Err(negative) => {
-                    let wait_time = negative
-                        .wait_time_from(DefaultClock::default().now())
-                        .as_secs();
-
-                    #[cfg(feature = "tracing")]
-                    {
-                        let key_name = match self.key_extractor.key_name(&key) {
-                            Some(n) => format!(" [{}]", &n),
-                            None => "".to_owned(),
-                        };
-                        tracing::info!(
-                            "Rate limit exceeded for {}{}, quota reset in {}s",
-                            self.key_extractor.name(),
-                            key_name,
-                            &wait_time
-                        );
-                    }
-
-                    let mut headers = HeaderMap::new();
-                    headers.insert("x-ratelimit-after", wait_time.into());
-                    headers.insert("retry-after", wait_time.into());
-                    headers.insert(
-                        "x-ratelimit-limit",
-                        negative.quota().burst_size().get().into(),
-                    );
-                    headers.insert("x-ratelimit-remaining", 0.into());
-
-                    let error_response = self.error_handler()(GovernorError::TooManyRequests {
-                        wait_time,
-                        headers: Some(headers),
-                    });
-
-                    ResponseFuture {
-                        inner: Kind::Error {
-                            error_response: Some(error_response),
-                        },
-                    }
-                }
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let verdict = self.verdict(&mut req, true);
+        let refund = RefundGuard::take(&mut req);
+        let mode = EnforcementMode::of(&req);
+        let inner = match verdict {
+            Verdict::Bypass => match &self.config.whitelisted_header {
+                Some(header) => Kind::WhitelistedHeader {
+                    future: self.inner.call(req),
+                    header: header.clone(),
+                },
+                None => Kind::Passthrough {
+                    future: self.inner.call(req),
+                },
             },
-
-            // Extraction failed, stop right now.
-            Err(e) => {
-                let error_response = self.error_handler()(e);
-                ResponseFuture {
-                    inner: Kind::Error {
-                        error_response: Some(error_response),
-                    },
-                }
+            Verdict::Forward => Kind::Passthrough {
+                future: self.inner.call(req),
+            },
+            Verdict::Observe(hook) => Kind::Observed {
+                future: self.inner.call(req),
+                on_response: Some(hook),
+            },
+            Verdict::Allowed(state, class) => {
+                let on_response = self.response_hook(&mut req, class);
+                let remaining = Some(state.remaining_burst_capacity());
+                self.forward_allowed(req, remaining, class, on_response, true)
+            }
+            // the requests only charged by their response don't know what is left yet
+            Verdict::Charge(hook, class) => {
+                self.forward_allowed(req, None, class, Some(hook), true)
             }
+            Verdict::Respond(response) => Kind::rejection(response, self.config.tarpit),
+            Verdict::Defer(response) => Kind::deferred(response, self.config.tarpit),
+        };
+        ResponseFuture {
+            inner,
+            refund,
+            mode,
         }
     }
 }
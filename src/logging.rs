@@ -0,0 +1,97 @@
+#[cfg(feature = "tarpit")]
+use std::time::{Duration, Instant};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use tracing::Level;
+
+/// Target of the events logged for rejections, e.g. to filter them with
+/// `tower_governor::rejections=warn`.
+pub const REJECTION_TARGET: &str = "tower_governor::rejections";
+
+/// Name of the spans of the responses held back by the tarpit, covering the time they spend
+/// parked in the governor.
+#[cfg(feature = "tarpit")]
+pub const TARPIT_SPAN: &str = "tower_governor::tarpit";
+
+const LEVELS: [Level; 5] = [
+    Level::TRACE,
+    Level::DEBUG,
+    Level::INFO,
+    Level::WARN,
+    Level::ERROR,
+];
+
+// The level of the events logged for rejections, shared by a configuration and its layers
+// so that it can be changed at runtime, see `GovernorConfig::set_rejection_log_level`.
+#[derive(Debug)]
+pub(crate) struct RejectionLevel(AtomicU8);
+
+impl RejectionLevel {
+    pub(crate) fn new(level: Level) -> Self {
+        Self(AtomicU8::new(Self::index(level)))
+    }
+
+    fn index(level: Level) -> u8 {
+        LEVELS.iter().position(|known| *known == level).unwrap_or(2) as u8
+    }
+
+    pub(crate) fn get(&self) -> Level {
+        LEVELS[usize::from(self.0.load(Ordering::Relaxed))]
+    }
+
+    pub(crate) fn set(&self, level: Level) {
+        self.0.store(Self::index(level), Ordering::Relaxed);
+    }
+
+    /// Log a rejection at the current level.
+    pub(crate) fn log(&self, message: fmt::Arguments<'_>) {
+        // the level of an event must be known at its call site
+        match self.get() {
+            Level::TRACE => tracing::trace!(target: REJECTION_TARGET, "{}", message),
+            Level::DEBUG => tracing::debug!(target: REJECTION_TARGET, "{}", message),
+            Level::INFO => tracing::info!(target: REJECTION_TARGET, "{}", message),
+            Level::WARN => tracing::warn!(target: REJECTION_TARGET, "{}", message),
+            _ => tracing::error!(target: REJECTION_TARGET, "{}", message),
+        }
+    }
+}
+
+// The span of a response held back by the tarpit, so that tracing and tokio-console show how
+// long requests spend parked in the governor, see `GovernorConfigBuilder::tarpit`.
+#[cfg(feature = "tarpit")]
+#[derive(Debug)]
+pub(crate) struct Parked {
+    pub(crate) span: tracing::Span,
+    since: Instant,
+}
+
+#[cfg(feature = "tarpit")]
+impl Parked {
+    pub(crate) fn new(delay: Duration) -> Self {
+        let span = tracing::debug_span!(
+            TARPIT_SPAN,
+            delay_ms = delay.as_millis() as u64,
+            class = tracing::field::Empty,
+            parked_ms = tracing::field::Empty,
+        );
+        Self {
+            span,
+            since: Instant::now(),
+        }
+    }
+
+    /// Record the class of the released `response`, if known, and the time it was parked.
+    pub(crate) fn release<B>(&self, response: &http::Response<B>) {
+        let class = response
+            .headers()
+            .get(crate::headers::CLASS_HEADER)
+            .and_then(|class| class.to_str().ok());
+        if let Some(class) = class {
+            self.span.record("class", class);
+        }
+        self.span
+            .record("parked_ms", self.since.elapsed().as_millis() as u64);
+    }
+}
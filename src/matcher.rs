@@ -0,0 +1,184 @@
+//! Predicates on requests, shared by the options of the builder that single requests out:
+//! the rate limited [`methods`], the [exemptions] and the exempted health checks.
+//!
+//! # Example
+//! ```rust
+//! use http::{header::USER_AGENT, Method};
+//! use tower_governor::{governor::GovernorConfigBuilder, matcher::RequestMatcher};
+//!
+//! // let the monitoring agent read the status pages without being limited
+//! let config = GovernorConfigBuilder::default()
+//!     .exempt(
+//!         RequestMatcher::new()
+//!             .methods([Method::GET])
+//!             .path("/status/*")
+//!             .header_prefix(USER_AGENT, ["Datadog Agent/"]),
+//!     )
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! [`methods`]: crate::governor::GovernorConfigBuilder::methods
+//! [exemptions]: crate::governor::GovernorConfigBuilder::exempt
+
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Uri};
+use std::{fmt, sync::Arc};
+
+/// The extensions of the static assets of web apps, exempted by
+/// [`web_defaults`](crate::governor::GovernorConfigBuilder::web_defaults).
+pub const STATIC_ASSET_EXTENSIONS: [&str; 16] = [
+    "css", "js", "mjs", "map", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp", "avif", "woff",
+    "woff2", "ttf", "wasm",
+];
+
+type MatchFn = dyn Fn(&Method, &Uri, &HeaderMap) -> bool + Send + Sync;
+
+// Closure matching requests, see `RequestMatcher::custom`.
+#[derive(Clone)]
+struct Custom(Arc<MatchFn>);
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Custom").finish()
+    }
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Custom {}
+
+// A condition on a header of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HeaderCondition {
+    Present(HeaderName),
+    Equals(HeaderName, HeaderValue),
+    Prefix(HeaderName, Vec<Arc<str>>),
+}
+
+impl HeaderCondition {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        match self {
+            Self::Present(name) => headers.contains_key(name),
+            Self::Equals(name, value) => headers.get(name) == Some(value),
+            Self::Prefix(name, prefixes) => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| prefixes.iter().any(|prefix| value.starts_with(&**prefix))),
+        }
+    }
+}
+
+/// A predicate on requests, matching those meeting every condition it was given.
+///
+/// A new matcher matches every request. Each list, such as the methods or the paths, matches
+/// requests meeting any of its entries.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RequestMatcher {
+    methods: Option<Vec<Method>>,
+    paths: Vec<String>,
+    extensions: Vec<&'static str>,
+    headers: Vec<HeaderCondition>,
+    custom: Option<Custom>,
+}
+
+impl RequestMatcher {
+    /// A matcher matching every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match the requests of one of `methods`, adding to the previous ones.
+    pub fn methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods.get_or_insert_with(Vec::new).extend(methods);
+        self
+    }
+
+    /// Only match the requests to `pattern` or to one of the previous paths: either a path,
+    /// or a prefix ending with `*`, e.g. `/static/*`.
+    pub fn path(mut self, pattern: impl Into<String>) -> Self {
+        self.paths.push(pattern.into());
+        self
+    }
+
+    /// Only match the requests to a path ending with one of `extensions` or of the previous
+    /// ones, given without their dot and compared ignoring case, e.g. `css`.
+    pub fn extensions(mut self, extensions: impl IntoIterator<Item = &'static str>) -> Self {
+        self.extensions.extend(extensions);
+        self
+    }
+
+    /// Only match the requests carrying the header `name`.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.headers.push(HeaderCondition::Present(name));
+        self
+    }
+
+    /// Only match the requests whose header `name` is `value`.
+    pub fn header_value(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push(HeaderCondition::Equals(name, value));
+        self
+    }
+
+    /// Only match the requests whose header `name` starts with one of `prefixes`.
+    pub fn header_prefix(
+        mut self,
+        name: HeaderName,
+        prefixes: impl IntoIterator<Item = impl Into<Arc<str>>>,
+    ) -> Self {
+        let prefixes = prefixes.into_iter().map(Into::into).collect();
+        self.headers.push(HeaderCondition::Prefix(name, prefixes));
+        self
+    }
+
+    /// Only match the requests `matches` accepts from their method, URI and headers,
+    /// replacing the previous closure.
+    pub fn custom<F>(mut self, matches: F) -> Self
+    where
+        F: Fn(&Method, &Uri, &HeaderMap) -> bool + Send + Sync + 'static,
+    {
+        self.custom = Some(Custom(Arc::new(matches)));
+        self
+    }
+
+    /// Whether `req` meets every condition.
+    pub fn matches<B>(&self, req: &Request<B>) -> bool {
+        self.matches_parts(req.method(), req.uri(), req.headers())
+    }
+
+    /// Whether a request with `method`, `uri` and `headers` meets every condition.
+    pub fn matches_parts(&self, method: &Method, uri: &Uri, headers: &HeaderMap) -> bool {
+        let path = uri.path();
+        self.methods
+            .as_ref()
+            .is_none_or(|methods| methods.contains(method))
+            && (self.paths.is_empty()
+                || self
+                    .paths
+                    .iter()
+                    .any(|pattern| match pattern.strip_suffix('*') {
+                        Some(prefix) => path.starts_with(prefix),
+                        None => path == pattern,
+                    }))
+            && (self.extensions.is_empty()
+                || path
+                    .rsplit_once('/')
+                    .and_then(|(_, file)| file.rsplit_once('.'))
+                    .is_some_and(|(_, extension)| {
+                        self.extensions
+                            .iter()
+                            .any(|known| known.eq_ignore_ascii_case(extension))
+                    }))
+            && self
+                .headers
+                .iter()
+                .all(|condition| condition.matches(headers))
+            && self
+                .custom
+                .as_ref()
+                .is_none_or(|Custom(matches)| matches(method, uri, headers))
+    }
+}
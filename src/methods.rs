@@ -0,0 +1,117 @@
+//! Rules choosing the rate limited methods of a request by its class or its key, see
+//! [`GovernorConfigBuilder::method_rules`].
+//!
+//! # Example
+//! ```rust
+//! use http::Method;
+//! use tower_governor::{governor::GovernorConfigBuilder, methods::MethodRules};
+//!
+//! // anonymous keys are limited on every method, the others only when writing
+//! let rules = MethodRules::new()
+//!     .key(|name| name.starts_with("anonymous:"), None)
+//!     .key(|_| true, Some(vec![Method::POST, Method::PUT]));
+//! let config = GovernorConfigBuilder::default()
+//!     .method_rules(rules)
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! [`GovernorConfigBuilder::method_rules`]: crate::governor::GovernorConfigBuilder::method_rules
+
+use http::Method;
+use std::{fmt, sync::Arc};
+
+type KeyNameFn = dyn Fn(&str) -> bool + Send + Sync;
+
+// Closure matching the names of the keys, see `MethodRules::key`.
+#[derive(Clone)]
+struct KeyMatcher(Arc<KeyNameFn>);
+
+impl fmt::Debug for KeyMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyMatcher").finish()
+    }
+}
+
+impl PartialEq for KeyMatcher {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for KeyMatcher {}
+
+// The requests a rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Matcher {
+    Class(&'static str),
+    Key(KeyMatcher),
+}
+
+/// Rules choosing the rate limited methods of a request, in order.
+///
+/// The first rule matching a request decides whether it is limited. The requests matching
+/// none are limited on the [`methods`] of the configuration.
+///
+/// [`methods`]: crate::governor::GovernorConfigBuilder::methods
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MethodRules {
+    rules: Vec<(Matcher, Option<Vec<Method>>)>,
+}
+
+impl MethodRules {
+    /// Rules matching no request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the requests of the [class](crate::governor::GovernorConfigBuilder::classify)
+    /// `name` on `methods`, `None` meaning all methods.
+    pub fn class(mut self, name: &'static str, methods: Option<Vec<Method>>) -> Self {
+        self.rules.push((Matcher::Class(name), methods));
+        self
+    }
+
+    /// Limit the requests whose key has a name matching `matches` on `methods`, `None`
+    /// meaning all methods.
+    ///
+    /// The names are those of [`KeyExtractor::key_name`]: the keys without one match no
+    /// such rule.
+    ///
+    /// [`KeyExtractor::key_name`]: crate::key_extractor::KeyExtractor::key_name
+    pub fn key<F>(mut self, matches: F, methods: Option<Vec<Method>>) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.rules
+            .push((Matcher::Key(KeyMatcher(Arc::new(matches))), methods));
+        self
+    }
+
+    /// Whether the first rule matching a request of `class` with a key named `key_name`
+    /// limits `method`, `None` if no rule matches.
+    pub(crate) fn limits(
+        &self,
+        method: &Method,
+        class: Option<&str>,
+        key_name: impl FnOnce() -> Option<String>,
+    ) -> Option<bool> {
+        let mut key_name = Some(key_name);
+        let mut name = None;
+        let (_, methods) = self.rules.iter().find(|(matcher, _)| match matcher {
+            Matcher::Class(rule) => class == Some(*rule),
+            Matcher::Key(KeyMatcher(matches)) => {
+                // the name is only needed by these rules
+                if let Some(key_name) = key_name.take() {
+                    name = key_name();
+                }
+                name.as_deref().is_some_and(|name| matches(name))
+            }
+        })?;
+        Some(
+            methods
+                .as_ref()
+                .is_none_or(|methods| methods.contains(method)),
+        )
+    }
+}
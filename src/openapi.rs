@@ -0,0 +1,157 @@
+//! OpenAPI documentation of the rate limiting responses, built with [utoipa].
+//!
+//! Register [`TooManyRequests`] as a response component, or let
+//! [`GovernorConfig::document_operation`] add the `429` response and the configured
+//! policy to the operations a configuration is applied to.
+
+use crate::governor::GovernorConfig;
+use crate::key_extractor::KeyExtractor;
+use governor::{clock::Clock, middleware::RateLimitingMiddleware};
+use utoipa::{
+    openapi::{
+        extensions::ExtensionsBuilder,
+        header::HeaderBuilder,
+        path::Operation,
+        response::{Response, ResponseBuilder},
+        schema::{ObjectBuilder, Type},
+        Content, RefOr,
+    },
+    ToResponse,
+};
+
+/// The `429 Too Many Requests` response produced by the middleware.
+///
+/// ```rust
+/// use tower_governor::openapi::TooManyRequests;
+/// use utoipa::OpenApi;
+///
+/// #[derive(OpenApi)]
+/// #[openapi(components(responses(TooManyRequests)))]
+/// struct ApiDoc;
+/// ```
+pub struct TooManyRequests;
+
+impl<'r> ToResponse<'r> for TooManyRequests {
+    fn response() -> (&'r str, RefOr<Response>) {
+        ("TooManyRequests", RefOr::T(too_many_requests()))
+    }
+}
+
+fn integer_header(description: &str) -> utoipa::openapi::header::Header {
+    HeaderBuilder::new()
+        .schema(ObjectBuilder::new().schema_type(Type::Integer))
+        .description(Some(description))
+        .build()
+}
+
+fn reason_property() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .enum_values(Some([
+            "quota_exceeded",
+            "banned",
+            "extraction_failed",
+            "global_ceiling",
+            "upgrade_denied",
+            "maintenance",
+        ]))
+        .description(Some("Why the request was rejected"))
+}
+
+fn string_property(description: &str) -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .description(Some(description))
+}
+
+/// Build the `429 Too Many Requests` response, with the headers set by the middleware and
+/// the bodies of the default error handler and of the [`handlers`](crate::handlers): plain text
+/// by default, `application/json` for [`handlers::json`] and `text/html` for [`handlers::html`].
+///
+/// [`handlers::json`]: crate::handlers::json
+/// [`handlers::html`]: crate::handlers::html
+pub fn too_many_requests() -> Response {
+    let json = ObjectBuilder::new()
+        .property(
+            "status",
+            ObjectBuilder::new()
+                .schema_type(Type::Integer)
+                .description(Some("HTTP status code")),
+        )
+        .property("message", string_property("Message of the error"))
+        .property(
+            "wait_time",
+            ObjectBuilder::new()
+                .schema_type(Type::Integer)
+                .description(Some(
+                    "Number of seconds after which the request may be retried",
+                )),
+        )
+        .property("reason", reason_property())
+        .required("status")
+        .required("message");
+
+    ResponseBuilder::new()
+        .description("Too Many Requests")
+        .header(
+            "retry-after",
+            integer_header("Number of seconds after which the request may be retried"),
+        )
+        .header(
+            "x-ratelimit-after",
+            integer_header("Same value as `retry-after`"),
+        )
+        .header(
+            "x-ratelimit-after-ms",
+            integer_header("Number of milliseconds after which the request may be retried, replacing `x-ratelimit-after` when configured"),
+        )
+        .header(
+            "x-ratelimit-limit",
+            integer_header("Request limit, only sent when headers are enabled"),
+        )
+        .header(
+            "x-ratelimit-remaining",
+            integer_header(
+                "The number of requests left for the time window, only sent when headers are enabled",
+            ),
+        )
+        .header(
+            "x-ratelimit-reason",
+            HeaderBuilder::new().schema(reason_property()).build(),
+        )
+        .content(
+            "text/plain",
+            Content::new(Some(ObjectBuilder::new().schema_type(Type::String))),
+        )
+        .content("application/json", Content::new(Some(json)))
+        .content(
+            "text/html",
+            Content::new(Some(ObjectBuilder::new().schema_type(Type::String))),
+        )
+        .build()
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> GovernorConfig<K, M, C> {
+    /// Document an operation protected by this configuration.
+    ///
+    /// Adds the [`too_many_requests`] response and the configured policy as the
+    /// `x-ratelimit-burst`, `x-ratelimit-period-ms` and `x-ratelimit-methods` extensions.
+    pub fn document_operation(&self, operation: &mut Operation) {
+        operation
+            .responses
+            .responses
+            .insert("429".to_owned(), RefOr::T(too_many_requests()));
+
+        let mut policy = ExtensionsBuilder::new()
+            .add("x-ratelimit-burst", self.burst_size())
+            .add("x-ratelimit-period-ms", self.period().as_millis() as u64);
+        if let Some(methods) = self.methods() {
+            let methods: Vec<_> = methods.iter().map(|m| m.as_str().to_owned()).collect();
+            policy = policy.add("x-ratelimit-methods", methods);
+        }
+        operation
+            .extensions
+            .get_or_insert_with(Default::default)
+            .merge(policy.build());
+    }
+}
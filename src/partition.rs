@@ -0,0 +1,59 @@
+//! Approximate global limits across replicas without a shared store.
+//!
+//! See [`GovernorConfigBuilder::partition`].
+//!
+//! [`GovernorConfigBuilder::partition`]: crate::governor::GovernorConfigBuilder::partition
+
+use std::{
+    fmt,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+/// Shared handle on the number of instances the quota is split between.
+///
+/// Clones share the same count, so it can be updated at runtime, e.g. whenever the
+/// autoscaler adds or removes replicas.
+#[derive(Clone)]
+pub struct Instances(Arc<AtomicU32>);
+
+impl Instances {
+    /// Split the quota between `count` instances. A count of zero is treated as one.
+    pub fn new(count: u32) -> Self {
+        Self(Arc::new(AtomicU32::new(count)))
+    }
+
+    /// The current number of instances.
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed).max(1)
+    }
+
+    /// Update the number of instances, taking effect on the next request.
+    pub fn set(&self, count: u32) {
+        self.0.store(count, Ordering::Relaxed);
+    }
+
+    /// The number of cells a request costs, never more than `burst_size` so it can be admitted.
+    pub(crate) fn cost(&self, burst_size: NonZeroU32) -> NonZeroU32 {
+        NonZeroU32::new(self.get())
+            .unwrap_or(NonZeroU32::MIN)
+            .min(burst_size)
+    }
+}
+
+impl fmt::Debug for Instances {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Instances").field(&self.get()).finish()
+    }
+}
+
+impl PartialEq for Instances {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Instances {}
@@ -0,0 +1,96 @@
+use crate::{deadline, expiring::ExpiringMap};
+use std::{
+    fmt,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+// Consecutive violations of a key.
+#[derive(Debug, Clone, Copy)]
+struct Violations {
+    count: u32,
+    // the wait time of the rejection that started the streak
+    base: Duration,
+    until: Instant,
+}
+
+// Growing wait times of the keys rejected again and again, see
+// `GovernorConfigBuilder::penalize_violations`.
+pub(crate) struct Penalties<Key> {
+    factor: u32,
+    cap: Duration,
+    keys: ExpiringMap<Key, Violations>,
+}
+
+impl<Key> fmt::Debug for Penalties<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Penalties")
+            .field("factor", &self.factor)
+            .field("cap", &self.cap)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> Penalties<Key> {
+    pub(crate) fn new(factor: u32, cap: Duration) -> Self {
+        Self {
+            factor: factor.max(1),
+            cap,
+            keys: ExpiringMap::new(),
+        }
+    }
+
+    /// The time left until the penalty of `key` ends, if penalized.
+    pub(crate) fn penalized_for(&self, key: &Key) -> Option<Duration> {
+        let keys = self.keys.lock(key);
+        keys.get(key)?
+            .until
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Count a violation of `key`, rejected for `wait_time`, returning the wait time it is
+    /// penalized with.
+    pub(crate) fn penalize(&self, key: &Key, wait_time: Duration) -> Duration {
+        let now = Instant::now();
+        let mut keys = self.keys.lock(key);
+        keys.purge(|violations| self.forgotten(violations, now));
+        let violations = keys.entry(key.clone()).or_insert(Violations {
+            count: 0,
+            base: wait_time,
+            until: now,
+        });
+        if self.forgotten(violations, now) {
+            violations.count = 0;
+        }
+        if violations.count == 0 {
+            violations.base = wait_time;
+        }
+        violations.count = violations.count.saturating_add(1);
+        let multiplier = self.factor.saturating_pow(violations.count - 1);
+        let penalty = violations
+            .base
+            .checked_mul(multiplier)
+            .map_or(self.cap, |penalty| penalty.min(self.cap))
+            .max(wait_time);
+        violations.until = deadline(now, penalty);
+        penalty
+    }
+
+    // Whether the key behaved for the cap since its last penalty ended.
+    fn forgotten(&self, violations: &Violations, now: Instant) -> bool {
+        now.checked_duration_since(violations.until)
+            .is_some_and(|since| since >= self.cap)
+    }
+
+    /// Decay the violations of `key` after an allowed request, halving their count.
+    pub(crate) fn forgive(&self, key: &Key) {
+        let mut keys = self.keys.lock(key);
+        if let Some(violations) = keys.get_mut(key) {
+            violations.count /= 2;
+            if violations.count == 0 {
+                keys.remove(key);
+            }
+        }
+    }
+}
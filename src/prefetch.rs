@@ -0,0 +1,109 @@
+use dashmap::DashMap;
+use governor::{
+    clock::{Clock, Reference},
+    middleware::RateLimitingMiddleware,
+    state::keyed::DefaultKeyedStateStore,
+    RateLimiter,
+};
+use std::{
+    fmt,
+    hash::Hash,
+    num::NonZeroU32,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// Number of keys holding prefetched cells after which they are all flushed.
+const MAX_KEYS: usize = 4096;
+
+// Cells claimed by a check, along with its outcome and when it happened.
+struct Cells<P, I> {
+    left: AtomicU32,
+    outcome: P,
+    claimed: I,
+}
+
+// Claims cells from the limiter in batches and hands them out from a per key counter.
+//
+// A single very hot key makes every request contend on the same atomic state in the keyed store.
+// With prefetching only one in `batch` requests touches that state, the others only decrement
+// the counter of the key, under a shared lock of its map shard. The cells expire after `ttl` so
+// that stale cells don't outlive the quota they came from.
+pub(crate) struct Prefetch<Key, P, I> {
+    batch: NonZeroU32,
+    ttl: Duration,
+    cells: DashMap<Key, Cells<P, I>>,
+}
+
+impl<Key: Hash + Eq, P, I> fmt::Debug for Prefetch<Key, P, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Prefetch")
+            .field("batch", &self.batch)
+            .field("ttl", &self.ttl)
+            .field("keys", &self.cells.len())
+            .finish()
+    }
+}
+
+impl<Key: Hash + Eq, P, I> Prefetch<Key, P, I> {
+    pub(crate) fn new(batch: NonZeroU32, ttl: Duration) -> Self {
+        Self {
+            batch,
+            ttl,
+            cells: DashMap::new(),
+        }
+    }
+
+    /// The same batch size, with nothing prefetched.
+    pub(crate) fn detached(&self) -> Self {
+        Self::new(self.batch, self.ttl)
+    }
+}
+
+impl<Key: Hash + Eq + Clone, P: Clone, I: Reference> Prefetch<Key, P, I> {
+    /// Check `key` against the limiter, using up prefetched cells first.
+    pub(crate) fn check_key<C, M>(
+        &self,
+        limiter: &RateLimiter<Key, DefaultKeyedStateStore<Key>, C, M>,
+        key: &Key,
+    ) -> Result<P, M::NegativeOutcome>
+    where
+        C: Clock<Instant = I>,
+        M: RateLimitingMiddleware<I, PositiveOutcome = P>,
+    {
+        let now = limiter.clock().now();
+        if let Some(cells) = self.cells.get(key) {
+            let fresh = Duration::from(now.duration_since(cells.claimed)) < self.ttl;
+            let taken = fresh
+                && cells
+                    .left
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                        left.checked_sub(1)
+                    })
+                    .is_ok();
+            if taken {
+                return Ok(cells.outcome.clone());
+            }
+        }
+
+        match limiter.check_key_n(key, self.batch) {
+            Ok(Ok(outcome)) => {
+                if self.cells.len() >= MAX_KEYS {
+                    // Dropping prefetched cells only ever admits fewer requests.
+                    self.cells.clear();
+                }
+                self.cells.insert(
+                    key.clone(),
+                    Cells {
+                        left: AtomicU32::new(self.batch.get() - 1),
+                        outcome: outcome.clone(),
+                        claimed: now,
+                    },
+                );
+                Ok(outcome)
+            }
+            // Not enough capacity left for a whole batch, fall back to single cells.
+            _ => limiter.check_key(key),
+        }
+    }
+}
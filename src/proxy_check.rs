@@ -0,0 +1,109 @@
+use crate::{expiring::ExpiringMap, forwarding::Trust};
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+// Runtime detection of an app keyed by the peer IP while running behind a reverse proxy.
+//
+// The symptom is a single peer IP sending requests on behalf of other clients, as told by the
+// forwarding headers. Once one peer did so for `threshold` requests within `window`, the
+// configuration is flagged as misconfigured. Only the detected peers trusted by `fallback`
+// have their requests keyed by the forwarding headers, as any client can forge them.
+pub(crate) struct ProxyCheck {
+    threshold: u32,
+    window: Duration,
+    fallback: Option<Trust>,
+    detected: AtomicBool,
+    peers: ExpiringMap<IpAddr, Streak>,
+}
+
+impl fmt::Debug for ProxyCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyCheck")
+            .field("threshold", &self.threshold)
+            .field("window", &self.window)
+            .field("fallback", &self.fallback)
+            .field("detected", &self.detected)
+            .finish_non_exhaustive()
+    }
+}
+
+// Requests forwarded by a peer within the current window.
+struct Streak {
+    since: Instant,
+    count: u32,
+    detected: bool,
+}
+
+impl ProxyCheck {
+    pub(crate) fn new(threshold: u32, window: Duration, fallback: Option<Trust>) -> Self {
+        Self {
+            threshold,
+            window,
+            fallback,
+            detected: AtomicBool::new(false),
+            peers: ExpiringMap::new(),
+        }
+    }
+
+    pub(crate) fn detected(&self) -> bool {
+        self.detected.load(Ordering::Relaxed)
+    }
+
+    /// Record a request keyed by `peer` while the forwarding headers name another client,
+    /// returning whether its key should be derived from the forwarding headers instead.
+    pub(crate) fn observe(&self, peer: IpAddr) -> bool {
+        let trusted = self
+            .fallback
+            .as_ref()
+            .is_some_and(|trust| trust.trusts(peer));
+        let now = Instant::now();
+        let mut peers = self.peers.lock(&peer);
+        peers.purge(|streak| !streak.detected && now.duration_since(streak.since) > self.window);
+        let streak = peers.entry(peer).or_insert(Streak {
+            since: now,
+            count: 0,
+            detected: false,
+        });
+        if streak.detected {
+            return true;
+        }
+        if now.duration_since(streak.since) > self.window {
+            (streak.since, streak.count) = (now, 0);
+        }
+        streak.count += 1;
+        if streak.count < self.threshold {
+            return false;
+        }
+        #[cfg(feature = "tracing")]
+        let count = streak.count;
+        // only the trusted proxies are remembered, to fall back for their requests
+        match trusted {
+            true => streak.detected = true,
+            false => {
+                peers.remove(&peer);
+            }
+        }
+        drop(peers);
+
+        if !self.detected.swap(true, Ordering::Relaxed) {
+            #[cfg(feature = "tracing")]
+            tracing::error!(
+                "{} requests forwarded by {} within {:?} were rate limited as a single client, \
+                 the app is likely deployed behind a reverse proxy with a key extractor using the peer IP{}",
+                count,
+                peer,
+                self.window,
+                if trusted {
+                    ", falling back to the client IP of the forwarding headers"
+                } else {
+                    ""
+                }
+            );
+        }
+        trusted
+    }
+}
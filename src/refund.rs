@@ -0,0 +1,154 @@
+use crate::expiring::ExpiringMap;
+use http::Request;
+use std::{
+    fmt,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// Cells given back to the keys whose allowed requests were cancelled before the inner service
+// responded, see `GovernorConfigBuilder::refund_cancelled`.
+//
+// The limiter can't give a cell back, so the refunded cells are held here instead and spent by
+// the next requests of the key the limiter rejects. They expire once the quota would have been
+// replenished anyway, and no more than a burst is refunded to a key in the meantime so that
+// cancelling requests on purpose doesn't get a client past its quota.
+pub(crate) struct Refunds<Key> {
+    credits: ExpiringMap<Key, Credit>,
+    // the time to replenish the whole quota
+    expiry: Duration,
+    burst_size: u32,
+}
+
+impl<Key> fmt::Debug for Refunds<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Refunds")
+            .field("expiry", &self.expiry)
+            .finish_non_exhaustive()
+    }
+}
+
+// The cells refunded to a key since the first refund of the current period.
+struct Credit {
+    since: Instant,
+    refunded: u32,
+    left: u32,
+}
+
+impl<Key: Hash + Eq> Refunds<Key> {
+    pub(crate) fn new(expiry: Duration, burst_size: u32) -> Self {
+        Self {
+            credits: ExpiringMap::new(),
+            expiry,
+            burst_size,
+        }
+    }
+
+    /// Give `cells` back to `key`, never more than its burst size per period.
+    pub(crate) fn refund(&self, key: Key, cells: u32) {
+        let now = Instant::now();
+        let mut credits = self.credits.lock(&key);
+        credits.purge(|credit| self.expired(credit, now));
+        let credit = credits.entry(key).or_insert(Credit {
+            since: now,
+            refunded: 0,
+            left: 0,
+        });
+        if self.expired(credit, now) {
+            *credit = Credit {
+                since: now,
+                refunded: 0,
+                left: 0,
+            };
+        }
+        let cells = cells.min(self.burst_size - credit.refunded);
+        credit.refunded += cells;
+        credit.left += cells;
+    }
+
+    /// Spend `cells` refunded to `key`, if it has that many.
+    pub(crate) fn take(&self, key: &Key, cells: u32) -> bool {
+        let now = Instant::now();
+        let mut credits = self.credits.lock(key);
+        let Some(credit) = credits.get_mut(key) else {
+            return false;
+        };
+        if self.expired(credit, now) || credit.left < cells {
+            return false;
+        }
+        // the spent cells still count as refunded until the period ends
+        credit.left -= cells;
+        true
+    }
+
+    // Whether the period of the refunds of `credit` ended.
+    fn expired(&self, credit: &Credit, now: Instant) -> bool {
+        now.duration_since(credit.since) >= self.expiry
+    }
+}
+
+// Gives the charge of an allowed request back, handed over to its response future through the
+// request extensions.
+#[derive(Clone)]
+pub(crate) struct Refund(Arc<dyn Fn() + Send + Sync>);
+
+impl Refund {
+    pub(crate) fn new<Key>(refunds: Arc<Refunds<Key>>, key: Key, cells: u32) -> Self
+    where
+        Key: Hash + Eq + Clone + Send + Sync + 'static,
+    {
+        Self(Arc::new(move || refunds.refund(key.clone(), cells)))
+    }
+
+    /// Hand the refund over to the response future of `req`, along with those of the
+    /// configurations checked before.
+    pub(crate) fn attach<B>(self, req: &mut Request<B>) {
+        match req.extensions_mut().get_mut::<PendingRefunds>() {
+            Some(PendingRefunds(refunds)) => refunds.push(self),
+            None => {
+                req.extensions_mut().insert(PendingRefunds(vec![self]));
+            }
+        }
+    }
+}
+
+// The refunds of a request not yet handed over to its response future.
+#[derive(Clone)]
+struct PendingRefunds(Vec<Refund>);
+
+// Refunds the charges of a request if its response future is dropped before the inner service
+// responded.
+#[derive(Default)]
+pub(crate) struct RefundGuard(Vec<Refund>);
+
+impl RefundGuard {
+    /// The guard of the refunds attached to `req`.
+    pub(crate) fn take<B>(req: &mut Request<B>) -> Self {
+        match req.extensions_mut().remove::<PendingRefunds>() {
+            Some(PendingRefunds(refunds)) => Self(refunds),
+            None => Self::default(),
+        }
+    }
+
+    /// The inner service responded, keep the charges.
+    pub(crate) fn disarm(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl fmt::Debug for RefundGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RefundGuard")
+            .field("armed", &!self.0.is_empty())
+            .finish()
+    }
+}
+
+impl Drop for RefundGuard {
+    fn drop(&mut self) {
+        for Refund(refund) in self.0.drain(..) {
+            refund();
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! A log of the last rate limiting decisions, to reconstruct what the limiter saw when a
+//! client disputes a rejection.
+//!
+//! See [`GovernorConfigBuilder::replay_log`] and [`GovernorConfig::replay`].
+//!
+//! [`GovernorConfigBuilder::replay_log`]: crate::governor::GovernorConfigBuilder::replay_log
+//! [`GovernorConfig::replay`]: crate::governor::GovernorConfig::replay
+
+use crate::{decision::Decision, headers::Bare};
+use governor::middleware::StateSnapshot;
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// A decision recorded by the replay log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayEntry {
+    /// When the decision was taken.
+    pub at: SystemTime,
+    /// The hash of the key, see [`GovernorConfig::replay_key_hash`].
+    ///
+    /// [`GovernorConfig::replay_key_hash`]: crate::governor::GovernorConfig::replay_key_hash
+    pub key_hash: u64,
+    /// The decision taken for the request.
+    pub decision: Decision,
+    /// The number of requests the key could still make right away. Always `Some(0)` for
+    /// rejections, only known for allowed requests when the [x-ratelimit headers] are
+    /// enabled.
+    ///
+    /// [x-ratelimit headers]: crate::governor::GovernorConfigBuilder::use_headers
+    pub remaining: Option<u32>,
+}
+
+/// The hash of a key as recorded in the replay log. The hasher uses fixed keys, so the hashes
+/// are the same across processes built by the same compiler.
+pub(crate) fn key_hash<Key: Hash>(key: &Key) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The outcomes of the limiter that may tell how many requests are left.
+pub(crate) trait Remaining {
+    /// Whether the outcomes tell how many requests are left.
+    const KNOWN: bool;
+
+    /// Whether the responses carry the rate limiting headers.
+    const HEADERS: bool = true;
+
+    fn remaining(&self) -> Option<u32>;
+}
+
+impl Remaining for () {
+    const KNOWN: bool = false;
+
+    fn remaining(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl Remaining for Bare {
+    const KNOWN: bool = false;
+    const HEADERS: bool = false;
+
+    fn remaining(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl Remaining for StateSnapshot {
+    const KNOWN: bool = true;
+
+    fn remaining(&self) -> Option<u32> {
+        Some(self.remaining_burst_capacity())
+    }
+}
+
+/// Ring buffer of the last decisions.
+#[derive(Debug)]
+pub(crate) struct ReplayLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<ReplayEntry>>,
+}
+
+impl ReplayLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub(crate) fn record<Key: Hash>(&self, key: &Key, decision: Decision, remaining: Option<u32>) {
+        let entry = ReplayEntry {
+            at: SystemTime::now(),
+            key_hash: key_hash(key),
+            decision,
+            remaining,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The recorded decisions, oldest first.
+    pub(crate) fn entries(&self) -> Vec<ReplayEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.iter().copied().collect()
+    }
+}
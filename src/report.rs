@@ -0,0 +1,619 @@
+//! Reports of the keys hitting a configuration the hardest and of its rejection rates.
+//!
+//! See [`GovernorConfigBuilder::report_window`], [`GovernorConfig::export_report`],
+//! [`GovernorConfig::near_limit_keys`], [`GovernorConfigBuilder::track_rates`] and
+//! [`GovernorConfig::rates`]. The quotas fitting the traffic can be suggested too, see
+//! [`GovernorConfigBuilder::analyze_traffic`] and [`GovernorConfig::suggest_quota`], and the
+//! responses of the allowed requests counted by status, see
+//! [`GovernorConfigBuilder::track_statuses`] and [`GovernorConfig::status_counts`].
+//!
+//! [`GovernorConfigBuilder::report_window`]: crate::governor::GovernorConfigBuilder::report_window
+//! [`GovernorConfig::export_report`]: crate::governor::GovernorConfig::export_report
+//! [`GovernorConfig::near_limit_keys`]: crate::governor::GovernorConfig::near_limit_keys
+//! [`GovernorConfigBuilder::track_rates`]: crate::governor::GovernorConfigBuilder::track_rates
+//! [`GovernorConfig::rates`]: crate::governor::GovernorConfig::rates
+//! [`GovernorConfigBuilder::analyze_traffic`]: crate::governor::GovernorConfigBuilder::analyze_traffic
+//! [`GovernorConfig::suggest_quota`]: crate::governor::GovernorConfig::suggest_quota
+//! [`GovernorConfigBuilder::track_statuses`]: crate::governor::GovernorConfigBuilder::track_statuses
+//! [`GovernorConfig::status_counts`]: crate::governor::GovernorConfig::status_counts
+
+use crate::expiring::ExpiringMap;
+use http::StatusCode;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::{self, Write},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Number of keys listed as top talkers.
+pub const TOP_TALKERS: usize = 100;
+
+/// Format of an exported report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// A single JSON object, with the `window_secs`, `rejected`, `banned` and `top_talkers`
+    /// fields.
+    Json,
+    /// One line per key, with the `kind,key,requests,rejected,banned_for_ms` columns.
+    /// `kind` is either `banned` or `talker`.
+    Csv,
+}
+
+/// Length of the slots the rate counters are made of.
+const SLOT: Duration = Duration::from_secs(5);
+
+/// Number of slots of the rate counters, covering the longest trailing window along with the
+/// current slot.
+const SLOTS: u64 = 15 * 60 / SLOT.as_secs() + 1;
+
+/// Allowed and rejected requests over the trailing 1, 5 and 15 minutes, see
+/// [`GovernorConfig::rates`].
+///
+/// The windows are made of 5 second slots, so they cover up to 5 more seconds than their
+/// length.
+///
+/// [`GovernorConfig::rates`]: crate::governor::GovernorConfig::rates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rates {
+    /// The requests of the trailing minute.
+    pub one_minute: WindowCounts,
+    /// The requests of the trailing 5 minutes.
+    pub five_minutes: WindowCounts,
+    /// The requests of the trailing 15 minutes.
+    pub fifteen_minutes: WindowCounts,
+}
+
+/// The requests counted over a trailing window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WindowCounts {
+    /// The number of allowed requests.
+    pub allowed: u64,
+    /// The number of rejected requests.
+    pub rejected: u64,
+}
+
+impl WindowCounts {
+    /// The share of rejected requests, between `0.0` and `1.0`. Zero if there was no request.
+    pub fn rejection_rate(&self) -> f64 {
+        match self.allowed + self.rejected {
+            0 => 0.0,
+            total => self.rejected as f64 / total as f64,
+        }
+    }
+}
+
+// Counts of a slot, `tick` being the index of the slot since the counters were created.
+#[derive(Debug, Default)]
+struct Slot {
+    tick: AtomicU64,
+    allowed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+// Ring buffer of per slot counts covering the last 15 minutes.
+#[derive(Debug)]
+pub(crate) struct RateCounters {
+    start: Instant,
+    slots: Vec<Slot>,
+}
+
+impl RateCounters {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            slots: (0..SLOTS).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        // ticks start at one so that the zeroed slots never count as current
+        self.start.elapsed().as_secs() / SLOT.as_secs() + 1
+    }
+
+    /// Count a request.
+    pub(crate) fn record(&self, rejected: bool) {
+        let tick = self.tick();
+        let slot = &self.slots[(tick % SLOTS) as usize];
+        let seen = slot.tick.load(Ordering::Acquire);
+        if seen != tick
+            && slot
+                .tick
+                .compare_exchange(seen, tick, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            // the slot was last used a full turn ago, the few requests counted by other
+            // threads in between are lost
+            slot.allowed.store(0, Ordering::Relaxed);
+            slot.rejected.store(0, Ordering::Relaxed);
+        }
+        let counter = if rejected {
+            &slot.rejected
+        } else {
+            &slot.allowed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn rates(&self) -> Rates {
+        let tick = self.tick();
+        let window = |minutes: u64| {
+            let slots = minutes * 60 / SLOT.as_secs();
+            let mut counts = WindowCounts::default();
+            for slot in &self.slots {
+                let age = tick.wrapping_sub(slot.tick.load(Ordering::Acquire));
+                if age <= slots {
+                    counts.allowed += slot.allowed.load(Ordering::Relaxed);
+                    counts.rejected += slot.rejected.load(Ordering::Relaxed);
+                }
+            }
+            counts
+        };
+        Rates {
+            one_minute: window(1),
+            five_minutes: window(5),
+            fifteen_minutes: window(15),
+        }
+    }
+}
+
+/// The route requests are counted under by [`GovernorConfig::route_rates`] when axum didn't
+/// match them to a route, or once [`MAX_ROUTES`] routes are tracked.
+///
+/// [`GovernorConfig::route_rates`]: crate::governor::GovernorConfig::route_rates
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Number of routes tracked by [`GovernorConfig::route_rates`], bounding the cardinality of
+/// the labels derived from them.
+///
+/// [`GovernorConfig::route_rates`]: crate::governor::GovernorConfig::route_rates
+pub const MAX_ROUTES: usize = 256;
+
+/// The cohort the keys without one are counted under by [`GovernorConfig::cohort_rates`].
+///
+/// [`GovernorConfig::cohort_rates`]: crate::governor::GovernorConfig::cohort_rates
+pub const DEFAULT_COHORT: &str = "default";
+
+// Rate counters of every matched route, or of every cohort of keys.
+#[derive(Debug, Default)]
+pub(crate) struct RouteRates {
+    routes: Mutex<HashMap<String, Arc<RateCounters>>>,
+}
+
+impl RouteRates {
+    /// Count a request of `route`, `None` if it wasn't matched.
+    pub(crate) fn record(&self, route: Option<&str>, rejected: bool) {
+        let counters = {
+            let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+            let route = match route {
+                Some(route) if routes.contains_key(route) || routes.len() < MAX_ROUTES => route,
+                _ => UNMATCHED_ROUTE,
+            };
+            match routes.get(route) {
+                Some(counters) => counters.clone(),
+                None => routes
+                    .entry(route.to_owned())
+                    .or_insert_with(|| Arc::new(RateCounters::new()))
+                    .clone(),
+            }
+        };
+        counters.record(rejected);
+    }
+
+    pub(crate) fn rates(&self) -> BTreeMap<String, Rates> {
+        let routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        routes
+            .iter()
+            .map(|(route, counters)| (route.clone(), counters.rates()))
+            .collect()
+    }
+}
+
+/// The responses of the allowed requests of a class, by status, see
+/// [`GovernorConfig::status_counts`].
+///
+/// [`GovernorConfig::status_counts`]: crate::governor::GovernorConfig::status_counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatusCounts {
+    /// The number of `2xx` responses.
+    pub success: u64,
+    /// The number of `4xx` responses.
+    pub client_errors: u64,
+    /// The number of `5xx` responses.
+    pub server_errors: u64,
+    /// The number of `1xx` and `3xx` responses.
+    pub other: u64,
+    /// The number of requests the inner service failed to respond to.
+    pub failures: u64,
+}
+
+impl StatusCounts {
+    /// The share of server errors and failures among the responses, between `0.0` and `1.0`.
+    /// Zero if there was no request.
+    pub fn error_rate(&self) -> f64 {
+        let errors = self.server_errors + self.failures;
+        match errors + self.success + self.client_errors + self.other {
+            0 => 0.0,
+            total => errors as f64 / total as f64,
+        }
+    }
+
+    fn record(&mut self, status: Option<StatusCode>) {
+        let count = match status {
+            Some(status) if status.is_success() => &mut self.success,
+            Some(status) if status.is_client_error() => &mut self.client_errors,
+            Some(status) if status.is_server_error() => &mut self.server_errors,
+            Some(_) => &mut self.other,
+            None => &mut self.failures,
+        };
+        *count += 1;
+    }
+}
+
+// The status counts of every class, `None` standing for the requests without one.
+#[derive(Debug, Default)]
+pub(crate) struct Statuses {
+    classes: Mutex<HashMap<Option<&'static str>, StatusCounts>>,
+}
+
+impl Statuses {
+    /// Count the response of an allowed request of `class`, `None` if the inner service
+    /// failed.
+    pub(crate) fn record(&self, class: Option<&'static str>, status: Option<StatusCode>) {
+        let mut classes = self.classes.lock().unwrap_or_else(|e| e.into_inner());
+        classes.entry(class).or_default().record(status);
+    }
+
+    pub(crate) fn counts(&self) -> BTreeMap<Option<&'static str>, StatusCounts> {
+        let classes = self.classes.lock().unwrap_or_else(|e| e.into_inner());
+        classes
+            .iter()
+            .map(|(class, counts)| (*class, *counts))
+            .collect()
+    }
+}
+
+/// A key that used a large share of its quota within the report window, see
+/// [`GovernorConfig::near_limit_keys`].
+///
+/// [`GovernorConfig::near_limit_keys`]: crate::governor::GovernorConfig::near_limit_keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearLimitKey<Key> {
+    /// The key.
+    pub key: Key,
+    /// The number of requests of the key within its current window, rejected or not.
+    pub requests: u64,
+    /// The number of those requests that were rejected.
+    pub rejected: u64,
+    /// The number of requests the quota allows over a whole window: the burst size plus the
+    /// elements replenished within the window.
+    pub capacity: u64,
+}
+
+impl<Key> NearLimitKey<Key> {
+    /// The share of the capacity the key used, above `1.0` for keys that got rejected.
+    pub fn usage(&self) -> f64 {
+        self.requests as f64 / self.capacity.max(1) as f64
+    }
+}
+
+// Requests of a key within the current window.
+#[derive(Debug, Clone, Copy)]
+struct Counts {
+    since: Instant,
+    requests: u64,
+    rejected: u64,
+}
+
+// Per key request and rejection counts over a window, restarting once it elapsed.
+pub(crate) struct Tracker<Key> {
+    window: Duration,
+    keys: ExpiringMap<Key, Counts>,
+}
+
+impl<Key> fmt::Debug for Tracker<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracker")
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone + fmt::Debug> Tracker<Key> {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            keys: ExpiringMap::new(),
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Count a request of `key`.
+    pub(crate) fn record(&self, key: &Key, rejected: bool) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock(key);
+        keys.purge(|counts| now.duration_since(counts.since) >= self.window);
+        let counts = keys.entry(key.clone()).or_insert(Counts {
+            since: now,
+            requests: 0,
+            rejected: 0,
+        });
+        if now.duration_since(counts.since) >= self.window {
+            *counts = Counts {
+                since: now,
+                requests: 0,
+                rejected: 0,
+            };
+        }
+        counts.requests += 1;
+        counts.rejected += u64::from(rejected);
+    }
+
+    /// The keys that made at least `threshold` of `capacity` requests within their current
+    /// window, the busiest first.
+    pub(crate) fn near_limit(&self, capacity: u64, threshold: f64) -> Vec<NearLimitKey<Key>> {
+        let now = Instant::now();
+        let min_requests = (capacity as f64 * threshold.max(0.0)).ceil() as u64;
+        let mut near = self.keys.filter_map(|key, counts| {
+            let recent = now.duration_since(counts.since) < self.window;
+            (recent && counts.requests >= min_requests.max(1)).then(|| NearLimitKey {
+                key: key.clone(),
+                requests: counts.requests,
+                rejected: counts.rejected,
+                capacity,
+            })
+        });
+        near.sort_by_key(|key| Reverse(key.requests));
+        near
+    }
+
+    /// Render the report, `banned` listing the blocked keys with the time left until they
+    /// are let through again.
+    pub(crate) fn export(&self, format: ReportFormat, banned: &[(Key, Duration)]) -> String {
+        let now = Instant::now();
+        let mut talkers = self.keys.filter_map(|key, counts| {
+            (now.duration_since(counts.since) < self.window).then(|| (key.clone(), *counts))
+        });
+        let rejected: u64 = talkers.iter().map(|(_, counts)| counts.rejected).sum();
+        talkers.sort_by_key(|(_, counts)| Reverse(counts.requests));
+        talkers.truncate(TOP_TALKERS);
+
+        let mut out = String::new();
+        match format {
+            ReportFormat::Json => {
+                let _ = write!(
+                    out,
+                    "{{\"window_secs\":{},\"rejected\":{},\"banned\":[",
+                    self.window.as_secs(),
+                    rejected
+                );
+                for (i, (key, left)) in banned.iter().enumerate() {
+                    let _ = write!(
+                        out,
+                        "{}{{\"key\":{},\"banned_for_ms\":{}}}",
+                        if i > 0 { "," } else { "" },
+                        json_string(&format!("{:?}", key)),
+                        left.as_millis()
+                    );
+                }
+                out.push_str("],\"top_talkers\":[");
+                for (i, (key, counts)) in talkers.iter().enumerate() {
+                    let _ = write!(
+                        out,
+                        "{}{{\"key\":{},\"requests\":{},\"rejected\":{}}}",
+                        if i > 0 { "," } else { "" },
+                        json_string(&format!("{:?}", key)),
+                        counts.requests,
+                        counts.rejected
+                    );
+                }
+                out.push_str("]}");
+            }
+            ReportFormat::Csv => {
+                out.push_str("kind,key,requests,rejected,banned_for_ms\n");
+                for (key, left) in banned {
+                    let _ = writeln!(
+                        out,
+                        "banned,{},,,{}",
+                        csv_field(&format!("{:?}", key)),
+                        left.as_millis()
+                    );
+                }
+                for (key, counts) in &talkers {
+                    let _ = writeln!(
+                        out,
+                        "talker,{},{},{},",
+                        csv_field(&format!("{:?}", key)),
+                        counts.requests,
+                        counts.rejected
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Number of recent arrivals kept per key by the [traffic analysis].
+///
+/// [traffic analysis]: crate::governor::GovernorConfigBuilder::analyze_traffic
+pub const MAX_ARRIVALS: usize = 256;
+
+/// Number of keys whose arrivals are kept by the [traffic analysis], the keys seen once it
+/// is reached are left out.
+///
+/// [traffic analysis]: crate::governor::GovernorConfigBuilder::analyze_traffic
+pub const MAX_ANALYZED_KEYS: usize = 4096;
+
+/// Largest burst size suggested by [`GovernorConfig::suggest_quota`].
+///
+/// [`GovernorConfig::suggest_quota`]: crate::governor::GovernorConfig::suggest_quota
+pub const MAX_SUGGESTED_BURST: u32 = 1024;
+
+/// A quota derived from the recorded traffic, see [`GovernorConfig::suggest_quota`].
+///
+/// [`GovernorConfig::suggest_quota`]: crate::governor::GovernorConfig::suggest_quota
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotaSuggestion {
+    /// The interval after which one element of the quota is replenished.
+    pub period: Duration,
+    /// The burst size.
+    pub burst_size: u32,
+    /// The share of the recorded requests the quota would have rejected, between `0.0` and
+    /// `1.0`.
+    pub rejection_rate: f64,
+    /// The number of keys whose arrivals were analyzed.
+    pub keys: usize,
+    /// The number of arrivals analyzed.
+    pub requests: u64,
+}
+
+// The recent arrivals of every key, replayed against candidate quotas.
+pub(crate) struct Arrivals<Key> {
+    keys: Mutex<HashMap<Key, VecDeque<Instant>>>,
+}
+
+impl<Key> fmt::Debug for Arrivals<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Arrivals").finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> Arrivals<Key> {
+    pub(crate) fn new() -> Self {
+        Self {
+            keys: Mutex::default(),
+        }
+    }
+
+    /// Record a request of `key`.
+    pub(crate) fn record(&self, key: &Key) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        if keys.len() >= MAX_ANALYZED_KEYS && !keys.contains_key(key) {
+            return;
+        }
+        let arrivals = keys.entry(key.clone()).or_default();
+        if arrivals.len() == MAX_ARRIVALS {
+            arrivals.pop_front();
+        }
+        arrivals.push_back(now);
+    }
+
+    /// The quota allowing the fewest requests while rejecting at most `target` of the
+    /// recorded ones, `None` until two requests of a key arrived apart.
+    pub(crate) fn suggest(&self, target: f64) -> Option<QuotaSuggestion> {
+        // the arrivals of every key, in nanoseconds since its first one
+        let sequences: Vec<Vec<u64>> = {
+            let keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+            keys.values()
+                .filter_map(|arrivals| {
+                    let first = *arrivals.front()?;
+                    let offsets = arrivals.iter().map(|arrival| {
+                        u64::try_from(arrival.duration_since(first).as_nanos()).unwrap_or(u64::MAX)
+                    });
+                    Some(offsets.collect())
+                })
+                .collect()
+        };
+        let requests: u64 = sequences.iter().map(|arrivals| arrivals.len() as u64).sum();
+        let span = sequences
+            .iter()
+            .filter_map(|arrivals| arrivals.last().copied())
+            .max()?;
+        let mut gaps: Vec<u64> = sequences
+            .iter()
+            .flat_map(|arrivals| arrivals.windows(2).map(|pair| pair[1] - pair[0]))
+            .filter(|gap| *gap > 0)
+            .collect();
+        if gaps.is_empty() {
+            return None;
+        }
+        gaps.sort_unstable();
+        // the periods worth trying are spread over the observed inter-arrival times
+        let mut periods: Vec<u64> = (0..20).map(|i| gaps[gaps.len() * i / 20]).collect();
+        periods.dedup();
+
+        let allowed = (requests as f64 * target.clamp(0.0, 1.0)).floor() as u64;
+        let mut best: Option<(u64, u64, u32, u64)> = None;
+        for period in periods {
+            if rejections(&sequences, period, MAX_SUGGESTED_BURST) > allowed {
+                continue;
+            }
+            // fewer rejections with every extra element of burst
+            let (mut low, mut high) = (1, MAX_SUGGESTED_BURST);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if rejections(&sequences, period, mid) <= allowed {
+                    high = mid;
+                } else {
+                    low = mid + 1;
+                }
+            }
+            let capacity = u64::from(low) + span / period;
+            if best.is_none_or(|(best, ..)| capacity < best) {
+                best = Some((capacity, period, low, rejections(&sequences, period, low)));
+            }
+        }
+        let (_, period, burst_size, rejected) = best?;
+        Some(QuotaSuggestion {
+            period: Duration::from_nanos(period),
+            burst_size,
+            rejection_rate: rejected as f64 / requests as f64,
+            keys: sequences.len(),
+            requests,
+        })
+    }
+}
+
+// The number of `sequences` arrivals a GCRA quota of `period` and `burst` would reject.
+fn rejections(sequences: &[Vec<u64>], period: u64, burst: u32) -> u64 {
+    let tolerance = period.saturating_mul(u64::from(burst) - 1);
+    let mut rejected = 0;
+    for arrivals in sequences {
+        // the theoretical arrival time of the next request
+        let mut tat = 0u64;
+        for &arrival in arrivals {
+            let next = tat.max(arrival);
+            if next - arrival <= tolerance {
+                tat = next.saturating_add(period);
+            } else {
+                rejected += 1;
+            }
+        }
+    }
+    rejected
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
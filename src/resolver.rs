@@ -0,0 +1,368 @@
+//! Per request configurations, e.g. for tenants with quotas of their own.
+//!
+//! A [`ResolvingGovernorLayer`] asks its [`ConfigResolver`] for the configuration of every
+//! request instead of using a single configuration baked into the layer.
+//!
+//! # Example
+//!
+//! An axum middleware looks up the configuration of the tenant in the application state and
+//! hands it to the [`ExtensionResolver`] through the request extensions.
+//!
+//! ```rust
+//! use axum::{
+//!     extract::{Request, State},
+//!     middleware::{self, Next},
+//!     response::Response,
+//!     routing::get,
+//!     Router,
+//! };
+//! use std::{collections::HashMap, sync::Arc};
+//! use tower::ServiceBuilder;
+//! use tower_governor::{
+//!     governor::{DefaultInstant, GovernorConfig, GovernorConfigBuilder},
+//!     key_extractor::PeerIpKeyExtractor,
+//!     resolver::{ExtensionResolver, ResolvingGovernorLayer},
+//! };
+//!
+//! type Config = Arc<GovernorConfig<PeerIpKeyExtractor, governor::middleware::NoOpMiddleware<DefaultInstant>>>;
+//!
+//! async fn tenant_config(
+//!     State(tenants): State<Arc<HashMap<String, Config>>>,
+//!     mut req: Request,
+//!     next: Next,
+//! ) -> Response {
+//!     let tenant = req.headers().get("x-tenant").and_then(|value| value.to_str().ok());
+//!     if let Some(config) = tenant.and_then(|tenant| tenants.get(tenant)) {
+//!         req.extensions_mut().insert(config.clone());
+//!     }
+//!     next.run(req).await
+//! }
+//!
+//! let premium = GovernorConfigBuilder::default().burst_size(100).finish().unwrap();
+//! let tenants = Arc::new(HashMap::from([("premium".to_owned(), Arc::new(premium))]));
+//! let fallback = Arc::new(GovernorConfig::default());
+//!
+//! let app: Router = Router::new().route("/", get(|| async { "Hello world" })).layer(
+//!     ServiceBuilder::new()
+//!         .layer(middleware::from_fn_with_state(tenants, tenant_config))
+//!         .layer(ResolvingGovernorLayer::new(ExtensionResolver::new(fallback))),
+//! );
+//! ```
+
+use crate::{
+    errors::SnapshotError,
+    governor::{DefaultClock, DefaultInstant, Governor, GovernorConfig},
+    key_extractor::KeyExtractor,
+};
+use governor::{
+    clock::Clock,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    Quota,
+};
+use http::Request;
+use std::{
+    fmt,
+    marker::PhantomData,
+    mem,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+// The configuration type of a resolving layer, which doesn't own any configuration.
+type ConfigType<K, M, C> = PhantomData<fn() -> (K, M, C)>;
+
+/// Picks the configuration a request is rate limited by.
+pub trait ConfigResolver<K, M = NoOpMiddleware<DefaultInstant>, C = DefaultClock>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// The configuration of `req`.
+    ///
+    /// Return the same `Arc` for requests sharing a quota: every configuration has a limiter
+    /// store of its own.
+    fn resolve<B>(&self, req: &Request<B>) -> Arc<GovernorConfig<K, M, C>>;
+}
+
+/// Resolves the configuration inserted into the request extensions as an
+/// `Arc<GovernorConfig>`, e.g. by an earlier middleware with access to the application
+/// state, falling back to a default configuration.
+pub struct ExtensionResolver<K, M = NoOpMiddleware<DefaultInstant>, C = DefaultClock>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fallback: Arc<GovernorConfig<K, M, C>>,
+}
+
+impl<K, M, C> ExtensionResolver<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Resolve the requests without a configuration in their extensions to `fallback`.
+    pub fn new(fallback: Arc<GovernorConfig<K, M, C>>) -> Self {
+        Self { fallback }
+    }
+}
+
+impl<K, M, C> ConfigResolver<K, M, C> for ExtensionResolver<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+    GovernorConfig<K, M, C>: Send + Sync + 'static,
+{
+    fn resolve<B>(&self, req: &Request<B>) -> Arc<GovernorConfig<K, M, C>> {
+        req.extensions()
+            .get::<Arc<GovernorConfig<K, M, C>>>()
+            .unwrap_or(&self.fallback)
+            .clone()
+    }
+}
+
+impl<K, M, C> Clone for ExtensionResolver<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn clone(&self) -> Self {
+        Self {
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+impl<K, M, C> fmt::Debug for ExtensionResolver<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtensionResolver").finish_non_exhaustive()
+    }
+}
+
+/// Handle onto a configuration which can be replaced while layers use it, e.g. to change
+/// the quota without restarting the server.
+///
+/// Every clone of the handle resolves to the current configuration: hand one to a
+/// [`ResolvingGovernorLayer`] and keep another to update it.
+///
+/// ```rust
+/// use governor::Quota;
+/// use std::time::Duration;
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder,
+///     resolver::{GovernorConfigHandle, ResolvingGovernorLayer},
+/// };
+///
+/// let config = GovernorConfigBuilder::default().track_state().finish().unwrap();
+/// let handle = GovernorConfigHandle::new(config);
+/// let layer = ResolvingGovernorLayer::new(handle.clone());
+///
+/// // later, e.g. on a reload of the settings
+/// let quota = Quota::with_period(Duration::from_secs(1)).unwrap();
+/// handle.set_quota(quota.allow_burst(20.try_into().unwrap())).unwrap();
+/// assert_eq!(handle.config().burst_size(), 20);
+/// ```
+pub struct GovernorConfigHandle<K, M = NoOpMiddleware<DefaultInstant>, C = DefaultClock>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    config: Arc<RwLock<Arc<GovernorConfig<K, M, C>>>>,
+}
+
+impl<K, M, C> GovernorConfigHandle<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Handle onto `config`.
+    pub fn new(config: GovernorConfig<K, M, C>) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// The current configuration.
+    pub fn config(&self) -> Arc<GovernorConfig<K, M, C>> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Replace the configuration with `config`, whose keys start over with a full quota.
+    pub fn set_config(&self, config: GovernorConfig<K, M, C>) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    }
+
+    /// Replace the quota of the configuration with `quota`, keeping every other state.
+    ///
+    /// Every key keeps the elements it has used, see [`GovernorConfig::with_quota`] for
+    /// how they replenish under the new quota. This requires
+    /// [`track_state`](crate::governor::GovernorConfigBuilder::track_state), failing with
+    /// [`SnapshotError::NotTracked`] otherwise rather than giving every key a full quota.
+    /// The requests in flight finish against the previous configuration.
+    pub fn set_quota(&self, quota: Quota) -> Result<(), SnapshotError>
+    where
+        C: Clone,
+    {
+        let mut config = self.config.write().unwrap_or_else(|e| e.into_inner());
+        if !config.tracks_state() {
+            return Err(SnapshotError::NotTracked);
+        }
+        *config = Arc::new(config.with_quota(quota));
+        Ok(())
+    }
+}
+
+impl<K, M, C> ConfigResolver<K, M, C> for GovernorConfigHandle<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn resolve<B>(&self, _: &Request<B>) -> Arc<GovernorConfig<K, M, C>> {
+        self.config()
+    }
+}
+
+impl<K, M, C> Clone for GovernorConfigHandle<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<K, M, C> fmt::Debug for GovernorConfigHandle<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorConfigHandle")
+            .finish_non_exhaustive()
+    }
+}
+
+/// Layer rate limiting every request by the configuration its [`ConfigResolver`] picks.
+pub struct ResolvingGovernorLayer<R, K, M = NoOpMiddleware<DefaultInstant>, C = DefaultClock> {
+    resolver: Arc<R>,
+    config: ConfigType<K, M, C>,
+}
+
+impl<R, K, M, C> ResolvingGovernorLayer<R, K, M, C>
+where
+    R: ConfigResolver<K, M, C>,
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Rate limit requests by the configuration `resolver` picks for them.
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            config: PhantomData,
+        }
+    }
+}
+
+impl<R, K, M, C> Clone for ResolvingGovernorLayer<R, K, M, C> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            config: PhantomData,
+        }
+    }
+}
+
+impl<R, K, M, C> fmt::Debug for ResolvingGovernorLayer<R, K, M, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvingGovernorLayer")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R, K, M, C, S> Layer<S> for ResolvingGovernorLayer<R, K, M, C> {
+    type Service = ResolvingGovernor<R, K, M, C, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResolvingGovernor {
+            resolver: self.resolver.clone(),
+            inner,
+            config: PhantomData,
+        }
+    }
+}
+
+/// Middleware created by a [`ResolvingGovernorLayer`].
+///
+/// The [`Governor`] of the resolved configuration is created for every request, which only
+/// clones a few handles onto the configuration.
+pub struct ResolvingGovernor<R, K, M, C, S> {
+    resolver: Arc<R>,
+    inner: S,
+    config: ConfigType<K, M, C>,
+}
+
+impl<R, K, M, C, S: Clone> Clone for ResolvingGovernor<R, K, M, C, S> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            inner: self.inner.clone(),
+            config: PhantomData,
+        }
+    }
+}
+
+impl<R, K, M, C, S: fmt::Debug> fmt::Debug for ResolvingGovernor<R, K, M, C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvingGovernor")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R, K, M, C, S, ReqBody> Service<Request<ReqBody>> for ResolvingGovernor<R, K, M, C, S>
+where
+    R: ConfigResolver<K, M, C>,
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+    S: Service<Request<ReqBody>> + Clone,
+    Governor<K, M, S, C>: Service<Request<ReqBody>, Error = S::Error>,
+{
+    type Response = <Governor<K, M, S, C> as Service<Request<ReqBody>>>::Response;
+    type Error = S::Error;
+    type Future = <Governor<K, M, S, C> as Service<Request<ReqBody>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let config = self.resolver.resolve(&req);
+        // hand the service that was driven to readiness to the governor
+        let clone = self.inner.clone();
+        let inner = mem::replace(&mut self.inner, clone);
+        Governor::new(inner, &config).call(req)
+    }
+}
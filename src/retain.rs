@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Store size based cleanup, see `GovernorConfigBuilder::retain_watermarks`.
+//
+// A cleanup runs once the store reaches the high-water mark. If it can't bring the store back
+// below the low-water mark, as most keys are still in use, the next cleanup is postponed until
+// as many keys as between both marks were added, so that a spike of active keys doesn't
+// trigger a scan of the whole store on every request.
+#[derive(Debug)]
+pub(crate) struct Watermarks {
+    high: usize,
+    low: usize,
+    next: AtomicUsize,
+    running: AtomicBool,
+}
+
+impl Watermarks {
+    pub(crate) fn new(high: usize, low: usize) -> Self {
+        Self {
+            high,
+            low: low.min(high),
+            next: AtomicUsize::new(high),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Run `retain` if the store of `len` keys reached the next trigger, `retain` returning the
+    /// number of keys left.
+    pub(crate) fn check(&self, len: usize, retain: impl FnOnce() -> usize) {
+        if len < self.next.load(Ordering::Relaxed) || self.running.swap(true, Ordering::Acquire) {
+            return;
+        }
+        let left = retain();
+        let next = if left <= self.low {
+            self.high
+        } else {
+            left.saturating_add(self.high - self.low).max(self.high)
+        };
+        self.next.store(next, Ordering::Relaxed);
+        self.running.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,106 @@
+//! Rate limiting outside of HTTP, e.g. for background jobs, queue consumers or CLI tools.
+
+use crate::{
+    decision::Decision,
+    governor::{DefaultClock, Governor, GovernorConfig},
+    key_extractor::KeyExtractor,
+};
+use governor::{clock::Clock, middleware::RateLimitingMiddleware, NotUntil};
+use std::{
+    convert::Infallible,
+    fmt,
+    future::{ready, Ready},
+    num::NonZeroU32,
+    task::{Context, Poll},
+};
+use tower::Service;
+
+/// A [`Service`] deciding whether a key conforms to the quota of a [`GovernorConfig`].
+///
+/// It shares the limiter store of the configuration, so the keys are charged against the
+/// same buckets as the requests going through a [`GovernorLayer`] built from it.
+///
+/// # Example
+/// ```rust
+/// use std::{net::IpAddr, sync::Arc};
+/// use tower::{Service, ServiceExt};
+/// use tower_governor::{governor::GovernorConfigBuilder, service::GovernorService};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let config = Arc::new(GovernorConfigBuilder::default().finish().unwrap());
+/// let mut service = GovernorService::new(&config);
+///
+/// let ip = IpAddr::from([10, 0, 0, 1]);
+/// let decision = service.ready().await.unwrap().call(ip).await.unwrap();
+/// assert!(decision.is_allowed());
+/// # }
+/// ```
+///
+/// [`GovernorLayer`]: crate::GovernorLayer
+pub struct GovernorService<K, M, C = DefaultClock>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    governor: Governor<K, M, (), C>,
+}
+
+impl<K, M, C> GovernorService<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    /// Create a service checking keys against the quota and the limiter store of `config`.
+    pub fn new(config: &GovernorConfig<K, M, C>) -> Self {
+        Self {
+            governor: Governor::new((), config),
+        }
+    }
+}
+
+impl<K, M, C> Clone for GovernorService<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn clone(&self) -> Self {
+        Self {
+            governor: self.governor.clone(),
+        }
+    }
+}
+
+impl<K, M, C> fmt::Debug for GovernorService<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant>,
+    C: Clock,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorService").finish_non_exhaustive()
+    }
+}
+
+impl<K, M, C> Service<K::Key> for GovernorService<K, M, C>
+where
+    K: KeyExtractor,
+    M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    M::PositiveOutcome: Clone,
+    C: Clock,
+{
+    type Response = Decision;
+    type Error = Infallible;
+    type Future = Ready<Result<Decision, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, key: K::Key) -> Self::Future {
+        ready(Ok(self.governor.decide(&key, NonZeroU32::MIN)))
+    }
+}
@@ -0,0 +1,269 @@
+//! The plain settings of a configuration, for checking configuration files in CI.
+//!
+//! [`GovernorSettings::validate`] finds settings the builder would reject or that are likely
+//! mistakes, and [`GovernorSettings::diff`] finds the changes between two versions of the
+//! settings that tighten the limits, so deployment tooling can ask for a second look before
+//! rolling them out.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//! use tower_governor::settings::{Finding, GovernorSettings, Severity};
+//!
+//! let old = GovernorSettings::new(Duration::from_millis(500), 100);
+//! let new = GovernorSettings::new(Duration::from_millis(500), 10);
+//!
+//! let findings = GovernorSettings::diff(&old, &new);
+//! assert_eq!(findings, [Finding::BurstReduced { from: 100, to: 10 }]);
+//! assert_eq!(findings[0].severity(), Severity::Risky);
+//! assert_eq!(findings[0].to_string(), "burst reduced 10x (100 -> 10)");
+//! ```
+
+use http::Method;
+use std::{fmt, time::Duration};
+
+/// The settings of a configuration that decide which requests are rate limited.
+///
+/// Apply them with [`GovernorConfigBuilder::settings`], read them back from a configuration
+/// with [`GovernorConfig::settings`].
+///
+/// [`GovernorConfigBuilder::settings`]: crate::governor::GovernorConfigBuilder::settings
+/// [`GovernorConfig::settings`]: crate::governor::GovernorConfig::settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GovernorSettings {
+    /// The interval after which one element of the quota is replenished.
+    pub period: Duration,
+    /// How many requests can occur before requests start being blocked.
+    pub burst_size: u32,
+    /// The HTTP methods the configuration applies to, `None` meaning all methods.
+    pub methods: Option<Vec<Method>>,
+}
+
+impl Default for GovernorSettings {
+    /// The settings of the default configuration.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), 8)
+    }
+}
+
+impl GovernorSettings {
+    /// Settings replenishing one element every `period`, up to `burst_size`, for all methods.
+    pub fn new(period: Duration, burst_size: u32) -> Self {
+        Self {
+            period,
+            burst_size,
+            methods: None,
+        }
+    }
+
+    /// Check the settings on their own, returning nothing if they are fine.
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        if self.period.is_zero() {
+            findings.push(Finding::ZeroPeriod);
+        }
+        if self.burst_size == 0 {
+            findings.push(Finding::ZeroBurstSize);
+        }
+        if self.methods.as_ref().is_some_and(Vec::is_empty) {
+            findings.push(Finding::NoMethods);
+        }
+        findings
+    }
+
+    /// The changes from `old` to `new` along with the findings of [`validate`] on `new`,
+    /// returning nothing if the settings are the same.
+    ///
+    /// [`validate`]: Self::validate
+    pub fn diff(old: &Self, new: &Self) -> Vec<Finding> {
+        let mut findings = new.validate();
+        if new.burst_size < old.burst_size {
+            findings.push(Finding::BurstReduced {
+                from: old.burst_size,
+                to: new.burst_size,
+            });
+        } else if new.burst_size > old.burst_size {
+            findings.push(Finding::BurstIncreased {
+                from: old.burst_size,
+                to: new.burst_size,
+            });
+        }
+        if new.period > old.period {
+            findings.push(Finding::PeriodIncreased {
+                from: old.period,
+                to: new.period,
+            });
+        } else if new.period < old.period {
+            findings.push(Finding::PeriodReduced {
+                from: old.period,
+                to: new.period,
+            });
+        }
+        match (&old.methods, &new.methods) {
+            (Some(_), None) => findings.push(Finding::MethodsFilterRemoved),
+            (None, Some(methods)) => findings.push(Finding::MethodsFilterAdded(methods.clone())),
+            (Some(old), Some(new)) => {
+                let added: Vec<_> = new.iter().filter(|m| !old.contains(m)).cloned().collect();
+                let removed: Vec<_> = old.iter().filter(|m| !new.contains(m)).cloned().collect();
+                if !added.is_empty() {
+                    findings.push(Finding::MethodsAdded(added));
+                }
+                if !removed.is_empty() {
+                    findings.push(Finding::MethodsRemoved(removed));
+                }
+            }
+            (None, None) => {}
+        }
+        findings
+    }
+}
+
+/// How much attention a [`Finding`] needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The change loosens the limits or rate limits fewer requests.
+    Info,
+    /// The change tightens the limits or rate limits more requests, which may reject clients
+    /// that are fine today.
+    Risky,
+    /// The settings are rejected by the builder or rate limit nothing.
+    Invalid,
+}
+
+/// A finding of [`GovernorSettings::validate`] or [`GovernorSettings::diff`].
+///
+/// Displays as a short sentence, e.g. `burst reduced 10x (100 -> 10)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Finding {
+    /// The period is zero.
+    ZeroPeriod,
+    /// The burst size is zero.
+    ZeroBurstSize,
+    /// The methods filter is empty, so no request is rate limited.
+    NoMethods,
+    /// The burst size was reduced.
+    BurstReduced {
+        /// The old burst size.
+        from: u32,
+        /// The new burst size.
+        to: u32,
+    },
+    /// The burst size was increased.
+    BurstIncreased {
+        /// The old burst size.
+        from: u32,
+        /// The new burst size.
+        to: u32,
+    },
+    /// The period was increased, replenishing the quota more slowly.
+    PeriodIncreased {
+        /// The old period.
+        from: Duration,
+        /// The new period.
+        to: Duration,
+    },
+    /// The period was reduced, replenishing the quota faster.
+    PeriodReduced {
+        /// The old period.
+        from: Duration,
+        /// The new period.
+        to: Duration,
+    },
+    /// The methods filter was removed, so requests of every method are rate limited.
+    MethodsFilterRemoved,
+    /// A methods filter was added, so only requests of these methods are rate limited.
+    MethodsFilterAdded(Vec<Method>),
+    /// These methods were added to the methods filter.
+    MethodsAdded(Vec<Method>),
+    /// These methods were removed from the methods filter.
+    MethodsRemoved(Vec<Method>),
+}
+
+impl Finding {
+    /// How much attention the finding needs.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::ZeroPeriod | Self::ZeroBurstSize | Self::NoMethods => Severity::Invalid,
+            Self::BurstReduced { .. }
+            | Self::PeriodIncreased { .. }
+            | Self::MethodsFilterRemoved
+            | Self::MethodsAdded(_) => Severity::Risky,
+            Self::BurstIncreased { .. }
+            | Self::PeriodReduced { .. }
+            | Self::MethodsFilterAdded(_)
+            | Self::MethodsRemoved(_) => Severity::Info,
+        }
+    }
+}
+
+// The ratio between two values preceded by a space, e.g. ` 10x`, empty if `smaller` is zero.
+fn factor(larger: f64, smaller: f64) -> String {
+    if smaller == 0.0 {
+        return String::new();
+    }
+    let factor = larger / smaller;
+    if factor.fract() == 0.0 {
+        format!(" {}x", factor)
+    } else {
+        format!(" {:.1}x", factor)
+    }
+}
+
+fn list(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroPeriod => f.write_str("period is zero"),
+            Self::ZeroBurstSize => f.write_str("burst size is zero"),
+            Self::NoMethods => f.write_str("methods filter is empty, nothing is rate limited"),
+            Self::BurstReduced { from, to } => write!(
+                f,
+                "burst reduced{} ({} -> {})",
+                factor(*from as f64, *to as f64),
+                from,
+                to
+            ),
+            Self::BurstIncreased { from, to } => write!(
+                f,
+                "burst increased{} ({} -> {})",
+                factor(*to as f64, *from as f64),
+                from,
+                to
+            ),
+            Self::PeriodIncreased { from, to } => write!(
+                f,
+                "period increased{} ({:?} -> {:?})",
+                factor(to.as_secs_f64(), from.as_secs_f64()),
+                from,
+                to
+            ),
+            Self::PeriodReduced { from, to } => write!(
+                f,
+                "period reduced{} ({:?} -> {:?})",
+                factor(from.as_secs_f64(), to.as_secs_f64()),
+                from,
+                to
+            ),
+            Self::MethodsFilterRemoved => {
+                f.write_str("methods filter removed, every method is rate limited")
+            }
+            Self::MethodsFilterAdded(methods) => {
+                write!(
+                    f,
+                    "methods filter added, only {} are rate limited",
+                    list(methods)
+                )
+            }
+            Self::MethodsAdded(methods) => write!(f, "methods added: {}", list(methods)),
+            Self::MethodsRemoved(methods) => write!(f, "methods removed: {}", list(methods)),
+        }
+    }
+}
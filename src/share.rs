@@ -0,0 +1,128 @@
+use crate::{headers, sync::Mutex};
+use governor::Quota;
+use http::HeaderValue;
+use std::time::{Duration, Instant};
+
+// The weights of the classes sharing a quota.
+pub(crate) type Shares = Vec<(&'static str, u32)>;
+
+// A class guaranteed a share of the quota.
+#[derive(Debug, Clone)]
+pub(crate) struct ShareClass {
+    pub(crate) name: &'static str,
+    /// The share of the burst size of the quota.
+    pub(crate) burst_size: u32,
+    pub(crate) scope: Option<HeaderValue>,
+    // nanoseconds between two replenished elements
+    interval: u64,
+    // how far ahead of now the next conforming arrival may be
+    tolerance: u64,
+}
+
+// Theoretical arrival times of the pool and of every class, in nanoseconds since the start.
+#[derive(Debug)]
+struct State {
+    pool: u64,
+    classes: Vec<u64>,
+}
+
+// A global quota shared between classes by weight, see `GovernorConfigBuilder::fair_share`.
+#[derive(Debug)]
+pub(crate) struct FairShare {
+    start: Instant,
+    pub(crate) quota: Quota,
+    interval: u64,
+    tolerance: u64,
+    classes: Vec<ShareClass>,
+    state: Mutex<State>,
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl FairShare {
+    pub(crate) fn new(quota: Quota, shares: &[(&'static str, u32)], policy: Option<&str>) -> Self {
+        let total: u64 = shares.iter().map(|&(_, weight)| u64::from(weight)).sum();
+        let interval = nanos(quota.replenish_interval()).max(1);
+        let burst_size = u64::from(quota.burst_size().get());
+        let classes: Vec<ShareClass> = shares
+            .iter()
+            .filter(|&&(_, weight)| weight > 0)
+            .map(|&(name, weight)| {
+                let weight = u64::from(weight);
+                // the class gets `weight / total` of the elements and of the burst
+                let burst_size = (burst_size * weight / total).max(1);
+                let interval =
+                    u64::try_from(u128::from(interval) * u128::from(total) / u128::from(weight))
+                        .unwrap_or(u64::MAX);
+                ShareClass {
+                    name,
+                    burst_size: u32::try_from(burst_size).unwrap_or(u32::MAX),
+                    scope: headers::scope(policy, Some(name)),
+                    interval,
+                    tolerance: interval.saturating_mul(burst_size - 1),
+                }
+            })
+            .collect();
+        Self {
+            start: Instant::now(),
+            quota,
+            interval,
+            tolerance: interval.saturating_mul(burst_size - 1),
+            state: Mutex::new(State {
+                pool: 0,
+                classes: vec![0; classes.len()],
+            }),
+            classes,
+        }
+    }
+
+    /// The same quota and shares, with none of them used.
+    pub(crate) fn detached(&self) -> Self {
+        Self {
+            start: Instant::now(),
+            quota: self.quota,
+            interval: self.interval,
+            tolerance: self.tolerance,
+            state: Mutex::new(State {
+                pool: 0,
+                classes: vec![0; self.classes.len()],
+            }),
+            classes: self.classes.clone(),
+        }
+    }
+
+    /// Admit a request of the class `name`, or tell how long it has to wait along with its
+    /// share, if it has one.
+    ///
+    /// Requests within the share of their class are always admitted. The others borrow from
+    /// the quota left unused, which every admitted request is charged to.
+    pub(crate) fn admit(&self, name: Option<&str>) -> Result<(), (Duration, Option<&ShareClass>)> {
+        let now = nanos(self.start.elapsed());
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let index = name.and_then(|name| self.classes.iter().position(|class| class.name == name));
+        let mut wait = u64::MAX;
+        if let Some(index) = index {
+            let class = &self.classes[index];
+            let tat = state.classes[index].max(now);
+            if tat - now <= class.tolerance {
+                state.classes[index] = tat.saturating_add(class.interval);
+                // even beyond its capacity, so that the other classes stop borrowing
+                state.pool = state.pool.max(now).saturating_add(self.interval);
+                return Ok(());
+            }
+            wait = tat - now - class.tolerance;
+        }
+        let tat = state.pool.max(now);
+        if tat - now <= self.tolerance {
+            state.pool = tat.saturating_add(self.interval);
+            return Ok(());
+        }
+        wait = wait.min(tat - now - self.tolerance);
+        Err((
+            Duration::from_nanos(wait),
+            index.map(|index| &self.classes[index]),
+        ))
+    }
+}
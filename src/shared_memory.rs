@@ -0,0 +1,156 @@
+use crate::errors::ConfigError;
+use governor::Quota;
+use memmap2::MmapMut;
+use std::{
+    fmt,
+    fs::OpenOptions,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Marks the files laid out by this module, along with the version of the layout.
+const MAGIC: u64 = u64::from_le_bytes(*b"tgshm\0\0\x01");
+
+/// The words of the header: the magic, the number of slots, the emission interval and the
+/// tolerance of the quota, both in nanoseconds.
+const HEADER_WORDS: usize = 4;
+
+/// Number of slots probed for a key before it shares the slot it hashes to.
+const MAX_PROBES: usize = 8;
+
+// The quota of every key shared by the processes of a host through a memory mapped file, see
+// `GovernorConfigBuilder::shared_memory`.
+//
+// After the header, the file holds `slots` pairs of words: the hash of a key, zero while the
+// slot is free, and its theoretical arrival time in nanoseconds since the Unix epoch, as
+// defined by the GCRA the in-process limiter implements. Both are only ever accessed
+// atomically, so any number of processes can map the file at once.
+pub(crate) struct SharedMemory {
+    map: MmapMut,
+    slots: usize,
+    interval: u64,
+    tolerance: u64,
+}
+
+impl fmt::Debug for SharedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedMemory")
+            .field("slots", &self.slots)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedMemory {
+    /// Map the file at `path`, creating it with `slots` slots for `quota` unless another
+    /// process did already.
+    pub(crate) fn open(path: &Path, slots: usize, quota: Quota) -> Result<Self, ConfigError> {
+        let error =
+            |e: std::io::Error| ConfigError::SharedMemory(format!("{}: {}", path.display(), e));
+        let slots = slots.max(1);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(error)?;
+        let len = ((HEADER_WORDS + 2 * slots) * size_of::<u64>()) as u64;
+        if file.metadata().map_err(error)?.len() < len {
+            file.set_len(len).map_err(error)?;
+        }
+        // SAFETY: the file is only modified through atomic operations, by this process and by
+        // the others mapping it, and the mapping outlives every access as it's owned by `Self`
+        let map = unsafe { MmapMut::map_mut(&file) }.map_err(error)?;
+        let interval = quota.replenish_interval().as_nanos() as u64;
+        let shared = Self {
+            map,
+            slots,
+            interval,
+            tolerance: interval * u64::from(quota.burst_size().get() - 1),
+        };
+
+        let header = &shared.words()[..HEADER_WORDS];
+        for (word, value) in header[1..]
+            .iter()
+            .zip([slots as u64, interval, shared.tolerance])
+        {
+            if let Err(existing) =
+                word.compare_exchange(0, value, Ordering::AcqRel, Ordering::Acquire)
+            {
+                if existing != value {
+                    return Err(ConfigError::SharedMemory(format!(
+                        "{} was created for another quota or number of slots",
+                        path.display()
+                    )));
+                }
+            }
+        }
+        match header[0].compare_exchange(0, MAGIC, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => Ok(shared),
+            Err(MAGIC) => Ok(shared),
+            Err(_) => Err(ConfigError::SharedMemory(format!(
+                "{} isn't a tower-governor shared memory file",
+                path.display()
+            ))),
+        }
+    }
+
+    /// The words of the file.
+    fn words(&self) -> &[AtomicU64] {
+        let len = HEADER_WORDS + 2 * self.slots;
+        // SAFETY: the mapping is page aligned and at least `len` words long, and `AtomicU64`
+        // has the same layout as `u64`
+        unsafe { std::slice::from_raw_parts(self.map.as_ptr().cast::<AtomicU64>(), len) }
+    }
+
+    /// The hash word and the theoretical arrival time of the slot of the key hashed to `hash`.
+    fn slot(&self, hash: u64, now: u64) -> (&AtomicU64, &AtomicU64) {
+        let words = &self.words()[HEADER_WORDS..];
+        let pair = |index: usize| (&words[2 * index], &words[2 * index + 1]);
+        // zero marks free slots
+        let hash = hash.max(1);
+        let home = (hash % self.slots as u64) as usize;
+        let probes =
+            || (0..MAX_PROBES.min(self.slots)).map(|probe| pair((home + probe) % self.slots));
+        if let Some(slot) = probes().find(|(key, _)| key.load(Ordering::Acquire) == hash) {
+            return slot;
+        }
+        for (key, tat) in probes() {
+            // take the slot over if free, or if its key replenished its whole quota
+            let current = key.load(Ordering::Acquire);
+            if current != 0 && tat.load(Ordering::Acquire) > now {
+                continue;
+            }
+            match key.compare_exchange(current, hash, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return (key, tat),
+                Err(actual) if actual == hash => return (key, tat),
+                Err(_) => {}
+            }
+        }
+        // every probed slot is busy, share the one the key hashes to
+        pair(home)
+    }
+
+    /// Charge `cells` to the key hashed to `hash`, or return the time to wait if it exceeds
+    /// the quota.
+    pub(crate) fn check(&self, hash: u64, cells: u32) -> Result<(), Duration> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        let (_, tat) = self.slot(hash, now);
+        let increment = self.interval.saturating_mul(u64::from(cells));
+        let mut current = tat.load(Ordering::Acquire);
+        loop {
+            let next = current.max(now).saturating_add(increment);
+            let allowed_at = next.saturating_sub(self.tolerance + self.interval);
+            if allowed_at > now {
+                return Err(Duration::from_nanos(allowed_at - now));
+            }
+            match tat.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return Ok(()),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
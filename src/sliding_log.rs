@@ -0,0 +1,64 @@
+use crate::expiring::ExpiringMap;
+use std::{
+    collections::VecDeque,
+    fmt,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+// The times of the last allowed requests of every key, allowing exactly `max_requests` within
+// any `window`, see `GovernorConfigBuilder::sliding_log`.
+pub(crate) struct SlidingLog<Key> {
+    pub(crate) max_requests: u32,
+    window: Duration,
+    // never more than `max_requests` times per key, the oldest first
+    logs: ExpiringMap<Key, VecDeque<Instant>>,
+}
+
+impl<Key> fmt::Debug for SlidingLog<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingLog")
+            .field("max_requests", &self.max_requests)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> SlidingLog<Key> {
+    pub(crate) fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            logs: ExpiringMap::new(),
+        }
+    }
+
+    /// The same limit, with an empty log.
+    pub(crate) fn detached(&self) -> Self {
+        Self::new(self.max_requests, self.window)
+    }
+
+    /// Log a request of `key`, or return the time until the oldest request of the window
+    /// leaves it if `key` already made `max_requests` within the window.
+    pub(crate) fn check(&self, key: &Key) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut logs = self.logs.lock(key);
+        logs.purge(|log| {
+            log.back()
+                .is_none_or(|last| now.duration_since(*last) >= self.window)
+        });
+        let log = logs.entry(key.clone()).or_default();
+        while log
+            .front()
+            .is_some_and(|first| now.duration_since(*first) >= self.window)
+        {
+            log.pop_front();
+        }
+        if log.len() >= self.max_requests as usize {
+            let first = log.front().expect("a full log isn't empty");
+            return Err(self.window - now.duration_since(*first));
+        }
+        log.push_back(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+//! Several rate limiting policies evaluated by a single layer.
+//!
+//! A [`GovernorStack`] checks a request against each of its configurations in order, e.g. a
+//! global ceiling, then a per IP quota, then a per user quota, and rejects it with the
+//! response of the first one it exceeds. Compared to stacking one [`GovernorLayer`] per
+//! policy, the request goes through a single service, and allowed responses only carry the
+//! rate limiting headers of the policy closest to rejecting the key, named by its
+//! [`SCOPE_HEADER`] when the configurations have [policy names].
+//!
+//! As with stacked layers, the policies evaluated before the rejecting one have charged the
+//! request against their quota.
+//!
+//! # Example
+//! ```rust
+//! use axum::{routing::get, Router};
+//! use tower_governor::{
+//!     governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor, stack::GovernorStack,
+//! };
+//!
+//! let global = GovernorConfigBuilder::default()
+//!     .key_extractor(GlobalKeyExtractor)
+//!     .per_millisecond(1)
+//!     .burst_size(1000)
+//!     .policy_name("global")
+//!     .use_headers()
+//!     .finish()
+//!     .unwrap();
+//! let per_ip = GovernorConfigBuilder::default()
+//!     .per_second(1)
+//!     .burst_size(10)
+//!     .policy_name("per-ip")
+//!     .use_headers()
+//!     .finish()
+//!     .unwrap();
+//!
+//! let app: Router = Router::new()
+//!     .route("/", get(|| async { "Hello world!" }))
+//!     .layer(GovernorStack::new().push(global).push(per_ip));
+//! ```
+//!
+//! [`GovernorLayer`]: crate::GovernorLayer
+//! [`SCOPE_HEADER`]: crate::headers::SCOPE_HEADER
+//! [policy names]: crate::governor::GovernorConfigBuilder::policy_name
+
+use crate::{
+    decision::{EnforcementMode, RateLimitSnapshot},
+    governor::{Governor, GovernorConfig, InnerErrorHook, ResponseHook, ResponseMapper, Verdict},
+    headers::UpstreamHeaders,
+    key_extractor::KeyExtractor,
+    refund::RefundGuard,
+    replay::Remaining,
+    Kind, ResponseFuture,
+};
+use axum::body::Body;
+use governor::{clock::Clock, middleware::RateLimitingMiddleware, NotUntil};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use pin_project::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service};
+
+// An allowed request, along with what its headers would be if its policy was binding.
+struct Allowed {
+    snapshot: RateLimitSnapshot,
+    headers: bool,
+    credits: bool,
+    upstream: UpstreamHeaders,
+    class: Option<&'static str>,
+    scope: Option<HeaderValue>,
+    trailers: bool,
+    on_error: Option<InnerErrorHook>,
+    mapper: Option<ResponseMapper>,
+    on_response: Option<ResponseHook>,
+    extra_headers: Option<HeaderMap>,
+}
+
+impl Allowed {
+    // Whether this policy is closer to rejecting the request than `other`.
+    fn binds_over(&self, other: &Allowed) -> bool {
+        match (self.headers, other.headers) {
+            (true, true) => self.snapshot.remaining < other.snapshot.remaining,
+            (headers, _) => headers && !other.headers,
+        }
+    }
+}
+
+// The outcome of a policy for a request.
+enum Checked {
+    Pass,
+    Allowed(Allowed),
+    Observe(ResponseHook),
+    Respond(Response<Body>, Option<Duration>),
+    Defer(crate::governor::DeferredResponse, Option<Duration>),
+}
+
+// A configuration of the stack, with its types erased.
+trait Policy: Send + Sync {
+    fn check(&self, req: &mut Request<()>) -> Checked;
+}
+
+impl<K, M, C> Policy for Governor<K, M, (), C>
+where
+    K: KeyExtractor + Send + Sync,
+    K::Key: Send + Sync + 'static,
+    M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>
+        + Send
+        + Sync
+        + 'static,
+    M::PositiveOutcome: Clone + Remaining + Send + Sync,
+    C: Clock + Send + Sync + 'static,
+{
+    fn check(&self, req: &mut Request<()>) -> Checked {
+        let headers = M::PositiveOutcome::KNOWN;
+        // the requests only charged by their response don't know what is left yet
+        let allowed = |req: &mut Request<()>, remaining, class, on_response| Allowed {
+            snapshot: self.allowed_snapshot(remaining, class),
+            extra_headers: self.extra_headers(req, remaining, class),
+            headers,
+            credits: self.config.credits,
+            upstream: self.config.upstream_headers,
+            class,
+            scope: self.scope(class).cloned(),
+            trailers: self.config.trailers,
+            on_error: self.config.inner_error_hook.clone(),
+            mapper: self.config.response_mapper.clone(),
+            on_response,
+        };
+        match self.verdict(req, headers) {
+            Verdict::Bypass | Verdict::Forward => Checked::Pass,
+            Verdict::Allowed(outcome, class) => {
+                let on_response = self.response_hook(req, class);
+                Checked::Allowed(allowed(req, outcome.remaining(), class, on_response))
+            }
+            Verdict::Charge(hook, class) => Checked::Allowed(allowed(req, None, class, Some(hook))),
+            Verdict::Observe(hook) => Checked::Observe(hook),
+            Verdict::Respond(response) => Checked::Respond(response, self.config.tarpit),
+            Verdict::Defer(response) => Checked::Defer(response, self.config.tarpit),
+        }
+    }
+}
+
+/// A layer checking the requests against several configurations in order, see the
+/// [module documentation](self).
+#[derive(Clone, Default)]
+pub struct GovernorStack {
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl GovernorStack {
+    /// A stack without any policy, forwarding every request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a configuration, checked after those added before.
+    ///
+    /// The configuration can use the default middleware or the one of
+    /// [`use_headers`](crate::governor::GovernorConfigBuilder::use_headers).
+    // `Remaining` seals the middlewares to those the layer supports
+    #[allow(private_bounds)]
+    pub fn push<K, M, C>(mut self, config: impl Into<Arc<GovernorConfig<K, M, C>>>) -> Self
+    where
+        K: KeyExtractor + Send + Sync + 'static,
+        K::Key: Send + Sync + 'static,
+        M: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>
+            + Send
+            + Sync
+            + 'static,
+        M::PositiveOutcome: Clone + Remaining + Send + Sync,
+        C: Clock + Send + Sync + 'static,
+    {
+        let config: Arc<GovernorConfig<K, M, C>> = config.into();
+        self.policies.push(Arc::new(Governor::new((), &config)));
+        self
+    }
+
+    /// The number of configurations of the stack.
+    pub fn len(&self) -> usize {
+        self.policies.len()
+    }
+
+    /// Whether the stack has no configuration.
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+}
+
+impl fmt::Debug for GovernorStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorStack")
+            .field("policies", &self.policies.len())
+            .finish()
+    }
+}
+
+impl<S> Layer<S> for GovernorStack {
+    type Service = GovernorStackService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GovernorStackService {
+            inner,
+            policies: self.policies.clone().into(),
+        }
+    }
+}
+
+/// The service of a [`GovernorStack`].
+#[derive(Clone)]
+pub struct GovernorStackService<S> {
+    inner: S,
+    policies: Arc<[Arc<dyn Policy>]>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for GovernorStackService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GovernorStackService")
+            .field("inner", &self.inner)
+            .field("policies", &self.policies.len())
+            .finish()
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for GovernorStackService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = StackFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // the policies only look at the parts of the request
+        let (parts, body) = req.into_parts();
+        let mut probe = Request::from_parts(parts, ());
+        let mut binding: Option<Allowed> = None;
+        let mut hooks = Vec::new();
+        for policy in self.policies.iter() {
+            let kind = match policy.check(&mut probe) {
+                Checked::Pass => continue,
+                Checked::Allowed(mut allowed) => {
+                    // every policy counts the response, binding or not
+                    hooks.extend(allowed.on_response.take());
+                    if binding
+                        .as_ref()
+                        .is_none_or(|binding| allowed.binds_over(binding))
+                    {
+                        binding = Some(allowed);
+                    }
+                    continue;
+                }
+                Checked::Observe(hook) => {
+                    hooks.push(hook);
+                    continue;
+                }
+                Checked::Respond(response, delay) => Kind::rejection(response, delay),
+                Checked::Defer(response, delay) => Kind::deferred(response, delay),
+            };
+            // the request never reaches the inner service, there is nothing to observe
+            return StackFuture {
+                inner: ResponseFuture {
+                    inner: kind,
+                    refund: RefundGuard::default(),
+                    mode: EnforcementMode::of(&probe),
+                },
+                hooks: Vec::new(),
+            };
+        }
+
+        let refund = RefundGuard::take(&mut probe);
+        let mode = EnforcementMode::of(&probe);
+        let (parts, ()) = probe.into_parts();
+        let mut req = Request::from_parts(parts, body);
+        let kind = match binding {
+            Some(allowed) => {
+                req.extensions_mut().insert(allowed.snapshot);
+                Kind::Allowed {
+                    future: self.inner.call(req),
+                    snapshot: allowed.snapshot,
+                    headers: allowed.headers,
+                    credits: allowed.credits,
+                    upstream: allowed.upstream,
+                    class: allowed.class,
+                    scope: allowed.scope,
+                    trailers: allowed.trailers,
+                    on_error: allowed.on_error,
+                    mapper: allowed.mapper,
+                    on_response: None,
+                    extra_headers: allowed.extra_headers,
+                }
+            }
+            None => Kind::Passthrough {
+                future: self.inner.call(req),
+            },
+        };
+        StackFuture {
+            inner: ResponseFuture {
+                inner: kind,
+                refund,
+                mode,
+            },
+            hooks,
+        }
+    }
+}
+
+/// Response future for [`GovernorStackService`].
+#[derive(Debug)]
+#[pin_project]
+pub struct StackFuture<F> {
+    #[pin]
+    inner: ResponseFuture<F>,
+    // the hooks of the policies observing the response of the inner service
+    hooks: Vec<ResponseHook>,
+}
+
+impl<F, E> Future for StackFuture<F>
+where
+    F: Future<Output = Result<Response<Body>, E>>,
+{
+    type Output = Result<Response<Body>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        for hook in this.hooks.drain(..) {
+            hook.call(result.as_ref().ok());
+        }
+        Poll::Ready(result)
+    }
+}
@@ -0,0 +1,282 @@
+//! The quota state of the keys, see [`GovernorConfigBuilder::track_state`].
+//!
+//! [`GovernorConfigBuilder::track_state`]: crate::governor::GovernorConfigBuilder::track_state
+
+use crate::{errors::SnapshotError, expiring::ExpiringMap};
+use std::{
+    fmt::{self, Display},
+    hash::Hash,
+    io::{BufRead, Write},
+    num::NonZeroU32,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    vec,
+};
+
+/// The first line of the snapshots written by this version.
+const SNAPSHOT_HEADER: &str = "tower-governor-state 1";
+
+// The quota state of a key.
+#[derive(Debug, Clone, Copy)]
+struct KeyState {
+    // when the quota of the key is full again
+    tat: Instant,
+    last_seen: Instant,
+    // when the key started using its quota since it was last full
+    window_start: Instant,
+}
+
+/// The quota state of a key, see [`GovernorConfig::iter_keys`].
+///
+/// [`GovernorConfig::iter_keys`]: crate::governor::GovernorConfig::iter_keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEntry<Key> {
+    /// The key.
+    pub key: Key,
+    /// The number of requests the key can make right away.
+    pub remaining: u32,
+    /// When the key was last charged.
+    pub last_seen: SystemTime,
+    /// When the current usage cycle of the key started: its first request since its quota
+    /// was last full.
+    pub window_start: SystemTime,
+}
+
+// The window start of the key of an allowed request, handed over to its response through
+// the request extensions, see `GovernorConfigBuilder::window_start_header`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WindowStart(pub(crate) SystemTime);
+
+// The wall clock time of `instant`, which is in the past.
+fn system_time(instant: Instant) -> SystemTime {
+    SystemTime::now() - instant.elapsed()
+}
+
+// Shadow copy of the quota state of the keys, which the limiter doesn't expose, see
+// `GovernorConfigBuilder::track_state`.
+pub(crate) struct KeyStates<Key> {
+    period: Duration,
+    burst_size: u32,
+    keys: ExpiringMap<Key, KeyState>,
+}
+
+impl<Key> fmt::Debug for KeyStates<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyStates")
+            .field("period", &self.period)
+            .field("burst_size", &self.burst_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Key: Hash + Eq + Clone> KeyStates<Key> {
+    pub(crate) fn new(period: Duration, burst_size: u32) -> Self {
+        Self {
+            period,
+            burst_size,
+            keys: ExpiringMap::new(),
+        }
+    }
+
+    /// Record `cells` elements of the quota of `key` as used.
+    pub(crate) fn charge(&self, key: &Key, cells: NonZeroU32) {
+        let now = Instant::now();
+        let mut keys = self.keys.lock(key);
+        keys.purge(|state| state.tat <= now);
+        let state = keys.entry(key.clone()).or_insert(KeyState {
+            tat: now,
+            last_seen: now,
+            window_start: now,
+        });
+        if state.tat <= now {
+            state.window_start = now;
+        }
+        state.tat = state.tat.max(now) + self.period * cells.get();
+        state.last_seen = now;
+    }
+
+    /// When the current usage cycle of `key` started, `None` if it isn't tracked.
+    pub(crate) fn window_start(&self, key: &Key) -> Option<SystemTime> {
+        let keys = self.keys.lock(key);
+        keys.get(key).map(|state| system_time(state.window_start))
+    }
+
+    /// The states of the keys under a quota replenishing one element every `period` with
+    /// bursts of `burst_size`, calling `carry` with every key whose quota isn't full and the
+    /// number of elements it has used, up to `burst_size`.
+    pub(crate) fn requota(
+        &self,
+        period: Duration,
+        burst_size: u32,
+        mut carry: impl FnMut(&Key, NonZeroU32),
+    ) -> Self {
+        let requota = Self::new(period, burst_size);
+        let now = Instant::now();
+        let used = self.keys.filter_map(|key, state| {
+            let debt = state
+                .tat
+                .checked_duration_since(now)
+                .filter(|debt| !debt.is_zero())?;
+            Some((
+                key.clone(),
+                debt.as_nanos().div_ceil(self.period.as_nanos()),
+            ))
+        });
+        for (key, cells) in used {
+            let cells = u32::try_from(cells).unwrap_or(u32::MAX).min(burst_size);
+            if let Some(cells) = NonZeroU32::new(cells) {
+                carry(&key, cells);
+                requota.charge(&key, cells);
+            }
+        }
+        requota
+    }
+
+    /// The state of `key`, `None` if it isn't tracked anymore.
+    fn entry(&self, key: Key) -> Option<KeyEntry<Key>> {
+        let state = *self.keys.lock(&key).get(&key)?;
+        let now = Instant::now();
+        let debt = state.tat.saturating_duration_since(now);
+        let used = debt.as_nanos().div_ceil(self.period.as_nanos());
+        Some(KeyEntry {
+            key,
+            remaining: self
+                .burst_size
+                .saturating_sub(u32::try_from(used).unwrap_or(u32::MAX)),
+            last_seen: system_time(state.last_seen),
+            window_start: system_time(state.window_start),
+        })
+    }
+
+    /// Write the keys whose quota isn't full, one per line after a header, as the time until
+    /// their quota is full in nanoseconds followed by the key.
+    pub(crate) fn save<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        Key: Display,
+    {
+        let now = Instant::now();
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        writeln!(writer, "{}", SNAPSHOT_HEADER)?;
+        writeln!(
+            writer,
+            "{} {} {}",
+            taken_at,
+            self.period.as_nanos(),
+            self.burst_size
+        )?;
+        let keys = self
+            .keys
+            .filter_map(|key, state| (state.tat > now).then(|| (key.to_string(), state.tat - now)));
+        for (key, debt) in keys {
+            // the key ends the line, so keys spanning lines can't be restored
+            if !key.contains(['\n', '\r']) {
+                writeln!(writer, "{} {}", debt.as_nanos(), key)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Read a snapshot written by [`save`](Self::save), calling `restore` with every key
+    /// whose quota isn't full yet and the number of elements it has used.
+    pub(crate) fn load<R: BufRead>(
+        &self,
+        reader: R,
+        mut restore: impl FnMut(&Key, NonZeroU32),
+    ) -> Result<usize, SnapshotError>
+    where
+        Key: FromStr,
+    {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != SNAPSHOT_HEADER {
+            return Err(SnapshotError::UnsupportedVersion(header));
+        }
+        let quota = lines.next().transpose()?.unwrap_or_default();
+        let mut fields = quota.split(' ').map(u128::from_str);
+        let (Some(Ok(taken_at)), Some(Ok(period)), Some(Ok(burst_size)), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(SnapshotError::Malformed(2));
+        };
+        if period != self.period.as_nanos() || burst_size != u128::from(self.burst_size) {
+            return Err(SnapshotError::QuotaMismatch);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let elapsed = now.checked_sub(taken_at).ok_or(SnapshotError::ClockSkew)?;
+        let elapsed = Duration::from_millis(elapsed.try_into().unwrap_or(u64::MAX));
+
+        let mut restored = 0;
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let malformed = || SnapshotError::Malformed(i + 3);
+            let (debt, key) = line.split_once(' ').ok_or_else(malformed)?;
+            let debt = debt.parse::<u64>().map_err(|_| malformed())?;
+            let key = key.parse::<Key>().map_err(|_| malformed())?;
+            let Some(debt) = Duration::from_nanos(debt).checked_sub(elapsed) else {
+                continue;
+            };
+            let cells = debt.as_nanos().div_ceil(self.period.as_nanos());
+            let cells = u32::try_from(cells)
+                .unwrap_or(u32::MAX)
+                .min(self.burst_size);
+            if let Some(cells) = NonZeroU32::new(cells) {
+                restore(&key, cells);
+                self.charge(&key, cells);
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+/// Iterator over the state of the tracked keys, see [`GovernorConfig::iter_keys`].
+///
+/// The keys are listed when the iterator is created, their state is read as they are
+/// yielded. Keys purged in between are skipped.
+///
+/// [`GovernorConfig::iter_keys`]: crate::governor::GovernorConfig::iter_keys
+pub struct KeyIter<Key> {
+    states: Option<Arc<KeyStates<Key>>>,
+    keys: vec::IntoIter<Key>,
+}
+
+impl<Key: Hash + Eq + Clone> KeyIter<Key> {
+    pub(crate) fn new(states: Option<Arc<KeyStates<Key>>>) -> Self {
+        let keys = match &states {
+            Some(states) => states.keys.filter_map(|key, _| Some(key.clone())),
+            None => Vec::new(),
+        };
+        Self {
+            states,
+            keys: keys.into_iter(),
+        }
+    }
+}
+
+impl<Key: Hash + Eq + Clone> Iterator for KeyIter<Key> {
+    type Item = KeyEntry<Key>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let states = self.states.as_ref()?;
+        self.keys.find_map(|key| states.entry(key))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.keys.size_hint().1)
+    }
+}
+
+impl<Key: fmt::Debug> fmt::Debug for KeyIter<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyIter")
+            .field("keys", &self.keys.as_slice())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,118 @@
+//! Rate limiting decisions as an async [`Stream`], e.g. for live dashboards.
+//!
+//! Enabled by the `stream` feature. See [`GovernorConfig::decision_stream`].
+//!
+//! Every stream buffers a bounded number of decisions and drops the oldest ones once full, so
+//! slow or stalled consumers never hold up requests nor grow the memory use of the process.
+//!
+//! [`GovernorConfig::decision_stream`]: crate::governor::GovernorConfig::decision_stream
+
+use crate::decision::Decision;
+use futures_core::Stream;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug, Default)]
+struct Buffer {
+    decisions: VecDeque<Decision>,
+    dropped: u64,
+    waker: Option<Waker>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    capacity: usize,
+    buffer: Mutex<Buffer>,
+}
+
+impl Shared {
+    fn push(&self, decision: Decision) {
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.decisions.len() == self.capacity {
+            buffer.decisions.pop_front();
+            buffer.dropped += 1;
+        }
+        buffer.decisions.push_back(decision);
+        if let Some(waker) = buffer.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The subscribers to the decisions of a configuration.
+#[derive(Debug, Default)]
+pub(crate) struct DecisionStreams {
+    // the number of subscribers, so that decisions aren't published when nobody listens
+    live: AtomicUsize,
+    subscribers: Mutex<Vec<Weak<Shared>>>,
+}
+
+impl DecisionStreams {
+    pub(crate) fn subscribe(&self, capacity: usize) -> DecisionStream {
+        let shared = Arc::new(Shared {
+            capacity: capacity.max(1),
+            buffer: Mutex::default(),
+        });
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        subscribers.push(Arc::downgrade(&shared));
+        self.live.store(subscribers.len(), Ordering::Relaxed);
+        DecisionStream { shared }
+    }
+
+    pub(crate) fn publish(&self, decision: Decision) {
+        if self.live.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| match subscriber.upgrade() {
+            Some(shared) => {
+                shared.push(decision);
+                true
+            }
+            None => false,
+        });
+        self.live.store(subscribers.len(), Ordering::Relaxed);
+    }
+}
+
+/// A [`Stream`] of the decisions taken by a configuration, created by
+/// [`GovernorConfig::decision_stream`].
+///
+/// The stream never ends. Dropping it unsubscribes from the decisions.
+///
+/// [`GovernorConfig::decision_stream`]: crate::governor::GovernorConfig::decision_stream
+#[derive(Debug)]
+pub struct DecisionStream {
+    shared: Arc<Shared>,
+}
+
+impl DecisionStream {
+    /// The number of decisions dropped so far because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        let buffer = self.shared.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        buffer.dropped
+    }
+}
+
+impl Stream for DecisionStream {
+    type Item = Decision;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Decision>> {
+        let mut buffer = self.shared.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        match buffer.decisions.pop_front() {
+            Some(decision) => Poll::Ready(Some(decision)),
+            None => {
+                buffer.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
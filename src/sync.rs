@@ -0,0 +1,8 @@
+// The locks of the internals deciding on requests, swapped for those of loom to model them
+// when built with the `loom` feature and `--cfg loom`. The flag must only reach this crate,
+// tokio having loom models of its own: see the `loom` feature in the manifest.
+
+#[cfg(all(feature = "loom", loom))]
+pub(crate) use loom::sync::{Mutex, MutexGuard};
+#[cfg(not(all(feature = "loom", loom)))]
+pub(crate) use std::sync::{Mutex, MutexGuard};
@@ -0,0 +1,94 @@
+//! Rejection pages rendered from [minijinja] templates, see
+//! [`GovernorConfigBuilder::rejection_template`].
+//!
+//! The templates are HTML escaped and given these variables:
+//! - `wait_seconds`: the advertised wait time in seconds,
+//! - `reset_at`: when the client may retry, as seconds since the Unix epoch,
+//! - `contact_url`: the [contact URL](RejectionTemplate::contact_url), if any,
+//! - `method` and `path`: the method and path of the rejected request.
+//!
+//! # Example
+//! ```rust
+//! use tower_governor::{governor::GovernorConfigBuilder, template::RejectionTemplate};
+//!
+//! let template = RejectionTemplate::new(
+//!     "<h1>Slow down</h1><p>Try again in {{ wait_seconds }} seconds.</p>\
+//!      {% if contact_url %}<a href=\"{{ contact_url }}\">Contact us</a>{% endif %}",
+//! )
+//! .unwrap()
+//! .contact_url("https://example.com/support");
+//!
+//! let config = GovernorConfigBuilder::default()
+//!     .rejection_template(template)
+//!     .finish()
+//!     .unwrap();
+//! ```
+//!
+//! [`GovernorConfigBuilder::rejection_template`]: crate::governor::GovernorConfigBuilder::rejection_template
+
+use crate::errors::ConfigError;
+use http::request::Parts;
+use minijinja::{context, Environment};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// The name of the template, its extension turning HTML escaping on.
+const NAME: &str = "rejection.html";
+
+/// A template of the body of the `429 Too Many Requests` responses.
+#[derive(Clone)]
+pub struct RejectionTemplate {
+    env: Arc<Environment<'static>>,
+    contact_url: Option<String>,
+}
+
+impl fmt::Debug for RejectionTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RejectionTemplate")
+            .field("contact_url", &self.contact_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RejectionTemplate {
+    /// Compile the template `source`, failing with [`ConfigError::InvalidTemplate`] if its
+    /// syntax is invalid.
+    pub fn new(source: impl Into<String>) -> Result<Self, ConfigError> {
+        let mut env = Environment::new();
+        env.add_template_owned(NAME, source.into())
+            .map_err(|e| ConfigError::InvalidTemplate(e.to_string()))?;
+        Ok(Self {
+            env: Arc::new(env),
+            contact_url: None,
+        })
+    }
+
+    /// Set the `contact_url` variable, e.g. the support page of the service.
+    pub fn contact_url(mut self, url: impl Into<String>) -> Self {
+        self.contact_url = Some(url.into());
+        self
+    }
+
+    /// The page of a request with `parts` told to wait `wait_time` seconds, falling back to
+    /// the default message if rendering fails.
+    pub fn render(&self, wait_time: u64, parts: &Parts) -> String {
+        let reset_at = (SystemTime::now() + Duration::from_secs(wait_time))
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.env
+            .get_template(NAME)
+            .and_then(|template| {
+                template.render(context! {
+                    wait_seconds => wait_time,
+                    reset_at => reset_at,
+                    contact_url => self.contact_url,
+                    method => parts.method.as_str(),
+                    path => parts.uri.path(),
+                })
+            })
+            .unwrap_or_else(|_| format!("Too Many Requests! Wait for {}s", wait_time))
+    }
+}
@@ -0,0 +1,290 @@
+//! Hooks to deterministically force rate limiting decisions in tests.
+//!
+//! Enabled by the `test-util` feature. This allows integration tests of client backoff logic
+//! to run without exhausting quotas in timing-sensitive loops, [`stress`] checks that a
+//! layer neither loses nor double counts charges under contention, and [`TestClocks`] moves
+//! the time of the policies of a multi-policy stack forward, separately or together.
+
+use crate::governor::GovernorConfig;
+use crate::key_extractor::KeyExtractor;
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    middleware::RateLimitingMiddleware,
+};
+use http::{Request, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    pin::pin,
+    sync::{Arc, Barrier, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+use tower::Service;
+
+/// A decision forced onto the next checks of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forced {
+    /// Allow the request without consulting the limiter.
+    Allow,
+    /// Reject the request as if the key would be allowed again after `wait_time`.
+    Reject { wait_time: Duration },
+}
+
+// Decisions forced per key, along with the number of checks they still apply to.
+pub(crate) struct Injections<Key> {
+    forced: Mutex<HashMap<Key, (Forced, usize)>>,
+}
+
+impl<Key> Default for Injections<Key> {
+    fn default() -> Self {
+        Self {
+            forced: Mutex::default(),
+        }
+    }
+}
+
+impl<Key> fmt::Debug for Injections<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Injections").finish()
+    }
+}
+
+impl<Key: Hash + Eq> Injections<Key> {
+    fn insert(&self, key: Key, forced: Forced, times: usize) {
+        let mut map = self.forced.lock().unwrap_or_else(|e| e.into_inner());
+        if times == 0 {
+            map.remove(&key);
+        } else {
+            map.insert(key, (forced, times));
+        }
+    }
+
+    /// Take the decision forced onto the next check of `key`, if any.
+    pub(crate) fn take(&self, key: &Key) -> Option<Forced> {
+        let mut map = self.forced.lock().unwrap_or_else(|e| e.into_inner());
+        let (forced, times) = map.get_mut(key)?;
+        let forced = *forced;
+        *times -= 1;
+        if *times == 0 {
+            map.remove(key);
+        }
+        Some(forced)
+    }
+}
+
+impl<K: KeyExtractor, M: RateLimitingMiddleware<C::Instant>, C: Clock> GovernorConfig<K, M, C> {
+    /// Force the next `times` checks of `key` to result in `forced`, replacing any decision
+    /// forced before. Forced checks don't consume any quota.
+    ///
+    /// # Example
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tower_governor::{governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor};
+    /// use tower_governor::test_util::Forced;
+    ///
+    /// let config = GovernorConfigBuilder::default()
+    ///     .key_extractor(GlobalKeyExtractor)
+    ///     .finish()
+    ///     .unwrap();
+    /// // the next three requests are rejected, telling clients to retry in 30 seconds
+    /// config.force((), Forced::Reject { wait_time: Duration::from_secs(30) }, 3);
+    /// ```
+    pub fn force(&self, key: K::Key, forced: Forced, times: usize) {
+        self.injections.insert(key, forced, times);
+    }
+
+    /// Stop forcing decisions onto the checks of `key`.
+    pub fn clear_forced(&self, key: K::Key) {
+        self.injections.insert(key, Forced::Allow, 0);
+    }
+}
+
+/// Fake clocks handed to the configurations of a multi-policy stack, e.g. a
+/// [`GovernorStack`](crate::stack::GovernorStack), so that each policy has a time of its own.
+///
+/// Every [`clock`](Self::clock) starts at zero and only moves when advanced, either on its own
+/// or along with all the others with [`advance_all`](Self::advance_all). Clones share the
+/// clocks.
+///
+/// Only the quotas measured by the limiters follow these clocks: those of the configurations,
+/// their [classes](crate::governor::GovernorConfigBuilder::class_quota) and
+/// [windows](crate::governor::GovernorConfigBuilder::window_quota).
+///
+/// # Example
+/// ```rust
+/// use std::{net::IpAddr, time::Duration};
+/// use tower_governor::{governor::GovernorConfigBuilder, test_util::TestClocks};
+///
+/// let clocks = TestClocks::new();
+/// let (burst, sustained) = (clocks.clock(), clocks.clock());
+/// let burst_config = GovernorConfigBuilder::default()
+///     .per_second(1)
+///     .burst_size(1)
+///     .clock(burst.clone())
+///     .finish()
+///     .unwrap();
+/// let sustained_config = GovernorConfigBuilder::default()
+///     .per_second(60)
+///     .burst_size(1)
+///     .clock(sustained)
+///     .finish()
+///     .unwrap();
+/// let ip = IpAddr::from([10, 0, 0, 1]);
+/// assert!(burst_config.check_blocking(&ip, 1).is_allowed());
+/// assert!(sustained_config.check_blocking(&ip, 1).is_allowed());
+///
+/// // only the burst policy replenished
+/// burst.advance(Duration::from_secs(1));
+/// assert!(burst_config.check_blocking(&ip, 1).is_allowed());
+/// assert!(!sustained_config.check_blocking(&ip, 1).is_allowed());
+///
+/// clocks.advance_all(Duration::from_secs(60));
+/// assert!(burst_config.check_blocking(&ip, 1).is_allowed());
+/// assert!(sustained_config.check_blocking(&ip, 1).is_allowed());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TestClocks {
+    clocks: Arc<Mutex<Vec<FakeRelativeClock>>>,
+}
+
+impl TestClocks {
+    /// A group without any clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new clock of the group, starting at zero. Its clones share its time.
+    pub fn clock(&self) -> FakeRelativeClock {
+        let clock = FakeRelativeClock::default();
+        let mut clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        clocks.push(clock.clone());
+        clock
+    }
+
+    /// Move every clock of the group forward by `by`.
+    pub fn advance_all(&self, by: Duration) {
+        let clocks = self.clocks.lock().unwrap_or_else(|e| e.into_inner());
+        for clock in clocks.iter() {
+            clock.advance(by);
+        }
+    }
+
+    /// The number of clocks of the group.
+    pub fn len(&self) -> usize {
+        self.clocks.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Whether the group has no clock.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The responses of a [`stress`] run, by outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StressReport {
+    /// The responses with a success status.
+    pub allowed: u64,
+    /// The `429 Too Many Requests` responses.
+    pub rejected: u64,
+    /// The other responses and the errors of the service.
+    pub other: u64,
+}
+
+/// Send `requests` requests built by `request` from each of `threads` threads, all starting at
+/// once, through a clone of `service` each, counting the responses by outcome. `request` is
+/// given the index of the thread and of the request.
+///
+/// The futures are polled on the threads themselves, so `service` must not need a runtime,
+/// e.g. a [`GovernorLayer`](crate::GovernorLayer) around a `tower::service_fn`. With a clock
+/// that doesn't advance, such as governor's `FakeRelativeClock`, exactly the burst size of
+/// every key is allowed: any other count means a charge was lost or counted twice.
+///
+/// # Example
+/// ```rust
+/// use axum::body::Body;
+/// use governor::clock::FakeRelativeClock;
+/// use http::{Request, Response};
+/// use std::convert::Infallible;
+/// use tower::{service_fn, Layer};
+/// use tower_governor::{
+///     governor::GovernorConfigBuilder, key_extractor::GlobalKeyExtractor, test_util::stress,
+/// };
+///
+/// let layer = GovernorConfigBuilder::default()
+///     .burst_size(100)
+///     .clock(FakeRelativeClock::default())
+///     .key_extractor(GlobalKeyExtractor)
+///     .into_layer()
+///     .unwrap();
+/// let service = layer.layer(service_fn(|_: Request<Body>| async {
+///     Ok::<_, Infallible>(Response::new(Body::empty()))
+/// }));
+///
+/// let report = stress(service, 8, 50, |_, _| Request::new(Body::empty()));
+/// assert_eq!(report.allowed, 100);
+/// assert_eq!(report.rejected, 300);
+/// ```
+pub fn stress<S, B, ResBody, F>(
+    service: S,
+    threads: usize,
+    requests: usize,
+    request: F,
+) -> StressReport
+where
+    S: Service<Request<B>, Response = Response<ResBody>> + Clone + Send,
+    F: Fn(usize, usize) -> Request<B> + Sync,
+{
+    let start = Barrier::new(threads);
+    let reports = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let mut service = service.clone();
+                let (start, request) = (&start, &request);
+                scope.spawn(move || {
+                    let mut report = StressReport::default();
+                    start.wait();
+                    for index in 0..requests {
+                        let status = block_on(std::future::poll_fn(|cx| service.poll_ready(cx)))
+                            .ok()
+                            .and_then(|()| block_on(service.call(request(thread, index))).ok())
+                            .map(|response| response.status());
+                        match status {
+                            Some(status) if status.is_success() => report.allowed += 1,
+                            Some(StatusCode::TOO_MANY_REQUESTS) => report.rejected += 1,
+                            _ => report.other += 1,
+                        }
+                    }
+                    report
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("the stress threads don't panic"))
+            .collect::<Vec<_>>()
+    });
+    reports
+        .into_iter()
+        .fold(StressReport::default(), |total, report| StressReport {
+            allowed: total.allowed + report.allowed,
+            rejected: total.rejected + report.rejected,
+            other: total.other + report.other,
+        })
+}
+
+// Poll `future` on the current thread until it completes.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::yield_now(),
+        }
+    }
+}
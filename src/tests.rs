@@ -3,7 +3,10 @@ use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::{governor::GovernorConfigBuilder, GovernorLayer};
+use crate::{
+    governor::{DefaultInstant, GovernorConfigBuilder},
+    GovernorLayer,
+};
 
 #[tokio::main]
 async fn _main() {
@@ -581,4 +584,6575 @@ mod governor_tests {
             .unwrap();
         assert_eq!(body.as_ref(), b"a custom error string");
     }
+
+    #[tokio::test]
+    async fn test_whitelisted_header() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::{header::HeaderName, Method};
+
+        let build = |header: Option<HeaderName>| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .methods(vec![Method::GET])
+                    .whitelisted_header(header)
+                    .key_extractor(GlobalKeyExtractor)
+                    .use_headers()
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async { "Hello, World!" }).post(|| async { "Hello, Post World!" }),
+                )
+                .layer(GovernorLayer { config })
+        };
+        let post = || {
+            http::Request::builder()
+                .method(Method::POST)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        let res = build(Some(HeaderName::from_static("x-bypass")))
+            .oneshot(post())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("x-bypass").unwrap(), "true");
+        assert!(res.headers().get("x-ratelimit-whitelisted").is_none());
+
+        let res = build(None).oneshot(post()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("x-bypass").is_none());
+        assert!(res.headers().get("x-ratelimit-whitelisted").is_none());
+    }
+
+    #[test]
+    fn test_layer_from_builder() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+
+        assert!(
+            GovernorLayer::try_from_builder(GovernorConfigBuilder::default().burst_size(3)).is_ok()
+        );
+        assert_eq!(
+            GovernorLayer::try_from_builder(GovernorConfigBuilder::default().burst_size(0)).err(),
+            Some(ConfigError::ZeroBurstSize)
+        );
+        assert_eq!(
+            GovernorLayer::try_from_builder(GovernorConfigBuilder::default().per_second(0)).err(),
+            Some(ConfigError::ZeroPeriod)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_wait_time() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .max_wait_time(std::time::Duration::from_secs(5))
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || http::Request::new(body::Body::empty());
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("x-ratelimit-after").unwrap(), "5");
+        assert_eq!(res.headers().get("retry-after").unwrap(), "5");
+    }
+
+    #[tokio::test]
+    async fn test_decide_only() {
+        use crate::decision::Decision;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .decide_only(true)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|req: http::Request<body::Body>| async move {
+                    assert!(req.extensions().get::<Decision>().is_some());
+                    req.headers()["x-ratelimit-decision"]
+                        .to_str()
+                        .unwrap()
+                        .to_owned()
+                }),
+            )
+            .layer(GovernorLayer { config });
+        let req = || http::Request::new(body::Body::empty());
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"allow");
+
+        // Over the quota, but still forwarded to the inner service.
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"deny");
+    }
+
+    #[tokio::test]
+    async fn test_rejection_cache() {
+        use crate::{errors::GovernorError, governor::RejectionCache};
+        use std::time::Duration;
+
+        let error = |wait_time| GovernorError::TooManyRequests {
+            wait_time,
+            wait_duration: Duration::from_secs(wait_time),
+            headers: None,
+        };
+        let body = |res: http::Response<body::Body>| async move {
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        let cache = RejectionCache::new();
+        let first = body(cache.render(error(2))).await;
+        let second = body(cache.render(error(2))).await;
+        // the body is rendered once, then shared
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert_eq!(first, body(error(2).as_response()).await);
+
+        // wait times past the cache are rendered as usual
+        assert_eq!(
+            body(cache.render(error(100))).await,
+            body(error(100).as_response()).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prefetch() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(4)
+                .prefetch(2)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || http::Request::new(body::Body::empty());
+
+        // Prefetching never admits more requests than the burst allows.
+        for _ in 0..4 {
+            let res = app.clone().oneshot(req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        assert!(GovernorConfigBuilder::default()
+            .burst_size(4)
+            .prefetch(8)
+            .finish()
+            .is_none());
+    }
+
+    #[test]
+    fn test_prefetch_expiry() {
+        use crate::prefetch::Prefetch;
+        use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+        use std::num::NonZeroU32;
+        use std::time::Duration;
+
+        let limiter = RateLimiter::keyed(Quota::per_hour(NonZeroU32::new(4).unwrap()));
+        let batch = NonZeroU32::new(2).unwrap();
+
+        // the prefetched cells serve the requests of their key only
+        let prefetch = Prefetch::new(batch, Duration::from_secs(3600));
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &2).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &2).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_err());
+
+        // expired cells are dropped instead of served, as told by the clock of the limiter
+        let clock = FakeRelativeClock::default();
+        let limiter = RateLimiter::dashmap_with_clock(
+            Quota::per_hour(NonZeroU32::new(4).unwrap()),
+            clock.clone(),
+        );
+        let prefetch = Prefetch::new(batch, Duration::from_secs(1));
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        clock.advance(Duration::from_secs(2));
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_ok());
+        assert!(prefetch.check_key(&limiter, &1).is_err());
+    }
+
+    #[test]
+    fn test_config_getters() {
+        use crate::governor::{GovernorConfig, GovernorConfigBuilder};
+        use http::Method;
+        use std::time::Duration;
+
+        let config = GovernorConfigBuilder::default()
+            .per_millisecond(250)
+            .burst_size(7)
+            .methods(vec![Method::POST])
+            .finish()
+            .unwrap();
+        assert_eq!(config.period(), Duration::from_millis(250));
+        assert_eq!(config.burst_size(), 7);
+        assert_eq!(config.quota().burst_size().get(), 7);
+        assert_eq!(config.methods(), Some(&[Method::POST][..]));
+
+        let config = GovernorConfig::default();
+        assert_eq!(config.period(), crate::governor::DEFAULT_PERIOD);
+        assert_eq!(config.burst_size(), crate::governor::DEFAULT_BURST_SIZE);
+        assert_eq!(config.methods(), None);
+    }
+
+    #[tokio::test]
+    async fn test_exempt_ip_ranges() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .exempt_loopback(true)
+                .exempt_private_ranges(true)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |ip: [u8; 4]| {
+            let mut req = http::Request::new(body::Body::empty());
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            req
+        };
+
+        for ip in [[127, 0, 0, 1], [10, 1, 2, 3], [192, 168, 0, 1]] {
+            for _ in 0..3 {
+                let res = app.clone().oneshot(req(ip)).await.unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                assert_eq!(
+                    res.headers().get("x-ratelimit-whitelisted").unwrap(),
+                    "true"
+                );
+            }
+        }
+
+        let res = app.clone().oneshot(req([1, 2, 3, 4])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req([1, 2, 3, 4])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_charge_only_failures() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .charge_only(|response| response.status().is_client_error())
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|req: http::Request<body::Body>| async move {
+                    if req.headers().contains_key("fail") {
+                        StatusCode::UNAUTHORIZED
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .layer(GovernorLayer { config });
+        let req = |fail: bool| {
+            let mut req = http::Request::new(body::Body::empty());
+            if fail {
+                req.headers_mut()
+                    .insert("fail", http::HeaderValue::from_static("1"));
+            }
+            req
+        };
+
+        // Successful requests are never charged, yet still carry the limit of their quota.
+        for _ in 0..5 {
+            let res = app.clone().oneshot(req(false)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["x-ratelimit-limit"], "2");
+        }
+
+        // Two failures use up the burst, the third one can't be charged anymore.
+        for _ in 0..3 {
+            let res = app.clone().oneshot(req(true)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let res = app.oneshot(req(false)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_forced_decisions() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::test_util::Forced;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let req = || http::Request::new(body::Body::empty());
+
+        config.force(
+            (),
+            Forced::Reject {
+                wait_time: Duration::from_secs(30),
+            },
+            2,
+        );
+        for _ in 0..2 {
+            let res = app.clone().oneshot(req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(res.headers().get("retry-after").unwrap(), "30");
+        }
+
+        // Forced allows don't consume the single cell of the burst.
+        config.force((), Forced::Allow, 3);
+        for _ in 0..3 {
+            let res = app.clone().oneshot(req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "audit")]
+    #[tokio::test]
+    async fn test_audit_sink() {
+        use crate::audit::AuditSink;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use tokio::io::AsyncBufReadExt;
+
+        let (writer, reader) = tokio::io::duplex(4096);
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .policy_name("login")
+                .audit_sink(AuditSink::writer(writer))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/login", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        for status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let req = http::Request::get("/login")
+                .body(body::Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), status);
+        }
+
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let allowed: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(allowed["decision"], "allow");
+        assert_eq!(allowed["route"], "/login");
+        assert_eq!(allowed["policy"], "login");
+        assert_eq!(allowed["key"], "()");
+        assert!(allowed["wait_ms"].is_null());
+        assert!(allowed["timestamp"].as_u64().unwrap() > 0);
+
+        let denied: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(denied["decision"], "deny");
+        assert!(denied["wait_ms"].as_u64().unwrap() > 59_000);
+    }
+
+    #[cfg(feature = "audit")]
+    #[tokio::test]
+    async fn test_audit_writer_capacity() {
+        use crate::audit::{AuditSink, GovernorEvent};
+        use crate::decision::Decision;
+        use std::time::SystemTime;
+        use tokio::io::AsyncBufReadExt;
+
+        let (writer, reader) = tokio::io::duplex(4096);
+        let sink = AuditSink::writer_with_capacity(writer, 1);
+        let event = |route: &str| GovernorEvent {
+            timestamp: SystemTime::now(),
+            key: "()".to_owned(),
+            source: None,
+            decision: Decision::Allowed,
+            route: route.to_owned(),
+            policy: None,
+            enforced: true,
+        };
+
+        // the writer task doesn't run before this task yields, so the buffer fills up
+        for route in ["/first", "/second", "/third"] {
+            sink.record(event(route));
+        }
+        assert_eq!(sink.dropped(), 2);
+
+        sink.record(event("/fourth"));
+        sink.close().await;
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let first: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(first["route"], "/first");
+        assert_eq!(sink.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_route_layers() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{GlobalKeyExtractor, Scoped};
+
+        let layer = GovernorLayer::try_from_builder(
+            &mut GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(Scoped::new(GlobalKeyExtractor)),
+        )
+        .unwrap();
+        let app = Router::new()
+            .route(
+                "/login",
+                get(|| async { "login" }).route_layer(layer.scoped("login")),
+            )
+            .route(
+                "/search",
+                get(|| async { "search" }).route_layer(layer.scoped("search")),
+            );
+
+        let call = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::get(uri).body(body::Body::empty()).unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(call("/login").await, StatusCode::OK);
+        assert_eq!(call("/search").await, StatusCode::OK);
+        assert_eq!(call("/login").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call("/search").await, StatusCode::TOO_MANY_REQUESTS);
+
+        // Both scopes live in the same store.
+        assert_eq!(layer.config.limiter().len(), 2);
+    }
+
+    #[test]
+    fn test_namespaces() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{GlobalKeyExtractor, ScopedKey};
+
+        let login = GovernorConfigBuilder::default()
+            .burst_size(1)
+            .key_extractor(GlobalKeyExtractor)
+            .namespace("login")
+            .finish()
+            .unwrap();
+        let search = login.scoped("search");
+        assert_eq!(login.namespace(), "login");
+        assert_eq!(search.namespace(), "search");
+
+        let key = |scope: &str| ScopedKey {
+            scope: scope.into(),
+            key: (),
+        };
+        assert!(login.limiter().check_key(&key("login")).is_ok());
+        assert!(login.limiter().check_key(&key("login")).is_err());
+        assert!(search.limiter().check_key(&key("search")).is_ok());
+
+        // One store for both namespaces.
+        assert!(Arc::ptr_eq(login.limiter(), search.limiter()));
+        assert_eq!(search.limiter().len(), 2);
+    }
+
+    #[test]
+    fn test_quota_validation() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use std::time::Duration;
+
+        let config = GovernorConfigBuilder::default()
+            .sustained_rps(3)
+            .burst(10)
+            .finish()
+            .unwrap();
+        assert_eq!(config.period(), Duration::from_nanos(333_333_333));
+        assert_eq!(config.burst_size(), 10);
+
+        assert_eq!(
+            GovernorConfigBuilder::default()
+                .sustained_rps(0)
+                .try_finish()
+                .unwrap_err(),
+            ConfigError::ZeroPeriod
+        );
+        assert_eq!(
+            GovernorConfigBuilder::default()
+                .per_second(u64::MAX / 1_000_000_000)
+                .burst(u32::MAX)
+                .try_finish()
+                .unwrap_err(),
+            ConfigError::QuotaOverflow
+        );
+        assert!(GovernorConfigBuilder::default()
+            .per_nanosecond(1)
+            .burst(u32::MAX)
+            .try_finish()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_require_explicit_key_extractor() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::PeerIpKeyExtractor;
+
+        let mut builder = GovernorConfigBuilder::default();
+        builder.require_explicit_key_extractor();
+        assert_eq!(
+            builder.try_finish().unwrap_err(),
+            ConfigError::ImplicitKeyExtractor
+        );
+        assert_eq!(
+            builder
+                .use_headers()
+                .namespace("login")
+                .try_finish()
+                .unwrap_err(),
+            ConfigError::ImplicitKeyExtractor
+        );
+        // choosing the peer IP explicitly is fine
+        assert!(builder
+            .key_extractor(PeerIpKeyExtractor)
+            .namespace("login")
+            .try_finish()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_misconfiguration() {
+        use crate::forwarding::Trust;
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .detect_proxy_misconfiguration(3, Duration::from_secs(60))
+                .proxy_fallback(Trust::Private)
+                .exempt_loopback(true)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let req = |peer: [u8; 4], client: &str| {
+            let mut req = http::Request::get("/")
+                .header("x-forwarded-for", client)
+                .body(body::Body::empty())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+            req
+        };
+        let call = |peer, client| {
+            let app = app.clone();
+            async move { app.oneshot(req(peer, client)).await.unwrap().status() }
+        };
+        let (proxy, other_proxy, forger) = ([10, 0, 0, 1], [10, 0, 0, 2], [203, 0, 113, 9]);
+
+        assert_eq!(call(proxy, "1.1.1.1").await, StatusCode::OK);
+        // every client shares the quota of the proxy, even as another proxy interleaves
+        assert_eq!(call(other_proxy, "1.1.1.1").await, StatusCode::OK);
+        assert_eq!(call(proxy, "2.2.2.2").await, StatusCode::TOO_MANY_REQUESTS);
+        assert!(!config.proxy_misconfiguration_detected());
+
+        // the third forwarded request trips the check, keys now come from the headers
+        assert_eq!(call(proxy, "3.3.3.3").await, StatusCode::OK);
+        assert!(config.proxy_misconfiguration_detected());
+        assert_eq!(call(proxy, "2.2.2.2").await, StatusCode::OK);
+        assert_eq!(call(proxy, "2.2.2.2").await, StatusCode::TOO_MANY_REQUESTS);
+        // but the keys forged through the headers are never exempt
+        assert_eq!(call(proxy, "127.0.0.1").await, StatusCode::OK);
+        assert_eq!(
+            call(proxy, "127.0.0.1").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // an untrusted peer is flagged but keeps its own key
+        for _ in 0..3 {
+            call(forger, "4.4.4.4").await;
+        }
+        assert_eq!(call(forger, "5.5.5.5").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_exempt_preflight() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+
+        let app = |builder: &mut GovernorConfigBuilder<GlobalKeyExtractor, _>| {
+            let config = Arc::new(builder.per_second(60).burst_size(1).finish().unwrap());
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config })
+        };
+        let preflight = || {
+            http::Request::options("/")
+                .header("access-control-request-method", "GET")
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        // exempt by default
+        let router = app(&mut GovernorConfigBuilder::default().key_extractor(GlobalKeyExtractor));
+        for _ in 0..3 {
+            let res = router.clone().oneshot(preflight()).await.unwrap();
+            assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        }
+        // plain OPTIONS requests are still charged
+        let options = || {
+            http::Request::options("/")
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        router.clone().oneshot(options()).await.unwrap();
+        let res = router.oneshot(options()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // not exempt once methods are filtered
+        let router = app(GovernorConfigBuilder::default()
+            .key_extractor(GlobalKeyExtractor)
+            .methods(vec![Method::OPTIONS]));
+        router.clone().oneshot(preflight()).await.unwrap();
+        let res = router.oneshot(preflight()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_retry_jitter() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(10)
+                .burst_size(1)
+                .retry_jitter(Duration::from_secs(20))
+                .max_wait_time(Duration::from_secs(15))
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        app.clone()
+            .oneshot(http::Request::new(body::Body::empty()))
+            .await
+            .unwrap();
+        // the jittered wait time stays within the actual one and the maximum
+        for _ in 0..20 {
+            let res = app
+                .clone()
+                .oneshot(http::Request::new(body::Body::empty()))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+            let retry_after: u64 = res.headers()["retry-after"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((9..=15).contains(&retry_after));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_extensions() {
+        use crate::decision::{Decision, RateLimitSnapshot};
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use axum::Extension;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(
+                    |Extension(snapshot): Extension<RateLimitSnapshot>| async move {
+                        snapshot.remaining.unwrap().to_string()
+                    },
+                ),
+            )
+            .layer(GovernorLayer { config });
+
+        let res = app
+            .clone()
+            .oneshot(http::Request::new(body::Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.extensions().get::<RateLimitSnapshot>(),
+            Some(&RateLimitSnapshot {
+                limit: 2,
+                remaining: Some(1),
+                decision: Decision::Allowed,
+            })
+        );
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"1");
+
+        app.clone()
+            .oneshot(http::Request::new(body::Body::empty()))
+            .await
+            .unwrap();
+        let res = app
+            .oneshot(http::Request::new(body::Body::empty()))
+            .await
+            .unwrap();
+        let snapshot = res.extensions().get::<RateLimitSnapshot>().unwrap();
+        assert_eq!(snapshot.remaining, Some(0));
+        assert!(!snapshot.decision.is_allowed());
+    }
+
+    #[tokio::test]
+    async fn test_connection_key_cache() {
+        use crate::connection::ConnectionKeyCache;
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{PeerIpKeyExtractor, SmartIpKeyExtractor};
+        use axum::extract::ConnectInfo;
+
+        // a proxy sends the requests of many clients down each connection
+        assert_eq!(
+            GovernorConfigBuilder::default()
+                .key_extractor(SmartIpKeyExtractor)
+                .cache_connection_keys(true)
+                .try_finish()
+                .unwrap_err(),
+            ConfigError::UnstableConnectionKeys
+        );
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(PeerIpKeyExtractor)
+                .cache_connection_keys(true)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |client: [u8; 4], cache: Option<&ConnectionKeyCache>| {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((client, 1234))));
+            if let Some(cache) = cache {
+                req.extensions_mut().insert(cache.clone());
+            }
+            req
+        };
+
+        let connection = ConnectionKeyCache::new();
+        let res = app
+            .clone()
+            .oneshot(req([1, 1, 1, 1], Some(&connection)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        // the key of the first request of the connection is reused
+        let res = app
+            .clone()
+            .oneshot(req([2, 2, 2, 2], Some(&connection)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let res = app.clone().oneshot(req([2, 2, 2, 2], None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .oneshot(req([3, 3, 3, 3], Some(&ConnectionKeyCache::new())))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_ip_sources() {
+        use crate::key_extractor::{KeyExtractor, SmartIpKeyExtractor, Source};
+        use axum::extract::ConnectInfo;
+
+        let mut req = http::Request::get("/")
+            .header("x-forwarded-for", "1.1.1.1")
+            .header("x-real-ip", "2.2.2.2")
+            .header("forwarded", "for=3.3.3.3")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([4, 4, 4, 4], 1234))));
+
+        let extract = |sources: &[Source]| {
+            SmartIpKeyExtractor::with_sources(sources.iter().copied())
+                .extract(&req)
+                .ok()
+                .map(|ip| ip.to_string())
+        };
+        assert_eq!(
+            extract(&SmartIpKeyExtractor::DEFAULT_SOURCES).as_deref(),
+            Some("1.1.1.1")
+        );
+        assert_eq!(
+            extract(&[Source::Forwarded, Source::XRealIp]).as_deref(),
+            Some("3.3.3.3")
+        );
+        assert_eq!(
+            extract(&[Source::XRealIp, Source::Peer]).as_deref(),
+            Some("2.2.2.2")
+        );
+        assert_eq!(extract(&[Source::Peer]).as_deref(), Some("4.4.4.4"));
+        assert_eq!(extract(&[]), None);
+    }
+
+    #[test]
+    fn test_ip_provenance() {
+        use crate::key_extractor::{
+            IpKey, KeyExtractor, ProvenanceIpKeyExtractor, SmartIpKeyExtractor, Source,
+        };
+        use axum::extract::ConnectInfo;
+
+        let extractor = ProvenanceIpKeyExtractor::default();
+        let mut req = http::Request::get("/")
+            .header("x-real-ip", "2.2.2.2")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([4, 4, 4, 4], 1234))));
+
+        let key = extractor.extract(&req).unwrap();
+        assert_eq!(
+            key,
+            IpKey {
+                ip: [2, 2, 2, 2].into(),
+                source: Source::XRealIp,
+            }
+        );
+        assert!(key.source.is_spoofable());
+        assert_eq!(extractor.key_source(&key), Some(Source::XRealIp));
+
+        let key = SmartIpKeyExtractor::with_sources([Source::Peer])
+            .with_provenance()
+            .extract(&req)
+            .unwrap();
+        assert_eq!(key.source, Source::Peer);
+        assert!(!key.source.is_spoofable());
+    }
+
+    #[tokio::test]
+    async fn test_partition() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::partition::Instances;
+
+        let instances = Instances::new(2);
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(6)
+                .partition(instances.clone())
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || async {
+            app.clone()
+                .oneshot(http::Request::new(body::Body::empty()))
+                .await
+                .unwrap()
+                .status()
+        };
+
+        // half of the burst of 6 for each of the 2 instances
+        for _ in 0..3 {
+            assert_eq!(call().await, StatusCode::OK);
+        }
+        assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+
+        // the cost is capped to the burst size
+        instances.set(10);
+        assert_eq!(instances.get(), 10);
+        assert_eq!(call().await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_config_display() {
+        use crate::governor::{DefaultInstant, GovernorConfigBuilder};
+        use crate::key_extractor::{PeerIpKeyExtractor, SmartIpKeyExtractor};
+        use http::Method;
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(6)
+            .burst_size(10)
+            .key_extractor(SmartIpKeyExtractor)
+            .methods(vec![Method::GET, Method::POST])
+            .use_headers()
+            .finish()
+            .unwrap();
+        assert_eq!(
+            config.to_string(),
+            "10 req / 60s burst=10 extractor=SmartIpKeyExtractor headers=on methods=GET,POST store=0"
+        );
+
+        let config = GovernorConfigBuilder::default()
+            .namespace("login")
+            .policy_name("login")
+            .finish()
+            .unwrap();
+        assert_eq!(
+            config.to_string(),
+            "2 req / 1s burst=8 extractor=Scoped<PeerIpKeyExtractor> headers=off policy=login store=0"
+        );
+        let debug = format!("{:?}", config);
+        assert!(debug.starts_with("GovernorConfig { period: 500ms, burst_size: 8,"));
+
+        // the middlewares of other crates keep their configurations printable
+        #[derive(Debug)]
+        struct Quiet;
+
+        impl governor::middleware::RateLimitingMiddleware<DefaultInstant> for Quiet {
+            type PositiveOutcome = ();
+            type NegativeOutcome = ();
+
+            fn allow<K>(_key: &K, _state: impl Into<governor::middleware::StateSnapshot>) {}
+
+            fn disallow<K>(
+                _key: &K,
+                _state: impl Into<governor::middleware::StateSnapshot>,
+                _start: DefaultInstant,
+            ) {
+            }
+        }
+        let config = GovernorConfigBuilder::<PeerIpKeyExtractor, Quiet>::const_default()
+            .finish()
+            .unwrap();
+        assert!(config.to_string().contains(" headers=unknown "));
+        assert!(format!("{:?}", config).contains("headers: None"));
+
+        // the module paths are dropped by bytes, not by chars
+        mod réseau {
+            use crate::{errors::GovernorError, key_extractor::KeyExtractor};
+
+            #[derive(Clone)]
+            pub struct Pair;
+
+            impl KeyExtractor for Pair {
+                type Key = ();
+
+                #[cfg(feature = "tracing")]
+                fn name(&self) -> &'static str {
+                    "pair"
+                }
+
+                fn extract<T>(&self, _req: &http::Request<T>) -> Result<(), GovernorError> {
+                    Ok(())
+                }
+            }
+        }
+        let config = GovernorConfigBuilder::default()
+            .key_extractor(réseau::Pair)
+            .finish()
+            .unwrap();
+        assert!(config.to_string().contains(" extractor=Pair "));
+    }
+
+    #[test]
+    fn test_const_builder() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::PeerIpKeyExtractor;
+        use ::governor::middleware::NoOpMiddleware;
+        use std::time::Duration;
+
+        const BUILDER: GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>> = {
+            let mut builder = GovernorConfigBuilder::const_default();
+            builder
+                .per_millisecond(250)
+                .burst_size(3)
+                .max_wait_time(Duration::from_secs(1))
+                .exempt_loopback(true);
+            builder
+        };
+        const CHAINED: GovernorConfigBuilder<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>> =
+            GovernorConfigBuilder::const_default()
+                .const_per_millisecond(250)
+                .const_burst_size(3);
+
+        // every use of a const is a fresh copy of the builder
+        let config = { BUILDER }.finish().unwrap();
+        assert_eq!(config.period(), Duration::from_millis(250));
+        assert_eq!(config.burst_size(), 3);
+        assert_eq!({ CHAINED }.finish().unwrap().burst_size(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_custom_clock() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use ::governor::clock::FakeRelativeClock;
+        use std::time::Duration;
+
+        let clock = FakeRelativeClock::default();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .use_headers()
+                .per_second(10)
+                .burst_size(1)
+                .error_handler(|_| http::Response::new("custom".into()))
+                .clock(clock.clone())
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || async {
+            app.clone()
+                .oneshot(http::Request::new(body::Body::empty()))
+                .await
+                .unwrap()
+        };
+
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "1");
+
+        // the error handler set before switching the clock is kept
+        let res = call().await;
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"custom");
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(call().await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_tower_service_fn() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::convert::Infallible;
+        use tower::ServiceBuilder;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let service = ServiceBuilder::new()
+            .layer(GovernorLayer { config })
+            .service_fn(|_: http::Request<String>| async {
+                Ok::<_, Infallible>(http::Response::new(body::Body::from("ok")))
+            });
+
+        let res = service
+            .clone()
+            .oneshot(http::Request::new(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+        let res = service
+            .oneshot(http::Request::new(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_shared_make_service() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::convert::Infallible;
+        use tower::{
+            make::{MakeService, Shared},
+            ServiceBuilder,
+        };
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let service = ServiceBuilder::new()
+            .layer(GovernorLayer { config })
+            .service_fn(|_: http::Request<()>| async {
+                Ok::<_, Infallible>(http::Response::new(body::Body::empty()))
+            });
+        let mut make_service = Shared::new(service);
+
+        // services made for each connection share the limiter
+        // `Shared` is always ready to make services
+        let first = make_service.make_service(()).await.unwrap();
+        let second = make_service.make_service(()).await.unwrap();
+        let res = first.oneshot(http::Request::new(())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = second.oneshot(http::Request::new(())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_hyper_server() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use bytes::Bytes;
+        use http_body_util::Full;
+        use hyper::server::conn::http1;
+        use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+        use std::convert::Infallible;
+        use tower::ServiceBuilder;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(2)
+                .finish()
+                .unwrap(),
+        );
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let service = ServiceBuilder::new()
+                    // the key extractor looks the peer address up in the request extensions
+                    .map_request(move |mut req: http::Request<_>| {
+                        req.extensions_mut().insert(ConnectInfo(peer));
+                        req
+                    })
+                    .layer(GovernorLayer {
+                        config: config.clone(),
+                    })
+                    // the inner service must respond with an axum body
+                    .map_response(|res: http::Response<Full<Bytes>>| res.map(body::Body::new))
+                    .service_fn(|_: http::Request<hyper::body::Incoming>| async {
+                        Ok::<_, Infallible>(http::Response::new(Full::new(Bytes::from("hyper"))))
+                    });
+                tokio::spawn(
+                    http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(service)),
+                );
+            }
+        });
+
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            let res = client.get(&url).send().await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.text().await.unwrap(), "hyper");
+        }
+        let res = client.get(&url).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_inner_error_hook() {
+        use crate::decision::RateLimitSnapshot;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::sync::Mutex;
+        use tower::ServiceBuilder;
+
+        let snapshots = Arc::new(Mutex::new(Vec::<RateLimitSnapshot>::new()));
+        let recorded = snapshots.clone();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(3)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .on_inner_error(move |snapshot| recorded.lock().unwrap().push(*snapshot))
+                .finish()
+                .unwrap(),
+        );
+        let service = ServiceBuilder::new()
+            .layer(GovernorLayer { config })
+            .service_fn(|_: http::Request<()>| async {
+                Err::<http::Response<body::Body>, _>("inner failure")
+            });
+
+        let error = service.oneshot(http::Request::new(())).await.unwrap_err();
+        assert_eq!(error, "inner failure");
+        let snapshots = snapshots.lock().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].limit, 3);
+        assert_eq!(snapshots[0].remaining, Some(2));
+    }
+
+    #[cfg(feature = "grpc")]
+    #[tokio::test]
+    async fn test_grpc_trailers() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use bytes::Bytes;
+        use http::HeaderMap;
+        use http_body_util::{BodyExt, Full};
+        use std::convert::Infallible;
+        use tower::ServiceBuilder;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(3)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .use_trailers(true)
+                .finish()
+                .unwrap(),
+        );
+        let service = ServiceBuilder::new()
+            .layer(GovernorLayer { config })
+            .service_fn(|req: http::Request<()>| async move {
+                let mut status = HeaderMap::new();
+                status.insert("grpc-status", "0".parse().unwrap());
+                let body = Full::new(Bytes::from("message"))
+                    .with_trailers(async { Some(Ok::<_, Infallible>(status)) });
+                let content_type = req.uri().path().trim_start_matches('/').to_owned();
+                Ok::<_, Infallible>(
+                    http::Response::builder()
+                        .header("content-type", content_type)
+                        .body(body::Body::new(body))
+                        .unwrap(),
+                )
+            });
+
+        let res = service
+            .clone()
+            .oneshot(http::Request::get("/application/grpc").body(()).unwrap())
+            .await
+            .unwrap();
+        let collected = res.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["x-ratelimit-limit"], "3");
+        assert_eq!(trailers["x-ratelimit-remaining"], "2");
+
+        // only gRPC responses get the trailers
+        let res = service
+            .oneshot(http::Request::get("/text/plain").body(()).unwrap())
+            .await
+            .unwrap();
+        let collected = res.into_body().collect().await.unwrap();
+        assert!(collected
+            .trailers()
+            .unwrap()
+            .get("x-ratelimit-limit")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_report() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::report::ReportFormat;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .report_window(Duration::from_secs(60))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        for _ in 0..3 {
+            app.clone()
+                .oneshot(http::Request::new(body::Body::empty()))
+                .await
+                .unwrap();
+        }
+
+        let json: serde_json::Value =
+            serde_json::from_str(&config.export_report(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "window_secs": 60,
+                "rejected": 1,
+                "banned": [],
+                "top_talkers": [{ "key": "()", "requests": 3, "rejected": 1 }],
+            })
+        );
+        assert_eq!(
+            config.export_report(ReportFormat::Csv).unwrap(),
+            "kind,key,requests,rejected,banned_for_ms\ntalker,(),3,1,\n"
+        );
+
+        let untracked = GovernorConfigBuilder::default().finish().unwrap();
+        assert_eq!(untracked.export_report(ReportFormat::Json), None);
+    }
+
+    #[tokio::test]
+    async fn test_retain_watermarks() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use ::governor::clock::FakeRelativeClock;
+        use std::time::Duration;
+
+        let clock = FakeRelativeClock::default();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .burst_size(1)
+                .retain_watermarks(3, 1)
+                .key_extractor(SmartIpKeyExtractor)
+                .clock(clock.clone())
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let call = |ip: u8| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .header("x-forwarded-for", format!("10.0.0.{}", ip))
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap();
+            }
+        };
+
+        // reaching the high-water mark while all keys are in use keeps them
+        for ip in 1..=3 {
+            call(ip).await;
+        }
+        assert_eq!(config.limiter().len(), 3);
+
+        // the next cleanup waits for as many keys as between the marks
+        clock.advance(Duration::from_secs(2));
+        call(4).await;
+        assert_eq!(config.limiter().len(), 4);
+        call(5).await;
+        assert_eq!(config.limiter().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retain_class_stores() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use ::governor::clock::FakeRelativeClock;
+        use http::Method;
+        use std::time::Duration;
+
+        let clock = FakeRelativeClock::default();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .burst_size(1)
+                .classify(|method, _, _| (method == Method::POST).then_some("write"))
+                .class_quota("write", Duration::from_secs(1), 1)
+                .retain_watermarks(5, 0)
+                .key_extractor(SmartIpKeyExtractor)
+                .clock(clock.clone())
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { "Hello, World!" }).post(|| async { "Posted" }),
+            )
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let call = |ip: u8, method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .header("x-forwarded-for", format!("10.0.0.{}", ip))
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap();
+            }
+        };
+
+        for ip in 1..=2 {
+            call(ip, Method::GET).await;
+            call(ip, Method::POST).await;
+        }
+        // the keys of the class quota count as much as the others
+        assert_eq!(config.limiter().len(), 2);
+        assert_eq!(config.stored_keys(), 4);
+        clock.advance(Duration::from_secs(2));
+        config.retain_recent();
+        assert_eq!(config.stored_keys(), 0);
+
+        // and are cleaned up with them once past the high-water mark
+        for ip in 1..=2 {
+            call(ip, Method::POST).await;
+        }
+        call(3, Method::GET).await;
+        clock.advance(Duration::from_secs(2));
+        call(4, Method::POST).await;
+        call(5, Method::GET).await;
+        assert_eq!(config.stored_keys(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retain_window_stores() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use ::governor::clock::FakeRelativeClock;
+        use std::time::Duration;
+
+        let clock = FakeRelativeClock::default();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .burst_size(1)
+                .window_quota("minute", Duration::from_secs(1), 2)
+                .key_extractor(SmartIpKeyExtractor)
+                .clock(clock.clone())
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        for ip in 1..=2 {
+            let req = http::Request::builder()
+                .header("x-forwarded-for", format!("10.0.0.{}", ip))
+                .body(body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+        // the keys of the windows are counted and cleaned up along with the others
+        assert_eq!(config.limiter().len(), 2);
+        assert_eq!(config.stored_keys(), 4);
+        clock.advance(Duration::from_secs(3));
+        config.retain_recent();
+        assert_eq!(config.stored_keys(), 0);
+    }
+
+    #[test]
+    fn test_expiring_map_purge() {
+        use crate::expiring::ExpiringMap;
+
+        let count = |map: &ExpiringMap<u32, bool>| map.filter_map(|_, _| Some(())).len();
+        let insert = |map: &ExpiringMap<u32, bool>, key: u32, expired: bool| {
+            let mut shard = map.lock(&key);
+            shard.purge(|expired| *expired);
+            shard.insert(key, expired);
+        };
+
+        // the expired entries are purged as the shards grow
+        let map = ExpiringMap::new();
+        for key in 0..10_000 {
+            insert(&map, key, true);
+        }
+        assert!(count(&map) <= 4096);
+
+        // while the live ones are all kept
+        let map = ExpiringMap::new();
+        for key in 0..10_000 {
+            insert(&map, key, false);
+        }
+        assert_eq!(count(&map), 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_request_classes() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .classify(|method, _, _| (method == Method::POST).then_some("write"))
+                .class_quota("write", Duration::from_secs(60), 2)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { "Hello, World!" }).post(|| async { "Hello, Post World!" }),
+            )
+            .layer(GovernorLayer { config });
+        let call = |method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        // writes have a bucket of their own
+        let res = call(Method::POST).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-class"], "write");
+        assert_eq!(res.headers()["x-ratelimit-limit"], "2");
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "1");
+        assert_eq!(call(Method::POST).await.status(), StatusCode::OK);
+        let res = call(Method::POST).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-class"], "write");
+
+        let res = call(Method::GET).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("x-ratelimit-class").is_none());
+        assert_eq!(res.headers()["x-ratelimit-limit"], "1");
+        assert_eq!(
+            call(Method::GET).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limit_keys_per_peer() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use axum::extract::ConnectInfo;
+        use std::net::{IpAddr, SocketAddr};
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let churned = Arc::new(Mutex::new(Vec::new()));
+        let observed = churned.clone();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(SmartIpKeyExtractor)
+                .limit_keys_per_peer(2, Duration::from_secs(60))
+                .on_key_churn(move |peer| observed.lock().unwrap().push(peer))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = |peer: [u8; 4], forwarded: &'static str| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder()
+                    .header("x-forwarded-for", forwarded)
+                    .body(body::Body::empty())
+                    .unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        // a peer cycling through spoofed addresses is stopped after its second key
+        assert_eq!(call([10, 0, 0, 1], "1.1.1.1").await, StatusCode::OK);
+        assert_eq!(call([10, 0, 0, 1], "2.2.2.2").await, StatusCode::OK);
+        assert_eq!(call([10, 0, 0, 1], "1.1.1.1").await, StatusCode::OK);
+        assert_eq!(
+            call([10, 0, 0, 1], "3.3.3.3").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(*churned.lock().unwrap(), vec![IpAddr::from([10, 0, 0, 1])]);
+
+        // other peers have a budget of their own
+        assert_eq!(call([10, 0, 0, 2], "3.3.3.3").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_message() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .rejection_message(
+                    |wait_time, parts| match parts.headers.get("accept-language") {
+                        Some(lang) if lang == "fr" => format!("Réessayez dans {}s", wait_time),
+                        _ => format!("Retry in {}s", wait_time),
+                    },
+                )
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = |lang: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .header("accept-language", lang)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        assert_eq!(call("en").await.status(), StatusCode::OK);
+        let res = call("fr").await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let wait_time = res.headers()["retry-after"].to_str().unwrap().to_owned();
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, format!("Réessayez dans {}s", wait_time));
+        let body = body::to_bytes(call("en").await.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"Retry in "));
+    }
+
+    #[tokio::test]
+    async fn test_enforce_ratio() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .enforce_ratio(0.5)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = |ip: u8| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder().body(body::Body::empty()).unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, ip], 1234))));
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        let mut enforced = 0;
+        for ip in 0..200 {
+            assert_eq!(call(ip).await, StatusCode::OK);
+            let status = call(ip).await;
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                enforced += 1;
+            } else {
+                assert_eq!(status, StatusCode::OK);
+            }
+            // a key stays in its cohort
+            assert_eq!(call(ip).await, status);
+        }
+        assert!((50..150).contains(&enforced), "{} keys enforced", enforced);
+    }
+
+    #[tokio::test]
+    async fn test_governor_service() {
+        use crate::decision::Decision;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::service::GovernorService;
+        use tower::Service;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let mut service = GovernorService::new(&config);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        // the service and the layer charge the same bucket
+        let decision = service.ready().await.unwrap().call(()).await.unwrap();
+        assert_eq!(decision, Decision::Allowed);
+        let req = http::Request::builder().body(body::Body::empty()).unwrap();
+        assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+        let decision = service.ready().await.unwrap().call(()).await.unwrap();
+        assert!(matches!(decision, Decision::Rejected { wait_time } if wait_time.as_secs() > 0));
+    }
+
+    #[tokio::test]
+    async fn test_head_requests() {
+        use crate::governor::{GovernorConfigBuilder, HeadRequests};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+
+        let app = |head_requests| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(1)
+                    .key_extractor(GlobalKeyExtractor)
+                    .methods(vec![Method::GET])
+                    .head_requests(head_requests)
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config })
+        };
+        let call = |app: &Router, method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        // HEAD isn't one of the configured methods
+        let separate = app(HeadRequests::Separate);
+        assert_eq!(call(&separate, Method::HEAD).await, StatusCode::OK);
+        assert_eq!(call(&separate, Method::HEAD).await, StatusCode::OK);
+
+        let as_get = app(HeadRequests::AsGet);
+        assert_eq!(call(&as_get, Method::HEAD).await, StatusCode::OK);
+        assert_eq!(
+            call(&as_get, Method::GET).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        let exempt = app(HeadRequests::Exempt);
+        assert_eq!(call(&exempt, Method::GET).await, StatusCode::OK);
+        assert_eq!(call(&exempt, Method::HEAD).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_handle_error() {
+        use crate::errors::GovernorError;
+        use crate::handle_error::{into_grpc_response, into_parts, into_response};
+        use http::HeaderMap;
+        use std::time::Duration;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", 3.into());
+        let rejection = || GovernorError::TooManyRequests {
+            wait_time: 3,
+            wait_duration: Duration::from_secs(3),
+            headers: Some(headers.clone()),
+        };
+
+        let (status, parts_headers, body) = into_parts(rejection().into());
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(parts_headers["retry-after"], "3");
+        assert_eq!(body, "Too Many Requests! Wait for 3s");
+
+        let response: http::Response<String> = into_response("backend down".into());
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body(), "Unhandled internal error: backend down");
+
+        let response: http::Response<body::Body> = into_grpc_response(rejection().into());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["grpc-status"], "8");
+        assert_eq!(
+            response.headers()["grpc-message"],
+            "Too Many Requests! Wait for 3s"
+        );
+        assert_eq!(response.headers()["retry-after"], "3");
+    }
+
+    #[tokio::test]
+    async fn test_rejection_hook() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .policy_name("api")
+                .on_rejection(|response, context| {
+                    let headers = response.headers_mut();
+                    if let Some(id) = context.headers.get("x-request-id") {
+                        headers.insert("x-request-id", id.clone());
+                    }
+                    let envelope = format!(
+                        "{} {} {} {}",
+                        context.key.unwrap_or_default(),
+                        context.policy.unwrap_or_default(),
+                        context.route,
+                        context.wait_time.as_secs() > 0
+                    );
+                    *response.body_mut() = body::Body::from(envelope);
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/hello", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder()
+                    .uri("/hello")
+                    .header("x-request-id", "abc")
+                    .body(body::Body::empty())
+                    .unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from(([192, 168, 0, 1], 1234))));
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("x-request-id").is_none());
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-request-id"], "abc");
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"192.*** api /hello true");
+    }
+
+    #[tokio::test]
+    async fn test_rates() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::report::WindowCounts;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .track_rates()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        for _ in 0..5 {
+            let req = http::Request::builder().body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let rates = config.rates().unwrap();
+        let expected = WindowCounts {
+            allowed: 2,
+            rejected: 3,
+        };
+        assert_eq!(rates.one_minute, expected);
+        assert_eq!(rates.five_minutes, expected);
+        assert_eq!(rates.fifteen_minutes, expected);
+        assert_eq!(rates.one_minute.rejection_rate(), 0.6);
+    }
+
+    #[tokio::test]
+    async fn test_config_resolver() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::resolver::{ExtensionResolver, ResolvingGovernorLayer};
+
+        let config = |burst_size| {
+            Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(burst_size)
+                    .key_extractor(GlobalKeyExtractor)
+                    .finish()
+                    .unwrap(),
+            )
+        };
+        let premium = config(3);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(ResolvingGovernorLayer::new(ExtensionResolver::new(config(
+                1,
+            ))));
+        let call = |tenant: Option<_>| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder().body(body::Body::empty()).unwrap();
+                if let Some(config) = tenant {
+                    req.extensions_mut().insert(config);
+                }
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(call(None).await, StatusCode::OK);
+        assert_eq!(call(None).await, StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..3 {
+            assert_eq!(call(Some(premium.clone())).await, StatusCode::OK);
+        }
+        assert_eq!(
+            call(Some(premium.clone())).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[cfg(feature = "async-key")]
+    #[tokio::test]
+    async fn test_async_key_extractor() {
+        use crate::async_key::{
+            AsyncGovernorLayer, AsyncKey, AsyncKeyExtractor, ExtractionFailure,
+        };
+        use crate::errors::GovernorError;
+        use crate::governor::GovernorConfigBuilder;
+        use http::request::Parts;
+        use std::time::Duration;
+
+        #[derive(Clone)]
+        struct TokenExtractor;
+
+        impl AsyncKeyExtractor for TokenExtractor {
+            type Key = String;
+
+            async fn extract(&self, parts: &Parts) -> Result<String, GovernorError> {
+                let token = parts
+                    .headers
+                    .get("authorization")
+                    .and_then(|token| token.to_str().ok())
+                    .ok_or(GovernorError::UnableToExtractKey)?;
+                if token == "slow" {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Ok(format!("customer of {}", token))
+            }
+        }
+
+        let app = |on_failure| {
+            let config = GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(
+                    AsyncKey::new(TokenExtractor)
+                        .timeout(Duration::from_millis(10))
+                        .on_failure(on_failure),
+                )
+                .finish()
+                .unwrap();
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(AsyncGovernorLayer::new(Arc::new(config)))
+        };
+        let call = |app: &Router, token: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .header("authorization", token)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        let reject = app(ExtractionFailure::Reject);
+        assert_eq!(call(&reject, "a").await, StatusCode::OK);
+        assert_eq!(call(&reject, "a").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call(&reject, "b").await, StatusCode::OK);
+        assert_eq!(
+            call(&reject, "slow").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let allow = app(ExtractionFailure::Allow);
+        assert_eq!(call(&allow, "slow").await, StatusCode::OK);
+        assert_eq!(call(&allow, "slow").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cache_extraction_failures() {
+        use crate::errors::GovernorError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::KeyExtractor;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        static EXTRACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct AuthExtractor;
+
+        impl KeyExtractor for AuthExtractor {
+            type Key = String;
+
+            #[cfg(feature = "tracing")]
+            fn name(&self) -> &'static str {
+                "auth"
+            }
+
+            fn extract<T>(&self, req: &http::Request<T>) -> Result<String, GovernorError> {
+                EXTRACTIONS.fetch_add(1, Ordering::Relaxed);
+                req.headers()
+                    .get("authorization")
+                    .and_then(|token| token.to_str().ok())
+                    .map(str::to_owned)
+                    .ok_or(GovernorError::UnableToExtractKey)
+            }
+        }
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .burst_size(2)
+                .key_extractor(AuthExtractor)
+                .cache_extraction_failures(Duration::from_secs(60))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call_for = |peer: [u8; 4], client: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder();
+                if let Some(client) = client {
+                    req = req.header("x-forwarded-for", client);
+                }
+                let mut req = req.body(body::Body::empty()).unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+                app.oneshot(req).await.unwrap()
+            }
+        };
+        let call = |peer: [u8; 4]| call_for(peer, None);
+
+        let status = |res: http::Response<body::Body>| res.status();
+        assert_eq!(
+            status(call([10, 0, 0, 1]).await),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(call([10, 0, 0, 1]).await),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 1);
+        // the cached failure answered as many requests as the burst size
+        let res = call([10, 0, 0, 1]).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 1);
+
+        assert_eq!(
+            status(call([10, 0, 0, 2]).await),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 2);
+
+        // the failures of the clients of a proxy are never cached
+        for _ in 0..3 {
+            assert_eq!(
+                status(call_for([10, 0, 0, 3], Some("1.1.1.1")).await),
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 5);
+    }
+
+    #[tokio::test]
+    async fn test_scope_header() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::SCOPE_HEADER;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .policy_name("api")
+                .classify(|method, _, _| (method == Method::POST).then_some("write"))
+                .class_quota("write", Duration::from_secs(60), 2)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { "Hello, World!" }).post(|| async { "Hello, Post World!" }),
+            )
+            .layer(GovernorLayer { config });
+        let call = |method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let res = call(Method::POST).await;
+        assert_eq!(res.headers()[SCOPE_HEADER], "api/write");
+        assert_eq!(res.headers()["x-ratelimit-limit"], "2");
+        let res = call(Method::GET).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[SCOPE_HEADER], "api");
+        let res = call(Method::GET).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[SCOPE_HEADER], "api");
+        assert_eq!(res.headers()["x-ratelimit-limit"], "1");
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+    }
+
+    #[test]
+    fn test_settings_diff() {
+        use crate::settings::{Finding, GovernorSettings, Severity};
+        use http::Method;
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(1)
+            .burst_size(50)
+            .methods(vec![Method::POST])
+            .finish()
+            .unwrap();
+        let old = config.settings();
+        let mut new = old.clone();
+        new.burst_size = 5;
+        new.methods = None;
+
+        let findings = GovernorSettings::diff(&old, &new);
+        assert_eq!(
+            findings,
+            [
+                Finding::BurstReduced { from: 50, to: 5 },
+                Finding::MethodsFilterRemoved
+            ]
+        );
+        assert!(findings.iter().all(|f| f.severity() == Severity::Risky));
+        assert!(GovernorSettings::diff(&old, &old).is_empty());
+
+        new.burst_size = 0;
+        assert_eq!(new.validate(), [Finding::ZeroBurstSize]);
+        let built = GovernorConfigBuilder::default()
+            .settings(&old)
+            .finish()
+            .unwrap();
+        assert_eq!(built.settings(), old);
+    }
+
+    #[test]
+    fn test_metadata_key_extractor() {
+        use crate::key_extractor::{KeyExtractor, MetadataKeyExtractor};
+        use http::HeaderValue;
+
+        let request = |name: &str, value: &[u8]| {
+            http::Request::builder()
+                .header(name, HeaderValue::from_bytes(value).unwrap())
+                .body(())
+                .unwrap()
+        };
+
+        let api_key = MetadataKeyExtractor::new(HeaderName::from_static("x-api-key"));
+        assert_eq!(
+            *api_key.extract(&request("x-api-key", b"abc")).unwrap(),
+            *b"abc"
+        );
+        assert!(api_key.extract(&request("x-api-key", b"\xffabc")).is_err());
+        assert!(api_key.extract(&request("x-other", b"abc")).is_err());
+
+        let bearer = MetadataKeyExtractor::new(HeaderName::from_static("authorization"))
+            .strip_prefix("Bearer ");
+        let req = request("authorization", b"Bearer token");
+        assert_eq!(*bearer.extract(&req).unwrap(), *b"token");
+        assert!(bearer
+            .extract(&request("authorization", b"Basic token"))
+            .is_err());
+
+        let binary = MetadataKeyExtractor::new(HeaderName::from_static("x-user-bin"));
+        let padded = binary.extract(&request("x-user-bin", b"3q2+7w==")).unwrap();
+        let unpadded = binary.extract(&request("x-user-bin", b"3q2+7w")).unwrap();
+        assert_eq!(*padded, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(padded, unpadded);
+        assert_eq!(binary.key_name(&padded).unwrap(), "deadbeef");
+        assert!(binary.extract(&request("x-user-bin", b"3q2*7w")).is_err());
+    }
+
+    #[cfg(feature = "audit")]
+    #[tokio::test]
+    async fn test_shutdown_flushes_audit_sink() {
+        use crate::audit::AuditSink;
+        use crate::governor::GovernorConfigBuilder;
+        use tokio::io::AsyncReadExt;
+
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .audit_sink(AuditSink::writer(writer))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        for _ in 0..3 {
+            let req = http::Request::get("/")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [127, 0, 0, 1],
+                    1,
+                ))))
+                .body(body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        config.shutdown().await;
+        // the writer was shut down, so the reader reaches the end of the events
+        let mut log = String::new();
+        reader.read_to_string(&mut log).await.unwrap();
+        assert_eq!(log.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_restore_state_snapshot() {
+        use crate::errors::SnapshotError;
+        use crate::governor::GovernorConfigBuilder;
+
+        let build = |burst_size| {
+            Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(burst_size)
+                    .track_state()
+                    .finish()
+                    .unwrap(),
+            )
+        };
+        let call = |config: Arc<_>| async move {
+            let app = Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config });
+            let req = http::Request::get("/")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [10, 0, 0, 1],
+                    1,
+                ))))
+                .body(body::Body::empty())
+                .unwrap();
+            app.oneshot(req).await.unwrap().status()
+        };
+
+        let config = build(2);
+        assert_eq!(call(config.clone()).await, StatusCode::OK);
+        assert_eq!(call(config.clone()).await, StatusCode::OK);
+        let mut snapshot = Vec::new();
+        config.save_state(&mut snapshot).unwrap();
+
+        let restarted = build(2);
+        assert_eq!(restarted.restore_from(snapshot.as_slice()).unwrap(), 1);
+        assert_eq!(call(restarted).await, StatusCode::TOO_MANY_REQUESTS);
+
+        assert!(matches!(
+            build(3).restore_from(snapshot.as_slice()),
+            Err(SnapshotError::QuotaMismatch)
+        ));
+        assert!(matches!(
+            build(2).restore_from(&b"tower-governor-state 0\n"[..]),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ban_escalation() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let bans = Arc::new(AtomicUsize::new(0));
+        let observed = bans.clone();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .ban_above(Duration::from_secs(30), Duration::from_secs(600))
+                .on_ban(move |context| {
+                    assert_eq!(context.wait_time, Duration::from_secs(600));
+                    observed.fetch_add(1, Ordering::Relaxed);
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || async {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap()
+        };
+
+        assert_eq!(call().await.status(), StatusCode::OK);
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.headers()["retry-after"], "600");
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let left: u64 = res.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((599..=600).contains(&left));
+        assert_eq!(bans.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ban_escalation_dry_run() {
+        use crate::decision::Decision;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use axum::Extension;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .ban_above(Duration::from_secs(30), Duration::from_secs(600))
+                .on_ban(|_| panic!("dry runs don't ban"))
+                .decide_only(true)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(decision): Extension<Decision>| async move {
+                    match decision {
+                        Decision::Rejected { wait_time } => wait_time.as_secs().to_string(),
+                        _ => "allowed".to_owned(),
+                    }
+                }),
+            )
+            .layer(GovernorLayer { config });
+        let call = || async {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(call().await, "allowed");
+        // rejected by the quota rather than by a ban, for as long as the quota tells
+        for _ in 0..2 {
+            let wait_time: u64 = std::str::from_utf8(&call().await).unwrap().parse().unwrap();
+            assert!((59..=60).contains(&wait_time));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_iter_keys() {
+        use crate::governor::GovernorConfigBuilder;
+        use std::time::SystemTime;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(5)
+                .track_state()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        for peer in [1, 1, 2] {
+            let req = http::Request::get("/")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [10, 0, 0, peer],
+                    1,
+                ))))
+                .body(body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let mut entries: Vec<_> = config.iter_keys().collect();
+        entries.sort_by_key(|entry| entry.key);
+        let remaining: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.key.to_string(), entry.remaining))
+            .collect();
+        assert_eq!(
+            remaining,
+            [("10.0.0.1".to_owned(), 3), ("10.0.0.2".to_owned(), 4)]
+        );
+        assert!(entries[0].last_seen <= SystemTime::now());
+    }
+
+    #[tokio::test]
+    async fn test_error_handler_presets() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::handlers;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let rejection = |builder: &mut GovernorConfigBuilder<_, _>| {
+            let config = Arc::new(
+                builder
+                    .per_second(60)
+                    .burst_size(1)
+                    .key_extractor(GlobalKeyExtractor)
+                    .use_headers()
+                    .finish()
+                    .unwrap(),
+            );
+            let app = Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config });
+            async move {
+                let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+                app.clone().oneshot(req()).await.unwrap();
+                app.oneshot(req()).await.unwrap()
+            }
+        };
+
+        let res = rejection(GovernorConfigBuilder::default().error_handler(handlers::json())).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+        let wait_time = res.headers()["retry-after"].to_str().unwrap().to_owned();
+        let bytes = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["status"], 429);
+        assert_eq!(json["wait_time"].to_string(), wait_time);
+
+        let res = rejection(
+            GovernorConfigBuilder::default().error_handler(handlers::html("<p>{status}</p>")),
+        )
+        .await;
+        assert!(res.headers().contains_key("retry-after"));
+        let bytes = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(bytes, "<p>429</p>");
+
+        let res = rejection(GovernorConfigBuilder::default().error_handler(handlers::grpc())).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["grpc-status"], "8");
+        assert!(res.headers().contains_key("x-ratelimit-after"));
+    }
+
+    #[tokio::test]
+    async fn test_unkeyed_global_limiter() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .unkeyed()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        for status in [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            assert_eq!(app.clone().oneshot(req).await.unwrap().status(), status);
+        }
+        // the keyed store was bypassed
+        assert!(config.limiter().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_headers() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::UpstreamHeaders;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let remaining = |upstream: UpstreamHeaders, value: &'static str| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(5)
+                    .key_extractor(GlobalKeyExtractor)
+                    .use_headers()
+                    .upstream_headers(upstream)
+                    .finish()
+                    .unwrap(),
+            );
+            let app = Router::new()
+                .route(
+                    "/",
+                    get(move || async move {
+                        (
+                            [
+                                ("x-ratelimit-limit", "100"),
+                                ("x-ratelimit-remaining", value),
+                            ],
+                            "Hello, World!",
+                        )
+                    }),
+                )
+                .layer(GovernorLayer { config });
+            async move {
+                let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                let header = |name| res.headers()[name].to_str().unwrap().to_owned();
+                (header("x-ratelimit-limit"), header("x-ratelimit-remaining"))
+            }
+        };
+
+        let pair = |limit: &str, remaining: &str| (limit.to_owned(), remaining.to_owned());
+        assert_eq!(
+            remaining(UpstreamHeaders::PreferLocal, "1").await,
+            pair("5", "4")
+        );
+        assert_eq!(
+            remaining(UpstreamHeaders::Combine, "1").await,
+            pair("100", "1")
+        );
+        assert_eq!(
+            remaining(UpstreamHeaders::Combine, "50").await,
+            pair("5", "4")
+        );
+        assert_eq!(
+            remaining(UpstreamHeaders::PreferUpstream, "50").await,
+            pair("100", "50")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_disabled() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .retry_after(false)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key("x-ratelimit-after"));
+        assert!(!res.headers().contains_key("retry-after"));
+    }
+
+    #[test]
+    fn test_key_extractor_validation() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{MetadataKeyExtractor, Scoped, SmartIpKeyExtractor};
+        use http::HeaderName;
+
+        let error = GovernorConfigBuilder::default()
+            .key_extractor(SmartIpKeyExtractor::with_sources([]))
+            .try_finish()
+            .unwrap_err();
+        assert!(matches!(error, ConfigError::InvalidKeyExtractor(_)));
+
+        let extractor = MetadataKeyExtractor::new(HeaderName::from_static("x-api-key-bin"))
+            .strip_prefix("Bearer ");
+        let error = GovernorConfigBuilder::default()
+            .key_extractor(Scoped::new(extractor))
+            .try_finish()
+            .unwrap_err();
+        assert!(matches!(error, ConfigError::InvalidKeyExtractor(_)));
+
+        let extractor = MetadataKeyExtractor::new(HeaderName::from_static("authorization"))
+            .strip_prefix("Bearer ");
+        assert!(GovernorConfigBuilder::default()
+            .key_extractor(extractor)
+            .try_finish()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_layer_conversions() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{GlobalKeyExtractor, PeerIpKeyExtractor};
+        use governor::middleware::NoOpMiddleware;
+
+        let layer = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .key_extractor(GlobalKeyExtractor)
+            .into_layer()
+            .unwrap();
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(tower::ServiceBuilder::new().layer(layer));
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let error = GovernorLayer::try_from(GovernorConfigBuilder::default().burst_size(0));
+        assert_eq!(error.err(), Some(ConfigError::ZeroBurstSize));
+
+        let secure = GovernorLayer::<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>>::secure();
+        assert_eq!(secure.config.burst_size(), 2);
+        let default =
+            GovernorLayer::<PeerIpKeyExtractor, NoOpMiddleware<DefaultInstant>>::default();
+        assert_eq!(default.config.burst_size(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_replay_log() {
+        use crate::decision::Decision;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .replay_log(2)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        for _ in 0..3 {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let entries = config.replay().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.key_hash == config.replay_key_hash(&())));
+        assert_eq!(entries[0].decision, Decision::Allowed);
+        assert_eq!(entries[0].remaining, Some(0));
+        assert!(!entries[1].decision.is_allowed());
+        assert_eq!(entries[1].remaining, Some(0));
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_decision_stream() {
+        use crate::decision::Decision;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use futures_core::Stream;
+        use std::{future::poll_fn, pin::Pin};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let mut decisions = config.decision_stream(2);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        for _ in 0..3 {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        // the first decision was dropped to make room for the last ones
+        assert_eq!(decisions.dropped(), 1);
+        for _ in 0..2 {
+            let decision = poll_fn(|cx| Pin::new(&mut decisions).poll_next(cx)).await;
+            assert!(matches!(decision, Some(Decision::Rejected { .. })));
+        }
+
+        let waiting =
+            tokio::spawn(async move { poll_fn(|cx| Pin::new(&mut decisions).poll_next(cx)).await });
+        tokio::task::yield_now().await;
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        app.oneshot(req).await.unwrap();
+        assert!(matches!(
+            waiting.await.unwrap(),
+            Some(Decision::Rejected { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_trusted_edge_marker() {
+        use crate::governor::{EdgeLimited, GovernorConfigBuilder};
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let app = |action| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(1)
+                    .key_extractor(GlobalKeyExtractor)
+                    .trust_edge_marker(action, |value, uri| value == "signed" && uri.path() == "/")
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config })
+        };
+        let req = |marker: &str| {
+            http::Request::get("/")
+                .header("x-edge-limited", marker)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        let bypass = app(EdgeLimited::Bypass);
+        for _ in 0..3 {
+            let res = bypass.clone().oneshot(req("signed")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = bypass.clone().oneshot(req("forged")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = bypass.oneshot(req("forged")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let dry_run = app(EdgeLimited::DryRun);
+        for _ in 0..3 {
+            let res = dry_run.clone().oneshot(req("signed")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = dry_run.oneshot(req("forged")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_credits() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        static COSTED: AtomicUsize = AtomicUsize::new(0);
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .credits(10, Duration::from_secs(60))
+                .refund_cancelled()
+                .request_cost(|request| {
+                    COSTED.fetch_add(1, Ordering::Relaxed);
+                    request
+                        .headers
+                        .get("x-cost")
+                        .and_then(|value| value.to_str().ok()?.parse().ok())
+                        .unwrap_or(1)
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |cost: &str| {
+            http::Request::get("/")
+                .header("x-cost", cost)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        let res = app.clone().oneshot(req("4")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-credits-limit"], "10");
+        assert_eq!(res.headers()["x-credits-remaining"], "6");
+        assert!(!res.headers().contains_key("x-ratelimit-remaining"));
+
+        let res = app.clone().oneshot(req("5")).await.unwrap();
+        assert_eq!(res.headers()["x-credits-remaining"], "1");
+
+        let res = app.clone().oneshot(req("2")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-credits-remaining"], "0");
+
+        let res = app.oneshot(req("0")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-credits-remaining"], "0");
+        // the cost of each request is only asked once
+        assert_eq!(COSTED.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn test_one_liner_layers() {
+        use crate::errors::ConfigError;
+        use std::time::Duration;
+
+        let layer = GovernorLayer::global(2, 1).unwrap();
+        assert_eq!(layer.config.period(), Duration::from_millis(500));
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(layer);
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let layer = GovernorLayer::per_ip(5, 10).unwrap();
+        assert_eq!(layer.config.burst_size(), 10);
+        assert_eq!(layer.config.period(), Duration::from_millis(200));
+        assert!(GovernorLayer::per_smart_ip(5, 10).is_ok());
+        assert_eq!(
+            GovernorLayer::per_ip(0, 10).err(),
+            Some(ConfigError::ZeroPeriod)
+        );
+    }
+
+    #[test]
+    fn test_high_rate_quotas() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = GovernorConfigBuilder::default()
+            .key_extractor(GlobalKeyExtractor)
+            .sustained_rps(1_000_000)
+            .burst_size(1000)
+            .finish()
+            .unwrap();
+        assert_eq!(config.period(), Duration::from_micros(1));
+        let limiter = config.limiter();
+        assert!((0..1000).all(|_| limiter.check_key(&()).is_ok()));
+
+        let config = GovernorConfigBuilder::default()
+            .sustained_rps(1_000_000_000)
+            .finish()
+            .unwrap();
+        assert_eq!(config.period(), Duration::from_nanos(1));
+
+        let config = GovernorConfigBuilder::default()
+            .sustained_rps(600_000_000)
+            .finish()
+            .unwrap();
+        assert_eq!(config.period(), Duration::from_nanos(2));
+        assert_eq!(
+            GovernorConfigBuilder::default()
+                .sustained_rps(2_000_000_000)
+                .try_finish()
+                .err(),
+            Some(ConfigError::UnrepresentableRate(2_000_000_000))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_error_handler() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let mut builder = GovernorConfigBuilder::default();
+        builder
+            .per_second(60)
+            .burst_size(1)
+            .async_error_handler(|mut error| async move {
+                tokio::task::yield_now().await;
+                let mut response = error.as_response::<body::Body>();
+                *response.body_mut() = body::Body::from("slow down");
+                response
+            })
+            .on_rejection(|response, _| {
+                response
+                    .headers_mut()
+                    .insert("x-hooked", "true".parse().unwrap());
+            });
+        let config = Arc::new(builder.key_extractor(GlobalKeyExtractor).finish().unwrap());
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-hooked"], "true");
+        assert!(res.headers().contains_key("x-ratelimit-after"));
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"slow down");
+    }
+
+    #[cfg(feature = "tarpit")]
+    #[tokio::test]
+    async fn test_tarpit() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::{Duration, Instant};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .tarpit(Duration::from_millis(50))
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let start = Instant::now();
+        let res = app.oneshot(req()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_axum_error_handler() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::GovernorError;
+        use axum::Json;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .axum_error_handler(|error| match error {
+                    GovernorError::TooManyRequests {
+                        wait_time, headers, ..
+                    } => (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        headers.unwrap_or_default(),
+                        Json(serde_json::json!({ "retry_after": wait_time })),
+                    ),
+                    _ => (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        http::HeaderMap::new(),
+                        Json(serde_json::json!({})),
+                    ),
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        app.clone().oneshot(req()).await.unwrap();
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert!(res.headers().contains_key("retry-after"));
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(json["retry_after"].as_u64().unwrap() > 0);
+    }
+
+    #[cfg(feature = "std-clock")]
+    #[tokio::test]
+    async fn test_std_clock() {
+        use crate::governor::DefaultClock;
+        use axum::extract::ConnectInfo;
+        use governor::clock::MonotonicClock;
+        use std::any::TypeId;
+
+        assert_eq!(TypeId::of::<DefaultClock>(), TypeId::of::<MonotonicClock>());
+        assert_eq!(
+            TypeId::of::<DefaultInstant>(),
+            TypeId::of::<std::time::Instant>()
+        );
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .finish()
+            .unwrap();
+        let app = Router::new()
+            .route("/", get(|| async { "Hello world!" }))
+            .layer(GovernorLayer::from(config));
+
+        let req = || {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_per_listener_keys() {
+        use crate::key_extractor::{
+            KeyExtractor, ListenerKey, LocalAddr, PerListener, SmartIpKeyExtractor,
+        };
+        use http::uri::Scheme;
+
+        let extractor = PerListener::new(SmartIpKeyExtractor);
+        let extract = |req: http::Request<()>| extractor.extract(&req).unwrap();
+        let ip = "1.2.3.4".parse().unwrap();
+
+        let public = extract(
+            http::Request::get("/")
+                .header("x-forwarded-for", "1.2.3.4")
+                .header("x-forwarded-proto", "https")
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(
+            public,
+            ListenerKey {
+                scheme: Some(Scheme::HTTPS),
+                port: Some(443),
+                key: ip,
+            }
+        );
+        assert_eq!(extractor.key_name(&public).unwrap(), "https:443@1.2.3.4");
+
+        let partner = extract(
+            http::Request::get("/")
+                .header(
+                    "forwarded",
+                    "for=1.2.3.4;proto=https;host=\"api.example.com:8443\"",
+                )
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!(partner.port, Some(8443));
+        assert_ne!(public, partner);
+
+        // the local address of the listener wins over the headers
+        let mut req = http::Request::get("/")
+            .header("x-forwarded-for", "1.2.3.4")
+            .header("x-forwarded-port", "8443")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(LocalAddr(SocketAddr::from(([0, 0, 0, 0], 9000))));
+        assert_eq!(extract(req).port, Some(9000));
+
+        let plain = extract(
+            http::Request::get("/")
+                .header("x-forwarded-for", "1.2.3.4")
+                .header("host", "localhost:3000")
+                .body(())
+                .unwrap(),
+        );
+        assert_eq!((plain.scheme, plain.port), (None, Some(3000)));
+    }
+
+    #[tokio::test]
+    async fn test_route_rates() {
+        use crate::report::UNMATCHED_ROUTE;
+        use axum::extract::ConnectInfo;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .track_route_rates()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/users/{id}", get(|| async { "Hello, World!" }))
+            .fallback(|| async { "Not found" })
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let req = |path: &str| {
+            let mut req = http::Request::get(path).body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+
+        let res = app.clone().oneshot(req("/users/1")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req("/users/2")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        app.oneshot(req("/missing")).await.unwrap();
+
+        let rates = config.route_rates().unwrap();
+        // both users share the label of their route
+        assert_eq!(
+            rates.keys().collect::<Vec<_>>(),
+            ["/users/{id}", UNMATCHED_ROUTE]
+        );
+        let users = rates["/users/{id}"].one_minute;
+        assert_eq!((users.allowed, users.rejected), (1, 1));
+        assert_eq!(rates[UNMATCHED_ROUTE].one_minute.rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_attributes() {
+        use crate::headers::SkipCompression;
+        use axum::extract::ConnectInfo;
+        use http::{header::CACHE_CONTROL, HeaderValue};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .rejection_cache_control(HeaderValue::from_static("no-store"))
+                .skip_rejection_compression(true)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(CACHE_CONTROL).is_none());
+        assert!(res.extensions().get::<SkipCompression>().is_none());
+
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[CACHE_CONTROL], "no-store");
+        assert!(res.extensions().get::<SkipCompression>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_governor_stack() {
+        use crate::headers::{REMAINING_HEADER, SCOPE_HEADER};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::stack::GovernorStack;
+        use axum::extract::ConnectInfo;
+
+        let global = GovernorConfigBuilder::default()
+            .key_extractor(GlobalKeyExtractor)
+            .per_second(60)
+            .burst_size(3)
+            .policy_name("global")
+            .use_headers()
+            .finish()
+            .unwrap();
+        let per_ip = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(2)
+            .policy_name("per-ip")
+            .use_headers()
+            .finish()
+            .unwrap();
+        let stack = GovernorStack::new().push(global).push(per_ip);
+        assert_eq!(stack.len(), 2);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(stack);
+        let req = |ip: [u8; 4]| {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            req
+        };
+
+        // the headers are those of the policy closest to rejecting the key
+        let res = app.clone().oneshot(req([1, 1, 1, 1])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[SCOPE_HEADER], "per-ip");
+        assert_eq!(res.headers()[REMAINING_HEADER], "1");
+        assert_eq!(res.headers().get_all(REMAINING_HEADER).iter().count(), 1);
+
+        let res = app.clone().oneshot(req([1, 1, 1, 1])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[REMAINING_HEADER], "0");
+
+        let res = app.clone().oneshot(req([1, 1, 1, 1])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[SCOPE_HEADER], "per-ip");
+
+        // the global ceiling was reached by the previous requests
+        let res = app.oneshot(req([2, 2, 2, 2])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[SCOPE_HEADER], "global");
+    }
+
+    #[tokio::test]
+    async fn test_penalize_violations() {
+        use crate::headers::AFTER_HEADER;
+        use axum::extract::ConnectInfo;
+        use std::time::Duration;
+
+        let app = |cap| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .period(Duration::from_secs(10))
+                    .burst_size(1)
+                    .penalize_violations(2, cap)
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config })
+        };
+        let after = |app: Router| async move {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            let res = app.oneshot(req).await.unwrap();
+            res.headers()
+                .get(AFTER_HEADER)
+                .map(|value| value.to_str().unwrap().parse::<u64>().unwrap())
+        };
+
+        let capped = app(Duration::from_secs(60));
+        assert_eq!(after(capped.clone()).await, None);
+        // every consecutive rejection doubles the wait time, up to the cap
+        let mut waits = [0; 4];
+        for wait in &mut waits {
+            *wait = after(capped.clone()).await.unwrap();
+        }
+        assert!((9..=10).contains(&waits[0]), "{:?}", waits);
+        assert!((19..=20).contains(&waits[1]), "{:?}", waits);
+        assert!((39..=40).contains(&waits[2]), "{:?}", waits);
+        assert_eq!(waits[3], 60);
+
+        // an unbounded cap keeps doubling
+        let unbounded = app(Duration::MAX);
+        assert_eq!(after(unbounded.clone()).await, None);
+        after(unbounded.clone()).await;
+        let wait = after(unbounded).await.unwrap();
+        assert!((19..=20).contains(&wait), "{}", wait);
+    }
+
+    #[test]
+    fn test_client_ip_resolver() {
+        use crate::forwarding::{ClientIpResolver, Position, Source, Trust};
+        use crate::key_extractor::{KeyExtractor, SmartIpKeyExtractor};
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let req = |peer: [u8; 4]| {
+            let mut req = http::Request::get("/")
+                .header("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")
+                .header("forwarded", "for=6.6.6.6, for=5.6.7.8")
+                .body(())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+            req
+        };
+        let ip = |ip: [u8; 4]| Some(IpAddr::from(ip));
+
+        // the default matches the SmartIpKeyExtractor
+        let resolver = ClientIpResolver::default();
+        assert_eq!(resolver.resolve(&req([10, 0, 0, 1])), ip([6, 6, 6, 6]));
+
+        let resolver = resolver.position(Position::Rightmost);
+        assert_eq!(resolver.resolve(&req([10, 0, 0, 1])), ip([10, 0, 0, 2]));
+
+        // the trusted proxies are skipped, and only trusted peers may set the headers
+        let resolver = resolver.trust(Trust::Private);
+        assert_eq!(
+            resolver.resolve_with_source(&req([10, 0, 0, 1])),
+            Some((IpAddr::from([1, 2, 3, 4]), Source::XForwardedFor))
+        );
+        assert_eq!(
+            resolver.resolve_with_source(&req([9, 9, 9, 9])),
+            Some((IpAddr::from([9, 9, 9, 9]), Source::Peer))
+        );
+
+        let resolver = ClientIpResolver::new([Source::Forwarded])
+            .rightmost()
+            .trust(Trust::Peers(vec![IpAddr::from([10, 0, 0, 1])]));
+        assert_eq!(resolver.resolve(&req([10, 0, 0, 1])), ip([5, 6, 7, 8]));
+        assert_eq!(resolver.resolve(&req([10, 0, 0, 3])), None);
+
+        let extractor = SmartIpKeyExtractor::with_resolver(
+            ClientIpResolver::default()
+                .rightmost()
+                .trust(Trust::Private),
+        );
+        assert_eq!(
+            extractor.extract(&req([10, 0, 0, 1])).ok(),
+            ip([1, 2, 3, 4])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_methods_header() {
+        use crate::headers::METHODS_HEADER;
+        use axum::{extract::ConnectInfo, routing::post};
+        use http::Method;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .methods(vec![Method::POST, Method::DELETE])
+                .methods_header(Some(METHODS_HEADER))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", post(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || {
+            let mut req = http::Request::post("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get(METHODS_HEADER).is_none());
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[METHODS_HEADER], "POST, DELETE");
+    }
+
+    #[tokio::test]
+    async fn test_bare_responses() {
+        use crate::headers::AFTER_HEADER;
+        use axum::extract::ConnectInfo;
+        use http::header::RETRY_AFTER;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .bare_responses()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .keys()
+            .any(|name| name.as_str().starts_with("x-ratelimit")));
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().get(AFTER_HEADER).is_none());
+        assert!(res.headers().get(RETRY_AFTER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pre_extracted_key() {
+        use crate::key_extractor::PreExtractedKey;
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |key: Option<[u8; 4]>| {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            if let Some(key) = key {
+                req.extensions_mut()
+                    .insert(PreExtractedKey(IpAddr::from(key)));
+            }
+            req
+        };
+
+        let res = app.clone().oneshot(req(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // the pre-extracted key wins over the peer address
+        let res = app.clone().oneshot(req(Some([1, 2, 3, 4]))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req(Some([1, 2, 3, 4]))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_check_blocking() {
+        use crate::decision::Decision;
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(3)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let req = || {
+            let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        // a synchronous worker shares the bucket of the layer
+        let worker = {
+            let config = config.clone();
+            std::thread::spawn(move || config.check_blocking(&ip, 2))
+        };
+        assert!(worker.join().unwrap().is_allowed());
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        match config.check_blocking(&ip, 0) {
+            Decision::Rejected { wait_time } => assert!(!wait_time.is_zero()),
+            Decision::Allowed => panic!("the bucket is empty"),
+        }
+    }
+
+    #[test]
+    fn test_key_interner() {
+        use crate::key_extractor::{KeyExtractor, KeyInterner, MetadataKeyExtractor};
+
+        let interner = KeyInterner::<[u8]>::new(2);
+        let extractor = MetadataKeyExtractor::new(HeaderName::from_static("x-api-key"))
+            .interner(interner.clone());
+        let extract = |key: &str| {
+            let req = http::Request::get("/")
+                .header("x-api-key", key)
+                .body(())
+                .unwrap();
+            extractor.extract(&req).unwrap()
+        };
+
+        let first = extract("first");
+        assert!(Arc::ptr_eq(&first, &extract("first")));
+        let second = extract("second");
+        assert_eq!(*second, *b"second");
+        assert_eq!(interner.len(), 2);
+
+        // over the capacity, the keys are still extracted but no longer shared
+        let third = extract("third");
+        assert_eq!(*third, *b"third");
+        assert!(!Arc::ptr_eq(&third, &extract("third")));
+        assert_eq!(interner.len(), 2);
+
+        // until a key is no longer used and evicted
+        drop(second);
+        extract("third");
+        let third = extract("third");
+        assert!(Arc::ptr_eq(&third, &extract("third")));
+        assert_eq!(interner.len(), 2);
+
+        // the size of the values is bounded too
+        let interner = KeyInterner::<[u8]>::new(100).max_bytes(8);
+        let long = interner.intern(b"0123456789");
+        assert!(!Arc::ptr_eq(&long, &interner.intern(b"0123456789")));
+        assert!(interner.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_interned_keys() {
+        use crate::errors::GovernorError;
+        use crate::key_extractor::{Interned, KeyExtractor};
+
+        #[derive(Clone)]
+        struct SessionId;
+
+        impl KeyExtractor for SessionId {
+            type Key = String;
+
+            #[cfg(feature = "tracing")]
+            fn name(&self) -> &'static str {
+                "session id"
+            }
+
+            fn extract<T>(&self, req: &http::Request<T>) -> Result<Self::Key, GovernorError> {
+                req.headers()
+                    .get("x-session")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+                    .ok_or(GovernorError::UnableToExtractKey)
+            }
+        }
+
+        let extractor = Interned::new(SessionId);
+        let req = |session: &str| {
+            http::Request::get("/")
+                .header("x-session", session)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        let first = extractor.extract(&req("abc")).unwrap();
+        let second = extractor.extract(&req("abc")).unwrap();
+        assert!(Arc::ptr_eq(&first.0, &second.0));
+        assert_eq!(extractor.key_name(&first).unwrap(), "abc");
+        assert_eq!(extractor.interner().len(), 1);
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(extractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let res = app.clone().oneshot(req("abc")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req("abc")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = app.oneshot(req("def")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .per_second(60)
+                .burst_size(1)
+                .circuit_breaker(0.5, 0.1, Duration::from_millis(200))
+                .finish()
+                .unwrap(),
+        );
+        let failing = Arc::new(AtomicBool::new(false));
+        let app = Router::new()
+            .route(
+                "/",
+                get({
+                    let failing = failing.clone();
+                    move || async move {
+                        match failing.load(Ordering::Relaxed) {
+                            true => StatusCode::INTERNAL_SERVER_ERROR,
+                            false => StatusCode::OK,
+                        }
+                    }
+                }),
+            )
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let status = || async {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap().status()
+        };
+
+        // healthy, the quota doesn't apply
+        for _ in 0..5 {
+            assert_eq!(status().await, StatusCode::OK);
+        }
+        assert!(!config.circuit_breaker_engaged());
+
+        // engaged at the 20th response, 15 of which failed
+        failing.store(true, Ordering::Relaxed);
+        for _ in 0..15 {
+            assert!(!config.circuit_breaker_engaged());
+            assert_eq!(status().await, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        assert!(config.circuit_breaker_engaged());
+        assert_eq!(status().await, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status().await, StatusCode::TOO_MANY_REQUESTS);
+
+        // the window holding the failure keeps the breaker engaged, the next one releases it
+        failing.store(false, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(status().await, StatusCode::TOO_MANY_REQUESTS);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(!config.circuit_breaker_engaged());
+        assert_eq!(status().await, StatusCode::OK);
+        assert_eq!(status().await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_bans() {
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .per_second(60)
+                .burst_size(1)
+                .circuit_breaker(0.5, 0.1, Duration::from_millis(200))
+                .ban_list()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let status = || async {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap().status()
+        };
+
+        // the disengaged breaker lifts the quota, not the bans
+        assert!(config.ban((), Duration::from_secs(60)));
+        assert!(!config.circuit_breaker_engaged());
+        assert_eq!(status().await, StatusCode::FORBIDDEN);
+        assert!(config.unban(&()));
+        for _ in 0..3 {
+            assert_eq!(status().await, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_near_limit_keys() {
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+        use std::time::Duration;
+
+        // 10 requests per window: a burst of 5, and 5 replenished over the 5 minutes
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(5)
+                .report_window(Duration::from_secs(300))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let send = |ip: [u8; 4], count: usize| {
+            let app = app.clone();
+            async move {
+                for _ in 0..count {
+                    let mut req = http::Request::get("/").body(body::Body::empty()).unwrap();
+                    req.extensions_mut()
+                        .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+                    app.clone().oneshot(req).await.unwrap();
+                }
+            }
+        };
+        send([10, 0, 0, 1], 2).await;
+        send([10, 0, 0, 2], 4).await;
+        send([10, 0, 0, 3], 6).await;
+
+        let near = config.near_limit_keys(0.4).unwrap();
+        let keys: Vec<IpAddr> = near.iter().map(|near| near.key).collect();
+        assert_eq!(
+            keys,
+            [IpAddr::from([10, 0, 0, 3]), IpAddr::from([10, 0, 0, 2])]
+        );
+        assert_eq!(near[0].capacity, 10);
+        assert_eq!(near[0].rejected, 1);
+        assert_eq!(near[1].usage(), 0.4);
+
+        let unreported = GovernorConfigBuilder::default().finish().unwrap();
+        assert!(unreported.near_limit_keys(0.8).is_none());
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses() {
+        use crate::forwarding::{ClientIpResolver, Source, Trust};
+        use crate::key_extractor::{KeyExtractor, PeerIpKeyExtractor, SmartIpKeyExtractor};
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let ip = IpAddr::from([1, 2, 3, 4]);
+        let forwarded = |header: &str, value: &str| {
+            http::Request::get("/")
+                .header(header, value)
+                .body(())
+                .unwrap()
+        };
+
+        // proxies emitting either form
+        for req in [
+            forwarded("x-forwarded-for", "1.2.3.4"),
+            forwarded("x-forwarded-for", "::ffff:1.2.3.4"),
+            forwarded("x-real-ip", "::ffff:1.2.3.4"),
+            forwarded("forwarded", "for=\"[::ffff:1.2.3.4]:4000\""),
+        ] {
+            assert_eq!(SmartIpKeyExtractor.extract(&req).unwrap(), ip);
+        }
+
+        // a dual-stack listener
+        let mut req = http::Request::get("/").body(()).unwrap();
+        let mapped: IpAddr = "::ffff:1.2.3.4".parse().unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(mapped, 4000)));
+        assert_eq!(PeerIpKeyExtractor.extract(&req).unwrap(), ip);
+
+        let raw = ClientIpResolver::new([Source::Peer]).canonical(false);
+        assert_eq!(raw.resolve(&req), Some(mapped));
+
+        // mapped proxies are trusted like their IPv4 form
+        let req = {
+            let mut req = forwarded("x-forwarded-for", "6.6.6.6");
+            let proxy: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(proxy, 4000)));
+            req
+        };
+        let private = ClientIpResolver::default().trust(Trust::Private);
+        assert_eq!(private.resolve(&req), Some(IpAddr::from([6, 6, 6, 6])));
+        let peers =
+            ClientIpResolver::default().trust(Trust::Peers(vec![IpAddr::from([10, 0, 0, 1])]));
+        assert_eq!(peers.resolve(&req), Some(IpAddr::from([6, 6, 6, 6])));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_elements() {
+        use crate::forwarding::{forwarded_elements, ForwardedElement, Node};
+        use http::uri::Scheme;
+        use std::time::Duration;
+
+        let req = http::Request::get("/")
+            .header(
+                "forwarded",
+                "for=1.2.3.4;by=_edge1;proto=http;host=example.com, for=unknown",
+            )
+            .header("forwarded", "for=\"[2001:db8::1]:4711\";by=10.0.0.1")
+            .body(())
+            .unwrap();
+        let elements = forwarded_elements(req.headers());
+        assert_eq!(
+            elements,
+            [
+                ForwardedElement {
+                    for_node: Some(Node::Ip([1, 2, 3, 4].into())),
+                    by: Some(Node::Obfuscated("_edge1".to_owned())),
+                    proto: Some(Scheme::HTTP),
+                    host: Some("example.com".to_owned()),
+                },
+                ForwardedElement {
+                    for_node: Some(Node::Unknown),
+                    ..Default::default()
+                },
+                ForwardedElement {
+                    for_node: Some(Node::Addr("[2001:db8::1]:4711".parse().unwrap())),
+                    by: Some(Node::Ip([10, 0, 0, 1].into())),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        // stricter limits for plain-HTTP traffic
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(crate::key_extractor::GlobalKeyExtractor)
+                .per_second(60)
+                .burst_size(10)
+                .classify(|_, _, headers| {
+                    let edge = forwarded_elements(headers).into_iter().next()?;
+                    (edge.proto == Some(Scheme::HTTP)).then_some("plain-http")
+                })
+                .class_quota("plain-http", Duration::from_secs(60), 1)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |proto: &str| {
+            http::Request::get("/")
+                .header("forwarded", format!("for=1.2.3.4;proto={}", proto))
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        let res = app.clone().oneshot(req("http")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req("http")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = app.oneshot(req("https")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_peer_sources() {
+        use crate::forwarding::{peer_ip, ClientIpResolver, PeerSource, Source};
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let mut req = http::Request::get("/").body(()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        req.extensions_mut()
+            .insert(SocketAddr::from(([10, 0, 0, 2], 4000)));
+
+        assert_eq!(peer_ip(&req), Some(IpAddr::from([10, 0, 0, 1])));
+        let resolver = ClientIpResolver::new([Source::Peer])
+            .peer_sources([PeerSource::SocketAddr, PeerSource::ConnectInfo]);
+        assert_eq!(resolver.resolve(&req), Some(IpAddr::from([10, 0, 0, 2])));
+
+        // a bare `SocketAddr` is enough with the axum feature too
+        let mut req = http::Request::get("/").body(()).unwrap();
+        req.extensions_mut()
+            .insert(SocketAddr::from(([10, 0, 0, 2], 4000)));
+        assert_eq!(peer_ip(&req), Some(IpAddr::from([10, 0, 0, 2])));
+    }
+
+    #[cfg(feature = "hyper-util")]
+    #[tokio::test]
+    async fn test_http_info_peer() {
+        use crate::forwarding::peer_ip;
+        use hyper_util::client::legacy::connect::{Connection, HttpConnector};
+        use tower::Service;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let uri: http::Uri = format!("http://{}", addr).parse().unwrap();
+        let stream = HttpConnector::new().call(uri).await.unwrap();
+
+        let mut req = http::Request::get("/").body(()).unwrap();
+        stream.connected().get_extras(req.extensions_mut());
+        assert_eq!(peer_ip(&req), Some(addr.ip()));
+    }
+
+    #[tokio::test]
+    async fn test_shared_budget() {
+        use crate::key_extractor::{MetadataKeyExtractor, Normalized};
+        use http::HeaderName;
+
+        let rest = GovernorConfigBuilder::default()
+            .key_extractor(Normalized::new(
+                MetadataKeyExtractor::new(HeaderName::from_static("x-api-key")),
+                |key: Arc<[u8]>| String::from_utf8_lossy(&key).to_ascii_lowercase(),
+            ))
+            .burst_size(2)
+            .per_second(60)
+            .finish()
+            .unwrap();
+        let grpc = rest
+            .with_key_extractor(Normalized::new(
+                MetadataKeyExtractor::new(HeaderName::from_static("api-key-bin")),
+                |key: Arc<[u8]>| key.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            ))
+            .unwrap();
+        let rest = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer::from(Arc::new(rest)));
+        let grpc = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer::from(Arc::new(grpc)));
+        let req = |name: &str, value: &str| {
+            http::Request::get("/")
+                .header(name, value)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        // 0xcafe, hex encoded over REST and base64 encoded over gRPC
+        let res = rest
+            .clone()
+            .oneshot(req("x-api-key", "CAFE"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = grpc
+            .clone()
+            .oneshot(req("api-key-bin", "yv4="))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = rest.oneshot(req("x-api-key", "cafe")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let res = grpc
+            .clone()
+            .oneshot(req("api-key-bin", "yv4"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        // another client has its own quota
+        let res = grpc.oneshot(req("api-key-bin", "yv8=")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_wait_duration() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::GovernorError;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_millisecond(500)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .error_handler(|error| match error {
+                    GovernorError::TooManyRequests {
+                        wait_time,
+                        wait_duration,
+                        ..
+                    } => {
+                        assert_eq!(wait_time, wait_duration.as_secs());
+                        assert!(wait_duration > Duration::from_millis(400));
+                        assert!(wait_duration <= Duration::from_millis(500));
+                        let mut response = http::Response::new(body::Body::empty());
+                        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                        response
+                    }
+                    mut error => error.as_response(),
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(feature = "tarpit")]
+    #[tokio::test]
+    async fn test_tarpit_capped_at_wait_time() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::{Duration, Instant};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_millisecond(20)
+                .burst_size(1)
+                .tarpit(Duration::from_secs(5))
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let start = Instant::now();
+        let res = app.oneshot(req()).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[cfg(all(feature = "http-body-util", feature = "tonic"))]
+    #[tokio::test]
+    async fn test_rejection_bodies() {
+        use crate::errors::GovernorError;
+        use crate::handle_error::into_response;
+        use bytes::Bytes;
+        use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
+        use std::convert::Infallible;
+        use std::time::Duration;
+
+        let rejection = || GovernorError::TooManyRequests {
+            wait_time: 3,
+            wait_duration: Duration::from_secs(3),
+            headers: None,
+        };
+        let expected = Bytes::from_static(b"Too Many Requests! Wait for 3s");
+
+        let response = rejection().as_response::<Full<Bytes>>();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected);
+
+        let response: http::Response<UnsyncBoxBody<Bytes, Infallible>> =
+            into_response(rejection().into());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected);
+
+        let response = rejection().as_response::<tonic::body::Body>();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_limit_on_rejections() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(3)
+                .limit_on_rejections(true)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        for _ in 0..3 {
+            let res = app.clone().oneshot(req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(!res.headers().contains_key("x-ratelimit-limit"));
+        }
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "3");
+        assert!(res.headers().contains_key("x-ratelimit-after"));
+        assert!(!res.headers().contains_key("x-ratelimit-remaining"));
+    }
+
+    #[tokio::test]
+    async fn test_suggest_quota() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .analyze_traffic()
+                .decide_only(true)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        assert_eq!(config.suggest_quota(0.0), None);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        for _ in 0..10 {
+            let res = app.clone().oneshot(req()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let suggestion = config.suggest_quota(0.0).unwrap();
+        assert_eq!(suggestion.keys, 1);
+        assert_eq!(suggestion.requests, 10);
+        assert_eq!(suggestion.rejection_rate, 0.0);
+        assert!(suggestion.burst_size >= 1);
+        let looser = config.suggest_quota(0.5).unwrap();
+        assert!(looser.rejection_rate <= 0.5);
+
+        let config = GovernorConfigBuilder::default().finish().unwrap();
+        assert_eq!(config.suggest_quota(0.0), None);
+    }
+
+    #[test]
+    fn test_header_limits() {
+        use crate::forwarding::{forwarded_elements, ClientIpResolver, HeaderLimits, Source};
+        use crate::key_extractor::{KeyExtractor, SmartIpKeyExtractor};
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        let req = |name: &str, value: String| {
+            let mut req = http::Request::get("/")
+                .header(name, value)
+                .body(())
+                .unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        };
+        let chain = |entries: usize| vec!["1.2.3.4"; entries].join(", ");
+
+        // within the limits
+        let extractor = SmartIpKeyExtractor;
+        let ok = req("x-forwarded-for", chain(64));
+        assert_eq!(extractor.extract(&ok).unwrap(), IpAddr::from([1, 2, 3, 4]));
+        // too many entries, or too long, the headers are skipped
+        let many = req("x-forwarded-for", chain(65));
+        assert_eq!(extractor.extract(&many).unwrap(), peer.ip());
+        let long = req("x-real-ip", format!("1.2.3.4{}", " ".repeat(5000)));
+        assert_eq!(extractor.extract(&long).unwrap(), peer.ip());
+        let forwarded = req("forwarded", vec!["for=1.2.3.4"; 65].join(", "));
+        assert!(forwarded_elements(forwarded.headers()).is_empty());
+        assert_eq!(extractor.extract(&forwarded).unwrap(), peer.ip());
+
+        // or the extraction fails
+        let limits = HeaderLimits {
+            max_entries: 8,
+            reject: true,
+            ..HeaderLimits::DEFAULT
+        };
+        let resolver = ClientIpResolver::new([Source::XForwardedFor, Source::Peer]).limits(limits);
+        let extractor = SmartIpKeyExtractor::with_resolver(resolver);
+        let ok = req("x-forwarded-for", chain(8));
+        assert_eq!(extractor.extract(&ok).unwrap(), IpAddr::from([1, 2, 3, 4]));
+        let many = req("x-forwarded-for", chain(9));
+        assert!(extractor.extract(&many).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fair_share() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(100)
+                .classify(
+                    |_, _, headers| match headers.contains_key("x-partner-key") {
+                        true => Some("partner"),
+                        false => Some("public"),
+                    },
+                )
+                .fair_share(Duration::from_secs(60), 10, [("partner", 7), ("public", 3)])
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let public = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let partner = || {
+            http::Request::get("/")
+                .header("x-partner-key", "secret")
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        // the public requests borrow the unused share of the partners
+        for _ in 0..10 {
+            let res = app.clone().oneshot(public()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app.clone().oneshot(public()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "3");
+        assert_eq!(res.headers()["x-ratelimit-class"], "public");
+
+        // yet the partners still get theirs
+        for _ in 0..7 {
+            let res = app.clone().oneshot(partner()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app.oneshot(partner()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "7");
+        assert_eq!(res.headers()["x-ratelimit-class"], "partner");
+    }
+
+    #[tokio::test]
+    async fn test_method_rules() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::MetadataKeyExtractor;
+        use crate::methods::MethodRules;
+        use http::{HeaderName, Method};
+
+        let rules = MethodRules::new()
+            .key(|name| name.starts_with("anonymous"), None)
+            .key(|_| true, Some(vec![Method::POST]));
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .method_rules(rules)
+                .key_extractor(MetadataKeyExtractor::new(HeaderName::from_static(
+                    "x-api-key",
+                )))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { "Hello, World!" }).post(|| async { "Posted" }),
+            )
+            .layer(GovernorLayer { config });
+
+        let req = |method: Method, key: &str| {
+            http::Request::builder()
+                .method(method)
+                .uri("/")
+                .header("x-api-key", key)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        // anonymous keys are limited on every method
+        let res = app
+            .clone()
+            .oneshot(req(Method::GET, "anonymous"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(req(Method::POST, "anonymous"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // the others only on POST
+        for _ in 0..3 {
+            let res = app
+                .clone()
+                .oneshot(req(Method::GET, "partner"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app
+            .clone()
+            .oneshot(req(Method::POST, "partner"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req(Method::POST, "partner")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_parse_rate_limit_headers() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::RateLimitHeaders;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req()).await.unwrap();
+        let allowed = RateLimitHeaders::parse(res.headers());
+        assert_eq!(allowed.limit, Some(2));
+        assert_eq!(allowed.remaining, Some(1));
+        assert_eq!(allowed.wait_time(), None);
+
+        app.clone().oneshot(req()).await.unwrap();
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let rejected = RateLimitHeaders::parse(res.headers());
+        assert_eq!(rejected.remaining, Some(0));
+        assert_eq!(rejected.after, rejected.retry_after);
+        assert!(rejected.wait_time().is_some());
+
+        let mut headers = http::HeaderMap::new();
+        rejected.encode(&mut headers);
+        assert_eq!(RateLimitHeaders::parse(&headers), rejected);
+    }
+
+    #[tokio::test]
+    async fn test_wait_time_in_milliseconds() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::WaitTimeUnit;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_millisecond(1500)
+                .burst_size(1)
+                .wait_time_unit(WaitTimeUnit::Milliseconds)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        app.clone().oneshot(req()).await.unwrap();
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!res.headers().contains_key("x-ratelimit-after"));
+        let after_ms: u64 = res.headers()["x-ratelimit-after-ms"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1400..=1500).contains(&after_ms), "{}", after_ms);
+        // still in seconds
+        assert_eq!(res.headers()["retry-after"], "1");
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_rejection_log_level() {
+        use crate::governor::GovernorConfigBuilder;
+        use tracing::Level;
+
+        let config = GovernorConfigBuilder::default()
+            .rejection_log_level(Level::WARN)
+            .finish()
+            .unwrap();
+        assert_eq!(config.rejection_log_level(), Level::WARN);
+
+        // clones share the level
+        let clone = config.clone();
+        config.set_rejection_log_level(Level::DEBUG);
+        assert_eq!(clone.rejection_log_level(), Level::DEBUG);
+    }
+
+    #[tokio::test]
+    async fn test_steer_branches() {
+        use crate::governor::{Governor, GovernorConfigBuilder};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::convert::Infallible;
+        use tower::{service_fn, steer::Steer};
+
+        let config = |burst_size| {
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(burst_size)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap()
+        };
+        let (reads, writes) = (config(3), config(1));
+        let inner = service_fn(|_: http::Request<body::Body>| async {
+            Ok::<_, Infallible>(http::Response::new(body::Body::empty()))
+        });
+        // the writes of both paths share the same policy
+        let service = Steer::new(
+            [
+                Governor::new(inner, &reads),
+                Governor::new(inner, &writes),
+                Governor::new(inner, &writes),
+            ],
+            |req: &http::Request<body::Body>, _: &[_]| match (req.method(), req.uri().path()) {
+                (&Method::GET, _) => 0,
+                (_, "/a") => 1,
+                _ => 2,
+            },
+        );
+
+        let req = |method: Method, path| {
+            http::Request::builder()
+                .method(method)
+                .uri(path)
+                .body(body::Body::empty())
+                .unwrap()
+        };
+        for _ in 0..3 {
+            let res = service
+                .clone()
+                .oneshot(req(Method::GET, "/a"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = service
+            .clone()
+            .oneshot(req(Method::GET, "/a"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let res = service
+            .clone()
+            .oneshot(req(Method::POST, "/a"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = service.oneshot(req(Method::POST, "/b")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_requests() {
+        use crate::governor::{GovernorConfigBuilder, UpgradeRequests};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+
+        let app = |upgrades| {
+            let config = Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(60)
+                    .burst_size(4)
+                    .methods(vec![Method::POST])
+                    .upgrade_requests(upgrades)
+                    .key_extractor(GlobalKeyExtractor)
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer { config })
+        };
+        let upgrade = || {
+            http::Request::get("/")
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .body(body::Body::empty())
+                .unwrap()
+        };
+
+        // not a limited method
+        let standard = app(UpgradeRequests::Standard);
+        for _ in 0..5 {
+            let res = standard.clone().oneshot(upgrade()).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        let costly = app(UpgradeRequests::Cost(3));
+        let res = costly.clone().oneshot(upgrade()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = costly.clone().oneshot(upgrade()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let plain = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = costly.oneshot(plain).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = app(UpgradeRequests::Deny).oneshot(upgrade()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_status_counts() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::report::StatusCounts;
+        use http::Method;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(3)
+                .classify(|method, _, _| (method == Method::POST).then_some("write"))
+                .class_quota("write", Duration::from_secs(1), 4)
+                .track_statuses()
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        assert_eq!(config.status_counts(), Some(Default::default()));
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .route(
+                "/fail",
+                axum::routing::post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        for _ in 0..2 {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let req = http::Request::post("/fail")
+            .body(body::Body::empty())
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap();
+        let req = http::Request::get("/missing")
+            .body(body::Body::empty())
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap();
+        // rejections aren't counted
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let counts = config.status_counts().unwrap();
+        assert_eq!(
+            counts[&None],
+            StatusCounts {
+                success: 2,
+                client_errors: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(counts[&Some("write")].server_errors, 1);
+        assert_eq!(counts[&Some("write")].error_rate(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_set_quota() {
+        use crate::errors::SnapshotError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::resolver::{GovernorConfigHandle, ResolvingGovernorLayer};
+        use governor::Quota;
+        use std::time::Duration;
+
+        let handle = GovernorConfigHandle::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .track_state()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(ResolvingGovernorLayer::new(handle.clone()));
+        let status = |app: Router| async move {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.oneshot(req).await.unwrap().status()
+        };
+
+        assert_eq!(status(app.clone()).await, StatusCode::OK);
+        // the element used is carried over to the new burst size
+        let quota = Quota::with_period(Duration::from_secs(60)).unwrap();
+        handle
+            .set_quota(quota.allow_burst(3.try_into().unwrap()))
+            .unwrap();
+        assert_eq!(handle.config().burst_size(), 3);
+        assert_eq!(status(app.clone()).await, StatusCode::OK);
+        assert_eq!(status(app.clone()).await, StatusCode::OK);
+        assert_eq!(status(app).await, StatusCode::TOO_MANY_REQUESTS);
+
+        // without tracking, the keys would start over with a full quota
+        let untracked = GovernorConfigHandle::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        assert!(matches!(
+            untracked.set_quota(quota.allow_burst(3.try_into().unwrap())),
+            Err(SnapshotError::NotTracked)
+        ));
+        assert_eq!(untracked.config().burst_size(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_window_quota() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::{POLICY_HEADER, RATELIMIT_HEADER, SCOPE_HEADER};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .burst_size(5)
+                .window_quota("hour", Duration::from_secs(3600) / 3, 3)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[POLICY_HEADER],
+            r#""default";q=5;w=5, "hour";q=3;w=3600"#
+        );
+        assert_eq!(
+            res.headers()[RATELIMIT_HEADER],
+            r#""default";r=4;t=1, "hour";r=2;t=1200"#
+        );
+
+        for _ in 0..2 {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+        // the quota of the key has requests left, the window doesn't
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "3");
+        assert_eq!(res.headers()[SCOPE_HEADER], "hour");
+    }
+
+    #[tokio::test]
+    async fn test_exempt_health_checks() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .exempt_health_checks()
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let status = |app: Router, agent: &'static str| async move {
+            let req = http::Request::get("/")
+                .header("user-agent", agent)
+                .body(body::Body::empty())
+                .unwrap();
+            app.oneshot(req).await.unwrap().status()
+        };
+
+        assert_eq!(status(app.clone(), "curl/8.5.0").await, StatusCode::OK);
+        for agent in ["kube-probe/1.29", "ELB-HealthChecker/2.0", "GoogleHC/1.0"] {
+            assert_eq!(status(app.clone(), agent).await, StatusCode::OK);
+        }
+        assert_eq!(
+            status(app, "curl/8.5.0").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_stress_fair_share() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::test_util::stress;
+        use std::convert::Infallible;
+        use std::time::Duration;
+        use tower::{service_fn, Layer};
+
+        let layer = GovernorConfigBuilder::default()
+            .per_second(1)
+            .burst_size(1000)
+            .classify(|_, _, _| Some("partner"))
+            .fair_share(Duration::from_secs(3600), 20, [("partner", 1)])
+            .key_extractor(GlobalKeyExtractor)
+            .into_layer()
+            .unwrap();
+        let service = layer.layer(service_fn(|_: http::Request<body::Body>| async {
+            Ok::<_, Infallible>(http::Response::new(body::Body::empty()))
+        }));
+
+        let report = stress(service, 8, 25, |_, _| {
+            http::Request::new(body::Body::empty())
+        });
+        // neither lost nor double counted
+        assert_eq!(report.allowed, 20);
+        assert_eq!(report.rejected, 180);
+    }
+
+    #[cfg(all(feature = "loom", loom))]
+    #[test]
+    fn test_loom_fair_share_admits_once() {
+        use crate::share::FairShare;
+        use governor::Quota;
+        use std::time::Duration;
+
+        loom::model(|| {
+            let quota = Quota::with_period(Duration::from_secs(3600)).unwrap();
+            let share = loom::sync::Arc::new(FairShare::new(quota, &[("partner", 1)], None));
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let share = share.clone();
+                    loom::thread::spawn(move || share.admit(Some("partner")).is_ok())
+                })
+                .collect();
+            let admitted = threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|admitted| *admitted)
+                .count();
+            assert_eq!(admitted, 1);
+        });
+    }
+
+    #[cfg(feature = "utoipa")]
+    #[test]
+    fn test_document_operation() {
+        use crate::governor::GovernorConfigBuilder;
+        use http::Method;
+        use utoipa::openapi::{path::Operation, RefOr};
+
+        let config = GovernorConfigBuilder::default()
+            .per_millisecond(250)
+            .burst_size(5)
+            .methods(vec![Method::POST])
+            .finish()
+            .unwrap();
+        let mut operation = Operation::new();
+        config.document_operation(&mut operation);
+
+        let RefOr::T(response) = &operation.responses.responses["429"] else {
+            panic!("the 429 response is inlined");
+        };
+        // the content types the default and the ready-made error handlers answer with
+        let content_types: Vec<_> = response.content.keys().map(String::as_str).collect();
+        assert_eq!(
+            content_types,
+            ["text/plain", "application/json", "text/html"]
+        );
+        assert!(response.headers.contains_key("retry-after"));
+
+        let extensions = operation.extensions.unwrap();
+        assert_eq!(extensions["x-ratelimit-burst"].as_u64(), Some(5));
+        assert_eq!(extensions["x-ratelimit-period-ms"].as_u64(), Some(250));
+        assert_eq!(extensions["x-ratelimit-methods"][0].as_str(), Some("POST"));
+    }
+
+    #[cfg(feature = "typed-header")]
+    #[tokio::test]
+    async fn test_authorization_key_extractors() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{BasicKeyExtractor, BearerKeyExtractor, KeyExtractor};
+
+        let req = |value: &'static str| {
+            http::Request::get("/")
+                .header("authorization", value)
+                .body(())
+                .unwrap()
+        };
+        let bearer = BearerKeyExtractor::default();
+        let token = bearer.extract(&req("Bearer abc123")).unwrap();
+        assert_eq!(&*token, "abc123");
+        // the token never shows up in logs
+        let name = bearer.key_name(&token).unwrap();
+        assert!(name.starts_with("token-") && !name.contains("abc123"));
+        assert_eq!(bearer.key_name(&token), Some(name));
+        assert!(bearer.extract(&req("Basic YWxhZGRpbjpvcGVu")).is_err());
+        let basic = BasicKeyExtractor::default();
+        assert_eq!(
+            &*basic.extract(&req("Basic YWxhZGRpbjpvcGVu")).unwrap(),
+            "aladdin"
+        );
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(BearerKeyExtractor::default())
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_exempt_matcher() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::matcher::RequestMatcher;
+        use http::Method;
+
+        let matcher = RequestMatcher::new()
+            .methods([Method::GET])
+            .path("/health")
+            .path("/assets/*")
+            .header_value(
+                HeaderName::from_static("x-probe"),
+                http::HeaderValue::from_static("1"),
+            );
+        let request = |method: Method, uri: &str, probe: bool| {
+            let mut req = http::Request::builder().method(method).uri(uri);
+            if probe {
+                req = req.header("x-probe", "1");
+            }
+            req.body(body::Body::empty()).unwrap()
+        };
+        assert!(matcher.matches(&request(Method::GET, "/health", true)));
+        assert!(matcher.matches(&request(Method::GET, "/assets/app.js", true)));
+        assert!(!matcher.matches(&request(Method::POST, "/health", true)));
+        assert!(!matcher.matches(&request(Method::GET, "/healthz", true)));
+        assert!(!matcher.matches(&request(Method::GET, "/health", false)));
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .exempt(matcher)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .layer(GovernorLayer { config });
+        for _ in 0..3 {
+            let res = app
+                .clone()
+                .oneshot(request(Method::GET, "/health", true))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = app
+            .clone()
+            .oneshot(request(Method::GET, "/health", false))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .oneshot(request(Method::GET, "/health", false))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_window_start() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::WINDOW_START_HEADER;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(3)
+                .window_start_header()
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+
+        let before = SystemTime::now();
+        let mut starts = Vec::new();
+        for _ in 0..2 {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            starts.push(res.headers()[WINDOW_START_HEADER].clone());
+        }
+        // the cycle started with the first request
+        assert_eq!(starts[0], starts[1]);
+        let entry = config.iter_keys().next().unwrap();
+        assert!(entry.window_start >= before - std::time::Duration::from_secs(1));
+        let start = entry.window_start.duration_since(UNIX_EPOCH).unwrap();
+        let header: u64 = starts[0].to_str().unwrap().parse().unwrap();
+        assert!(header.abs_diff(start.as_secs()) <= 1);
+    }
+
+    // Compiling is the test: executors such as `tokio::spawn` and servers such as jsonrpsee
+    // need these bounds, which are otherwise only found missing downstream.
+    #[test]
+    fn test_auto_traits() {
+        use crate::governor::{Governor, GovernorConfig};
+        use crate::key_extractor::{GlobalKeyExtractor, PeerIpKeyExtractor, SmartIpKeyExtractor};
+        use crate::resolver::{ExtensionResolver, GovernorConfigHandle, ResolvingGovernorLayer};
+        use crate::stack::{GovernorStack, GovernorStackService, StackFuture};
+        use crate::ResponseFuture;
+        use ::governor::middleware::{NoOpMiddleware, StateInformationMiddleware};
+        use axum::routing::Route;
+        use std::future::Ready;
+
+        fn send_sync<T: Send + Sync + 'static>() {}
+        fn send<T: Send + 'static>() {}
+
+        type NoOp = NoOpMiddleware<crate::governor::DefaultInstant>;
+        send_sync::<GovernorConfig<PeerIpKeyExtractor, NoOp>>();
+        send_sync::<GovernorConfig<SmartIpKeyExtractor, StateInformationMiddleware>>();
+        send_sync::<GovernorLayer<PeerIpKeyExtractor, NoOp>>();
+        send_sync::<GovernorLayer<GlobalKeyExtractor, StateInformationMiddleware>>();
+        send_sync::<GovernorConfigHandle<PeerIpKeyExtractor>>();
+        send_sync::<
+            ResolvingGovernorLayer<ExtensionResolver<PeerIpKeyExtractor>, PeerIpKeyExtractor>,
+        >();
+        send_sync::<GovernorStack>();
+        send::<GovernorStackService<Route>>();
+        send::<Governor<PeerIpKeyExtractor, NoOp, Route>>();
+        send::<Governor<SmartIpKeyExtractor, StateInformationMiddleware, Route>>();
+        // sync as long as the inner service is
+        send_sync::<Governor<PeerIpKeyExtractor, NoOp, tower::util::BoxCloneSyncService<(), (), ()>>>(
+        );
+
+        type Inner = Ready<Result<http::Response<body::Body>, std::convert::Infallible>>;
+        send::<ResponseFuture<Inner>>();
+        send::<StackFuture<Inner>>();
+        #[cfg(not(feature = "tarpit"))]
+        {
+            fn unpin<T: Unpin>() {}
+            unpin::<ResponseFuture<Inner>>();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refund_cancelled() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::convert::Infallible;
+        use std::time::Duration;
+        use tower::{service_fn, Layer, Service};
+
+        let layer = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .refund_cancelled()
+            .key_extractor(GlobalKeyExtractor)
+            .into_layer()
+            .unwrap();
+        let mut service = layer.layer(service_fn(|req: http::Request<body::Body>| async move {
+            if req.uri().path() == "/slow" {
+                std::future::pending::<()>().await;
+            }
+            Ok::<_, Infallible>(http::Response::new(body::Body::empty()))
+        }));
+
+        // the client gives up before the inner service responded
+        let req = http::Request::get("/slow")
+            .body(body::Body::empty())
+            .unwrap();
+        drop(service.ready().await.unwrap().call(req));
+
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        // the completed request kept its charge
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = service.ready().await.unwrap().call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // no more than a burst is refunded per period, even once the refunds were spent
+        let refunds = crate::refund::Refunds::new(Duration::from_secs(60), 2);
+        refunds.refund("key", 1);
+        assert!(refunds.take(&"key", 1));
+        refunds.refund("key", 1);
+        refunds.refund("key", 1);
+        assert!(refunds.take(&"key", 1));
+        assert!(!refunds.take(&"key", 1));
+    }
+
+    #[tokio::test]
+    async fn test_ban_list() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use std::net::IpAddr;
+        use std::time::Duration;
+
+        let builder = || {
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(5)
+                .key_extractor(SmartIpKeyExtractor)
+                .ban_list()
+                .finish()
+                .unwrap()
+        };
+        let config = Arc::new(builder());
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        let call = |client: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::get("/")
+                    .header("x-forwarded-for", client)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        let abuser: IpAddr = "1.1.1.1".parse().unwrap();
+        assert!(config.ban(abuser, Duration::from_secs(600)));
+        assert_eq!(call("1.1.1.1").await, StatusCode::FORBIDDEN);
+        assert_eq!(call("2.2.2.2").await, StatusCode::OK);
+
+        // the ban survives a restart
+        let mut saved = Vec::new();
+        config.save_bans(&mut saved).unwrap();
+        let restarted = builder();
+        assert_eq!(restarted.restore_bans(saved.as_slice()).unwrap(), 1);
+        let bans = restarted.bans();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].0, abuser);
+        assert!(bans[0].1 > Duration::from_secs(590));
+
+        assert!(config.unban(&abuser));
+        assert!(!config.unban(&abuser));
+        assert_eq!(call("1.1.1.1").await, StatusCode::OK);
+
+        // banned for good, which survives a restart too
+        assert!(config.ban(abuser, Duration::MAX));
+        assert_eq!(call("1.1.1.1").await, StatusCode::FORBIDDEN);
+        let mut saved = Vec::new();
+        config.save_bans(&mut saved).unwrap();
+        assert_eq!(builder().restore_bans(saved.as_slice()).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sample_over_quota() {
+        use crate::decision::{Sampled, SAMPLED_HEADER};
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http_body_util::BodyExt;
+        use std::num::NonZeroU32;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .sample_over_quota(NonZeroU32::MIN)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|req: http::Request<body::Body>| async move {
+                    let sampled = req.extensions().get::<Sampled>().is_some()
+                        && req.headers().contains_key(SAMPLED_HEADER);
+                    sampled.to_string()
+                }),
+            )
+            .layer(GovernorLayer { config });
+        let call = || async {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        assert_eq!(call().await, "false");
+        // every request over quota is sampled
+        assert_eq!(call().await, "true");
+        assert_eq!(call().await, "true");
+    }
+
+    #[tokio::test]
+    async fn test_limit_auth_failures() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .burst_size(100)
+                .key_extractor(SmartIpKeyExtractor)
+                .limit_auth_failures(2, Duration::from_secs(60), Duration::from_secs(900))
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .route("/login", get(|| async { StatusCode::UNAUTHORIZED }))
+            .layer(GovernorLayer { config });
+        let call = |client: &'static str, path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::get(path)
+                    .header("x-forwarded-for", client)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        assert_eq!(
+            call("1.1.1.1", "/login").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(call("1.1.1.1", "/").await.status(), StatusCode::OK);
+        assert_eq!(
+            call("1.1.1.1", "/login").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        // the second failure bans the key, whatever it requests
+        let res = call("1.1.1.1", "/").await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let left: u64 = res.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((899..=900).contains(&left));
+        assert_eq!(call("2.2.2.2", "/").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cache_exemptions() {
+        use crate::errors::GovernorError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{KeyExtractor, PeerIpKeyExtractor};
+        use axum::extract::ConnectInfo;
+        use std::net::IpAddr;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        static EXTRACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct CountingPeerIp;
+
+        impl KeyExtractor for CountingPeerIp {
+            type Key = IpAddr;
+
+            #[cfg(feature = "tracing")]
+            fn name(&self) -> &'static str {
+                "counting peer IP"
+            }
+
+            fn extract<T>(&self, req: &http::Request<T>) -> Result<IpAddr, GovernorError> {
+                EXTRACTIONS.fetch_add(1, Ordering::Relaxed);
+                PeerIpKeyExtractor.extract(req)
+            }
+
+            fn key_ip(&self, key: &IpAddr) -> Option<IpAddr> {
+                Some(*key)
+            }
+
+            fn connection_stable(&self) -> bool {
+                true
+            }
+        }
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(CountingPeerIp)
+                .exempt_loopback(true)
+                .cache_exemptions(Duration::from_secs(60))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |ip: [u8; 4]| {
+            let mut req = http::Request::new(body::Body::empty());
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            req
+        };
+
+        for _ in 0..3 {
+            let res = app.clone().oneshot(req([127, 0, 0, 1])).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        // only the first request of the exempt peer had its key extracted
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 1);
+
+        for _ in 0..2 {
+            app.clone().oneshot(req([1, 2, 3, 4])).await.unwrap();
+        }
+        assert_eq!(EXTRACTIONS.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cache_exemptions_behind_proxy() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::SmartIpKeyExtractor;
+        use axum::extract::ConnectInfo;
+        use std::time::Duration;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(SmartIpKeyExtractor)
+                .exempt_loopback(true)
+                .cache_exemptions(Duration::from_secs(60))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = |client: Option<&'static str>| {
+            let mut req = http::Request::builder();
+            if let Some(client) = client {
+                req = req.header("x-forwarded-for", client);
+            }
+            let mut req = req.body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))));
+            req
+        };
+
+        // the own requests of the proxy don't exempt those it forwards
+        let res = app.clone().oneshot(req(None)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req(Some("8.8.8.8"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.oneshot(req(Some("8.8.8.8"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_cohort_rates() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::{Cohorts, SmartIpKeyExtractor};
+        use crate::report::DEFAULT_COHORT;
+        use std::net::IpAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(Cohorts::new(SmartIpKeyExtractor, |ip: &IpAddr| {
+                    if ip.is_loopback() {
+                        "internal"
+                    } else {
+                        "public"
+                    }
+                }))
+                .track_cohort_rates()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: config.clone(),
+            });
+        for client in ["127.0.0.1", "1.1.1.1", "1.1.1.1", "2.2.2.2"] {
+            let req = http::Request::get("/")
+                .header("x-forwarded-for", client)
+                .body(body::Body::empty())
+                .unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let rates = config.cohort_rates().unwrap();
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates["internal"].one_minute.allowed, 1);
+        assert_eq!(rates["public"].one_minute.allowed, 2);
+        assert_eq!(rates["public"].one_minute.rejected, 1);
+        assert!(!rates.contains_key(DEFAULT_COHORT));
+    }
+
+    #[tokio::test]
+    async fn test_map_response() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::HeaderValue;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(2)
+                .key_extractor(GlobalKeyExtractor)
+                .use_headers()
+                .map_response(|response, snapshot| {
+                    let value = match (snapshot.decision.is_allowed(), snapshot.remaining) {
+                        (true, Some(0)) => "throttled-soon",
+                        (true, _) => "ok",
+                        (false, _) => "throttled",
+                    };
+                    let value = HeaderValue::from_static(value);
+                    response.headers_mut().insert("x-quota-warning", value);
+                })
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+
+        for warning in ["ok", "throttled-soon", "throttled"] {
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.headers()["x-quota-warning"], warning);
+        }
+    }
+
+    #[cfg(feature = "jwt")]
+    #[tokio::test]
+    async fn test_jwt_key_extractor() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::JwtKeyExtractor;
+        use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(JwtKeyExtractor::new(
+                    DecodingKey::from_secret(b"secret"),
+                    Validation::new(Algorithm::HS256),
+                ))
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let token = |sub: &str, secret: &[u8]| {
+            let claims = serde_json::json!({ "sub": sub, "exp": 4_000_000_000u64 });
+            let key = EncodingKey::from_secret(secret);
+            jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap()
+        };
+        let call = |token: String| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::get("/")
+                    .header("authorization", format!("Bearer {}", token))
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(call(token("alice", b"secret")).await, StatusCode::OK);
+        assert_eq!(
+            call(token("alice", b"secret")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(token("bob", b"secret")).await, StatusCode::OK);
+        assert_eq!(
+            call(token("mallory", b"forged")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_web_defaults() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .web_defaults()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .fallback(|| async { "Hello, World!" })
+            .layer(GovernorLayer { config });
+        let call = |method: Method, path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .uri(path)
+                    .header("access-control-request-method", "POST")
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let res = call(Method::GET, "/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+        for (method, path) in [
+            (Method::OPTIONS, "/"),
+            (Method::HEAD, "/"),
+            (Method::GET, "/static/app.JS"),
+            (Method::GET, "/fonts/icons.woff2"),
+        ] {
+            assert_eq!(call(method, path).await.status(), StatusCode::OK);
+        }
+        assert_eq!(
+            call(Method::GET, "/reports.csv").await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enforcement_mode_header() {
+        use crate::decision::{EnforcementMode, MODE_HEADER};
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .enforce_ratio(0.5)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route(
+                "/",
+                get(|req: http::Request<body::Body>| async move {
+                    // the inner service sees the mode too
+                    assert!(req.extensions().get::<EnforcementMode>().is_some());
+                    "Hello, World!"
+                }),
+            )
+            .layer(GovernorLayer { config });
+        let call = |ip: u8| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder().body(body::Body::empty()).unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, ip], 1234))));
+                let res = app.oneshot(req).await.unwrap();
+                let mode = res.headers()[MODE_HEADER].to_str().unwrap().to_owned();
+                (res.status(), mode)
+            }
+        };
+
+        let (mut enforced, mut observed) = (0, 0);
+        for ip in 0..50 {
+            let (status, mode) = call(ip).await;
+            assert_eq!(status, StatusCode::OK);
+            // the mode is reported whether the request conforms to the quota or not
+            let (status, over_quota) = call(ip).await;
+            assert_eq!(over_quota, mode);
+            match mode.as_str() {
+                "enforce" => {
+                    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+                    enforced += 1;
+                }
+                "observe" => {
+                    assert_eq!(status, StatusCode::OK);
+                    observed += 1;
+                }
+                other => panic!("unexpected mode {}", other),
+            }
+        }
+        assert!(enforced > 0 && observed > 0);
+    }
+
+    #[test]
+    fn test_reserve_lease() {
+        use crate::errors::LeaseError;
+        use crate::governor::GovernorConfigBuilder;
+        use std::{net::IpAddr, time::Duration};
+
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let disabled = GovernorConfigBuilder::default().finish().unwrap();
+        assert_eq!(
+            disabled
+                .reserve(ip, 1, Duration::from_secs(60))
+                .unwrap_err(),
+            LeaseError::Disabled
+        );
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(4)
+            .leases()
+            .finish()
+            .unwrap();
+        let mut lease = config.reserve(ip, 4, Duration::from_secs(60)).unwrap();
+        assert!(matches!(
+            config.reserve(ip, 1, Duration::from_secs(60)),
+            Err(LeaseError::TooManyRequests { .. })
+        ));
+        assert!(lease.consume(3));
+        assert!(!lease.consume(2));
+        assert_eq!(lease.remaining(), 1);
+        drop(lease);
+        // the unused cost is given back once
+        assert!(config.check_blocking(&ip, 1).is_allowed());
+        assert!(!config.check_blocking(&ip, 1).is_allowed());
+
+        // an expired lease forfeits the cost left
+        let other = IpAddr::from([10, 0, 0, 2]);
+        let lease = config.reserve(other, 4, Duration::ZERO).unwrap();
+        assert!(lease.is_expired());
+        assert_eq!(lease.remaining(), 0);
+        drop(lease);
+        assert!(!config.check_blocking(&other, 1).is_allowed());
+
+        // a time to live too long to represent never expires
+        let third = IpAddr::from([10, 0, 0, 3]);
+        let lease = config.reserve(third, 4, Duration::MAX).unwrap();
+        assert!(!lease.is_expired());
+        assert_eq!(lease.remaining(), 4);
+    }
+
+    #[test]
+    fn test_check_request() {
+        use crate::decision::{Decision, RequestDecision};
+        use crate::errors::GovernorError;
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(2)
+            .exempt_loopback(true)
+            .use_headers()
+            .finish()
+            .unwrap();
+        let request = |ip: [u8; 4]| {
+            let mut req = http::Request::new(());
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            req
+        };
+
+        let req = request([10, 0, 0, 1]);
+        match config.check_request(&req) {
+            RequestDecision::Allowed { snapshot } => {
+                assert_eq!(snapshot.limit, 2);
+                assert_eq!(snapshot.remaining, Some(1));
+                assert_eq!(snapshot.decision, Decision::Allowed);
+            }
+            other => panic!("unexpected decision {:?}", other),
+        }
+        assert!(matches!(
+            config.check_request(&req),
+            RequestDecision::Allowed { .. }
+        ));
+        assert!(matches!(
+            config.check_request(&req),
+            RequestDecision::Rejected { .. }
+        ));
+        assert!(matches!(
+            config.check_request(&request([127, 0, 0, 1])),
+            RequestDecision::Exempt
+        ));
+        assert!(matches!(
+            config.check_request(&http::Request::new(())),
+            RequestDecision::Error(GovernorError::UnableToExtractKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_charge_once() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let nested = |charge_once: bool| {
+            let mut builder = GovernorConfigBuilder::default();
+            builder.per_second(60).burst_size(2);
+            if charge_once {
+                builder.charge_once();
+            }
+            let config = Arc::new(builder.use_headers().finish().unwrap());
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async { "Hello, World!" }).route_layer(GovernorLayer {
+                        config: config.clone(),
+                    }),
+                )
+                .layer(GovernorLayer { config })
+        };
+        let call = |app: Router| async move {
+            let mut req = http::Request::builder().body(body::Body::empty()).unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+            app.oneshot(req).await.unwrap()
+        };
+
+        let app = nested(false);
+        assert_eq!(call(app.clone()).await.status(), StatusCode::OK);
+        assert_eq!(call(app).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let app = nested(true);
+        let res = call(app.clone()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "1");
+        assert_eq!(call(app.clone()).await.status(), StatusCode::OK);
+        assert_eq!(call(app).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_mode() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::headers::RejectionMode;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .per_second(60)
+                .burst_size(1)
+                .rejection_mode(RejectionMode::ServiceUnavailable)
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder().body(body::Body::empty()).unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(res.headers().contains_key("retry-after"));
+        assert!(res.headers().contains_key("x-ratelimit-after"));
+        assert!(!res.headers().contains_key("x-ratelimit-limit"));
+        assert!(!res.headers().contains_key("x-ratelimit-remaining"));
+    }
+
+    #[tokio::test]
+    async fn test_sliding_log() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::time::Duration;
+
+        assert_eq!(
+            GovernorConfigBuilder::default()
+                .sliding_log(0, Duration::from_secs(1))
+                .try_finish()
+                .unwrap_err(),
+            ConfigError::ZeroBurstSize
+        );
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .key_extractor(GlobalKeyExtractor)
+                .per_millisecond(1)
+                .burst_size(10)
+                .sliding_log(3, Duration::from_millis(300))
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder().body(body::Body::empty()).unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        for _ in 0..3 {
+            assert_eq!(call().await.status(), StatusCode::OK);
+        }
+        // the quota replenished long ago, the log still holds the three requests
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "3");
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(call().await.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "shared-memory")]
+    #[test]
+    fn test_shared_memory() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use std::net::IpAddr;
+
+        let path = std::env::temp_dir().join(format!("tower-governor-{}", std::process::id()));
+        // two workers of the same host
+        let worker = || {
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(3)
+                .shared_memory(&path, 16)
+                .finish()
+                .unwrap()
+        };
+        let (first, second) = (worker(), worker());
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        assert!(first.check_blocking(&ip, 2).is_allowed());
+        assert!(second.check_blocking(&ip, 1).is_allowed());
+        assert!(!second.check_blocking(&ip, 1).is_allowed());
+        assert!(!first.check_blocking(&ip, 1).is_allowed());
+        // the other keys have their own quota
+        assert!(second
+            .check_blocking(&IpAddr::from([10, 0, 0, 2]), 3)
+            .is_allowed());
+
+        assert!(matches!(
+            GovernorConfigBuilder::default()
+                .per_second(1)
+                .shared_memory(&path, 16)
+                .try_finish(),
+            Err(ConfigError::SharedMemory(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg(all(feature = "tarpit", feature = "tracing"))]
+    async fn test_tarpit_span() {
+        use crate::governor::{GovernorConfigBuilder, TARPIT_SPAN};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use std::{io, sync::Mutex, time::Duration};
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        #[derive(Clone, Default)]
+        struct Logs(Arc<Mutex<Vec<u8>>>);
+
+        impl io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .tarpit(Duration::from_millis(20))
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let req = || http::Request::get("/").body(body::Body::empty()).unwrap();
+        assert_eq!(
+            app.clone().oneshot(req()).await.unwrap().status(),
+            StatusCode::OK
+        );
+        let res = app.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let closed = logs
+            .lines()
+            .find(|line| line.contains(TARPIT_SPAN) && line.contains("close"))
+            .expect("the span of the held rejection closed");
+        assert!(closed.contains("delay_ms=20"), "{}", closed);
+        assert!(closed.contains("parked_ms="), "{}", closed);
+    }
+
+    #[test]
+    fn test_duplicate_forwarded_for_headers() {
+        use crate::forwarding::{ClientIpResolver, DuplicateHeaders, Source, Trust};
+        use axum::extract::ConnectInfo;
+        use http::HeaderValue;
+        use std::net::IpAddr;
+
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        let req = |values: &[&[u8]]| {
+            let mut req = http::Request::get("/").body(()).unwrap();
+            for value in values {
+                req.headers_mut()
+                    .append("x-forwarded-for", HeaderValue::from_bytes(value).unwrap());
+            }
+            req.extensions_mut().insert(ConnectInfo(peer));
+            req
+        };
+        let resolve = |duplicates, rightmost: bool, values: &[&[u8]]| {
+            let resolver = ClientIpResolver::new([Source::XForwardedFor, Source::Peer])
+                .trust(Trust::Private)
+                .duplicate_headers(duplicates);
+            let resolver = match rightmost {
+                true => resolver.rightmost(),
+                false => resolver,
+            };
+            resolver.resolve(&req(values))
+        };
+        let client = Some(IpAddr::from([1, 2, 3, 4]));
+        let spoofed = Some(IpAddr::from([6, 6, 6, 6]));
+        let policies = [
+            DuplicateHeaders::Merge,
+            DuplicateHeaders::Last,
+            DuplicateHeaders::Reject,
+        ];
+
+        // a single header reads the same under every policy
+        for duplicates in policies {
+            assert_eq!(resolve(duplicates, false, &[b"1.2.3.4, 10.0.0.2"]), client);
+            assert_eq!(resolve(duplicates, true, &[b"6.6.6.6, 1.2.3.4"]), client);
+            assert_eq!(resolve(duplicates, false, &[]), Some(peer.ip()));
+        }
+
+        // the client splits the header in front of the one the proxy appended to
+        let split: &[&[u8]] = &[b"6.6.6.6", b"1.2.3.4, 10.0.0.2"];
+        assert_eq!(resolve(DuplicateHeaders::Merge, false, split), spoofed);
+        assert_eq!(resolve(DuplicateHeaders::Last, false, split), client);
+        assert_eq!(resolve(DuplicateHeaders::Reject, false, split), None);
+        for duplicates in [DuplicateHeaders::Merge, DuplicateHeaders::Last] {
+            assert_eq!(resolve(duplicates, true, split), client);
+        }
+
+        // or behind it, fooling the proxies reading the last line
+        let trailing: &[&[u8]] = &[b"1.2.3.4", b"6.6.6.6"];
+        assert_eq!(resolve(DuplicateHeaders::Merge, true, trailing), spoofed);
+        assert_eq!(resolve(DuplicateHeaders::Last, true, trailing), spoofed);
+        assert_eq!(resolve(DuplicateHeaders::Reject, true, trailing), None);
+
+        // empty, invalid or non UTF-8 lines still count as duplicates
+        let duplicated: [&[&[u8]]; 4] = [
+            &[b"", b"1.2.3.4"],
+            &[b"1.2.3.4", b""],
+            &[b"not an ip", b"1.2.3.4"],
+            &[b"1.2.3.4", b"\xff6.6.6.6"],
+        ];
+        for values in duplicated {
+            assert_eq!(resolve(DuplicateHeaders::Merge, false, values), client);
+            assert_eq!(resolve(DuplicateHeaders::Reject, false, values), None);
+            assert_eq!(resolve(DuplicateHeaders::Reject, true, values), None);
+        }
+        // the last line alone falls back to the next source when it holds no address
+        assert_eq!(
+            resolve(DuplicateHeaders::Last, false, &[b"1.2.3.4", b""]),
+            Some(peer.ip())
+        );
+        assert_eq!(
+            resolve(DuplicateHeaders::Last, false, &[b"6.6.6.6", b"\xff"]),
+            Some(peer.ip())
+        );
+        assert_eq!(
+            resolve(DuplicateHeaders::Last, false, &[b"", b"1.2.3.4"]),
+            client
+        );
+
+        // untrusted peers are ignored before the headers are even read
+        let resolver = ClientIpResolver::new([Source::XForwardedFor, Source::Peer])
+            .trust(Trust::Peers(vec![IpAddr::from([10, 0, 0, 9])]))
+            .duplicate_headers(DuplicateHeaders::Reject);
+        assert_eq!(resolver.resolve(&req(split)), Some(peer.ip()));
+    }
+
+    #[tokio::test]
+    async fn test_write_quota_percent() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::time::Duration;
+
+        let app = |builder: &mut GovernorConfigBuilder<_, _>| {
+            let config = Arc::new(
+                builder
+                    .per_second(1)
+                    .burst_size(10)
+                    .key_extractor(GlobalKeyExtractor)
+                    .use_headers()
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async { "Hello, World!" }).fallback(|| async { "Hello!" }),
+                )
+                .layer(GovernorLayer { config })
+        };
+        let call = |app: &Router, method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let split = app(GovernorConfigBuilder::default().write_quota_percent(30));
+        for _ in 0..10 {
+            assert_eq!(call(&split, Method::GET).await.status(), StatusCode::OK);
+        }
+        assert_eq!(
+            call(&split, Method::GET).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // the writes have a bucket of their own, with 30% of the burst and of the rate
+        let res = call(&split, Method::POST).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-class"], "write");
+        assert_eq!(res.headers()["x-ratelimit-limit"], "3");
+        assert_eq!(call(&split, Method::PUT).await.status(), StatusCode::OK);
+        assert_eq!(call(&split, Method::DELETE).await.status(), StatusCode::OK);
+        let res = call(&split, Method::PATCH).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let wait_time: u64 = res.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((3..=4).contains(&wait_time));
+
+        // an explicit quota of the writes wins
+        let explicit = app(GovernorConfigBuilder::default()
+            .write_quota_percent(30)
+            .class_quota("write", Duration::from_secs(1), 5));
+        let res = call(&explicit, Method::POST).await;
+        assert_eq!(res.headers()["x-ratelimit-limit"], "5");
+
+        // the percentage is clamped, leaving at least one write
+        let clamped = app(GovernorConfigBuilder::default().write_quota_percent(0));
+        let res = call(&clamped, Method::POST).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "1");
+        assert_eq!(
+            call(&clamped, Method::POST).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clone_detached() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::time::Duration;
+
+        let config = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(10)
+            .key_extractor(GlobalKeyExtractor)
+            .classify(|method, _, _| (method == Method::POST).then_some("write"))
+            .class_quota("write", Duration::from_secs(60), 1)
+            .window_quota("hour", Duration::from_secs(3600), 3)
+            .finish()
+            .unwrap();
+        let app = |config| {
+            Router::new()
+                .route("/", get(|| async { "Hello!" }).post(|| async { "Hello!" }))
+                .layer(GovernorLayer {
+                    config: Arc::new(config),
+                })
+        };
+        let call = |app: &Router, method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        let first = app(config.clone());
+        let shared = app(config.clone_shared());
+        let detached = app(config.clone_detached());
+        assert_eq!(call(&first, Method::GET).await, StatusCode::OK);
+        assert_eq!(call(&first, Method::POST).await, StatusCode::OK);
+        // the shared clone counts against the same quotas, the window is spent
+        assert_eq!(call(&shared, Method::GET).await, StatusCode::OK);
+        assert_eq!(
+            call(&shared, Method::GET).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            call(&shared, Method::POST).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // the detached one has budgets of its own, for the classes and windows too
+        assert_eq!(call(&detached, Method::POST).await, StatusCode::OK);
+        assert_eq!(
+            call(&detached, Method::POST).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(&detached, Method::GET).await, StatusCode::OK);
+        assert_eq!(call(&detached, Method::GET).await, StatusCode::OK);
+        assert_eq!(
+            call(&detached, Method::GET).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(config.limiter().len(), 1);
+        assert_eq!(config.clone_detached().limiter().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_classes() {
+        use crate::governor::GovernorConfigBuilder;
+        use axum::extract::ConnectInfo;
+        use http::header::USER_AGENT;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(5)
+                .classify(move |_, _, headers| {
+                    counted.fetch_add(1, Ordering::Relaxed);
+                    match headers.get(USER_AGENT)?.to_str().ok()?.contains("bot") {
+                        true => Some("bot"),
+                        false => None,
+                    }
+                })
+                .class_quota("bot", Duration::from_secs(60), 1)
+                .cache_classes(Duration::from_millis(100))
+                .use_headers()
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = |ip: [u8; 4], agent: &'static str| {
+            let app = app.clone();
+            async move {
+                let mut req = http::Request::builder()
+                    .header(USER_AGENT, agent)
+                    .body(body::Body::empty())
+                    .unwrap();
+                req.extensions_mut()
+                    .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        let res = call([10, 0, 0, 1], "crawlbot/1.0").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-class"], "bot");
+        // the key keeps its class, without classifying its requests again
+        let res = call([10, 0, 0, 1], "Mozilla/5.0").await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-class"], "bot");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // other keys are classified on their own
+        let res = call([10, 0, 0, 2], "Mozilla/5.0").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("x-ratelimit-class").is_none());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // until the class expires
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let res = call([10, 0, 0, 1], "Mozilla/5.0").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[cfg(feature = "template")]
+    #[tokio::test]
+    async fn test_rejection_template() {
+        use crate::errors::ConfigError;
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::template::RejectionTemplate;
+        use http::header::CONTENT_TYPE;
+
+        assert!(matches!(
+            RejectionTemplate::new("{% if %}"),
+            Err(ConfigError::InvalidTemplate(_))
+        ));
+
+        let template = RejectionTemplate::new(
+            "<p>{{ method }} {{ path }}: retry in {{ wait_seconds }}s</p>\
+             <a href=\"{{ contact_url }}\">help</a>{% if reset_at > 0 %}<i>reset</i>{% endif %}",
+        )
+        .unwrap()
+        .contact_url("https://example.com/?a=1&b=<2>");
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(30)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .rejection_template(template)
+                .finish()
+                .unwrap(),
+        );
+        let app = Router::new()
+            .route("/page", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer { config });
+        let call = || {
+            let app = app.clone();
+            async move {
+                let req = http::Request::get("/page")
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        assert_eq!(call().await.status(), StatusCode::OK);
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        let wait_time = res.headers()["x-ratelimit-after"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let page = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        // the variables are HTML escaped
+        assert_eq!(
+            page,
+            format!(
+                "<p>GET &#x2f;page: retry in {}s</p><a \
+                 href=\"https:&#x2f;&#x2f;example.com&#x2f;?a=1&amp;b=&lt;2&gt;\">help</a>\
+                 <i>reset</i>",
+                wait_time
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_methods() {
+        use crate::governor::{GovernorConfigBuilder, HeadRequests, EQUIVALENT_METHODS};
+        use crate::key_extractor::GlobalKeyExtractor;
+        use http::Method;
+        use std::time::Duration;
+
+        let app = |builder: &mut GovernorConfigBuilder<_, _>| {
+            let config = Arc::new(
+                builder
+                    .per_second(60)
+                    .burst_size(2)
+                    .key_extractor(GlobalKeyExtractor)
+                    .classify(|method, _, _| match *method {
+                        Method::HEAD => Some("HEAD"),
+                        Method::PUT => Some("PUT"),
+                        Method::PATCH => Some("PATCH"),
+                        _ => None,
+                    })
+                    .class_quota("HEAD", Duration::from_secs(60), 2)
+                    .class_quota("PUT", Duration::from_secs(60), 2)
+                    .class_quota("PATCH", Duration::from_secs(60), 2)
+                    .finish()
+                    .unwrap(),
+            );
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async { "Hello!" }).fallback(|| async { "Hello!" }),
+                )
+                .layer(GovernorLayer { config })
+        };
+        let call = |app: &Router, method: Method| {
+            let app = app.clone();
+            async move {
+                let req = http::Request::builder()
+                    .method(method)
+                    .body(body::Body::empty())
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+        let alternate = |app: Router, first: Method, second: Method| async move {
+            let mut allowed = 0;
+            for method in [first, second].iter().cycle().take(6) {
+                if call(&app, method.clone()).await == StatusCode::OK {
+                    allowed += 1;
+                }
+            }
+            allowed
+        };
+
+        // alternating verbs doubles the budget of separate buckets
+        let separate = app(&mut GovernorConfigBuilder::default());
+        assert_eq!(
+            alternate(separate.clone(), Method::PUT, Method::PATCH).await,
+            4
+        );
+        assert_eq!(alternate(separate, Method::GET, Method::HEAD).await, 4);
+
+        // not once the verbs are grouped
+        let grouped = app(GovernorConfigBuilder::default().group_methods(EQUIVALENT_METHODS));
+        assert_eq!(
+            alternate(grouped.clone(), Method::PATCH, Method::PUT).await,
+            2
+        );
+        assert_eq!(alternate(grouped, Method::HEAD, Method::GET).await, 2);
+
+        // groups sharing a method merge, and HEAD is grouped after being handled
+        let merged = app(GovernorConfigBuilder::default()
+            .head_requests(HeadRequests::AsGet)
+            .group_methods([
+                vec![Method::PUT, Method::PATCH],
+                vec![Method::PATCH, Method::GET],
+            ]));
+        assert_eq!(
+            alternate(merged.clone(), Method::HEAD, Method::PATCH).await,
+            2
+        );
+        assert_eq!(
+            call(&merged, Method::GET).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(&merged, Method::DELETE).await, StatusCode::OK);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn test_clocks() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::key_extractor::GlobalKeyExtractor;
+        use crate::stack::GovernorStack;
+        use crate::test_util::TestClocks;
+        use std::time::Duration;
+
+        let clocks = TestClocks::new();
+        let (minute, hour) = (clocks.clock(), clocks.clock());
+        let per_minute = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .clock(minute.clone())
+            .key_extractor(GlobalKeyExtractor)
+            .policy_name("minute")
+            .finish()
+            .unwrap();
+        let per_hour = GovernorConfigBuilder::default()
+            .per_second(1800)
+            .burst_size(2)
+            .clock(hour.clone())
+            .key_extractor(GlobalKeyExtractor)
+            .policy_name("hour")
+            .finish()
+            .unwrap();
+        assert_eq!(clocks.len(), 2);
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorStack::new().push(per_minute).push(per_hour));
+        let call = || async {
+            app.clone()
+                .oneshot(http::Request::new(body::Body::empty()))
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(call().await.status(), StatusCode::OK);
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-scope"], "minute");
+
+        // the minute passed for the first policy only
+        minute.advance(Duration::from_secs(60));
+        assert_eq!(call().await.status(), StatusCode::OK);
+        minute.advance(Duration::from_secs(60));
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["x-ratelimit-scope"], "hour");
+
+        // and half an hour for both
+        clocks.advance_all(Duration::from_secs(1800));
+        assert_eq!(call().await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rejection_reason() {
+        use crate::governor::{GovernorConfig, GovernorConfigBuilder};
+        use crate::handlers;
+        use crate::headers::{RejectionReason, REASON_HEADER};
+        use crate::key_extractor::{GlobalKeyExtractor, PeerIpKeyExtractor};
+        use std::time::Duration;
+
+        let app = |config: GovernorConfig<_, _>| {
+            Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(GovernorLayer {
+                    config: Arc::new(config),
+                })
+        };
+        let reasons = |app: Router| async move {
+            let mut reasons = Vec::new();
+            for _ in 0..3 {
+                let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                reasons.push(RejectionReason::of(res.headers()));
+            }
+            reasons
+        };
+
+        let keyed = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .key_extractor(GlobalKeyExtractor)
+            .finish()
+            .unwrap();
+        assert_eq!(
+            reasons(app(keyed)).await,
+            [
+                None,
+                Some(RejectionReason::QuotaExceeded),
+                Some(RejectionReason::QuotaExceeded)
+            ]
+        );
+        let unkeyed = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .key_extractor(GlobalKeyExtractor)
+            .unkeyed()
+            .finish()
+            .unwrap();
+        assert_eq!(
+            reasons(app(unkeyed)).await,
+            [
+                None,
+                Some(RejectionReason::GlobalCeiling),
+                Some(RejectionReason::GlobalCeiling)
+            ]
+        );
+        let banning = GovernorConfigBuilder::default()
+            .per_second(60)
+            .burst_size(1)
+            .key_extractor(GlobalKeyExtractor)
+            .ban_above(Duration::from_secs(30), Duration::from_secs(600))
+            .finish()
+            .unwrap();
+        assert_eq!(
+            reasons(app(banning)).await,
+            [
+                None,
+                Some(RejectionReason::Banned),
+                Some(RejectionReason::Banned)
+            ]
+        );
+
+        // without connection info the peer IP can't be extracted
+        let app = Router::new()
+            .route("/", get(|| async { "Hello, World!" }))
+            .layer(GovernorLayer {
+                config: Arc::new(
+                    GovernorConfigBuilder::default()
+                        .key_extractor(PeerIpKeyExtractor)
+                        .error_handler(handlers::json())
+                        .finish()
+                        .unwrap(),
+                ),
+            });
+        let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.headers()[REASON_HEADER], "extraction_failed");
+        let bytes = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["reason"], "extraction_failed");
+    }
+
+    #[tokio::test]
+    async fn test_layer_with_options() {
+        use crate::governor::GovernorConfigBuilder;
+        use crate::handlers;
+        use crate::key_extractor::GlobalKeyExtractor;
+
+        let config = Arc::new(
+            GovernorConfigBuilder::default()
+                .per_second(60)
+                .burst_size(1)
+                .key_extractor(GlobalKeyExtractor)
+                .finish()
+                .unwrap(),
+        );
+        // the struct literal of the older examples
+        let literal = GovernorLayer {
+            config: config.clone(),
+        };
+        let layer = GovernorLayer::from_config(config)
+            .with_error_handler(handlers::json())
+            .with_rejection_hook(|response, _| {
+                response
+                    .headers_mut()
+                    .insert("x-hooked", "true".parse().unwrap());
+            });
+        let call = |layer: GovernorLayer<_, _>| async move {
+            let app = Router::new()
+                .route("/", get(|| async { "Hello, World!" }))
+                .layer(layer);
+            let req = http::Request::get("/").body(body::Body::empty()).unwrap();
+            app.oneshot(req).await.unwrap()
+        };
+
+        assert_eq!(call(literal.clone()).await.status(), StatusCode::OK);
+        // both layers share the quota of the configuration
+        let res = call(layer).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert_eq!(res.headers()["x-hooked"], "true");
+        let res = call(literal).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!res.headers().contains_key("x-hooked"));
+    }
 }
@@ -0,0 +1,105 @@
+use crate::decision::RateLimitSnapshot;
+use axum::body::Body;
+use http::Response;
+
+// Rate limiting trailers of gRPC responses, see `GovernorConfigBuilder::use_trailers`.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use crate::headers::{LIMIT_HEADER, REMAINING_HEADER};
+    use bytes::Bytes;
+    use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Response};
+    use http_body::{Body, Frame, SizeHint};
+    use pin_project::pin_project;
+    use std::{
+        pin::Pin,
+        task::{ready, Context, Poll},
+    };
+
+    use super::RateLimitSnapshot;
+
+    // Body adding `trailers` to the trailers of `inner`, or after its last frame if it has none.
+    #[pin_project]
+    pub(super) struct WithTrailers<B> {
+        #[pin]
+        inner: B,
+        trailers: Option<HeaderMap>,
+    }
+
+    impl<B> WithTrailers<B> {
+        pub(super) fn new(inner: B, trailers: HeaderMap) -> Self {
+            Self {
+                inner,
+                trailers: Some(trailers),
+            }
+        }
+    }
+
+    impl<B> Body for WithTrailers<B>
+    where
+        B: Body<Data = Bytes>,
+    {
+        type Data = Bytes;
+        type Error = B::Error;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            let this = self.project();
+            match ready!(this.inner.poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_trailers() {
+                    Ok(mut trailers) => {
+                        // a body has a single trailers frame, extend the one of the inner body
+                        if let Some(ours) = this.trailers.take() {
+                            trailers.extend(ours);
+                        }
+                        Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+                    }
+                    Err(frame) => Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(error)) => Poll::Ready(Some(Err(error))),
+                None => Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t)))),
+            }
+        }
+
+        fn is_end_stream(&self) -> bool {
+            self.trailers.is_none() && self.inner.is_end_stream()
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            self.inner.size_hint()
+        }
+    }
+
+    pub(super) fn is_grpc<B>(response: &Response<B>) -> bool {
+        response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/grpc"))
+    }
+
+    pub(super) fn trailers(snapshot: &RateLimitSnapshot) -> HeaderMap {
+        let mut trailers = HeaderMap::new();
+        trailers.insert(LIMIT_HEADER, HeaderValue::from(snapshot.limit));
+        if let Some(remaining) = snapshot.remaining {
+            trailers.insert(REMAINING_HEADER, HeaderValue::from(remaining));
+        }
+        trailers
+    }
+}
+
+/// Add the rate limiting trailers to the response if it is a gRPC one.
+#[cfg(feature = "grpc")]
+pub(crate) fn append(response: Response<Body>, snapshot: &RateLimitSnapshot) -> Response<Body> {
+    if !grpc::is_grpc(&response) {
+        return response;
+    }
+    let trailers = grpc::trailers(snapshot);
+    response.map(|body| Body::new(grpc::WithTrailers::new(body, trailers)))
+}
+
+#[cfg(not(feature = "grpc"))]
+pub(crate) fn append(response: Response<Body>, _snapshot: &RateLimitSnapshot) -> Response<Body> {
+    response
+}
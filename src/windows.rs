@@ -0,0 +1,156 @@
+use crate::{
+    governor::SharedRateLimiter,
+    headers::{RateLimitPolicies, RateLimitPolicy},
+};
+use governor::{clock::Clock, middleware::StateInformationMiddleware, Quota};
+use http::HeaderValue;
+use std::{fmt, hash::Hash, time::Duration};
+
+// A longer window every key must also stay within, see `GovernorConfigBuilder::window_quota`.
+pub(crate) struct Window<Key, C>
+where
+    Key: Hash + Eq + Clone,
+    C: Clock,
+{
+    pub(crate) quota: Quota,
+    limiter: SharedRateLimiter<Key, StateInformationMiddleware, C>,
+    pub(crate) scope: Option<HeaderValue>,
+    // the name of its policy, the scope if it is a valid header value
+    policy: String,
+}
+
+// The windows of a configuration, in the order they were added.
+pub(crate) struct Windows<Key, C>(Vec<Window<Key, C>>)
+where
+    Key: Hash + Eq + Clone,
+    C: Clock;
+
+// The policies of the windows of an allowed request, handed over to its response through the
+// request extensions.
+#[derive(Debug, Clone)]
+pub(crate) struct WindowPolicies(pub(crate) Vec<RateLimitPolicy>);
+
+impl<Key, C> fmt::Debug for Windows<Key, C>
+where
+    Key: Hash + Eq + Clone,
+    C: Clock,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(|window| window.policy.as_str()).collect();
+        f.debug_struct("Windows").field("windows", &names).finish()
+    }
+}
+
+impl<Key, C> Windows<Key, C>
+where
+    Key: Hash + Eq + Clone,
+    C: Clock,
+{
+    /// The windows of `quotas`, each named `name` in the headers unless `scope` is known.
+    pub(crate) fn new(
+        quotas: impl IntoIterator<Item = (&'static str, Quota, Option<HeaderValue>)>,
+        clock: &C,
+    ) -> Self
+    where
+        C: Clone,
+    {
+        let windows = quotas
+            .into_iter()
+            .map(|(name, quota, scope)| Window {
+                quota,
+                limiter: SharedRateLimiter::new(governor::RateLimiter::new(
+                    quota,
+                    Default::default(),
+                    clock.clone(),
+                )),
+                policy: scope
+                    .as_ref()
+                    .and_then(|scope| scope.to_str().ok())
+                    .unwrap_or(name)
+                    .to_owned(),
+                scope,
+            })
+            .collect();
+        Self(windows)
+    }
+
+    /// The number of keys held by the stores of the windows.
+    pub(crate) fn len(&self) -> usize {
+        self.0.iter().map(|window| window.limiter.len()).sum()
+    }
+
+    /// Clean up the stores of the windows, see [`RateLimiter::retain_recent`].
+    ///
+    /// [`RateLimiter::retain_recent`]: governor::RateLimiter::retain_recent
+    pub(crate) fn retain_recent(&self) {
+        for window in &self.0 {
+            window.limiter.retain_recent();
+            window.limiter.shrink_to_fit();
+        }
+    }
+
+    /// The same windows, with stores of their own.
+    pub(crate) fn detached(&self) -> Self
+    where
+        C: Clone,
+    {
+        let windows = self
+            .0
+            .iter()
+            .map(|window| Window {
+                quota: window.quota,
+                limiter: SharedRateLimiter::new(governor::RateLimiter::new(
+                    window.quota,
+                    Default::default(),
+                    window.limiter.clock().clone(),
+                )),
+                scope: window.scope.clone(),
+                policy: window.policy.clone(),
+            })
+            .collect();
+        Self(windows)
+    }
+
+    /// Charge a request of `key` to every window, returning its policy in each of them, or
+    /// the time to wait along with the first window it exceeds.
+    ///
+    /// The windows before the one it exceeds keep the charge, as the quota of the key does.
+    pub(crate) fn check(&self, key: &Key) -> Result<WindowPolicies, (Duration, &Window<Key, C>)> {
+        let mut policies = Vec::with_capacity(self.0.len());
+        for window in &self.0 {
+            match window.limiter.check_key(key) {
+                Ok(state) => policies.push(
+                    RateLimitPolicy::new(window.policy.as_str(), window.quota)
+                        .remaining(state.remaining_burst_capacity()),
+                ),
+                Err(not_until) => {
+                    let now = window.limiter.clock().now();
+                    return Err((not_until.wait_time_from(now), window));
+                }
+            }
+        }
+        Ok(WindowPolicies(policies))
+    }
+}
+
+impl WindowPolicies {
+    /// The policies of a request allowed by `quota`, named `name`, followed by those of the
+    /// windows.
+    pub(crate) fn with(
+        self,
+        name: &str,
+        quota: Quota,
+        remaining: Option<u32>,
+    ) -> RateLimitPolicies {
+        let mut policies = RateLimitPolicies::new();
+        let policy = RateLimitPolicy::new(name, quota);
+        policies.push(match remaining {
+            Some(remaining) => policy.remaining(remaining),
+            None => policy,
+        });
+        for policy in self.0 {
+            policies.push(policy);
+        }
+        policies
+    }
+}